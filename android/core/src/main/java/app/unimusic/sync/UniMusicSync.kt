@@ -1,18 +1,36 @@
 package app.unimusic.sync
 
+import uniffi.unimusic_sync.ErrorCategory
 import uniffi.unimusic_sync.IrohFactory
 import uniffi.unimusic_sync.IrohManager
+import uniffi.unimusic_sync.ReconnectReport
+import uniffi.unimusic_sync.SharedException
 import uniffi.unimusic_sync.UDocTicket
 import uniffi.unimusic_sync.UEntry
 import uniffi.unimusic_sync.UHash
 import uniffi.unimusic_sync.UNamespaceId
 import uniffi.unimusic_sync.UNodeId
+import uniffi.unimusic_sync.errorCategory
+import uniffi.unimusic_sync.errorCode
+import uniffi.unimusic_sync.errorIsRetryable
+
+/** Stable identifier of the error, it doesn't change between releases */
+val SharedException.code: String
+  get() = errorCode(this)
+
+val SharedException.category: ErrorCategory
+  get() = errorCategory(this)
+
+/** Whether repeating the operation later might succeed */
+val SharedException.isRetryable: Boolean
+  get() = errorIsRetryable(this)
 
 @Suppress("unused")
 class UniMusicSync(private val irohManager: IrohManager) {
   companion object {
-    suspend fun create(path: String): UniMusicSync {
-      val irohFactory = IrohFactory()
+    /** With a passphrase, keys, peer lists, music and entries are encrypted at rest */
+    suspend fun create(path: String, passphrase: String? = null): UniMusicSync {
+      val irohFactory = passphrase?.let { IrohFactory.withPassphrase(it) } ?: IrohFactory()
       val irohManager = irohFactory.irohManager(path)
       val instance = UniMusicSync(irohManager)
       return instance
@@ -20,9 +38,13 @@ class UniMusicSync(private val irohManager: IrohManager) {
   }
 
   // This allows to attach a context, which results in much cleaner stack traces
+  // SharedException is rethrown as-is, so callers can still match on its type
   private inline fun <T> handleException(block: () -> T): T {
     return try {
       block()
+    } catch (exception: SharedException) {
+      exception.addSuppressed(RuntimeException("Exception in handleException block"))
+      throw exception
     } catch (exception: Exception) {
       throw RuntimeException("Exception in handleException block", exception)
     }
@@ -107,7 +129,9 @@ class UniMusicSync(private val irohManager: IrohManager) {
     handleException { irohManager.sync(namespace) }
   }
 
-  suspend fun reconnect() {
-    handleException { irohManager.reconnect() }
+  /** Reports which known peers connected, and how */
+  suspend fun reconnect(): ReconnectReport {
+    val report = handleException { irohManager.reconnect() }
+    return report
   }
 }
@@ -8,8 +8,9 @@ import Foundation
 public class UniMusicSync {
     public let irohManager: IrohManager
 
-    public init(_ path: String) async throws {
-        let irohFactory = IrohFactory()
+    /// With a passphrase, keys, peer lists, music and entries are encrypted at rest
+    public init(_ path: String, passphrase: String? = nil) async throws {
+        let irohFactory = passphrase.map { IrohFactory.withPassphrase(passphrase: $0) } ?? IrohFactory()
         irohManager = try await irohFactory.irohManager(path: path)
     }
 
@@ -92,7 +93,26 @@ public class UniMusicSync {
         try await irohManager.sync(namespace: namespace)
     }
 
-    public func reconnect() async {
-        await irohManager.reconnect()
+    /// Reports which known peers connected, and how
+    @discardableResult
+    public func reconnect() async -> ReconnectReport {
+        let report = await irohManager.reconnect()
+        return report
+    }
+}
+
+public extension SharedError {
+    /// Stable identifier of the error, it doesn't change between releases
+    var code: String {
+        errorCode(error: self)
+    }
+
+    var category: ErrorCategory {
+        errorCategory(error: self)
+    }
+
+    /// Whether repeating the operation later might succeed
+    var isRetryable: Bool {
+        errorIsRetryable(error: self)
     }
 }
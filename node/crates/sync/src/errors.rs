@@ -0,0 +1,39 @@
+use neon::prelude::*;
+use unimusic_sync::errors::{ErrorCategory, SharedError};
+
+/// Error thrown into JS, carrying `code`, `category` and `retryable` properties
+#[derive(Debug)]
+pub struct SyncError(anyhow::Error);
+
+impl<E: Into<anyhow::Error>> From<E> for SyncError {
+    fn from(value: E) -> Self {
+        Self(value.into())
+    }
+}
+
+impl SyncError {
+    pub fn into_js<'cx>(self, cx: &mut Cx<'cx>) -> JsResult<'cx, JsError> {
+        let shared_error = self.0.downcast_ref::<SharedError>();
+
+        let (code, category, retryable) = match shared_error {
+            Some(error) => (error.code(), error.category(), error.is_retryable()),
+            None => ("INTERNAL", ErrorCategory::Internal, false),
+        };
+
+        let category = match category {
+            ErrorCategory::Network => "network",
+            ErrorCategory::Storage => "storage",
+            ErrorCategory::NotFound => "notFound",
+            ErrorCategory::Permission => "permission",
+            ErrorCategory::InvalidInput => "invalidInput",
+            ErrorCategory::Cancelled => "cancelled",
+            ErrorCategory::Internal => "internal",
+        };
+
+        let error = cx.error(format!("{:#}", self.0))?;
+        error.prop(cx, "code").set(code)?;
+        error.prop(cx, "category").set(category)?;
+        error.prop(cx, "retryable").set(retryable)?;
+        Ok(error)
+    }
+}
@@ -1,245 +1,1676 @@
-use std::sync::OnceLock;
+mod errors;
+use errors::SyncError;
+
+use std::sync::{Arc, Mutex, OnceLock};
 
 use anyhow::anyhow;
 use neon::{
     prelude::*,
-    types::extract::{self, Error, TryIntoJs},
+    types::extract::{self, Boxed, Buffer, TryIntoJs},
+};
+use serde::{Deserialize, Deserializer};
+use tokio::{fs, runtime::Runtime, task::JoinHandle};
+use unimusic_sync::{
+    IrohFactory, IrohManager, SyncScope,
+    backoff::BackoffPolicy,
+    cancellation::CancellationHandle,
+    diagnostics::{AddressKind, Diagnostics},
+    events::SyncEvent,
+    export::ExportProgressHandle,
+    invitation::Invitation,
+    metadata::TrackMetadata,
+    reconnect::{PeerConnectionType, ReconnectOutcome},
+    relay::parse_relay_map,
+    scheduler::AutoSyncConfig,
+    streaming::StreamingServerInfo,
+    types::UEntry,
 };
-use tokio::{fs, runtime::Runtime};
-use unimusic_sync::{IrohFactory, IrohManager};
 
-type Result<T> = std::result::Result<T, Error>;
+type Result<T> = std::result::Result<T, SyncError>;
+
+/// Converts the result into a JS value, throwing errors as JS errors with additional properties
+fn settle<T>(result: Result<T>) -> impl for<'cx> TryIntoJs<'cx>
+where
+    T: for<'cx> TryIntoJs<'cx>,
+{
+    extract::with(move |cx| match result {
+        Ok(value) => Ok(value.try_into_js(cx)?.upcast::<JsValue>()),
+        Err(error) => {
+            let error = error.into_js(cx)?;
+            cx.throw(error)
+        }
+    })
+}
+
+fn invitation_into_js<'cx>(cx: &mut Cx<'cx>, invitation: &Invitation) -> JsResult<'cx, JsObject> {
+    let obj = cx.empty_object();
+
+    let id = invitation.id.as_str().try_into_js(cx);
+    obj.prop(cx, "id").set(id)?;
+
+    let namespace = invitation.namespace.to_string().try_into_js(cx);
+    obj.prop(cx, "namespace").set(namespace)?;
+
+    let ticket = invitation.ticket.to_string().try_into_js(cx);
+    obj.prop(cx, "ticket").set(ticket)?;
+
+    obj.prop(cx, "createdAtMs")
+        .set(invitation.created_at_ms as f64)?;
+
+    if let Some(expires_at_ms) = invitation.expires_at_ms {
+        obj.prop(cx, "expiresAtMs").set(expires_at_ms as f64)?;
+    }
+
+    obj.prop(cx, "singleUse").set(invitation.single_use)?;
+    obj.prop(cx, "readOnly").set(invitation.read_only)?;
+    obj.prop(cx, "uses").set(invitation.uses as f64)?;
+    obj.prop(cx, "revoked").set(invitation.revoked)?;
+
+    Ok(obj)
+}
+
+fn entry_into_js<'cx>(cx: &mut Cx<'cx>, entry: &UEntry) -> JsResult<'cx, JsObject> {
+    let obj = cx.empty_object();
+
+    let key = entry.key().try_into_js(cx);
+    obj.prop(cx, "key").set(key)?;
+
+    let author = entry.author().to_string().try_into_js(cx);
+    obj.prop(cx, "author").set(author)?;
+
+    let timestamp = entry.timestamp();
+    obj.prop(cx, "timestamp").set(timestamp as f64)?;
+
+    let content_hash = entry.content_hash().to_string().try_into_js(cx);
+    obj.prop(cx, "contentHash").set(content_hash)?;
+
+    let content_len = entry.content_len();
+    obj.prop(cx, "contentLen").set(content_len as f64)?;
+
+    let file_len = entry.file_len();
+    obj.prop(cx, "fileLen").set(file_len as f64)?;
+
+    Ok(obj)
+}
+
+fn track_into_js<'cx>(cx: &mut Cx<'cx>, track: &TrackMetadata) -> JsResult<'cx, JsObject> {
+    let obj = cx.empty_object();
 
-static UNIMUSIC: OnceLock<IrohManager> = OnceLock::new();
+    let key = track.key.as_str().try_into_js(cx);
+    obj.prop(cx, "key").set(key)?;
+
+    for (name, value) in [
+        ("title", &track.title),
+        ("artist", &track.artist),
+        ("album", &track.album),
+    ] {
+        if let Some(value) = value {
+            let value = value.as_str().try_into_js(cx);
+            obj.prop(cx, name).set(value)?;
+        }
+    }
+
+    if let Some(track_number) = track.track_number {
+        obj.prop(cx, "trackNumber").set(track_number as f64)?;
+    }
+
+    obj.prop(cx, "durationMs").set(track.duration_ms as f64)?;
+
+    let codec = track.codec.as_str().try_into_js(cx);
+    obj.prop(cx, "codec").set(codec)?;
+
+    if let Some(artwork) = &track.artwork {
+        let artwork = artwork.as_str().try_into_js(cx);
+        obj.prop(cx, "artwork").set(artwork)?;
+    }
+
+    Ok(obj)
+}
+
+fn connection_type_str(connection_type: PeerConnectionType) -> &'static str {
+    match connection_type {
+        PeerConnectionType::Direct => "direct",
+        PeerConnectionType::Relay => "relay",
+        PeerConnectionType::Mixed => "mixed",
+        PeerConnectionType::None => "none",
+    }
+}
+
+fn diagnostics_into_js<'cx>(cx: &mut Cx<'cx>, report: &Diagnostics) -> JsResult<'cx, JsObject> {
+    let obj = cx.empty_object();
+
+    let node_id = report.node_id.to_string().try_into_js(cx);
+    obj.prop(cx, "nodeId").set(node_id)?;
+
+    let version = report.version.as_str().try_into_js(cx);
+    obj.prop(cx, "version").set(version)?;
+
+    obj.prop(cx, "createdAtMs")
+        .set(report.created_at_ms as f64)?;
+
+    let addresses = cx.empty_array();
+    for (i, address) in report.addresses.iter().enumerate() {
+        let item = cx.empty_object();
+
+        let addr = address.addr.as_str().try_into_js(cx);
+        item.prop(cx, "addr").set(addr)?;
+
+        let kind = match address.kind {
+            AddressKind::Local => "local",
+            AddressKind::Stun => "stun",
+            AddressKind::Portmapped => "portmapped",
+            AddressKind::Unknown => "unknown",
+        };
+        item.prop(cx, "kind").set(kind)?;
+
+        addresses.prop(cx, i as u32).set(item)?;
+    }
+    obj.prop(cx, "addresses").set(addresses)?;
+
+    if let Some(home_relay) = &report.home_relay {
+        let home_relay = home_relay.as_str().try_into_js(cx);
+        obj.prop(cx, "homeRelay").set(home_relay)?;
+    }
+
+    if let Some(nat) = &report.nat {
+        let item = cx.empty_object();
+
+        item.prop(cx, "udp").set(nat.udp)?;
+        item.prop(cx, "ipv4").set(nat.ipv4)?;
+        item.prop(cx, "ipv6").set(nat.ipv6)?;
+
+        for (name, value) in [
+            ("publicIpv4", &nat.public_ipv4),
+            ("publicIpv6", &nat.public_ipv6),
+        ] {
+            if let Some(value) = value {
+                let value = value.as_str().try_into_js(cx);
+                item.prop(cx, name).set(value)?;
+            }
+        }
+
+        for (name, value) in [
+            (
+                "mappingVariesByDestination",
+                nat.mapping_varies_by_destination,
+            ),
+            ("hairPinning", nat.hair_pinning),
+            ("captivePortal", nat.captive_portal),
+            ("upnp", nat.upnp),
+            ("pcp", nat.pcp),
+            ("natPmp", nat.nat_pmp),
+        ] {
+            if let Some(value) = value {
+                item.prop(cx, name).set(value)?;
+            }
+        }
+
+        obj.prop(cx, "nat").set(item)?;
+    }
+
+    let peers = cx.empty_array();
+    for (i, peer) in report.peers.iter().enumerate() {
+        let item = cx.empty_object();
+
+        let node_id = peer.node_id.to_string().try_into_js(cx);
+        item.prop(cx, "nodeId").set(node_id)?;
+
+        let connection_type = connection_type_str(peer.connection_type);
+        item.prop(cx, "connectionType").set(connection_type)?;
+
+        if let Some(path) = &peer.path {
+            let path = path.as_str().try_into_js(cx);
+            item.prop(cx, "path").set(path)?;
+        }
+
+        if let Some(rtt_ms) = peer.rtt_ms {
+            item.prop(cx, "rttMs").set(rtt_ms as f64)?;
+        }
+
+        if let Some(last_used_ms) = peer.last_used_ms {
+            item.prop(cx, "lastUsedMs").set(last_used_ms as f64)?;
+        }
+
+        peers.prop(cx, i as u32).set(item)?;
+    }
+    obj.prop(cx, "peers").set(peers)?;
+
+    obj.prop(cx, "bytesSent").set(report.bytes_sent as f64)?;
+    obj.prop(cx, "bytesReceived")
+        .set(report.bytes_received as f64)?;
+
+    Ok(obj)
+}
+
+/// Moves the data into a Node `Buffer`. With the `external-buffers` feature the memory is
+/// handed over without copying, which runtimes with the V8 memory cage, like Electron, abort on.
+fn buffer_into_js<'cx>(cx: &mut Cx<'cx>, data: Vec<u8>) -> JsResult<'cx, JsBuffer> {
+    #[cfg(feature = "external-buffers")]
+    return Ok(JsBuffer::external(cx, data));
+
+    #[cfg(not(feature = "external-buffers"))]
+    JsBuffer::from_slice(cx, &data)
+}
+
+#[derive(Clone)]
+struct Cancellation(Arc<CancellationHandle>);
+
+impl Finalize for Cancellation {}
+
+#[derive(Clone)]
+struct ExportProgress(Arc<ExportProgressHandle>);
+
+impl Finalize for ExportProgress {}
+
+static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+
+/// Manager owned by a JS `UniMusicSync` instance, empty once it's shut down
+#[derive(Clone)]
+struct Manager(Arc<Mutex<Option<IrohManager>>>);
+
+impl Manager {
+    fn get(&self) -> Result<IrohManager> {
+        let manager = self.0.lock().unwrap().clone();
+        Ok(manager.ok_or_else(|| anyhow!("UniMusicSync instance is shut down!"))?)
+    }
+}
+
+impl Finalize for Manager {
+    fn finalize<'a, C: Context<'a>>(self, _: &mut C) {
+        // Instances which weren't shut down get shut down once garbage collected
+        let manager = self.0.lock().unwrap().take();
+        if let (Some(manager), Some(runtime)) = (manager, RUNTIME.get()) {
+            runtime.spawn(async move { manager.shutdown().await });
+        }
+    }
+}
 
 #[neon::export]
-async fn initialize(path: String) -> Result<()> {
-    let factory = IrohFactory::new();
-    let iroh_manager = factory.iroh_manager(&path).await?;
-    UNIMUSIC
-        .set(iroh_manager)
-        .map_err(|_| anyhow!("You can only create one UniMusicSync instance!"))?;
-    Ok(())
+async fn initialize(
+    path: String,
+    passphrase: Option<String>,
+    relays: Option<extract::Json<Vec<String>>>,
+) -> impl for<'cx> TryIntoJs<'cx> {
+    settle(
+        async move {
+            let mut factory = match passphrase {
+                Some(passphrase) => IrohFactory::with_passphrase(passphrase),
+                None => IrohFactory::new(),
+            };
+            if let Some(extract::Json(relays)) = relays {
+                factory = factory.with_relay_map(parse_relay_map(&relays)?);
+            }
+            let iroh_manager = factory.iroh_manager(&path).await?;
+            Ok(Boxed(Manager(Arc::new(Mutex::new(Some(iroh_manager))))))
+        }
+        .await,
+    )
 }
 
 #[neon::export]
-async fn shutdown() -> Result<()> {
-    let unimusic = UNIMUSIC
-        .get()
-        .ok_or_else(|| anyhow!("UniMusicSync is not initialized!"))?;
-    unimusic.shutdown().await?;
-    Ok(())
+async fn shutdown(Boxed(manager): Boxed<Manager>) -> impl for<'cx> TryIntoJs<'cx> {
+    settle(
+        async move {
+            // Shutting down twice does nothing
+            let unimusic = manager.0.lock().unwrap().take();
+            if let Some(unimusic) = unimusic {
+                unimusic.shutdown().await?;
+            }
+            Ok(())
+        }
+        .await,
+    )
 }
 
 #[neon::export]
-async fn create_namespace() -> Result<String> {
-    let unimusic = UNIMUSIC
-        .get()
-        .ok_or_else(|| anyhow!("UniMusicSync is not initialized!"))?;
+async fn create_namespace(Boxed(manager): Boxed<Manager>) -> impl for<'cx> TryIntoJs<'cx> {
+    settle::<String>(
+        async move {
+            let unimusic = manager.get()?;
 
-    let namespace = unimusic.create_namespace().await?;
+            let namespace = unimusic.create_namespace().await?;
 
-    Ok(namespace.into())
+            Ok(namespace.into())
+        }
+        .await,
+    )
 }
 
 #[neon::export]
-async fn delete_namespace(namespace: String) -> Result<()> {
-    let unimusic = UNIMUSIC
-        .get()
-        .ok_or_else(|| anyhow!("UniMusicSync is not initialized!"))?;
+async fn delete_namespace(
+    Boxed(manager): Boxed<Manager>,
+    namespace: String,
+) -> impl for<'cx> TryIntoJs<'cx> {
+    settle(
+        async move {
+            let unimusic = manager.get()?;
 
-    let namespace = namespace.parse()?;
-    unimusic.delete_namespace(namespace).await?;
+            let namespace = namespace.parse()?;
+            unimusic.delete_namespace(namespace).await?;
 
-    Ok(())
+            Ok(())
+        }
+        .await,
+    )
 }
 
 #[neon::export]
-async fn get_author() -> Result<String> {
-    let unimusic = UNIMUSIC
-        .get()
-        .ok_or_else(|| anyhow!("UniMusicSync is not initialized!"))?;
+async fn get_author(Boxed(manager): Boxed<Manager>) -> impl for<'cx> TryIntoJs<'cx> {
+    settle::<String>(
+        async move {
+            let unimusic = manager.get()?;
 
-    let author = unimusic.get_author().await?;
+            let author = unimusic.get_author().await?;
 
-    Ok(author.into())
+            Ok(author.into())
+        }
+        .await,
+    )
 }
 
 #[neon::export]
-async fn get_node_id() -> Result<String> {
-    let unimusic = UNIMUSIC
-        .get()
-        .ok_or_else(|| anyhow!("UniMusicSync is not initialized!"))?;
+async fn get_node_id(Boxed(manager): Boxed<Manager>) -> impl for<'cx> TryIntoJs<'cx> {
+    settle::<String>(
+        async move {
+            let unimusic = manager.get()?;
 
-    let node_id = unimusic.get_node_id().await;
+            let node_id = unimusic.get_node_id().await;
 
-    Ok(node_id.into())
+            Ok(node_id.into())
+        }
+        .await,
+    )
 }
 
 #[neon::export]
-async fn get_files(namespace: String) -> Result<impl for<'cx> TryIntoJs<'cx>> {
-    let unimusic = UNIMUSIC
-        .get()
-        .ok_or_else(|| anyhow!("UniMusicSync is not initialized!"))?;
+async fn get_namespaces(Boxed(manager): Boxed<Manager>) -> impl for<'cx> TryIntoJs<'cx> {
+    settle(
+        async move {
+            let unimusic = manager.get()?;
 
-    let namespace = namespace.parse()?;
-    let files = unimusic.get_files(namespace).await?;
+            let namespaces = unimusic.get_namespaces().await?;
 
-    Ok(extract::with(move |cx| {
-        let result = cx.empty_array();
+            Ok(extract::Json(
+                namespaces
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>(),
+            ))
+        }
+        .await,
+    )
+}
 
-        for (i, entry) in files.iter().enumerate() {
-            let obj = cx.empty_object();
+#[neon::export]
+async fn get_known_nodes(Boxed(manager): Boxed<Manager>) -> impl for<'cx> TryIntoJs<'cx> {
+    settle(
+        async move {
+            let unimusic = manager.get()?;
 
-            let key = entry.key().try_into_js(cx);
-            obj.prop(cx, "key").set(key)?;
+            let node_ids = unimusic.get_known_nodes().await;
+
+            Ok(extract::Json(
+                node_ids.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            ))
+        }
+        .await,
+    )
+}
+
+#[neon::export]
+async fn get_namespace_nodes(
+    Boxed(manager): Boxed<Manager>,
+    namespace: String,
+) -> impl for<'cx> TryIntoJs<'cx> {
+    settle(
+        async move {
+            let unimusic = manager.get()?;
 
-            let author = entry.author().to_string().try_into_js(cx);
-            obj.prop(cx, "author").set(author)?;
+            let namespace = namespace.parse()?;
+            let node_ids = unimusic.get_namespace_nodes(namespace).await;
 
-            let timestamp = entry.timestamp();
-            obj.prop(cx, "timestamp").set(timestamp as f64)?;
+            Ok(extract::Json(
+                node_ids.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            ))
+        }
+        .await,
+    )
+}
+
+#[neon::export]
+async fn get_files(
+    Boxed(manager): Boxed<Manager>,
+    namespace: String,
+) -> impl for<'cx> TryIntoJs<'cx> {
+    settle(
+        async move {
+            let unimusic = manager.get()?;
+
+            let namespace = namespace.parse()?;
+            let files = unimusic.get_files(namespace).await?;
 
-            let content_hash = entry.content_hash().to_string().try_into_js(cx);
-            obj.prop(cx, "contentHash").set(content_hash)?;
+            Ok(extract::with(move |cx| {
+                let result = cx.empty_array();
 
-            let content_len = entry.content_len();
-            obj.prop(cx, "contentLen").set(content_len as f64)?;
+                for (i, entry) in files.iter().enumerate() {
+                    let obj = entry_into_js(cx, entry)?;
+                    result.prop(cx, i as u32).set(obj)?;
+                }
 
-            result.prop(cx, i as u32).set(obj)?;
+                Ok(result)
+            }))
         }
+        .await,
+    )
+}
 
-        Ok(result)
-    }))
+#[neon::export]
+async fn get_file(
+    Boxed(manager): Boxed<Manager>,
+    namespace: String,
+    sync_path: String,
+) -> impl for<'cx> TryIntoJs<'cx> {
+    settle(
+        async move {
+            let unimusic = manager.get()?;
+
+            let namespace = namespace.parse()?;
+            let entry = unimusic.get_file(namespace, &sync_path).await?;
+
+            Ok(extract::with(move |cx| entry_into_js(cx, &entry)))
+        }
+        .await,
+    )
 }
 
 #[neon::export]
-async fn write_file(namespace: String, sync_path: String, source_path: String) -> Result<String> {
-    let unimusic = UNIMUSIC
-        .get()
-        .ok_or_else(|| anyhow!("UniMusicSync is not initialized!"))?;
+async fn write_file(
+    Boxed(manager): Boxed<Manager>,
+    namespace: String,
+    sync_path: String,
+    source_path: String,
+) -> impl for<'cx> TryIntoJs<'cx> {
+    settle::<String>(
+        async move {
+            let unimusic = manager.get()?;
+
+            let namespace = namespace.parse()?;
+            let file_hash = unimusic
+                .write_file_from_path(namespace, sync_path, source_path)
+                .await?;
+
+            Ok(file_hash.into())
+        }
+        .await,
+    )
+}
 
-    let namespace = namespace.parse()?;
-    let data = fs::read(source_path).await?;
+#[neon::export]
+async fn write_bytes(
+    Boxed(manager): Boxed<Manager>,
+    namespace: String,
+    sync_path: String,
+    Buffer(data): Buffer<Vec<u8>>,
+) -> impl for<'cx> TryIntoJs<'cx> {
+    settle::<String>(
+        async move {
+            let unimusic = manager.get()?;
 
-    let file_hash = unimusic.write_file(namespace, sync_path, data).await?;
+            let namespace = namespace.parse()?;
+            let file_hash = unimusic.write_file(namespace, sync_path, data).await?;
 
-    Ok(file_hash.into())
+            Ok(file_hash.into())
+        }
+        .await,
+    )
 }
 
 #[neon::export]
-async fn delete_file(namespace: String, sync_path: String) -> Result<()> {
-    let unimusic = UNIMUSIC
-        .get()
-        .ok_or_else(|| anyhow!("UniMusicSync is not initialized!"))?;
+async fn write_file_split(
+    Boxed(manager): Boxed<Manager>,
+    namespace: String,
+    sync_path: String,
+    source_path: String,
+) -> impl for<'cx> TryIntoJs<'cx> {
+    settle::<String>(
+        async move {
+            let unimusic = manager.get()?;
 
-    let namespace = namespace.parse()?;
-    unimusic.delete_file(namespace, sync_path).await?;
+            let namespace = namespace.parse()?;
+            let data = fs::read(source_path).await?;
 
-    Ok(())
+            let file_hash = unimusic
+                .write_file_split(namespace, sync_path, data)
+                .await?;
+
+            Ok(file_hash.into())
+        }
+        .await,
+    )
 }
 
 #[neon::export]
-async fn read_file(namespace: String, sync_path: String) -> Result<Vec<u8>> {
-    let unimusic = UNIMUSIC
-        .get()
-        .ok_or_else(|| anyhow!("UniMusicSync is not initialized!"))?;
+async fn get_track_metadata(
+    Boxed(manager): Boxed<Manager>,
+    namespace: String,
+    sync_path: String,
+) -> impl for<'cx> TryIntoJs<'cx> {
+    settle(
+        async move {
+            let unimusic = manager.get()?;
 
-    let namespace = namespace.parse()?;
-    let data = unimusic.read_file(namespace, &sync_path).await?;
+            let namespace = namespace.parse()?;
+            let track = unimusic.get_track_metadata(namespace, sync_path).await?;
 
-    Ok(data)
+            Ok(extract::with(move |cx| match &track {
+                Some(track) => Ok(track_into_js(cx, track)?.upcast::<JsValue>()),
+                None => Ok(cx.undefined().upcast()),
+            }))
+        }
+        .await,
+    )
 }
 
 #[neon::export]
-async fn read_file_hash(file_hash: String) -> Result<Vec<u8>> {
-    let unimusic = UNIMUSIC
-        .get()
-        .ok_or_else(|| anyhow!("UniMusicSync is not initialized!"))?;
+async fn get_tracks(
+    Boxed(manager): Boxed<Manager>,
+    namespace: String,
+    artist: Option<String>,
+    album: Option<String>,
+) -> impl for<'cx> TryIntoJs<'cx> {
+    settle(
+        async move {
+            let unimusic = manager.get()?;
 
-    let file_hash = file_hash.parse()?;
-    let data = unimusic.read_file_hash(file_hash).await?;
+            let namespace = namespace.parse()?;
+            let tracks = unimusic.get_tracks(namespace, artist, album).await?;
 
-    Ok(data)
+            Ok(extract::with(move |cx| {
+                let array = cx.empty_array();
+                for (i, track) in tracks.iter().enumerate() {
+                    let obj = track_into_js(cx, track)?;
+                    array.prop(cx, i as u32).set(obj)?;
+                }
+                Ok(array)
+            }))
+        }
+        .await,
+    )
 }
 
 #[neon::export]
-async fn export_file(namespace: String, sync_path: String, destination_path: String) -> Result<()> {
-    let unimusic = UNIMUSIC
-        .get()
-        .ok_or_else(|| anyhow!("UniMusicSync is not initialized!"))?;
+async fn get_artwork(
+    Boxed(manager): Boxed<Manager>,
+    namespace: String,
+    artwork_hash: String,
+    size: Option<f64>,
+) -> impl for<'cx> TryIntoJs<'cx> {
+    settle(
+        async move {
+            let unimusic = manager.get()?;
 
-    let namespace = namespace.parse()?;
-    unimusic
-        .export(namespace, &sync_path, &destination_path)
-        .await?;
+            let namespace = namespace.parse()?;
+            let artwork_hash = artwork_hash.parse()?;
+            let size = size.map(|size| size as u32);
+            let image = unimusic.get_artwork(namespace, artwork_hash, size).await?;
 
-    Ok(())
+            Ok(extract::with(move |cx| match image {
+                Some(image) => Ok(buffer_into_js(cx, image)?.upcast::<JsValue>()),
+                None => Ok(cx.undefined().upcast()),
+            }))
+        }
+        .await,
+    )
 }
 
 #[neon::export]
-async fn export_file_hash(file_hash: String, destination_path: String) -> Result<()> {
-    let unimusic = UNIMUSIC
-        .get()
-        .ok_or_else(|| anyhow!("UniMusicSync is not initialized!"))?;
+async fn search(
+    Boxed(manager): Boxed<Manager>,
+    namespace: String,
+    query: String,
+    limit: Option<f64>,
+) -> impl for<'cx> TryIntoJs<'cx> {
+    settle(
+        async move {
+            let unimusic = manager.get()?;
 
-    let file_hash = file_hash.parse()?;
-    unimusic.export_hash(file_hash, &destination_path).await?;
+            let namespace = namespace.parse()?;
+            let limit = limit.map_or(50, |limit| limit as u32);
+            let results = unimusic.search(namespace, query, limit).await?;
 
-    Ok(())
+            Ok(extract::with(move |cx| {
+                let array = cx.empty_array();
+                for (i, result) in results.iter().enumerate() {
+                    let obj = cx.empty_object();
+
+                    let key = result.key.as_str().try_into_js(cx);
+                    obj.prop(cx, "key").set(key)?;
+                    obj.prop(cx, "score").set(result.score as f64)?;
+                    if let Some(track) = &result.track {
+                        let track = track_into_js(cx, track)?;
+                        obj.prop(cx, "track").set(track)?;
+                    }
+
+                    array.prop(cx, i as u32).set(obj)?;
+                }
+                Ok(array)
+            }))
+        }
+        .await,
+    )
 }
 
 #[neon::export]
-async fn share(namespace: String) -> Result<String> {
-    let unimusic = UNIMUSIC
-        .get()
-        .ok_or_else(|| anyhow!("UniMusicSync is not initialized!"))?;
+async fn delete_file(
+    Boxed(manager): Boxed<Manager>,
+    namespace: String,
+    sync_path: String,
+) -> impl for<'cx> TryIntoJs<'cx> {
+    settle(
+        async move {
+            let unimusic = manager.get()?;
 
-    let namespace = namespace.parse()?;
-    let ticket = unimusic.share(namespace).await?;
+            let namespace = namespace.parse()?;
+            unimusic.delete_file(namespace, sync_path).await?;
 
-    Ok(ticket.into())
+            Ok(())
+        }
+        .await,
+    )
 }
 
 #[neon::export]
-async fn import_ticket(ticket: String) -> Result<String> {
-    let unimusic = UNIMUSIC
-        .get()
-        .ok_or_else(|| anyhow!("UniMusicSync is not initialized!"))?;
+async fn read_file(
+    Boxed(manager): Boxed<Manager>,
+    namespace: String,
+    sync_path: String,
+) -> impl for<'cx> TryIntoJs<'cx> {
+    settle(
+        async move {
+            let unimusic = manager.get()?;
 
-    let ticket = ticket.parse()?;
-    let namespace = unimusic.import(ticket).await?;
+            let namespace = namespace.parse()?;
+            let data = unimusic.read_file(namespace, &sync_path).await?;
 
-    Ok(namespace.into())
+            Ok(extract::with(move |cx| buffer_into_js(cx, data)))
+        }
+        .await,
+    )
 }
 
 #[neon::export]
-async fn sync(namespace: String) -> Result<()> {
-    let unimusic = UNIMUSIC
-        .get()
-        .ok_or_else(|| anyhow!("UniMusicSync is not initialized!"))?;
+async fn read_file_hash(
+    Boxed(manager): Boxed<Manager>,
+    file_hash: String,
+) -> impl for<'cx> TryIntoJs<'cx> {
+    settle(
+        async move {
+            let unimusic = manager.get()?;
 
-    let namespace = namespace.parse()?;
-    unimusic.sync(namespace).await?;
+            let file_hash = file_hash.parse()?;
+            let data = unimusic.read_file_hash(file_hash).await?;
 
-    Ok(())
+            Ok(extract::with(move |cx| buffer_into_js(cx, data)))
+        }
+        .await,
+    )
 }
 
 #[neon::export]
-async fn reconnect() -> Result<()> {
-    let unimusic = UNIMUSIC
-        .get()
-        .ok_or_else(|| anyhow!("UniMusicSync is not initialized!"))?;
+async fn export_file(
+    Boxed(manager): Boxed<Manager>,
+    namespace: String,
+    sync_path: String,
+    destination_path: String,
+) -> impl for<'cx> TryIntoJs<'cx> {
+    settle(
+        async move {
+            let unimusic = manager.get()?;
 
-    unimusic.reconnect().await;
+            let namespace = namespace.parse()?;
+            unimusic
+                .export(namespace, &sync_path, &destination_path)
+                .await?;
 
-    Ok(())
+            Ok(())
+        }
+        .await,
+    )
+}
+
+#[neon::export]
+async fn export_file_hash(
+    Boxed(manager): Boxed<Manager>,
+    file_hash: String,
+    destination_path: String,
+) -> impl for<'cx> TryIntoJs<'cx> {
+    settle(
+        async move {
+            let unimusic = manager.get()?;
+
+            let file_hash = file_hash.parse()?;
+            unimusic.export_hash(file_hash, &destination_path).await?;
+
+            Ok(())
+        }
+        .await,
+    )
+}
+
+#[neon::export]
+async fn share(Boxed(manager): Boxed<Manager>, namespace: String) -> impl for<'cx> TryIntoJs<'cx> {
+    settle::<String>(
+        async move {
+            let unimusic = manager.get()?;
+
+            let namespace = namespace.parse()?;
+            let ticket = unimusic.share(namespace).await?;
+
+            Ok(ticket.into())
+        }
+        .await,
+    )
+}
+
+#[neon::export]
+fn compact_ticket(ticket: String) -> impl for<'cx> TryIntoJs<'cx> {
+    settle((|| -> Result<String> {
+        let ticket = ticket.parse()?;
+        Ok(unimusic_sync::ticket::compact_ticket(ticket).into())
+    })())
+}
+
+#[neon::export]
+fn ticket_to_uri(ticket: String) -> impl for<'cx> TryIntoJs<'cx> {
+    settle((|| -> Result<String> {
+        let ticket = ticket.parse()?;
+        Ok(unimusic_sync::ticket::ticket_to_uri(ticket))
+    })())
+}
+
+#[neon::export]
+async fn import_ticket(
+    Boxed(manager): Boxed<Manager>,
+    ticket: String,
+) -> impl for<'cx> TryIntoJs<'cx> {
+    settle::<String>(
+        async move {
+            let unimusic = manager.get()?;
+
+            let ticket = ticket.parse()?;
+            let namespace = unimusic.import(ticket).await?;
+
+            Ok(namespace.into())
+        }
+        .await,
+    )
+}
+
+#[neon::export]
+async fn sync(Boxed(manager): Boxed<Manager>, namespace: String) -> impl for<'cx> TryIntoJs<'cx> {
+    settle(
+        async move {
+            let unimusic = manager.get()?;
+
+            let namespace = namespace.parse()?;
+            unimusic.sync(namespace).await?;
+
+            Ok(())
+        }
+        .await,
+    )
+}
+
+#[neon::export]
+async fn start_live_sync(
+    Boxed(manager): Boxed<Manager>,
+    namespace: String,
+) -> impl for<'cx> TryIntoJs<'cx> {
+    settle(
+        async move {
+            let unimusic = manager.get()?;
+
+            let namespace = namespace.parse()?;
+            unimusic.start_live_sync(namespace).await?;
+
+            Ok(())
+        }
+        .await,
+    )
+}
+
+#[neon::export]
+async fn download_missing(
+    Boxed(manager): Boxed<Manager>,
+    namespace: String,
+) -> impl for<'cx> TryIntoJs<'cx> {
+    settle(
+        async move {
+            let unimusic = manager.get()?;
+
+            let namespace = namespace.parse()?;
+            let downloaded = unimusic.download_missing(namespace).await?;
+
+            Ok(downloaded as f64)
+        }
+        .await,
+    )
+}
+
+#[neon::export]
+async fn import_directory(
+    Boxed(manager): Boxed<Manager>,
+    namespace: String,
+    dir: String,
+    key_prefix: Option<String>,
+    ignore_globs: Option<extract::Json<Vec<String>>>,
+    tombstone_missing: Option<bool>,
+) -> impl for<'cx> TryIntoJs<'cx> {
+    settle(
+        async move {
+            let unimusic = manager.get()?;
+
+            let namespace = namespace.parse()?;
+            let report = unimusic
+                .import_directory(
+                    namespace,
+                    dir,
+                    key_prefix.unwrap_or_default(),
+                    ignore_globs.map(|globs| globs.0).unwrap_or_default(),
+                    tombstone_missing.unwrap_or(false),
+                )
+                .await?;
+
+            Ok(extract::with(move |cx| {
+                let obj = cx.empty_object();
+                obj.prop(cx, "added").set(report.added as f64)?;
+                obj.prop(cx, "updated").set(report.updated as f64)?;
+                obj.prop(cx, "unchanged").set(report.unchanged as f64)?;
+                obj.prop(cx, "ignored").set(report.ignored as f64)?;
+                obj.prop(cx, "tombstoned").set(report.tombstoned as f64)?;
+
+                let failures = cx.empty_array();
+                for (i, failure) in report.failures.iter().enumerate() {
+                    let item = cx.empty_object();
+                    let path = failure.path.as_str().try_into_js(cx);
+                    item.prop(cx, "path").set(path)?;
+                    let error = failure.error.as_str().try_into_js(cx);
+                    item.prop(cx, "error").set(error)?;
+                    failures.prop(cx, i as u32).set(item)?;
+                }
+                obj.prop(cx, "failures").set(failures)?;
+
+                Ok(obj)
+            }))
+        }
+        .await,
+    )
+}
+
+#[neon::export]
+fn create_export_progress() -> Boxed<ExportProgress> {
+    Boxed(ExportProgress(Arc::new(ExportProgressHandle::new())))
+}
+
+#[neon::export]
+fn export_progress(Boxed(handle): Boxed<ExportProgress>) -> impl for<'cx> TryIntoJs<'cx> {
+    let progress = handle.0.progress();
+
+    extract::with(move |cx| {
+        let obj = cx.empty_object();
+        obj.prop(cx, "totalFiles")
+            .set(progress.total_files as f64)?;
+        obj.prop(cx, "processedFiles")
+            .set(progress.processed_files as f64)?;
+        obj.prop(cx, "totalBytes")
+            .set(progress.total_bytes as f64)?;
+        obj.prop(cx, "processedBytes")
+            .set(progress.processed_bytes as f64)?;
+        Ok(obj)
+    })
+}
+
+#[neon::export]
+async fn export_all(
+    Boxed(manager): Boxed<Manager>,
+    namespace: String,
+    prefix: Option<String>,
+    destination_dir: String,
+    progress: Option<Boxed<ExportProgress>>,
+) -> impl for<'cx> TryIntoJs<'cx> {
+    settle(
+        async move {
+            let unimusic = manager.get()?;
+
+            let namespace = namespace.parse()?;
+            let progress = progress.map(|Boxed(progress)| progress.0);
+            let report = unimusic
+                .export_all(
+                    namespace,
+                    prefix.unwrap_or_default(),
+                    destination_dir,
+                    progress,
+                )
+                .await?;
+
+            Ok(extract::with(move |cx| {
+                let obj = cx.empty_object();
+                obj.prop(cx, "exported").set(report.exported as f64)?;
+                obj.prop(cx, "unchanged").set(report.unchanged as f64)?;
+                obj.prop(cx, "tombstoned").set(report.tombstoned as f64)?;
+                obj.prop(cx, "exportedBytes")
+                    .set(report.exported_bytes as f64)?;
+
+                let failures = cx.empty_array();
+                for (i, failure) in report.failures.iter().enumerate() {
+                    let item = cx.empty_object();
+                    let key = failure.key.as_str().try_into_js(cx);
+                    item.prop(cx, "key").set(key)?;
+                    let error = failure.error.as_str().try_into_js(cx);
+                    item.prop(cx, "error").set(error)?;
+                    failures.prop(cx, i as u32).set(item)?;
+                }
+                obj.prop(cx, "failures").set(failures)?;
+
+                Ok(obj)
+            }))
+        }
+        .await,
+    )
+}
+
+#[neon::export]
+async fn start_mirror(
+    Boxed(manager): Boxed<Manager>,
+    namespace: String,
+    dir: String,
+    key_prefix: Option<String>,
+    ignore_globs: Option<extract::Json<Vec<String>>>,
+) -> impl for<'cx> TryIntoJs<'cx> {
+    settle(
+        async move {
+            let unimusic = manager.get()?;
+
+            let namespace = namespace.parse()?;
+            unimusic
+                .start_mirror(
+                    namespace,
+                    dir,
+                    key_prefix.unwrap_or_default(),
+                    ignore_globs.map(|globs| globs.0).unwrap_or_default(),
+                )
+                .await?;
+
+            Ok(())
+        }
+        .await,
+    )
+}
+
+#[neon::export]
+fn stop_mirror(Boxed(manager): Boxed<Manager>, namespace: String) -> impl for<'cx> TryIntoJs<'cx> {
+    settle((|| -> Result<()> {
+        let unimusic = manager.get()?;
+
+        let namespace = namespace.parse()?;
+        unimusic.stop_mirror(namespace);
+
+        Ok(())
+    })())
+}
+
+#[neon::export]
+fn get_mirrors(Boxed(manager): Boxed<Manager>) -> impl for<'cx> TryIntoJs<'cx> {
+    settle((|| -> Result<_> {
+        let unimusic = manager.get()?;
+
+        let mirrors = unimusic.get_mirrors();
+
+        Ok(extract::with(move |cx| {
+            let array = cx.empty_array();
+            for (i, mirror) in mirrors.iter().enumerate() {
+                let obj = cx.empty_object();
+                let namespace = mirror.namespace.to_string().try_into_js(cx);
+                obj.prop(cx, "namespace").set(namespace)?;
+                let dir = mirror.dir.as_str().try_into_js(cx);
+                obj.prop(cx, "dir").set(dir)?;
+                let key_prefix = mirror.key_prefix.as_str().try_into_js(cx);
+                obj.prop(cx, "keyPrefix").set(key_prefix)?;
+                array.prop(cx, i as u32).set(obj)?;
+            }
+            Ok(array)
+        }))
+    })())
+}
+
+fn streaming_server_into_js<'cx>(
+    cx: &mut Cx<'cx>,
+    info: &StreamingServerInfo,
+) -> JsResult<'cx, JsObject> {
+    let obj = cx.empty_object();
+
+    obj.prop(cx, "port").set(info.port as f64)?;
+    let token = info.token.as_str().try_into_js(cx);
+    obj.prop(cx, "token").set(token)?;
+    let base_url = info.base_url.as_str().try_into_js(cx);
+    obj.prop(cx, "baseUrl").set(base_url)?;
+
+    Ok(obj)
+}
+
+#[neon::export]
+async fn start_streaming_server(
+    Boxed(manager): Boxed<Manager>,
+    port: Option<f64>,
+) -> impl for<'cx> TryIntoJs<'cx> {
+    settle(
+        async move {
+            let unimusic = manager.get()?;
+
+            let port = port.map_or(0, |port| port as u16);
+            let info = unimusic.start_streaming_server(port).await?;
+
+            Ok(extract::with(move |cx| streaming_server_into_js(cx, &info)))
+        }
+        .await,
+    )
+}
+
+#[neon::export]
+fn stop_streaming_server(Boxed(manager): Boxed<Manager>) -> impl for<'cx> TryIntoJs<'cx> {
+    settle((|| -> Result<()> {
+        let unimusic = manager.get()?;
+
+        unimusic.stop_streaming_server();
+
+        Ok(())
+    })())
+}
+
+#[neon::export]
+fn get_streaming_server(Boxed(manager): Boxed<Manager>) -> impl for<'cx> TryIntoJs<'cx> {
+    settle((|| -> Result<_> {
+        let unimusic = manager.get()?;
+
+        let info = unimusic.get_streaming_server();
+
+        Ok(extract::with(move |cx| match &info {
+            Some(info) => Ok(streaming_server_into_js(cx, info)?.upcast::<JsValue>()),
+            None => Ok(cx.undefined().upcast()),
+        }))
+    })())
+}
+
+#[neon::export]
+fn get_stream_url(
+    Boxed(manager): Boxed<Manager>,
+    namespace: String,
+    sync_path: String,
+) -> impl for<'cx> TryIntoJs<'cx> {
+    settle((|| -> Result<String> {
+        let unimusic = manager.get()?;
+
+        let namespace = namespace.parse()?;
+        Ok(unimusic.get_stream_url(namespace, sync_path)?)
+    })())
+}
+
+#[neon::export]
+fn get_blob_stream_url(
+    Boxed(manager): Boxed<Manager>,
+    file_hash: String,
+) -> impl for<'cx> TryIntoJs<'cx> {
+    settle((|| -> Result<String> {
+        let unimusic = manager.get()?;
+
+        let file_hash = file_hash.parse()?;
+        Ok(unimusic.get_blob_stream_url(file_hash)?)
+    })())
+}
+
+#[neon::export]
+async fn backup_namespace(
+    Boxed(manager): Boxed<Manager>,
+    namespace: String,
+    archive_path: String,
+    include_secret: Option<bool>,
+) -> impl for<'cx> TryIntoJs<'cx> {
+    settle(
+        async move {
+            let unimusic = manager.get()?;
+
+            let namespace = namespace.parse()?;
+            let report = unimusic
+                .backup_namespace(namespace, archive_path, include_secret.unwrap_or(false))
+                .await?;
+
+            Ok(extract::with(move |cx| {
+                let obj = cx.empty_object();
+                obj.prop(cx, "entries").set(report.entries as f64)?;
+                obj.prop(cx, "blobs").set(report.blobs as f64)?;
+                obj.prop(cx, "missingBlobs")
+                    .set(report.missing_blobs as f64)?;
+                obj.prop(cx, "includesSecret").set(report.includes_secret)?;
+                Ok(obj)
+            }))
+        }
+        .await,
+    )
+}
+
+#[neon::export]
+async fn restore_namespace(
+    Boxed(manager): Boxed<Manager>,
+    archive_path: String,
+) -> impl for<'cx> TryIntoJs<'cx> {
+    settle(
+        async move {
+            let unimusic = manager.get()?;
+
+            let report = unimusic.restore_namespace(archive_path).await?;
+
+            Ok(extract::with(move |cx| {
+                let obj = cx.empty_object();
+                let namespace = report.namespace.to_string().try_into_js(cx);
+                obj.prop(cx, "namespace").set(namespace)?;
+                obj.prop(cx, "entries").set(report.entries as f64)?;
+                obj.prop(cx, "skipped").set(report.skipped as f64)?;
+                obj.prop(cx, "blobs").set(report.blobs as f64)?;
+                obj.prop(cx, "writable").set(report.writable)?;
+                Ok(obj)
+            }))
+        }
+        .await,
+    )
+}
+
+#[neon::export]
+async fn create_invitation(
+    Boxed(manager): Boxed<Manager>,
+    namespace: String,
+    expires_in_ms: Option<f64>,
+    single_use: Option<bool>,
+    read_only: Option<bool>,
+) -> impl for<'cx> TryIntoJs<'cx> {
+    settle(
+        async move {
+            let unimusic = manager.get()?;
+
+            let namespace = namespace.parse()?;
+            let invitation = unimusic
+                .create_invitation(
+                    namespace,
+                    expires_in_ms.map(|expires_in_ms| expires_in_ms as u64),
+                    single_use.unwrap_or(false),
+                    read_only.unwrap_or(false),
+                )
+                .await?;
+
+            Ok(extract::with(move |cx| invitation_into_js(cx, &invitation)))
+        }
+        .await,
+    )
+}
+
+#[neon::export]
+async fn get_invitations(
+    Boxed(manager): Boxed<Manager>,
+    namespace: Option<String>,
+) -> impl for<'cx> TryIntoJs<'cx> {
+    settle(
+        async move {
+            let unimusic = manager.get()?;
+
+            let namespace = namespace.map(|namespace| namespace.parse()).transpose()?;
+            let invitations = unimusic.get_invitations(namespace).await;
+
+            Ok(extract::with(move |cx| {
+                let result = cx.empty_array();
+
+                for (i, invitation) in invitations.iter().enumerate() {
+                    let obj = invitation_into_js(cx, invitation)?;
+                    result.prop(cx, i as u32).set(obj)?;
+                }
+
+                Ok(result)
+            }))
+        }
+        .await,
+    )
+}
+
+#[neon::export]
+async fn revoke_invitation(
+    Boxed(manager): Boxed<Manager>,
+    id: String,
+) -> impl for<'cx> TryIntoJs<'cx> {
+    settle(
+        async move {
+            let unimusic = manager.get()?;
+
+            unimusic.revoke_invitation(id).await?;
+
+            Ok(())
+        }
+        .await,
+    )
+}
+
+#[neon::export]
+async fn accept_invitation(
+    Boxed(manager): Boxed<Manager>,
+    ticket: String,
+) -> impl for<'cx> TryIntoJs<'cx> {
+    settle::<String>(
+        async move {
+            let unimusic = manager.get()?;
+
+            let ticket = ticket.parse()?;
+            let namespace = unimusic.accept_invitation(ticket).await?;
+
+            Ok(namespace.into())
+        }
+        .await,
+    )
+}
+
+#[neon::export]
+fn invitation_to_uri(ticket: String) -> impl for<'cx> TryIntoJs<'cx> {
+    settle((|| -> Result<String> {
+        let ticket = ticket.parse()?;
+        Ok(unimusic_sync::invitation::invitation_to_uri(ticket))
+    })())
+}
+
+#[neon::export]
+fn create_cancellation() -> Boxed<Cancellation> {
+    Boxed(Cancellation(Arc::new(CancellationHandle::new())))
+}
+
+#[neon::export]
+fn cancel(Boxed(cancellation): Boxed<Cancellation>) {
+    cancellation.0.cancel();
+}
+
+#[neon::export]
+async fn sync_cancellable(
+    Boxed(manager): Boxed<Manager>,
+    namespace: String,
+    cancellation: Option<Boxed<Cancellation>>,
+    timeout_ms: Option<f64>,
+    all_known: Option<bool>,
+) -> impl for<'cx> TryIntoJs<'cx> {
+    settle(
+        async move {
+            let unimusic = manager.get()?;
+
+            let namespace = namespace.parse()?;
+            let cancellation = cancellation.map(|Boxed(cancellation)| cancellation.0);
+            let scope = match all_known {
+                Some(true) => SyncScope::AllKnown,
+                _ => SyncScope::Namespace,
+            };
+            unimusic
+                .sync_cancellable(
+                    namespace,
+                    scope,
+                    cancellation,
+                    timeout_ms.map(|timeout_ms| timeout_ms as u64),
+                )
+                .await?;
+
+            Ok(())
+        }
+        .await,
+    )
+}
+
+#[neon::export]
+async fn import_ticket_cancellable(
+    Boxed(manager): Boxed<Manager>,
+    ticket: String,
+    cancellation: Option<Boxed<Cancellation>>,
+    timeout_ms: Option<f64>,
+) -> impl for<'cx> TryIntoJs<'cx> {
+    settle::<String>(
+        async move {
+            let unimusic = manager.get()?;
+
+            let ticket = ticket.parse()?;
+            let cancellation = cancellation.map(|Boxed(cancellation)| cancellation.0);
+            let namespace = unimusic
+                .import_cancellable(
+                    ticket,
+                    cancellation,
+                    timeout_ms.map(|timeout_ms| timeout_ms as u64),
+                )
+                .await?;
+
+            Ok(namespace.into())
+        }
+        .await,
+    )
+}
+
+#[neon::export]
+async fn reconnect(
+    Boxed(manager): Boxed<Manager>,
+    timeout_ms: Option<f64>,
+) -> impl for<'cx> TryIntoJs<'cx> {
+    settle(
+        async move {
+            let unimusic = manager.get()?;
+
+            let report = match timeout_ms {
+                Some(timeout_ms) => unimusic.reconnect_with_timeout(timeout_ms as u64).await,
+                None => unimusic.reconnect().await,
+            };
+
+            Ok(extract::with(move |cx| {
+                let peers = cx.empty_array();
+
+                for (i, peer) in report.peers.iter().enumerate() {
+                    let obj = cx.empty_object();
+
+                    let node_id = peer.node_id.to_string().try_into_js(cx);
+                    obj.prop(cx, "nodeId").set(node_id)?;
+
+                    let outcome = match &peer.outcome {
+                        ReconnectOutcome::Connected => "connected",
+                        ReconnectOutcome::Failed { reason } => {
+                            let reason = reason.as_str().try_into_js(cx);
+                            obj.prop(cx, "error").set(reason)?;
+                            "failed"
+                        }
+                        ReconnectOutcome::TimedOut => "timedOut",
+                        ReconnectOutcome::BackingOff { retry_in_ms } => {
+                            obj.prop(cx, "retryInMs").set(*retry_in_ms as f64)?;
+                            "backingOff"
+                        }
+                    };
+                    obj.prop(cx, "outcome").set(outcome)?;
+
+                    let connection_type = connection_type_str(peer.connection_type);
+                    obj.prop(cx, "connectionType").set(connection_type)?;
+
+                    if let Some(latency_ms) = peer.latency_ms {
+                        obj.prop(cx, "latencyMs").set(latency_ms as f64)?;
+                    }
+
+                    peers.prop(cx, i as u32).set(obj)?;
+                }
+
+                let result = cx.empty_object();
+                result.prop(cx, "peers").set(peers)?;
+                Ok(result)
+            }))
+        }
+        .await,
+    )
+}
+
+#[neon::export]
+async fn diagnostics(Boxed(manager): Boxed<Manager>) -> impl for<'cx> TryIntoJs<'cx> {
+    settle(
+        async move {
+            let unimusic = manager.get()?;
+            let report = unimusic.diagnostics().await;
+            Ok(extract::with(move |cx| diagnostics_into_js(cx, &report)))
+        }
+        .await,
+    )
+}
+
+/// Fields of `AutoSyncConfig` the caller wants to change
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct AutoSyncOptions {
+    interval_ms: Option<u64>,
+    debounce_ms: Option<u64>,
+    sync_timeout_ms: Option<u64>,
+    /// `null` retries until the sync succeeds
+    #[serde(deserialize_with = "deserialize_some")]
+    max_retries: Option<Option<u32>>,
+    initial_backoff_ms: Option<u64>,
+    max_backoff_ms: Option<u64>,
+}
+
+/// Tells an explicit `null` apart from a missing field
+fn deserialize_some<'de, T, D>(deserializer: D) -> std::result::Result<Option<T>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+impl From<AutoSyncOptions> for AutoSyncConfig {
+    fn from(options: AutoSyncOptions) -> Self {
+        let default = AutoSyncConfig::default();
+        AutoSyncConfig {
+            interval_ms: options.interval_ms.unwrap_or(default.interval_ms),
+            debounce_ms: options.debounce_ms.unwrap_or(default.debounce_ms),
+            sync_timeout_ms: options.sync_timeout_ms.unwrap_or(default.sync_timeout_ms),
+            max_retries: options.max_retries.unwrap_or(default.max_retries),
+            backoff: BackoffPolicy {
+                initial_delay_ms: options
+                    .initial_backoff_ms
+                    .unwrap_or(default.backoff.initial_delay_ms),
+                max_delay_ms: options
+                    .max_backoff_ms
+                    .unwrap_or(default.backoff.max_delay_ms),
+            },
+        }
+    }
+}
+
+#[neon::export]
+async fn start_auto_sync(
+    Boxed(manager): Boxed<Manager>,
+    options: Option<extract::Json<AutoSyncOptions>>,
+) -> impl for<'cx> TryIntoJs<'cx> {
+    settle(
+        async move {
+            let unimusic = manager.get()?;
+
+            let config = options.map(|options| options.0).unwrap_or_default().into();
+            unimusic.start_auto_sync(config).await;
+
+            Ok(())
+        }
+        .await,
+    )
+}
+
+#[neon::export]
+fn stop_auto_sync(Boxed(manager): Boxed<Manager>) -> impl for<'cx> TryIntoJs<'cx> {
+    settle((|| -> Result<()> {
+        let unimusic = manager.get()?;
+
+        unimusic.stop_auto_sync();
+
+        Ok(())
+    })())
+}
+
+#[neon::export]
+async fn add_auto_sync_namespace(
+    Boxed(manager): Boxed<Manager>,
+    namespace: String,
+) -> impl for<'cx> TryIntoJs<'cx> {
+    settle(
+        async move {
+            let unimusic = manager.get()?;
+
+            let namespace = namespace.parse()?;
+            unimusic.add_auto_sync_namespace(namespace).await;
+
+            Ok(())
+        }
+        .await,
+    )
+}
+
+#[neon::export]
+fn is_auto_sync_running(Boxed(manager): Boxed<Manager>) -> impl for<'cx> TryIntoJs<'cx> {
+    settle((|| -> Result<bool> {
+        let unimusic = manager.get()?;
+        Ok(unimusic.is_auto_sync_running())
+    })())
+}
+
+#[neon::export]
+fn get_auto_sync_namespaces(Boxed(manager): Boxed<Manager>) -> impl for<'cx> TryIntoJs<'cx> {
+    settle((|| -> Result<_> {
+        let unimusic = manager.get()?;
+
+        let namespaces = unimusic.get_auto_sync_namespaces();
+
+        Ok(extract::Json(
+            namespaces
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+        ))
+    })())
+}
+
+#[neon::export]
+fn remove_auto_sync_namespace(
+    Boxed(manager): Boxed<Manager>,
+    namespace: String,
+) -> impl for<'cx> TryIntoJs<'cx> {
+    settle((|| -> Result<()> {
+        let unimusic = manager.get()?;
+
+        let namespace = namespace.parse()?;
+        unimusic.remove_auto_sync_namespace(namespace);
+
+        Ok(())
+    })())
+}
+
+fn event_into_js<'cx>(cx: &mut Cx<'cx>, event: &SyncEvent) -> JsResult<'cx, JsObject> {
+    let obj = cx.empty_object();
+
+    let kind = match event {
+        SyncEvent::InsertLocal { key, hash, deleted } => {
+            let key = key.as_str().try_into_js(cx);
+            obj.prop(cx, "key").set(key)?;
+            let hash = hash.to_string().try_into_js(cx);
+            obj.prop(cx, "hash").set(hash)?;
+            obj.prop(cx, "deleted").set(*deleted)?;
+            "insertLocal"
+        }
+        SyncEvent::InsertRemote {
+            key,
+            hash,
+            deleted,
+            from,
+            content_ready,
+        } => {
+            let key = key.as_str().try_into_js(cx);
+            obj.prop(cx, "key").set(key)?;
+            let hash = hash.to_string().try_into_js(cx);
+            obj.prop(cx, "hash").set(hash)?;
+            obj.prop(cx, "deleted").set(*deleted)?;
+            let from = from.to_string().try_into_js(cx);
+            obj.prop(cx, "from").set(from)?;
+            obj.prop(cx, "contentReady").set(*content_ready)?;
+            "insertRemote"
+        }
+        SyncEvent::ContentReady { hash } => {
+            let hash = hash.to_string().try_into_js(cx);
+            obj.prop(cx, "hash").set(hash)?;
+            "contentReady"
+        }
+        SyncEvent::PendingContentReady => "pendingContentReady",
+        SyncEvent::NeighborUp { node_id } => {
+            let node_id = node_id.to_string().try_into_js(cx);
+            obj.prop(cx, "nodeId").set(node_id)?;
+            "neighborUp"
+        }
+        SyncEvent::NeighborDown { node_id } => {
+            let node_id = node_id.to_string().try_into_js(cx);
+            obj.prop(cx, "nodeId").set(node_id)?;
+            "neighborDown"
+        }
+        SyncEvent::SyncFinished { node_id, error } => {
+            let node_id = node_id.to_string().try_into_js(cx);
+            obj.prop(cx, "nodeId").set(node_id)?;
+            if let Some(error) = error {
+                let error = error.as_str().try_into_js(cx);
+                obj.prop(cx, "error").set(error)?;
+            }
+            "syncFinished"
+        }
+    };
+    obj.prop(cx, "type").set(kind)?;
+
+    Ok(obj)
+}
+
+/// Task forwarding events of a namespace to a JS callback
+#[derive(Clone)]
+struct Subscription(Arc<JoinHandle<()>>);
+
+impl Finalize for Subscription {
+    fn finalize<'a, C: Context<'a>>(self, _: &mut C) {
+        self.0.abort();
+    }
+}
+
+/// Calls `callback` with every event of the namespace, and without one once the node shuts down
+#[neon::export]
+async fn subscribe(
+    channel: Channel,
+    Boxed(manager): Boxed<Manager>,
+    namespace: String,
+    callback: Root<JsFunction>,
+) -> impl for<'cx> TryIntoJs<'cx> {
+    settle(
+        async move {
+            let unimusic = manager.get()?;
+
+            let namespace = namespace.parse()?;
+            let events = unimusic.subscribe(namespace).await?;
+
+            let callback = Arc::new(callback);
+            let task = tokio::spawn(async move {
+                loop {
+                    let event = events.next().await;
+                    let finished = event.is_none();
+
+                    let callback = callback.clone();
+                    channel.send(move |mut cx| {
+                        let args = match &event {
+                            Some(event) => vec![event_into_js(&mut cx, event)?.upcast()],
+                            None => vec![],
+                        };
+                        let this = cx.undefined();
+                        callback.to_inner(&mut cx).call(&mut cx, this, args)?;
+                        Ok(())
+                    });
+
+                    if finished {
+                        break;
+                    }
+                }
+            });
+
+            Ok(Boxed(Subscription(Arc::new(task))))
+        }
+        .await,
+    )
+}
+
+#[neon::export]
+fn unsubscribe(Boxed(subscription): Boxed<Subscription>) {
+    subscription.0.abort();
 }
 
 #[neon::main]
 fn main(mut cx: ModuleContext) -> NeonResult<()> {
-    static RUNTIME: OnceLock<Runtime> = OnceLock::new();
-
     let runtime = match RUNTIME.get() {
         Some(runtime) => runtime,
         _ => {
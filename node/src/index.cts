@@ -1,6 +1,7 @@
 // This module is the CJS entry point for the library.
 
-import * as addon from "./load.cjs";
+import { EventEmitter, on } from "node:events";
+import * as native from "./load.cjs";
 
 declare module "./load.cjs" {
   type NamespaceId = string;
@@ -15,36 +16,1122 @@ declare module "./load.cjs" {
     timestamp: number;
     contentHash: string;
     contentLen: number;
+    /** Length of the file, which differs from contentLen for split files */
+    fileLen: number;
   }
 
-  function initialize(path: string): Promise<void>;
-  function shutdown(): Promise<void>;
-  function createNamespace(): Promise<string>;
-  function deleteNamespace(namespace: NamespaceId): Promise<void>;
-  function getAuthor(): Promise<AuthorId>;
-  function getNodeId(): Promise<NodeId>;
-  function getFiles(namespace: NamespaceId): Promise<FileInfo[]>;
+  interface TrackMetadata {
+    key: string;
+    title?: string;
+    artist?: string;
+    album?: string;
+    trackNumber?: number;
+    durationMs: number;
+    /** Lowercase codec name, such as "mp3", "flac", "aac" or "vorbis" */
+    codec: string;
+    /** Hash of the embedded cover art, see getArtwork */
+    artwork?: Hash;
+  }
+
+  interface SearchResult {
+    key: string;
+    /** Higher is better, only comparable within the same search */
+    score: number;
+    track?: TrackMetadata;
+  }
+
+  type ConnectionType = "direct" | "relay" | "mixed" | "none";
+
+  interface PeerReconnectResult {
+    nodeId: NodeId;
+    outcome: "connected" | "failed" | "timedOut" | "backingOff";
+    connectionType: ConnectionType;
+    latencyMs?: number;
+    /** Set when outcome is "failed" */
+    error?: string;
+    /** Set when outcome is "backingOff" */
+    retryInMs?: number;
+  }
+
+  interface ReconnectReport {
+    peers: PeerReconnectResult[];
+  }
+
+  /** Network state of the node, `JSON.stringify` it to attach it to support tickets */
+  interface Diagnostics {
+    nodeId: NodeId;
+    version: string;
+    createdAtMs: number;
+    addresses: { addr: string; kind: "local" | "stun" | "portmapped" | "unknown" }[];
+    homeRelay?: string;
+    /** Missing until the first network report finishes */
+    nat?: {
+      udp: boolean;
+      ipv4: boolean;
+      ipv6: boolean;
+      publicIpv4?: string;
+      publicIpv6?: string;
+      mappingVariesByDestination?: boolean;
+      hairPinning?: boolean;
+      captivePortal?: boolean;
+      upnp?: boolean;
+      pcp?: boolean;
+      natPmp?: boolean;
+    };
+    peers: {
+      nodeId: NodeId;
+      connectionType: ConnectionType;
+      /** Direct address or relay URL the traffic goes through */
+      path?: string;
+      rttMs?: number;
+      lastUsedMs?: number;
+    }[];
+    /** Traffic of the whole node since it started */
+    bytesSent: number;
+    bytesReceived: number;
+  }
+
+  interface DirectoryImportReport {
+    added: number;
+    updated: number;
+    unchanged: number;
+    ignored: number;
+    tombstoned: number;
+    failures: { path: string; error: string }[];
+  }
+
+  interface ExportReport {
+    exported: number;
+    unchanged: number;
+    tombstoned: number;
+    exportedBytes: number;
+    failures: { key: string; error: string }[];
+  }
+
+  interface ExportProgress {
+    /** Known once entries of the namespace are listed */
+    totalFiles: number;
+    /** Exported, unchanged or failed files */
+    processedFiles: number;
+    totalBytes: number;
+    processedBytes: number;
+  }
+
+  /** Opaque handle created by `createExportProgress` */
+  interface ExportProgressHandle {
+    readonly __brand: "ExportProgressHandle";
+  }
+
+  interface MirrorInfo {
+    namespace: NamespaceId;
+    dir: string;
+    keyPrefix: string;
+  }
+
+  interface StreamingServerInfo {
+    port: number;
+    /** Passed as the `token` query parameter or as a bearer token */
+    token: string;
+    /** Such as "http://127.0.0.1:port" */
+    baseUrl: string;
+  }
+
+  interface BackupReport {
+    entries: number;
+    blobs: number;
+    /** Entries whose content wasn't available locally */
+    missingBlobs: number;
+    includesSecret: boolean;
+  }
+
+  interface RestoreReport {
+    namespace: NamespaceId;
+    entries: number;
+    /** Entries the namespace already had in a newer version */
+    skipped: number;
+    blobs: number;
+    /** False if the archive had no secret, new entries then come from peers */
+    writable: boolean;
+  }
+
+  type InvitationTicket = string;
+
+  interface Invitation {
+    id: string;
+    namespace: NamespaceId;
+    ticket: InvitationTicket;
+    createdAtMs: number;
+    /** Missing if the invitation never expires */
+    expiresAtMs?: number;
+    singleUse: boolean;
+    /** Invited nodes get only a read capability */
+    readOnly: boolean;
+    uses: number;
+    revoked: boolean;
+  }
+
+  /** Opaque handle created by `createCancellation` */
+  interface Cancellation {
+    readonly __brand: "Cancellation";
+  }
+
+  /** Opaque handle of a node created by `initialize` */
+  interface Manager {
+    readonly __brand: "Manager";
+  }
+
+  type SyncEvent =
+    /** Entry written on this node */
+    | { type: "insertLocal"; key: string; hash: Hash; deleted: boolean }
+    /** Entry received from the peer, its content might not be downloaded yet */
+    | {
+        type: "insertRemote";
+        key: string;
+        hash: Hash;
+        deleted: boolean;
+        from: NodeId;
+        contentReady: boolean;
+      }
+    /** Content of a remote entry got downloaded */
+    | { type: "contentReady"; hash: Hash }
+    /** Content of every entry received since the last sync got downloaded */
+    | { type: "pendingContentReady" }
+    | { type: "neighborUp"; nodeId: NodeId }
+    | { type: "neighborDown"; nodeId: NodeId }
+    /** Sync with the peer finished, it failed if there's an error */
+    | { type: "syncFinished"; nodeId: NodeId; error?: string };
+
+  /** Opaque handle created by `subscribe` */
+  interface Subscription {
+    readonly __brand: "Subscription";
+  }
+
+  /** Missing fields keep their defaults */
+  interface AutoSyncOptions {
+    /** How often every namespace gets re-synced, 5 minutes by default */
+    intervalMs?: number;
+    /** How long to wait for more changes after a local write or a peer coming back */
+    debounceMs?: number;
+    /** Time after which a single sync is considered failed */
+    syncTimeoutMs?: number;
+    /** Retries of a failed sync before waiting for the next trigger, `null` retries until it succeeds */
+    maxRetries?: number | null;
+    /** Delay after the first failure, doubled after every consecutive one */
+    initialBackoffMs?: number;
+    maxBackoffMs?: number;
+  }
+
+  /** With a passphrase, keys, peer lists, music and entries are encrypted at rest */
+  function initialize(
+    path: string,
+    passphrase?: string,
+    relays?: string[]
+  ): Promise<Manager>;
+  /** Further calls with the manager throw, shutting down twice does nothing */
+  function shutdown(manager: Manager): Promise<void>;
+  function createNamespace(manager: Manager): Promise<string>;
+  function deleteNamespace(
+    manager: Manager,
+    namespace: NamespaceId
+  ): Promise<void>;
+  function getAuthor(manager: Manager): Promise<AuthorId>;
+  function getNodeId(manager: Manager): Promise<NodeId>;
+  /** Namespaces stored on this node, including read-only ones */
+  function getNamespaces(manager: Manager): Promise<NamespaceId[]>;
+  function getKnownNodes(manager: Manager): Promise<NodeId[]>;
+  /** Peers known to have synced the namespace */
+  function getNamespaceNodes(
+    manager: Manager,
+    namespace: NamespaceId
+  ): Promise<NodeId[]>;
+  function getFiles(
+    manager: Manager,
+    namespace: NamespaceId
+  ): Promise<FileInfo[]>;
+  /** Latest entry of the key, its content doesn't have to be stored locally */
+  function getFile(
+    manager: Manager,
+    namespace: NamespaceId,
+    syncPath: string
+  ): Promise<FileInfo>;
   function writeFile(
+    manager: Manager,
+    namespace: NamespaceId,
+    syncPath: string,
+    sourcePath: string
+  ): Promise<Hash>;
+  function writeBytes(
+    manager: Manager,
+    namespace: NamespaceId,
+    syncPath: string,
+    data: Buffer
+  ): Promise<Hash>;
+  /**
+   * Stores tags of MP3 and FLAC files separately from the audio,
+   * so editing a tag doesn't sync the whole track again
+   */
+  function writeFileSplit(
+    manager: Manager,
     namespace: NamespaceId,
     syncPath: string,
     sourcePath: string
   ): Promise<Hash>;
-  function deleteFile(namespace: NamespaceId, syncPath: string): Promise<void>;
+  function deleteFile(
+    manager: Manager,
+    namespace: NamespaceId,
+    syncPath: string
+  ): Promise<void>;
+  /** Tags of the track indexed when it was written, available without its content */
+  function getTrackMetadata(
+    manager: Manager,
+    namespace: NamespaceId,
+    syncPath: string
+  ): Promise<TrackMetadata | undefined>;
+  /** Filters ignore case */
+  function getTracks(
+    manager: Manager,
+    namespace: NamespaceId,
+    artist?: string,
+    album?: string
+  ): Promise<TrackMetadata[]>;
+  /**
+   * Returns the cover art of a track. With a size, the smallest JPEG thumbnail
+   * at least that large is returned, up to 512 pixels. Without one the original image.
+   */
+  function getArtwork(
+    manager: Manager,
+    namespace: NamespaceId,
+    artworkHash: Hash,
+    size?: number
+  ): Promise<Buffer | undefined>;
+  /**
+   * Searches keys and track metadata, best matches first.
+   * Every word has to match the start of a word, ignoring case and diacritics.
+   * Returns at most 50 results by default.
+   */
+  function search(
+    manager: Manager,
+    namespace: NamespaceId,
+    query: string,
+    limit?: number
+  ): Promise<SearchResult[]>;
   function readFile(
+    manager: Manager,
     namespace: NamespaceId,
     syncPath: string
-  ): Promise<Uint8Array>;
-  function readFileHash(hash: string): Promise<Uint8Array>;
+  ): Promise<Buffer>;
+  function readFileHash(manager: Manager, hash: string): Promise<Buffer>;
   function exportFile(
+    manager: Manager,
     namespace: NamespaceId,
     syncPath: string,
     destinationPath: string
   ): Promise<void>;
-  function exportFileHash(hash: string, destinationPath: string): Promise<void>;
-  function share(namespace: NamespaceId): Promise<DocTicket>;
-  function importTicket(ticket: DocTicket): Promise<NamespaceId>;
-  function sync(namespace: NamespaceId): Promise<void>;
-  function reconnect(): Promise<void>;
+  function exportFileHash(
+    manager: Manager,
+    hash: string,
+    destinationPath: string
+  ): Promise<void>;
+  /**
+   * Exports entries under `prefix` into the directory, in parallel and skipping unchanged files.
+   * Key components which aren't valid file names are sanitised.
+   */
+  function exportAll(
+    manager: Manager,
+    namespace: NamespaceId,
+    prefix: string | undefined,
+    destinationDir: string,
+    progress?: ExportProgressHandle
+  ): Promise<ExportReport>;
+  function createExportProgress(): ExportProgressHandle;
+  function exportProgress(handle: ExportProgressHandle): ExportProgress;
+  function share(manager: Manager, namespace: NamespaceId): Promise<DocTicket>;
+  /** Accepts plain, compact and `unimusic://join?ticket=...` tickets */
+  function importTicket(
+    manager: Manager,
+    ticket: DocTicket
+  ): Promise<NamespaceId>;
+  /** Drops direct addresses from the ticket, making it fit into a QR code */
+  function compactTicket(ticket: DocTicket): DocTicket;
+  /** Returns `unimusic://join?ticket=...` deep link of the ticket */
+  function ticketToUri(ticket: DocTicket): string;
+  function sync(manager: Manager, namespace: NamespaceId): Promise<void>;
+  /**
+   * Keeps syncing the namespace live without waiting, so peers can sync with
+   * this node whenever they connect
+   */
+  function startLiveSync(manager: Manager, namespace: NamespaceId): Promise<void>;
+  /**
+   * Downloads content of the latest entries which isn't stored locally from any
+   * known peer, returning how many blobs were downloaded
+   */
+  function downloadMissing(
+    manager: Manager,
+    namespace: NamespaceId
+  ): Promise<number>;
+  /**
+   * Imports the directory tree under `keyPrefix`, skipping files unchanged since the last import.
+   * `ignoreGlobs` match relative paths or file names.
+   * With `tombstoneMissing`, keys of files which disappeared get deleted.
+   */
+  function importDirectory(
+    manager: Manager,
+    namespace: NamespaceId,
+    dir: string,
+    keyPrefix?: string,
+    ignoreGlobs?: string[],
+    tombstoneMissing?: boolean
+  ): Promise<DirectoryImportReport>;
+  /**
+   * Keeps the directory and keys under `keyPrefix` in sync in both directions,
+   * until the mirror is stopped. Starting a mirror replaces the previous one of the namespace.
+   */
+  function startMirror(
+    manager: Manager,
+    namespace: NamespaceId,
+    dir: string,
+    keyPrefix?: string,
+    ignoreGlobs?: string[]
+  ): Promise<void>;
+  function stopMirror(manager: Manager, namespace: NamespaceId): void;
+  function getMirrors(manager: Manager): MirrorInfo[];
+  /**
+   * Serves entries and blobs on 127.0.0.1 for media players, fetching missing content
+   * from peers. Picks an unused port by default. Returns the running server if there is one.
+   */
+  function startStreamingServer(
+    manager: Manager,
+    port?: number
+  ): Promise<StreamingServerInfo>;
+  function stopStreamingServer(manager: Manager): void;
+  function getStreamingServer(
+    manager: Manager
+  ): StreamingServerInfo | undefined;
+  /** URL of the entry including the access token, throws if the server isn't running */
+  function getStreamUrl(
+    manager: Manager,
+    namespace: NamespaceId,
+    syncPath: string
+  ): string;
+  function getBlobStreamUrl(manager: Manager, hash: Hash): string;
+  /**
+   * With `includeSecret`, the archive has to be kept as safe as the device.
+   * Read-only namespaces are backed up without a secret
+   */
+  function backupNamespace(
+    manager: Manager,
+    namespace: NamespaceId,
+    archivePath: string,
+    includeSecret?: boolean
+  ): Promise<BackupReport>;
+  function restoreNamespace(
+    manager: Manager,
+    archivePath: string
+  ): Promise<RestoreReport>;
+  function createInvitation(
+    manager: Manager,
+    namespace: NamespaceId,
+    expiresInMs?: number,
+    singleUse?: boolean,
+    readOnly?: boolean
+  ): Promise<Invitation>;
+  function getInvitations(
+    manager: Manager,
+    namespace?: NamespaceId
+  ): Promise<Invitation[]>;
+  function revokeInvitation(manager: Manager, id: string): Promise<void>;
+  /** Accepts plain invitations and `unimusic://join?invite=...` URIs */
+  function acceptInvitation(
+    manager: Manager,
+    ticket: InvitationTicket
+  ): Promise<NamespaceId>;
+  /** Returns `unimusic://join?invite=...` deep link of the invitation */
+  function invitationToUri(ticket: InvitationTicket): string;
+  function createCancellation(): Cancellation;
+  function cancel(cancellation: Cancellation): void;
+  function syncCancellable(
+    manager: Manager,
+    namespace: NamespaceId,
+    cancellation?: Cancellation,
+    timeoutMs?: number,
+    /** Contact every known peer, not only ones known to have the namespace */
+    allKnown?: boolean
+  ): Promise<void>;
+  function importTicketCancellable(
+    manager: Manager,
+    ticket: DocTicket,
+    cancellation?: Cancellation,
+    timeoutMs?: number
+  ): Promise<NamespaceId>;
+  function startAutoSync(
+    manager: Manager,
+    options?: AutoSyncOptions
+  ): Promise<void>;
+  function stopAutoSync(manager: Manager): void;
+  function isAutoSyncRunning(manager: Manager): boolean;
+  function getAutoSyncNamespaces(manager: Manager): NamespaceId[];
+  function addAutoSyncNamespace(
+    manager: Manager,
+    namespace: NamespaceId
+  ): Promise<void>;
+  function removeAutoSyncNamespace(
+    manager: Manager,
+    namespace: NamespaceId
+  ): void;
+  function reconnect(
+    manager: Manager,
+    timeoutMs?: number
+  ): Promise<ReconnectReport>;
+  function diagnostics(manager: Manager): Promise<Diagnostics>;
+  /**
+   * Calls `callback` with every event of the namespace, and without an event once
+   * the node shuts down. Events of peers only arrive while the namespace syncs live.
+   */
+  function subscribe(
+    manager: Manager,
+    namespace: NamespaceId,
+    callback: (event?: SyncEvent) => void
+  ): Promise<Subscription>;
+  function unsubscribe(subscription: Subscription): void;
+}
+
+export type ErrorCategory =
+  | "network"
+  | "storage"
+  | "notFound"
+  | "permission"
+  | "invalidInput"
+  | "cancelled"
+  | "internal";
+
+/** Stable error codes, they don't change between releases */
+export type ErrorCode =
+  | "INTERNAL"
+  | "GOSSIP"
+  | "SERIALIZATION"
+  | "IO"
+  | "NETWORK"
+  | "STORAGE"
+  | "NOT_FOUND"
+  | "PERMISSION_DENIED"
+  | "INVALID_INPUT"
+  | "REPLICA_MISSING"
+  | "ENTRY_MISSING"
+  | "ENTRY_TOMBSTONED"
+  | "INVALID_NAMESPACE_ID"
+  | "SYNC_FAILED"
+  | "CANCELLED"
+  | "TIMED_OUT";
+
+export class UniMusicSyncError extends Error {
+  constructor(
+    message: string,
+    readonly code: ErrorCode,
+    readonly category: ErrorCategory,
+    /** Whether repeating the operation later might succeed */
+    readonly retryable: boolean,
+    options?: ErrorOptions
+  ) {
+    super(message, options);
+    this.name = new.target.name;
+  }
+}
+
+export class NetworkError extends UniMusicSyncError {}
+export class StorageError extends UniMusicSyncError {}
+export class NotFoundError extends UniMusicSyncError {}
+export class PermissionError extends UniMusicSyncError {}
+export class InvalidInputError extends UniMusicSyncError {}
+export class CancelledError extends UniMusicSyncError {}
+export class InternalError extends UniMusicSyncError {}
+
+const errorClasses: Record<ErrorCategory, typeof UniMusicSyncError> = {
+  network: NetworkError,
+  storage: StorageError,
+  notFound: NotFoundError,
+  permission: PermissionError,
+  invalidInput: InvalidInputError,
+  cancelled: CancelledError,
+  internal: InternalError,
+};
+
+interface NativeError extends Error {
+  code: ErrorCode;
+  category: ErrorCategory;
+  retryable: boolean;
+}
+
+function isNativeError(error: unknown): error is NativeError {
+  return (
+    error instanceof Error &&
+    !(error instanceof UniMusicSyncError) &&
+    "code" in error &&
+    "category" in error &&
+    "retryable" in error
+  );
+}
+
+/** Converts errors thrown by the native addon into `UniMusicSyncError` subclasses */
+function toSyncError(error: unknown): unknown {
+  if (!isNativeError(error)) {
+    return error;
+  }
+
+  const ErrorClass = errorClasses[error.category] ?? UniMusicSyncError;
+  return new ErrorClass(
+    error.message,
+    error.code,
+    error.category,
+    error.retryable,
+    { cause: error }
+  );
+}
+
+const addon: typeof native = new Proxy(native, {
+  get(target, property, receiver) {
+    const value = Reflect.get(target, property, receiver);
+    if (typeof value !== "function") {
+      return value;
+    }
+
+    return (...args: unknown[]) => {
+      try {
+        const result = value(...args);
+        if (result instanceof Promise) {
+          return result.catch((error: unknown) => {
+            throw toSyncError(error);
+          });
+        }
+        return result;
+      } catch (error) {
+        throw toSyncError(error);
+      }
+    };
+  },
+});
+
+export interface OperationOptions {
+  /** Aborting the signal cancels the operation and stops live sync it started */
+  signal?: AbortSignal;
+  /** Time after which the operation fails */
+  timeoutMs?: number;
+}
+
+async function withAbortSignal<T>(
+  { signal, timeoutMs }: OperationOptions,
+  run: (
+    cancellation: native.Cancellation,
+    timeoutMs?: number
+  ) => Promise<T>
+): Promise<T> {
+  signal?.throwIfAborted();
+
+  const cancellation = addon.createCancellation();
+  const onAbort = () => addon.cancel(cancellation);
+  signal?.addEventListener("abort", onAbort, { once: true });
+
+  try {
+    return await run(cancellation, timeoutMs);
+  } finally {
+    signal?.removeEventListener("abort", onAbort);
+  }
+}
+
+export interface ExportAllOptions {
+  /** Only keys under this prefix are exported, it's removed from file paths */
+  prefix?: string;
+  /** Called periodically while files are exported */
+  onProgress?: (progress: native.ExportProgress) => void;
+  /** How often `onProgress` gets called, defaults to 250 ms */
+  progressIntervalMs?: number;
+}
+
+export type {
+  NamespaceId,
+  AuthorId,
+  NodeId,
+  Hash,
+  DocTicket,
+  InvitationTicket,
+  FileInfo,
+  TrackMetadata,
+  SearchResult,
+  ConnectionType,
+  PeerReconnectResult,
+  ReconnectReport,
+  Diagnostics,
+  DirectoryImportReport,
+  ExportReport,
+  ExportProgress,
+  MirrorInfo,
+  StreamingServerInfo,
+  BackupReport,
+  RestoreReport,
+  Invitation,
+  SyncEvent,
+  AutoSyncOptions,
+} from "./load.cjs";
+
+export interface SyncOptions extends OperationOptions {
+  /** Contact every known peer, not only ones known to have the namespace */
+  allKnown?: boolean;
+}
+
+export interface NamespaceEventMap {
+  event: [native.SyncEvent];
+  close: [];
+}
+
+/**
+ * Emits `event` for every change of the namespace, and `close` once it's closed or
+ * the node shuts down. Can be iterated with `for await` too.
+ */
+export class NamespaceEvents extends EventEmitter<NamespaceEventMap> {
+  private subscription?: native.Subscription;
+  private closed = false;
+
+  private constructor() {
+    super();
+  }
+
+  /** @internal */
+  static async subscribe(
+    manager: native.Manager,
+    namespace: native.NamespaceId
+  ): Promise<NamespaceEvents> {
+    const events = new NamespaceEvents();
+    events.subscription = await addon.subscribe(manager, namespace, (event) => {
+      if (event) {
+        events.emit("event", event);
+      } else {
+        events.close();
+      }
+    });
+    return events;
+  }
+
+  close(): void {
+    if (this.closed) {
+      return;
+    }
+    this.closed = true;
+    if (this.subscription) {
+      addon.unsubscribe(this.subscription);
+    }
+    this.emit("close");
+  }
+
+  async *[Symbol.asyncIterator](): AsyncGenerator<native.SyncEvent> {
+    if (this.closed) {
+      return;
+    }
+    for await (const [event] of on(this, "event", { close: ["close"] })) {
+      yield event as native.SyncEvent;
+    }
+  }
+}
+
+export interface OpenOptions {
+  /** With a passphrase, keys, peer lists, music and entries are encrypted at rest */
+  passphrase?: string;
+  /** Relay URLs used instead of the default relays */
+  relays?: string[];
+}
+
+/**
+ * Node storing its data in a directory. Every instance needs its own directory,
+ * call `shutdown` once it's no longer needed.
+ */
+export class UniMusicSync {
+  private constructor(private readonly manager: native.Manager) {}
+
+  static async open(
+    path: string,
+    { passphrase, relays }: OpenOptions = {}
+  ): Promise<UniMusicSync> {
+    return new UniMusicSync(await addon.initialize(path, passphrase, relays));
+  }
+
+  /** Further calls throw, shutting down twice does nothing */
+  shutdown(): Promise<void> {
+    return addon.shutdown(this.manager);
+  }
+
+  createNamespace(): Promise<native.NamespaceId> {
+    return addon.createNamespace(this.manager);
+  }
+
+  deleteNamespace(namespace: native.NamespaceId): Promise<void> {
+    return addon.deleteNamespace(this.manager, namespace);
+  }
+
+  getAuthor(): Promise<native.AuthorId> {
+    return addon.getAuthor(this.manager);
+  }
+
+  getNodeId(): Promise<native.NodeId> {
+    return addon.getNodeId(this.manager);
+  }
+
+  /** Namespaces stored on this node, including read-only ones */
+  getNamespaces(): Promise<native.NamespaceId[]> {
+    return addon.getNamespaces(this.manager);
+  }
+
+  getKnownNodes(): Promise<native.NodeId[]> {
+    return addon.getKnownNodes(this.manager);
+  }
+
+  /** Peers known to have synced the namespace */
+  getNamespaceNodes(namespace: native.NamespaceId): Promise<native.NodeId[]> {
+    return addon.getNamespaceNodes(this.manager, namespace);
+  }
+
+  getFiles(namespace: native.NamespaceId): Promise<native.FileInfo[]> {
+    return addon.getFiles(this.manager, namespace);
+  }
+
+  /** Latest entry of the key, its content doesn't have to be stored locally */
+  getFile(
+    namespace: native.NamespaceId,
+    syncPath: string
+  ): Promise<native.FileInfo> {
+    return addon.getFile(this.manager, namespace, syncPath);
+  }
+
+  /** Streams the file into the store, without reading it into memory */
+  writeFile(
+    namespace: native.NamespaceId,
+    syncPath: string,
+    sourcePath: string
+  ): Promise<native.Hash> {
+    return addon.writeFile(this.manager, namespace, syncPath, sourcePath);
+  }
+
+  writeBytes(
+    namespace: native.NamespaceId,
+    syncPath: string,
+    data: Buffer
+  ): Promise<native.Hash> {
+    return addon.writeBytes(this.manager, namespace, syncPath, data);
+  }
+
+  /**
+   * Stores tags of MP3 and FLAC files separately from the audio,
+   * so editing a tag doesn't sync the whole track again
+   */
+  writeFileSplit(
+    namespace: native.NamespaceId,
+    syncPath: string,
+    sourcePath: string
+  ): Promise<native.Hash> {
+    return addon.writeFileSplit(this.manager, namespace, syncPath, sourcePath);
+  }
+
+  deleteFile(namespace: native.NamespaceId, syncPath: string): Promise<void> {
+    return addon.deleteFile(this.manager, namespace, syncPath);
+  }
+
+  /** Tags of the track indexed when it was written, available without its content */
+  getTrackMetadata(
+    namespace: native.NamespaceId,
+    syncPath: string
+  ): Promise<native.TrackMetadata | undefined> {
+    return addon.getTrackMetadata(this.manager, namespace, syncPath);
+  }
+
+  /** Filters ignore case */
+  getTracks(
+    namespace: native.NamespaceId,
+    artist?: string,
+    album?: string
+  ): Promise<native.TrackMetadata[]> {
+    return addon.getTracks(this.manager, namespace, artist, album);
+  }
+
+  /**
+   * Returns the cover art of a track. With a size, the smallest JPEG thumbnail
+   * at least that large is returned, up to 512 pixels. Without one the original image.
+   */
+  getArtwork(
+    namespace: native.NamespaceId,
+    artworkHash: native.Hash,
+    size?: number
+  ): Promise<Buffer | undefined> {
+    return addon.getArtwork(this.manager, namespace, artworkHash, size);
+  }
+
+  /**
+   * Searches keys and track metadata, best matches first.
+   * Every word has to match the start of a word, ignoring case and diacritics.
+   * Returns at most 50 results by default.
+   */
+  search(
+    namespace: native.NamespaceId,
+    query: string,
+    limit?: number
+  ): Promise<native.SearchResult[]> {
+    return addon.search(this.manager, namespace, query, limit);
+  }
+
+  readFile(namespace: native.NamespaceId, syncPath: string): Promise<Buffer> {
+    return addon.readFile(this.manager, namespace, syncPath);
+  }
+
+  readFileHash(hash: native.Hash): Promise<Buffer> {
+    return addon.readFileHash(this.manager, hash);
+  }
+
+  exportFile(
+    namespace: native.NamespaceId,
+    syncPath: string,
+    destinationPath: string
+  ): Promise<void> {
+    return addon.exportFile(this.manager, namespace, syncPath, destinationPath);
+  }
+
+  exportFileHash(hash: native.Hash, destinationPath: string): Promise<void> {
+    return addon.exportFileHash(this.manager, hash, destinationPath);
+  }
+
+  /** Exports the namespace into the directory, reporting progress through `options.onProgress` */
+  async exportAll(
+    namespace: native.NamespaceId,
+    destinationDir: string,
+    { prefix, onProgress, progressIntervalMs = 250 }: ExportAllOptions = {}
+  ): Promise<native.ExportReport> {
+    const handle = addon.createExportProgress();
+    const interval = onProgress
+      ? setInterval(() => onProgress(addon.exportProgress(handle)), progressIntervalMs)
+      : undefined;
+
+    try {
+      return await addon.exportAll(
+        this.manager,
+        namespace,
+        prefix,
+        destinationDir,
+        handle
+      );
+    } finally {
+      clearInterval(interval);
+      onProgress?.(addon.exportProgress(handle));
+    }
+  }
+
+  share(namespace: native.NamespaceId): Promise<native.DocTicket> {
+    return addon.share(this.manager, namespace);
+  }
+
+  /**
+   * Imports the ticket, can be aborted using `options.signal`. An aborted import
+   * of a new namespace deletes it again.
+   */
+  importTicket(
+    ticket: native.DocTicket,
+    options: OperationOptions = {}
+  ): Promise<native.NamespaceId> {
+    return withAbortSignal(options, (cancellation, timeoutMs) =>
+      addon.importTicketCancellable(this.manager, ticket, cancellation, timeoutMs)
+    );
+  }
+
+  /** Syncs the namespace, can be aborted using `options.signal` */
+  sync(
+    namespace: native.NamespaceId,
+    options: SyncOptions = {}
+  ): Promise<void> {
+    return withAbortSignal(options, (cancellation, timeoutMs) =>
+      addon.syncCancellable(
+        this.manager,
+        namespace,
+        cancellation,
+        timeoutMs,
+        options.allKnown
+      )
+    );
+  }
+
+  /**
+   * Keeps syncing the namespace live without waiting, so peers can sync with
+   * this node whenever they connect
+   */
+  startLiveSync(namespace: native.NamespaceId): Promise<void> {
+    return addon.startLiveSync(this.manager, namespace);
+  }
+
+  /**
+   * Downloads content of the latest entries which isn't stored locally from any
+   * known peer, returning how many blobs were downloaded
+   */
+  downloadMissing(namespace: native.NamespaceId): Promise<number> {
+    return addon.downloadMissing(this.manager, namespace);
+  }
+
+  /** Events of the namespace, until the returned emitter is closed or the node shuts down */
+  subscribe(namespace: native.NamespaceId): Promise<NamespaceEvents> {
+    return NamespaceEvents.subscribe(this.manager, namespace);
+  }
+
+  /**
+   * Imports the directory tree under `keyPrefix`, skipping files unchanged since the last import.
+   * `ignoreGlobs` match relative paths or file names.
+   * With `tombstoneMissing`, keys of files which disappeared get deleted.
+   */
+  importDirectory(
+    namespace: native.NamespaceId,
+    dir: string,
+    keyPrefix?: string,
+    ignoreGlobs?: string[],
+    tombstoneMissing?: boolean
+  ): Promise<native.DirectoryImportReport> {
+    return addon.importDirectory(
+      this.manager,
+      namespace,
+      dir,
+      keyPrefix,
+      ignoreGlobs,
+      tombstoneMissing
+    );
+  }
+
+  /**
+   * Keeps the directory and keys under `keyPrefix` in sync in both directions,
+   * until the mirror is stopped. Starting a mirror replaces the previous one of the namespace.
+   */
+  startMirror(
+    namespace: native.NamespaceId,
+    dir: string,
+    keyPrefix?: string,
+    ignoreGlobs?: string[]
+  ): Promise<void> {
+    return addon.startMirror(this.manager, namespace, dir, keyPrefix, ignoreGlobs);
+  }
+
+  stopMirror(namespace: native.NamespaceId): void {
+    addon.stopMirror(this.manager, namespace);
+  }
+
+  getMirrors(): native.MirrorInfo[] {
+    return addon.getMirrors(this.manager);
+  }
+
+  /**
+   * Serves entries and blobs on 127.0.0.1 for media players, fetching missing content
+   * from peers. Picks an unused port by default. Returns the running server if there is one.
+   */
+  startStreamingServer(port?: number): Promise<native.StreamingServerInfo> {
+    return addon.startStreamingServer(this.manager, port);
+  }
+
+  stopStreamingServer(): void {
+    addon.stopStreamingServer(this.manager);
+  }
+
+  getStreamingServer(): native.StreamingServerInfo | undefined {
+    return addon.getStreamingServer(this.manager);
+  }
+
+  /** URL of the entry including the access token, throws if the server isn't running */
+  getStreamUrl(namespace: native.NamespaceId, syncPath: string): string {
+    return addon.getStreamUrl(this.manager, namespace, syncPath);
+  }
+
+  getBlobStreamUrl(hash: native.Hash): string {
+    return addon.getBlobStreamUrl(this.manager, hash);
+  }
+
+  /**
+   * With `includeSecret`, the archive has to be kept as safe as the device.
+   * Read-only namespaces are backed up without a secret
+   */
+  backupNamespace(
+    namespace: native.NamespaceId,
+    archivePath: string,
+    includeSecret?: boolean
+  ): Promise<native.BackupReport> {
+    return addon.backupNamespace(this.manager, namespace, archivePath, includeSecret);
+  }
+
+  restoreNamespace(archivePath: string): Promise<native.RestoreReport> {
+    return addon.restoreNamespace(this.manager, archivePath);
+  }
+
+  createInvitation(
+    namespace: native.NamespaceId,
+    expiresInMs?: number,
+    singleUse?: boolean,
+    readOnly?: boolean
+  ): Promise<native.Invitation> {
+    return addon.createInvitation(
+      this.manager,
+      namespace,
+      expiresInMs,
+      singleUse,
+      readOnly
+    );
+  }
+
+  getInvitations(namespace?: native.NamespaceId): Promise<native.Invitation[]> {
+    return addon.getInvitations(this.manager, namespace);
+  }
+
+  revokeInvitation(id: string): Promise<void> {
+    return addon.revokeInvitation(this.manager, id);
+  }
+
+  /** Accepts plain invitations and `unimusic://join?invite=...` URIs */
+  acceptInvitation(ticket: native.InvitationTicket): Promise<native.NamespaceId> {
+    return addon.acceptInvitation(this.manager, ticket);
+  }
+
+  startAutoSync(options?: native.AutoSyncOptions): Promise<void> {
+    return addon.startAutoSync(this.manager, options);
+  }
+
+  stopAutoSync(): void {
+    addon.stopAutoSync(this.manager);
+  }
+
+  isAutoSyncRunning(): boolean {
+    return addon.isAutoSyncRunning(this.manager);
+  }
+
+  getAutoSyncNamespaces(): native.NamespaceId[] {
+    return addon.getAutoSyncNamespaces(this.manager);
+  }
+
+  addAutoSyncNamespace(namespace: native.NamespaceId): Promise<void> {
+    return addon.addAutoSyncNamespace(this.manager, namespace);
+  }
+
+  removeAutoSyncNamespace(namespace: native.NamespaceId): void {
+    addon.removeAutoSyncNamespace(this.manager, namespace);
+  }
+
+  reconnect(timeoutMs?: number): Promise<native.ReconnectReport> {
+    return addon.reconnect(this.manager, timeoutMs);
+  }
+
+  /** `JSON.stringify` it for support tickets */
+  diagnostics(): Promise<native.Diagnostics> {
+    return addon.diagnostics(this.manager);
+  }
+}
+
+/** Drops direct addresses from the ticket, making it fit into a QR code */
+export function compactTicket(ticket: native.DocTicket): native.DocTicket {
+  return addon.compactTicket(ticket);
+}
+
+/** Returns `unimusic://join?ticket=...` deep link of the ticket */
+export function ticketToUri(ticket: native.DocTicket): string {
+  return addon.ticketToUri(ticket);
+}
+
+/** Returns `unimusic://join?invite=...` deep link of the invitation */
+export function invitationToUri(ticket: native.InvitationTicket): string {
+  return addon.invitationToUri(ticket);
 }
 
 export { addon };
@@ -1,3 +1,4 @@
 // This module is the ESM entry point for the library.
 
 export * from './index.cjs';
+export { UniMusicSync as default } from './index.cjs';
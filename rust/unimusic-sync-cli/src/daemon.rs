@@ -0,0 +1,410 @@
+use anyhow::{Context, Result};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::{
+    collections::BTreeMap,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+    sync::RwLock,
+    task::JoinHandle,
+    time::MissedTickBehavior,
+};
+use unimusic_sync::{IrohManager, ticket::parse_ticket, types::UNamespaceId};
+
+/// Configuration of the daemon, read from a TOML file
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Data directory of the node, `--dir` is used when unset
+    pub dir: Option<PathBuf>,
+    /// Tickets of namespaces to join and seed, in any format `join` accepts
+    #[serde(default)]
+    pub tickets: Vec<String>,
+    /// Seconds between contacting peers and downloading missing content
+    #[serde(default = "default_interval_secs")]
+    pub interval_secs: u64,
+    /// Address serving `GET /health`, disabled when unset
+    pub health_addr: Option<SocketAddr>,
+    /// Relay URLs used instead of the default relays, `--relay` is used when empty.
+    /// Changes take effect after a restart.
+    #[serde(default)]
+    pub relays: Vec<String>,
+}
+
+fn default_interval_secs() -> u64 {
+    60
+}
+
+impl Config {
+    pub async fn read(path: &Path) -> Result<Self> {
+        let config = tokio::fs::read_to_string(path)
+            .await
+            .with_context(|| format!("Failed to read config {}", path.display()))?;
+        let config: Config = toml::from_str(&config)
+            .with_context(|| format!("Invalid config {}", path.display()))?;
+        anyhow::ensure!(config.interval_secs > 0, "interval_secs must be positive");
+        Ok(config)
+    }
+
+    fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval_secs)
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+struct NamespaceHealth {
+    files: usize,
+    /// Content downloaded during the last maintenance
+    downloaded: u32,
+    error: Option<String>,
+}
+
+/// State reported by the health endpoint, updated after every maintenance
+struct Health {
+    started: Instant,
+    interval: Duration,
+    last_maintenance: Option<Instant>,
+    /// Tickets which couldn't be joined by their label, with the reason
+    failed_tickets: BTreeMap<String, String>,
+    namespaces: BTreeMap<String, NamespaceHealth>,
+    known_peers: usize,
+}
+
+impl Health {
+    /// Healthy once maintenance ran recently and every ticket was joined
+    fn is_healthy(&self) -> bool {
+        let recent = self
+            .last_maintenance
+            .is_some_and(|last| last.elapsed() < self.interval * 3);
+        recent && self.failed_tickets.is_empty()
+    }
+
+    fn report(&self, node_id: &str) -> Value {
+        json!({
+            "status": if self.is_healthy() { "ok" } else { "degraded" },
+            "node_id": node_id,
+            "uptime_secs": self.started.elapsed().as_secs(),
+            "last_maintenance_secs": self.last_maintenance.map(|last| last.elapsed().as_secs()),
+            "failed_tickets": self.failed_tickets,
+            "namespaces": self.namespaces,
+            "known_peers": self.known_peers,
+        })
+    }
+}
+
+enum Event {
+    Shutdown,
+    Reload,
+}
+
+#[cfg(unix)]
+struct Signals {
+    terminate: tokio::signal::unix::Signal,
+    hangup: tokio::signal::unix::Signal,
+}
+
+#[cfg(unix)]
+impl Signals {
+    fn new() -> Result<Self> {
+        use tokio::signal::unix::{SignalKind, signal};
+        Ok(Self {
+            terminate: signal(SignalKind::terminate())?,
+            hangup: signal(SignalKind::hangup())?,
+        })
+    }
+
+    /// SIGINT and SIGTERM shut the daemon down, SIGHUP reloads the config
+    async fn recv(&mut self) -> Event {
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => Event::Shutdown,
+            _ = self.terminate.recv() => Event::Shutdown,
+            _ = self.hangup.recv() => Event::Reload,
+        }
+    }
+}
+
+#[cfg(not(unix))]
+struct Signals;
+
+#[cfg(not(unix))]
+impl Signals {
+    fn new() -> Result<Self> {
+        Ok(Self)
+    }
+
+    async fn recv(&mut self) -> Event {
+        let _ = tokio::signal::ctrl_c().await;
+        Event::Shutdown
+    }
+}
+
+/// Seeds namespaces of the config until the process is signalled to stop
+pub async fn run(
+    dir: PathBuf,
+    passphrase: Option<String>,
+    relays: Vec<String>,
+    config_path: PathBuf,
+) -> Result<()> {
+    let mut config = Config::read(&config_path).await?;
+    let dir = config.dir.clone().unwrap_or(dir);
+    let relays = match config.relays.is_empty() {
+        true => relays,
+        false => config.relays.clone(),
+    };
+    let mut signals = Signals::new()?;
+
+    let manager = crate::factory(passphrase, &relays)?
+        .iroh_manager(&dir.to_string_lossy())
+        .await
+        .with_context(|| format!("Failed to open node in {}", dir.display()))?;
+    let node_id = manager.get_node_id().await.to_string();
+    info!("[daemon] seeding from {} as {node_id}", dir.display());
+
+    let health = Arc::new(RwLock::new(Health {
+        started: Instant::now(),
+        interval: config.interval(),
+        last_maintenance: None,
+        failed_tickets: BTreeMap::new(),
+        namespaces: BTreeMap::new(),
+        known_peers: 0,
+    }));
+    let server = match config.health_addr {
+        Some(addr) => Some(tokio::spawn(serve_health(
+            TcpListener::bind(addr)
+                .await
+                .with_context(|| format!("Failed to listen on {addr}"))?,
+            health.clone(),
+            node_id,
+        ))),
+        None => None,
+    };
+
+    // Maintenance runs in its own task, so signals are handled while it waits on peers
+    let mut maintenance: Option<JoinHandle<()>> = None;
+    let mut ticker = tokio::time::interval(config.interval());
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {
+                if maintenance.as_ref().is_some_and(|task| !task.is_finished()) {
+                    info!("[daemon] previous maintenance is still running");
+                    continue;
+                }
+                let (manager, config, health) = (manager.clone(), config.clone(), health.clone());
+                maintenance = Some(tokio::spawn(async move {
+                    maintain(&manager, &config, &health).await
+                }));
+            }
+            event = signals.recv() => match event {
+                Event::Shutdown => break,
+                Event::Reload => match Config::read(&config_path).await {
+                    Ok(reloaded) => {
+                        info!("[daemon] reloaded {}", config_path.display());
+                        if reloaded.interval() != config.interval() {
+                            ticker = tokio::time::interval(reloaded.interval());
+                            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+                        }
+                        health.write().await.interval = reloaded.interval();
+                        config = reloaded;
+                        ticker.reset_immediately();
+                    }
+                    Err(error) => warn!("[daemon] keeping previous config: {error:#}"),
+                },
+            },
+        }
+    }
+
+    info!("[daemon] shutting down");
+    if let Some(maintenance) = maintenance {
+        maintenance.abort();
+        let _ = maintenance.await;
+    }
+    if let Some(server) = server {
+        server.abort();
+    }
+    manager.shutdown().await?;
+    Ok(())
+}
+
+/// Joins missing namespaces, keeps every namespace live and downloads content missing locally
+async fn maintain(manager: &IrohManager, config: &Config, health: &RwLock<Health>) {
+    let timeout_ms = config.interval().as_millis() as u64;
+
+    let mut failed_tickets = BTreeMap::new();
+    for ticket in &config.tickets {
+        if let Err(error) = join(manager, ticket, timeout_ms).await {
+            let label = ticket_label(ticket);
+            warn!("[daemon] failed to join {label}: {error:#}");
+            failed_tickets.insert(label, format!("{error:#}"));
+        }
+    }
+
+    let mut namespaces = BTreeMap::new();
+    match manager.get_namespaces().await {
+        Ok(ids) => {
+            for namespace in ids {
+                let status = seed(manager, namespace).await.unwrap_or_else(|error| {
+                    warn!("[daemon] failed to seed {namespace}: {error:#}");
+                    NamespaceHealth {
+                        error: Some(format!("{error:#}")),
+                        ..Default::default()
+                    }
+                });
+                namespaces.insert(namespace.to_string(), status);
+            }
+        }
+        Err(error) => warn!("[daemon] failed to list namespaces: {error:#}"),
+    }
+
+    let known_peers = manager.get_known_nodes().await.len();
+    let mut health = health.write().await;
+    health.last_maintenance = Some(Instant::now());
+    health.failed_tickets = failed_tickets;
+    health.namespaces = namespaces;
+    health.known_peers = known_peers;
+}
+
+/// Names the ticket in logs and the health report without revealing its capability.
+/// Tickets are named by their namespace, invalid ones by a prefix of their hash.
+fn ticket_label(ticket: &str) -> String {
+    match parse_ticket(ticket) {
+        Ok(ticket) => UNamespaceId::from(ticket.capability.id()).to_string(),
+        Err(_) => format!(
+            "invalid-{}",
+            &blake3::hash(ticket.as_bytes()).to_hex()[..16]
+        ),
+    }
+}
+
+/// Imports the namespace of the ticket unless it's already stored
+async fn join(manager: &IrohManager, ticket: &str, timeout_ms: u64) -> Result<()> {
+    let ticket = parse_ticket(ticket)?;
+    let namespace: UNamespaceId = ticket.capability.id().into();
+    if manager.get_namespaces().await?.contains(&namespace) {
+        return Ok(());
+    }
+
+    info!("[daemon] joining {namespace}");
+    match manager
+        .import_cancellable(ticket.into(), None, Some(timeout_ms))
+        .await
+    {
+        Ok(_) => Ok(()),
+        // The namespace is stored even when the provider is offline, it gets synced later
+        Err(error) if manager.get_namespaces().await?.contains(&namespace) => {
+            info!("[daemon] joined {namespace} without syncing: {error}");
+            Ok(())
+        }
+        Err(error) => Err(error.into()),
+    }
+}
+
+async fn seed(manager: &IrohManager, namespace: UNamespaceId) -> Result<NamespaceHealth> {
+    manager.start_live_sync(namespace).await?;
+    let downloaded = manager.download_missing(namespace).await?;
+    let files = manager.get_files(namespace).await?.len();
+    Ok(NamespaceHealth {
+        files,
+        downloaded,
+        error: None,
+    })
+}
+
+async fn serve_health(listener: TcpListener, health: Arc<RwLock<Health>>, node_id: String) {
+    loop {
+        let Ok((mut stream, _)) = listener.accept().await else {
+            continue;
+        };
+        let health = health.clone();
+        let node_id = node_id.clone();
+        tokio::spawn(async move {
+            let mut request = [0; 1024];
+            let Ok(read) = stream.read(&mut request).await else {
+                return;
+            };
+            let request = String::from_utf8_lossy(&request[..read]);
+            let path = request.split_whitespace().nth(1).unwrap_or_default();
+
+            let (status, body) = match path {
+                "/health" => {
+                    let health = health.read().await;
+                    let status = match health.is_healthy() {
+                        true => "200 OK",
+                        false => "503 Service Unavailable",
+                    };
+                    (status, health.report(&node_id).to_string())
+                }
+                _ => ("404 Not Found", json!({ "error": "not found" }).to_string()),
+            };
+            let response = format!(
+                "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            );
+            let _ = stream.write_all(response.as_bytes()).await;
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Config, Health, ticket_label};
+    use std::{collections::BTreeMap, time::Duration, time::Instant};
+
+    #[test]
+    fn test_config() {
+        let config: Config = toml::from_str(
+            r#"
+            dir = "/srv/unimusic"
+            tickets = ["unimusic://join?ticket=doc"]
+            interval_secs = 30
+            health_addr = "127.0.0.1:7878"
+            relays = ["http://127.0.0.1:3340"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.dir, Some("/srv/unimusic".into()));
+        assert_eq!(config.tickets, ["unimusic://join?ticket=doc"]);
+        assert_eq!(config.interval_secs, 30);
+        assert_eq!(config.health_addr, Some("127.0.0.1:7878".parse().unwrap()));
+        assert_eq!(config.relays, ["http://127.0.0.1:3340"]);
+
+        let config: Config = toml::from_str("").unwrap();
+        assert_eq!(config.interval_secs, 60);
+        assert!(config.tickets.is_empty() && config.health_addr.is_none());
+        assert!(config.relays.is_empty());
+
+        assert!(toml::from_str::<Config>("interval = 30").is_err());
+    }
+
+    #[test]
+    fn test_health() {
+        let mut health = Health {
+            started: Instant::now(),
+            interval: Duration::from_secs(60),
+            last_maintenance: None,
+            failed_tickets: BTreeMap::new(),
+            namespaces: BTreeMap::new(),
+            known_peers: 0,
+        };
+        assert!(!health.is_healthy());
+
+        health.last_maintenance = Some(Instant::now());
+        assert!(health.is_healthy());
+        assert_eq!(health.report("node")["status"], "ok");
+
+        // Tickets carry capabilities, invalid ones are reported by a stable hash
+        let label = ticket_label("unimusic://join?ticket=doc");
+        assert_eq!(label, ticket_label("unimusic://join?ticket=doc"));
+        assert!(label.starts_with("invalid-") && !label.contains("doc"));
+        health.failed_tickets.insert(label, "Invalid ticket".into());
+        assert!(!health.is_healthy());
+        assert_eq!(health.report("node")["status"], "degraded");
+    }
+}
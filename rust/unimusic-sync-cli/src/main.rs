@@ -0,0 +1,446 @@
+mod daemon;
+mod output;
+
+use anyhow::{Context, Result, bail};
+use clap::{Parser, Subcommand, ValueEnum};
+use serde_json::{Value, json};
+use std::{net::SocketAddr, path::PathBuf, str::FromStr, time::Duration};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use unimusic_sync::{
+    IrohFactory, IrohManager, SyncScope,
+    reconnect::PeerConnectionType,
+    relay::{LocalRelay, parse_relay_map},
+    ticket::{compact_ticket, parse_ticket, ticket_to_uri},
+    types::UNamespaceId,
+};
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "unimusic-sync",
+    version,
+    about = "Inspect and script UniMusicSync nodes"
+)]
+struct Cli {
+    /// Data directory of the node
+    #[arg(
+        long,
+        short,
+        env = "UNIMUSIC_SYNC_DIR",
+        default_value = "unimusic-sync"
+    )]
+    dir: PathBuf,
+
+    /// Passphrase the storage of the node is encrypted with
+    #[arg(long, env = "UNIMUSIC_SYNC_PASSPHRASE", hide_env_values = true)]
+    passphrase: Option<String>,
+
+    /// Relay URL used instead of the default relays, can be repeated
+    #[arg(long = "relay", env = "UNIMUSIC_SYNC_RELAYS", value_delimiter = ',')]
+    relays: Vec<String>,
+
+    /// Print JSON instead of plain text
+    #[arg(long, global = true)]
+    json: bool,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Creates the data directory and identity of a new node
+    Init,
+    /// Lists namespaces stored on the node
+    Namespaces {
+        /// Create a new namespace first
+        #[arg(long)]
+        create: bool,
+    },
+    /// Lists files of the namespace
+    Ls {
+        #[arg(value_parser = parse_namespace)]
+        namespace: UNamespaceId,
+        /// Only keys starting with the prefix
+        #[arg(long, default_value = "")]
+        prefix: String,
+    },
+    /// Writes contents of the file to stdout
+    Cat {
+        #[arg(value_parser = parse_namespace)]
+        namespace: UNamespaceId,
+        key: String,
+    },
+    /// Writes the local file, or stdin, under the key
+    Put {
+        #[arg(value_parser = parse_namespace)]
+        namespace: UNamespaceId,
+        key: String,
+        /// Read from stdin when omitted
+        path: Option<PathBuf>,
+    },
+    /// Deletes the file
+    Rm {
+        #[arg(value_parser = parse_namespace)]
+        namespace: UNamespaceId,
+        key: String,
+    },
+    /// Exports files of the namespace into the directory
+    Export {
+        #[arg(value_parser = parse_namespace)]
+        namespace: UNamespaceId,
+        destination: PathBuf,
+        /// Only keys starting with the prefix
+        #[arg(long, default_value = "")]
+        prefix: String,
+    },
+    /// Prints a ticket other nodes can join the namespace with
+    Share {
+        #[arg(value_parser = parse_namespace)]
+        namespace: UNamespaceId,
+        #[arg(long, value_enum, default_value_t = TicketFormat::Plain)]
+        format: TicketFormat,
+    },
+    /// Joins the namespace of the ticket and downloads its files
+    Join {
+        /// Plain, compact or `unimusic://join` ticket
+        ticket: String,
+    },
+    /// Syncs the namespace with peers and downloads missing content
+    Sync {
+        #[arg(value_parser = parse_namespace)]
+        namespace: UNamespaceId,
+        /// Contact every known peer, not only ones known to have the namespace
+        #[arg(long)]
+        all_known: bool,
+    },
+    /// Lists known peers
+    Peers {
+        /// Only peers known to have the namespace
+        #[arg(long, value_parser = parse_namespace)]
+        namespace: Option<UNamespaceId>,
+        /// Connect to the peers first, reporting how they are reachable
+        #[arg(long)]
+        reconnect: bool,
+    },
+    /// Prints addresses, NAT state and connections of the node, attach `--json` output to bug reports
+    Diagnostics {
+        /// Connect to known peers first, so that their connections are reported
+        #[arg(long)]
+        reconnect: bool,
+    },
+    /// Seeds namespaces of the config until stopped, for always-on machines like a NAS
+    ///
+    /// SIGHUP reloads the config, SIGINT or SIGTERM shut the node down.
+    Daemon {
+        /// TOML file with `tickets`, `interval_secs`, `health_addr` and optionally `dir`
+        #[arg(long, short)]
+        config: PathBuf,
+    },
+    /// Runs a relay server without TLS until stopped, for local testing
+    ///
+    /// Nodes use it when started with `--relay` and the printed URL.
+    Relay {
+        /// Address of the relay, STUN is served on the same IP with any free port
+        #[arg(long, default_value = "127.0.0.1:3340")]
+        bind: SocketAddr,
+    },
+    /// Prints counts of namespaces, files and peers
+    Stats {
+        /// Only files of the namespace
+        #[arg(value_parser = parse_namespace)]
+        namespace: Option<UNamespaceId>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum TicketFormat {
+    Plain,
+    /// Without direct addresses, short enough for QR codes
+    Compact,
+    /// `unimusic://join` deep link
+    Uri,
+}
+
+/// How long `diagnostics` waits for the first network report
+const NET_REPORT_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn parse_namespace(input: &str) -> std::result::Result<UNamespaceId, String> {
+    UNamespaceId::from_str(input).map_err(|error| format!("invalid namespace: {error}"))
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
+    let cli = Cli::parse();
+
+    // The daemon creates the node itself, possibly in the directory of its config
+    if let Command::Daemon { config } = cli.command {
+        return daemon::run(cli.dir, cli.passphrase, cli.relays, config).await;
+    }
+    if let Command::Relay { bind } = cli.command {
+        return relay(bind, cli.json).await;
+    }
+
+    let is_init = matches!(cli.command, Command::Init);
+    if !is_init && !cli.dir.exists() {
+        bail!(
+            "No node in {}, create one with `unimusic-sync init`",
+            cli.dir.display()
+        );
+    }
+
+    let manager = factory(cli.passphrase, &cli.relays)?
+        .iroh_manager(&cli.dir.to_string_lossy())
+        .await
+        .with_context(|| format!("Failed to open node in {}", cli.dir.display()))?;
+
+    let result = run(&manager, cli.command).await;
+    manager.shutdown().await?;
+
+    if let Some(value) = result? {
+        output::print(&value, cli.json)?;
+    }
+    Ok(())
+}
+
+/// Factory of nodes encrypted with the passphrase, using the relays instead of the default ones
+fn factory(passphrase: Option<String>, relays: &[String]) -> Result<IrohFactory> {
+    let factory = match passphrase {
+        Some(passphrase) => IrohFactory::with_passphrase(passphrase),
+        None => IrohFactory::new(),
+    };
+    if relays.is_empty() {
+        return Ok(factory);
+    }
+    Ok(factory.with_relay_map(parse_relay_map(relays)?))
+}
+
+async fn relay(bind: SocketAddr, json: bool) -> Result<()> {
+    let relay = LocalRelay::spawn(bind)
+        .await
+        .with_context(|| format!("Failed to start relay on {bind}"))?;
+    output::print(&json!({ "url": relay.url().to_string() }), json)?;
+
+    tokio::signal::ctrl_c().await?;
+    relay.shutdown().await?;
+    Ok(())
+}
+
+/// Runs the command, returning what should be printed
+async fn run(manager: &IrohManager, command: Command) -> Result<Option<Value>> {
+    let value = match command {
+        Command::Init => json!({
+            "node_id": manager.get_node_id().await.to_string(),
+            "author": manager.get_author().await?.to_string(),
+            "dir": manager.path,
+        }),
+
+        Command::Namespaces { create } => {
+            if create {
+                manager.create_namespace().await?;
+            }
+            let mut namespaces = Vec::new();
+            for namespace in manager.get_namespaces().await? {
+                let files = manager.get_files(namespace).await?;
+                namespaces.push(json!({
+                    "namespace": namespace.to_string(),
+                    "files": files.len(),
+                }));
+            }
+            Value::Array(namespaces)
+        }
+
+        Command::Ls { namespace, prefix } => {
+            let files = manager.get_files(namespace).await?;
+            let files = files
+                .iter()
+                .filter(|entry| entry.key().starts_with(&prefix))
+                .map(|entry| {
+                    json!({
+                        "key": entry.key(),
+                        "size": entry.file_len(),
+                        "hash": entry.content_hash().to_string(),
+                        "timestamp": entry.timestamp(),
+                        "author": entry.author().to_string(),
+                    })
+                })
+                .collect();
+            Value::Array(files)
+        }
+
+        Command::Cat { namespace, key } => {
+            let data = manager.read_file(namespace, &key).await?;
+            let mut stdout = tokio::io::stdout();
+            stdout.write_all(&data).await?;
+            stdout.flush().await?;
+            return Ok(None);
+        }
+
+        Command::Put {
+            namespace,
+            key,
+            path,
+        } => {
+            let data = match path {
+                Some(path) => tokio::fs::read(&path)
+                    .await
+                    .with_context(|| format!("Failed to read {}", path.display()))?,
+                None => {
+                    let mut data = Vec::new();
+                    tokio::io::stdin().read_to_end(&mut data).await?;
+                    data
+                }
+            };
+            let size = data.len();
+            let hash = manager.write_file(namespace, key.clone(), data).await?;
+            json!({ "key": key, "size": size, "hash": hash.to_string() })
+        }
+
+        Command::Rm { namespace, key } => {
+            // Deleting a key which doesn't exist would still write a tombstone
+            manager.get_file(namespace, &key).await?;
+            manager.delete_file(namespace, key.clone()).await?;
+            json!({ "key": key, "deleted": true })
+        }
+
+        Command::Export {
+            namespace,
+            destination,
+            prefix,
+        } => {
+            // Blobs are exported by the store, which needs an absolute path
+            let destination = std::path::absolute(&destination)?;
+            let report = manager
+                .export_all(
+                    namespace,
+                    prefix,
+                    destination.to_string_lossy().into_owned(),
+                    None,
+                )
+                .await?;
+            serde_json::to_value(report)?
+        }
+
+        Command::Share { namespace, format } => {
+            let ticket = manager.share(namespace).await?;
+            let ticket = match format {
+                TicketFormat::Plain => ticket.to_string(),
+                TicketFormat::Compact => compact_ticket(ticket).to_string(),
+                TicketFormat::Uri => ticket_to_uri(ticket),
+            };
+            Value::String(ticket)
+        }
+
+        Command::Join { ticket } => {
+            let ticket = parse_ticket(&ticket)?;
+            let namespace = manager.import(ticket.into()).await?;
+            let files = manager.get_files(namespace).await?;
+            json!({ "namespace": namespace.to_string(), "files": files.len() })
+        }
+
+        Command::Sync {
+            namespace,
+            all_known,
+        } => {
+            let scope = if all_known {
+                SyncScope::AllKnown
+            } else {
+                SyncScope::Namespace
+            };
+            manager.sync_with(namespace, scope).await?;
+            let downloaded = manager.download_missing(namespace).await?;
+            let files = manager.get_files(namespace).await?;
+            json!({
+                "namespace": namespace.to_string(),
+                "files": files.len(),
+                "downloaded": downloaded,
+            })
+        }
+
+        Command::Peers {
+            namespace,
+            reconnect,
+        } => peers(manager, namespace, reconnect).await?,
+
+        Command::Diagnostics { reconnect } => {
+            if reconnect {
+                manager.reconnect().await;
+            }
+            // The node has just started, give it a moment to probe the network
+            let mut net_report = manager.router.endpoint().net_report();
+            let _ = tokio::time::timeout(NET_REPORT_TIMEOUT, net_report.initialized()).await;
+            serde_json::to_value(manager.diagnostics().await)?
+        }
+
+        Command::Daemon { .. } | Command::Relay { .. } => {
+            unreachable!("the command runs without a shared manager")
+        }
+
+        Command::Stats { namespace } => {
+            let namespaces = match namespace {
+                Some(namespace) => vec![namespace],
+                None => manager.get_namespaces().await?,
+            };
+            let (mut files, mut bytes) = (0, 0);
+            for namespace in &namespaces {
+                for entry in manager.get_files(*namespace).await? {
+                    files += 1;
+                    bytes += entry.file_len();
+                }
+            }
+            json!({
+                "node_id": manager.get_node_id().await.to_string(),
+                "namespaces": namespaces.len(),
+                "files": files,
+                "bytes": bytes,
+                "known_peers": manager.get_known_nodes().await.len(),
+            })
+        }
+    };
+    Ok(Some(value))
+}
+
+async fn peers(
+    manager: &IrohManager,
+    namespace: Option<UNamespaceId>,
+    reconnect: bool,
+) -> Result<Value> {
+    let report = match reconnect {
+        true => Some(manager.reconnect().await),
+        false => None,
+    };
+
+    let node_storage = manager.node_storage.read().await;
+    let node_ids = match namespace {
+        Some(namespace) => node_storage.get_namespace_nodes(&namespace),
+        None => node_storage.nodes.keys().copied().collect(),
+    };
+
+    let mut peers = Vec::new();
+    for node_id in node_ids {
+        let Some(data) = node_storage.get_unode_data(&node_id) else {
+            continue;
+        };
+        let connection_type = manager
+            .router
+            .endpoint()
+            .remote_info(node_id.into())
+            .map_or(PeerConnectionType::None, |info| (&info.conn_type).into());
+
+        let mut peer = json!({
+            "node_id": node_id.to_string(),
+            "connection_type": connection_type,
+            "relay_url": data.relay_url.as_ref().map(ToString::to_string),
+            "direct_addresses": data.direct_addresses,
+        });
+        if let Some(result) = report
+            .as_ref()
+            .and_then(|report| report.peers.iter().find(|peer| peer.node_id == node_id))
+        {
+            peer["outcome"] = serde_json::to_value(&result.outcome)?;
+            peer["latency_ms"] = json!(result.latency_ms);
+        }
+        peers.push(peer);
+    }
+    Ok(Value::Array(peers))
+}
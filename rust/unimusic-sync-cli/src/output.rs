@@ -0,0 +1,66 @@
+use serde_json::Value;
+use std::io::{self, Write};
+
+/// Prints the value as pretty JSON, or as plain text meant for reading and `cut`/`awk`
+pub fn print(value: &Value, json: bool) -> io::Result<()> {
+    let mut stdout = io::stdout().lock();
+    if json {
+        serde_json::to_writer_pretty(&mut stdout, value)?;
+        writeln!(stdout)
+    } else {
+        stdout.write_all(render(value).as_bytes())
+    }
+}
+
+/// Arrays are printed one item per line with fields of objects separated by tabs,
+/// objects one `name: value` pair per line
+pub fn render(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::Array(items) => items
+            .iter()
+            .map(|item| match item {
+                Value::Object(fields) => fields.values().map(scalar).collect::<Vec<_>>().join("\t"),
+                item => scalar(item),
+            })
+            .map(|line| line + "\n")
+            .collect(),
+        Value::Object(fields) => fields
+            .iter()
+            .map(|(name, value)| format!("{name}: {}\n", scalar(value)))
+            .collect(),
+        value => scalar(value) + "\n",
+    }
+}
+
+fn scalar(value: &Value) -> String {
+    match value {
+        Value::Null => "-".to_string(),
+        Value::String(string) => string.clone(),
+        Value::Array(items) => items.iter().map(scalar).collect::<Vec<_>>().join(","),
+        value => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::render;
+    use serde_json::json;
+
+    #[test]
+    fn test_render() {
+        assert_eq!(render(&json!("ticket")), "ticket\n");
+        assert_eq!(render(&json!(null)), "");
+        assert_eq!(
+            render(&json!({ "node_id": "abc", "files": 3, "relay_url": null })),
+            "node_id: abc\nfiles: 3\nrelay_url: -\n"
+        );
+        assert_eq!(
+            render(&json!([
+                { "key": "a.mp3", "size": 10 },
+                { "key": "b.mp3", "size": 20, "addresses": ["1.2.3.4:5", "[::1]:5"] },
+            ])),
+            "a.mp3\t10\nb.mp3\t20\t1.2.3.4:5,[::1]:5\n"
+        );
+    }
+}
@@ -0,0 +1,191 @@
+use image::{codecs::jpeg::JpegEncoder, imageops::FilterType};
+use iroh_blobs::Hash;
+use iroh_docs::store::Query;
+use lofty::{picture::PictureType, tag::Tag};
+use log::{info, warn};
+
+use crate::{
+    IrohManager, TOMBSTONE,
+    errors::{Result, SharedError},
+    types::UNamespaceId,
+};
+
+/// Keys of entries holding cover art, followed by the hash of the original image
+/// and `/original` or the thumbnail size
+pub const ARTWORK_PREFIX: &str = ".unimusic/art/";
+/// Longest side of generated thumbnails in pixels
+pub const THUMBNAIL_SIZES: [u32; 3] = [128, 256, 512];
+/// Larger images are almost certainly not cover art and aren't stored
+const MAX_ARTWORK_LEN: usize = 16 * 1024 * 1024;
+const JPEG_QUALITY: u8 = 85;
+
+/// Returns the front cover of the tag, or its first picture if none is marked as one
+pub(crate) fn from_tag(tag: &Tag) -> Option<Vec<u8>> {
+    let pictures = tag.pictures();
+    pictures
+        .iter()
+        .find(|picture| picture.pic_type() == PictureType::CoverFront)
+        .or_else(|| pictures.first())
+        .map(|picture| picture.data().to_vec())
+        .filter(|data| !data.is_empty() && data.len() <= MAX_ARTWORK_LEN)
+}
+
+fn artwork_key(hash: Hash, size: Option<u32>) -> String {
+    match size {
+        Some(size) => format!("{ARTWORK_PREFIX}{hash}/{size}"),
+        // Keys prefixing other keys would delete them when written
+        None => format!("{ARTWORK_PREFIX}{hash}/original"),
+    }
+}
+
+/// Scales the image down so its longer side fits each of [`THUMBNAIL_SIZES`], encoded as JPEG.
+/// Smaller images aren't scaled up.
+pub(crate) fn thumbnails(image: &[u8]) -> Result<Vec<(u32, Vec<u8>)>> {
+    let image = image::load_from_memory(image)
+        .map_err(|error| SharedError::InvalidInput(format!("Invalid cover art: {error}")))?;
+
+    THUMBNAIL_SIZES
+        .iter()
+        .map(|&size| {
+            let thumbnail = if image.width() > size || image.height() > size {
+                image.resize(size, size, FilterType::Triangle)
+            } else {
+                image.clone()
+            };
+
+            // JPEG has no alpha channel
+            let mut jpeg = Vec::new();
+            JpegEncoder::new_with_quality(&mut jpeg, JPEG_QUALITY)
+                .encode_image(&thumbnail.into_rgb8())
+                .map_err(|error| SharedError::Storage(error.to_string()))?;
+            Ok((size, jpeg))
+        })
+        .collect()
+}
+
+/// Stores the image and its thumbnails once per namespace, returning its hash
+pub(crate) async fn store(
+    manager: &IrohManager,
+    namespace: UNamespaceId,
+    image: Vec<u8>,
+) -> Result<Hash> {
+    let docs_client = manager.docs.client();
+    let replica = docs_client
+        .open(namespace.into())
+        .await?
+        .ok_or(SharedError::ReplicaMissing(namespace))?;
+
+    let hash = Hash::new(&image);
+    let key = artwork_key(hash, None);
+    // The original is written last, so once it exists its thumbnails do too
+    let existing = replica
+        .get_one(Query::single_latest_per_key().key_exact(&key))
+        .await?;
+    if existing.is_some_and(|entry| entry.content_hash() == hash) {
+        return Ok(hash);
+    }
+
+    let thumbnails = {
+        let image = image.clone();
+        tokio::task::spawn_blocking(move || thumbnails(&image))
+            .await
+            .map_err(|error| SharedError::Iroh(error.to_string()))??
+    };
+
+    let author = docs_client.authors().default().await?;
+    for (size, thumbnail) in thumbnails {
+        replica
+            .set_bytes(author, artwork_key(hash, Some(size)), thumbnail)
+            .await?;
+    }
+    replica.set_bytes(author, key, image).await?;
+    info!("[artwork] stored cover art {hash} in namespace {namespace}");
+
+    Ok(hash)
+}
+
+/// Same as [`store`], but only logs failures, so tracks are still indexed without their art
+pub(crate) async fn store_logged(
+    manager: &IrohManager,
+    namespace: UNamespaceId,
+    image: Vec<u8>,
+) -> Option<Hash> {
+    match store(manager, namespace, image).await {
+        Ok(hash) => Some(hash),
+        Err(error) => {
+            warn!("[artwork] failed to store cover art: {error}");
+            None
+        }
+    }
+}
+
+/// Returns the smallest thumbnail at least `size` pixels large, or the largest one.
+/// Without a size the original image is returned.
+pub(crate) async fn get(
+    manager: &IrohManager,
+    namespace: UNamespaceId,
+    hash: Hash,
+    size: Option<u32>,
+) -> Result<Option<Vec<u8>>> {
+    let replica = manager
+        .docs
+        .client()
+        .open(namespace.into())
+        .await?
+        .ok_or(SharedError::ReplicaMissing(namespace))?;
+
+    let size = size.map(|size| {
+        THUMBNAIL_SIZES
+            .into_iter()
+            .find(|thumbnail| *thumbnail >= size)
+            .unwrap_or(THUMBNAIL_SIZES[THUMBNAIL_SIZES.len() - 1])
+    });
+    let Some(entry) = replica
+        .get_one(Query::single_latest_per_key().key_exact(artwork_key(hash, size)))
+        .await?
+    else {
+        return Ok(None);
+    };
+    if entry.content_hash() == Hash::new(TOMBSTONE) {
+        return Ok(None);
+    }
+
+    let bytes = manager
+        .blobs
+        .client()
+        .read_to_bytes(entry.content_hash())
+        .await?;
+    Ok(Some(bytes.to_vec()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{THUMBNAIL_SIZES, thumbnails};
+    use image::{ImageFormat, RgbaImage};
+    use std::io::Cursor;
+
+    #[test]
+    fn test_thumbnails() {
+        let mut png = Vec::new();
+        RgbaImage::new(800, 400)
+            .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+            .unwrap();
+
+        let generated = thumbnails(&png).unwrap();
+        assert_eq!(generated.len(), THUMBNAIL_SIZES.len());
+        for (size, jpeg) in generated {
+            let thumbnail = image::load_from_memory(&jpeg).unwrap();
+            assert_eq!((thumbnail.width(), thumbnail.height()), (size, size / 2));
+        }
+
+        // Small images keep their size
+        let mut small = Vec::new();
+        RgbaImage::new(64, 64)
+            .write_to(&mut Cursor::new(&mut small), ImageFormat::Png)
+            .unwrap();
+        let thumbnail = image::load_from_memory(&thumbnails(&small).unwrap()[2].1).unwrap();
+        assert_eq!(thumbnail.width(), 64);
+
+        assert!(thumbnails(b"not an image").is_err());
+    }
+}
@@ -0,0 +1,146 @@
+use rand::Rng;
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    time::{Duration, Instant},
+};
+
+/// Exponential backoff policy, doubling the delay after every consecutive failure
+#[cfg_attr(feature = "default", derive(uniffi::Record))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BackoffPolicy {
+    /// Delay after the first failure
+    pub initial_delay_ms: u64,
+    /// Upper bound for the delay
+    pub max_delay_ms: u64,
+}
+
+impl Default for BackoffPolicy {
+    fn default() -> Self {
+        Self {
+            initial_delay_ms: 1_000,
+            max_delay_ms: 5 * 60 * 1_000,
+        }
+    }
+}
+
+impl BackoffPolicy {
+    /// Longest delay to wait after given amount of consecutive failures
+    pub fn delay(&self, failures: u32) -> Duration {
+        if failures == 0 {
+            return Duration::ZERO;
+        }
+
+        let exponent = (failures - 1).min(32);
+        let delay = self
+            .initial_delay_ms
+            .saturating_mul(1u64 << exponent)
+            .min(self.max_delay_ms);
+        Duration::from_millis(delay)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct BackoffState {
+    failures: u32,
+    retry_at: Instant,
+}
+
+/// Keeps track of consecutive failures per key. Keys wait a random time between half and
+/// all of the policy delay, so that keys failing together aren't retried together.
+#[derive(Debug)]
+pub struct Backoff<K> {
+    pub policy: BackoffPolicy,
+    states: HashMap<K, BackoffState>,
+}
+
+impl<K: Eq + Hash> Backoff<K> {
+    pub fn new(policy: BackoffPolicy) -> Self {
+        Self {
+            policy,
+            states: HashMap::new(),
+        }
+    }
+
+    /// Returns how long the key should still be left alone, if at all
+    pub fn remaining(&self, key: &K) -> Option<Duration> {
+        let state = self.states.get(key)?;
+        let remaining = state.retry_at.saturating_duration_since(Instant::now());
+        (!remaining.is_zero()).then_some(remaining)
+    }
+
+    /// Returns amount of consecutive failures of the key
+    pub fn failures(&self, key: &K) -> u32 {
+        self.states.get(key).map_or(0, |state| state.failures)
+    }
+
+    /// Records a failure, returning the delay before the next attempt
+    pub fn failed(&mut self, key: K) -> Duration {
+        let state = self.states.entry(key).or_insert(BackoffState {
+            failures: 0,
+            retry_at: Instant::now(),
+        });
+        state.failures = state.failures.saturating_add(1);
+
+        let max_delay = self.policy.delay(state.failures);
+        let delay = rand::thread_rng().gen_range(max_delay / 2..=max_delay);
+        state.retry_at = Instant::now() + delay;
+        delay
+    }
+
+    /// Resets the key after a successful attempt
+    pub fn succeeded(&mut self, key: &K) {
+        self.states.remove(key);
+    }
+}
+
+impl<K: Eq + Hash> Default for Backoff<K> {
+    fn default() -> Self {
+        Self::new(BackoffPolicy::default())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{Backoff, BackoffPolicy};
+
+    const POLICY: BackoffPolicy = BackoffPolicy {
+        initial_delay_ms: 1_000,
+        max_delay_ms: 10_000,
+    };
+
+    #[test]
+    fn test_policy_delay() {
+        let delays: Vec<_> = (0..7).map(|failures| POLICY.delay(failures)).collect();
+        assert_eq!(
+            delays,
+            [0, 1_000, 2_000, 4_000, 8_000, 10_000, 10_000].map(Duration::from_millis)
+        );
+        assert_eq!(POLICY.delay(u32::MAX), Duration::from_millis(10_000));
+    }
+
+    #[test]
+    fn test_backoff() {
+        let mut backoff = Backoff::new(POLICY);
+        assert_eq!(backoff.failures(&"peer"), 0);
+        assert_eq!(backoff.remaining(&"peer"), None);
+
+        for failures in 1..=6 {
+            let max_delay = POLICY.delay(failures);
+            let delay = backoff.failed("peer");
+            assert!(delay >= max_delay / 2 && delay <= max_delay, "{delay:?}");
+            assert_eq!(backoff.failures(&"peer"), failures);
+
+            let remaining = backoff.remaining(&"peer").expect("peer is backing off");
+            assert!(remaining <= delay);
+        }
+        assert_eq!(backoff.remaining(&"other"), None);
+
+        backoff.succeeded(&"peer");
+        assert_eq!(backoff.failures(&"peer"), 0);
+        assert_eq!(backoff.remaining(&"peer"), None);
+        assert!(backoff.failed("peer") <= POLICY.delay(1));
+    }
+}
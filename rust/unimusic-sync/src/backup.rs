@@ -0,0 +1,596 @@
+use iroh_blobs::{Hash, util::SetTagOption};
+use iroh_docs::{
+    Author, Capability, CapabilityKind, NamespaceSecret, SignedEntry,
+    store::Query,
+    sync::{ContentStatus, InsertError},
+};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    fmt::Display,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    fs::{self, File},
+    io::{AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader, BufWriter},
+};
+use tokio_stream::StreamExt;
+
+use crate::{
+    IrohManager,
+    encryption::Encryption,
+    errors::{Result, SharedError},
+    types::UNamespaceId,
+};
+
+/// Archive starts with this prefix, followed by the version of the format, a byte telling
+/// whether it's encrypted, then the manifest and blobs
+const MAGIC: &[u8] = b"UMSBAK\0";
+const VERSION: u32 = 2;
+/// Upper bound for the manifest, protects against allocating garbage sizes
+const MAX_MANIFEST_SIZE: u32 = 256 * 1024 * 1024;
+/// Data of encrypted archives is split into frames of this size, each encrypted on its own
+const FRAME_SIZE: usize = 1024 * 1024;
+/// Index of the frame and whether it's the last one, preceding data of the frame
+const FRAME_HEADER_LEN: usize = 9;
+/// Frame with room for what the encryption adds
+const MAX_FRAME_LEN: u32 = (FRAME_SIZE + FRAME_HEADER_LEN + 1024) as u32;
+/// Blobs are copied in chunks of this size
+const COPY_CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Manifest {
+    namespace: UNamespaceId,
+    created_at_ms: u64,
+    /// Namespace secret, without it the namespace is restored read-only
+    secret: Option<String>,
+    /// Secrets of local authors, so this device keeps writing as the same author
+    authors: Vec<String>,
+    /// Latest entries with the signatures of their authors, restored exactly as they were
+    entries: Vec<SignedEntry>,
+    /// Blobs in the order they follow the manifest
+    blobs: Vec<ManifestBlob>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ManifestBlob {
+    hash: String,
+    len: u64,
+}
+
+#[cfg_attr(feature = "default", derive(uniffi::Record))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackupReport {
+    pub entries: u64,
+    pub blobs: u64,
+    /// Entries whose content isn't available locally, they are backed up without it
+    pub missing_blobs: u64,
+    pub includes_secret: bool,
+}
+
+#[cfg_attr(feature = "default", derive(uniffi::Record))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RestoreReport {
+    pub namespace: UNamespaceId,
+    pub entries: u64,
+    /// Entries which weren't restored, because the namespace already has newer ones
+    pub skipped: u64,
+    pub blobs: u64,
+    /// Whether the archive contained the secret, otherwise new entries can only come from peers
+    pub writable: bool,
+}
+
+fn invalid_archive(reason: impl Display) -> SharedError {
+    SharedError::InvalidInput(format!("Invalid backup archive: {reason}"))
+}
+
+/// Writes the archive, encrypting it frame by frame when encryption is enabled
+struct ArchiveWriter {
+    file: BufWriter<File>,
+    encryption: Option<Encryption>,
+    /// Plaintext of the frame being filled
+    frame: Vec<u8>,
+    frames: u64,
+}
+
+impl ArchiveWriter {
+    async fn create(path: &Path, encryption: Option<Encryption>) -> Result<Self> {
+        let mut file = BufWriter::new(File::create(path).await?);
+        file.write_all(MAGIC).await?;
+        file.write_u32_le(VERSION).await?;
+        file.write_u8(encryption.is_some().into()).await?;
+
+        Ok(Self {
+            file,
+            encryption,
+            frame: Vec::new(),
+            frames: 0,
+        })
+    }
+
+    async fn write_all(&mut self, mut data: &[u8]) -> Result<()> {
+        if self.encryption.is_none() {
+            self.file.write_all(data).await?;
+            return Ok(());
+        }
+
+        while !data.is_empty() {
+            let len = (FRAME_SIZE - self.frame.len()).min(data.len());
+            self.frame.extend_from_slice(&data[..len]);
+            data = &data[len..];
+            if self.frame.len() == FRAME_SIZE {
+                self.write_frame(false).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn write_frame(&mut self, last: bool) -> Result<()> {
+        let Some(encryption) = &self.encryption else {
+            return Ok(());
+        };
+
+        // Index and flag of the last frame are encrypted with the data,
+        // so frames can't be reordered or cut off unnoticed
+        let mut plaintext = Vec::with_capacity(FRAME_HEADER_LEN + self.frame.len());
+        plaintext.extend_from_slice(&self.frames.to_le_bytes());
+        plaintext.push(last.into());
+        plaintext.append(&mut self.frame);
+
+        let ciphertext = encryption.encrypt(&plaintext)?;
+        self.file.write_u32_le(ciphertext.len() as u32).await?;
+        self.file.write_all(&ciphertext).await?;
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// Writes the last frame and flushes the archive to the disk
+    async fn finish(mut self) -> Result<()> {
+        self.write_frame(true).await?;
+        self.file.flush().await?;
+        self.file.into_inner().sync_all().await?;
+        Ok(())
+    }
+}
+
+/// Reads the archive, decrypting it if it's encrypted
+struct ArchiveReader {
+    file: BufReader<File>,
+    encryption: Option<Encryption>,
+    /// Plaintext of the current frame and how much of it has been read
+    frame: Vec<u8>,
+    position: usize,
+    frames: u64,
+    last: bool,
+}
+
+impl ArchiveReader {
+    async fn open(path: &Path, encryption: Option<Encryption>) -> Result<Self> {
+        let mut file = BufReader::new(File::open(path).await?);
+
+        let mut magic = [0; MAGIC.len()];
+        file.read_exact(&mut magic)
+            .await
+            .map_err(|_| invalid_archive("file is too short"))?;
+        if magic != MAGIC {
+            return Err(invalid_archive("not a backup archive"));
+        }
+        let version = file
+            .read_u32_le()
+            .await
+            .map_err(|_| invalid_archive("file is too short"))?;
+        if version != VERSION {
+            return Err(invalid_archive(format!("unsupported version {version}")));
+        }
+
+        let encryption = match file.read_u8().await? {
+            0 => None,
+            1 => Some(encryption.ok_or_else(|| {
+                SharedError::PermissionDenied(
+                    "Backup archive is encrypted, a key or passphrase is required".to_string(),
+                )
+            })?),
+            _ => return Err(invalid_archive("unknown format")),
+        };
+
+        Ok(Self {
+            file,
+            encryption,
+            frame: Vec::new(),
+            position: 0,
+            frames: 0,
+            last: false,
+        })
+    }
+
+    async fn read_exact(&mut self, mut buf: &mut [u8]) -> Result<()> {
+        if self.encryption.is_none() {
+            self.file
+                .read_exact(buf)
+                .await
+                .map_err(|_| invalid_archive("file is truncated"))?;
+            return Ok(());
+        }
+
+        while !buf.is_empty() {
+            if self.position == self.frame.len() {
+                self.read_frame().await?;
+            }
+            let len = (self.frame.len() - self.position).min(buf.len());
+            buf[..len].copy_from_slice(&self.frame[self.position..self.position + len]);
+            self.position += len;
+            buf = &mut buf[len..];
+        }
+        Ok(())
+    }
+
+    async fn read_frame(&mut self) -> Result<()> {
+        let Some(encryption) = &self.encryption else {
+            return Ok(());
+        };
+        if self.last {
+            return Err(invalid_archive("file is truncated"));
+        }
+
+        let len = self
+            .file
+            .read_u32_le()
+            .await
+            .map_err(|_| invalid_archive("file is truncated"))?;
+        if len > MAX_FRAME_LEN {
+            return Err(invalid_archive("frame is too large"));
+        }
+        let mut ciphertext = vec![0; len as usize];
+        self.file
+            .read_exact(&mut ciphertext)
+            .await
+            .map_err(|_| invalid_archive("file is truncated"))?;
+
+        let plaintext = encryption.decrypt(&ciphertext)?;
+        if plaintext.len() < FRAME_HEADER_LEN {
+            return Err(invalid_archive("frame is too short"));
+        }
+        let (header, data) = plaintext.split_at(FRAME_HEADER_LEN);
+        let index = u64::from_le_bytes(header[..8].try_into().expect("header has 8 bytes"));
+        if index != self.frames {
+            return Err(invalid_archive("frames are out of order"));
+        }
+
+        self.frames += 1;
+        self.last = header[8] == 1;
+        self.frame = data.to_vec();
+        self.position = 0;
+        Ok(())
+    }
+
+    async fn read_u32_le(&mut self) -> Result<u32> {
+        let mut bytes = [0; 4];
+        self.read_exact(&mut bytes).await?;
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    async fn read_u64_le(&mut self) -> Result<u64> {
+        let mut bytes = [0; 8];
+        self.read_exact(&mut bytes).await?;
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    /// Reads `len` bytes, allocating only as much as the archive actually holds
+    async fn read_vec(&mut self, len: u64) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        let mut chunk = vec![0; COPY_CHUNK_SIZE];
+        let mut remaining = len;
+        while remaining > 0 {
+            let chunk = &mut chunk[..remaining.min(COPY_CHUNK_SIZE as u64) as usize];
+            self.read_exact(chunk).await?;
+            data.extend_from_slice(chunk);
+            remaining -= chunk.len() as u64;
+        }
+        Ok(data)
+    }
+
+    /// Copies `len` bytes into the writer and closes it
+    async fn copy_to(&mut self, len: u64, mut writer: impl AsyncWrite + Unpin) -> Result<()> {
+        let mut chunk = vec![0; COPY_CHUNK_SIZE];
+        let mut remaining = len;
+        while remaining > 0 {
+            let chunk = &mut chunk[..remaining.min(COPY_CHUNK_SIZE as u64) as usize];
+            self.read_exact(chunk).await?;
+            writer.write_all(chunk).await?;
+            remaining -= chunk.len() as u64;
+        }
+        writer.shutdown().await?;
+        Ok(())
+    }
+}
+
+/// Latest entries of the namespace with their signatures
+async fn latest_entries(
+    manager: &IrohManager,
+    namespace: UNamespaceId,
+) -> Result<Vec<SignedEntry>> {
+    let (sender, receiver) = async_channel::bounded(64);
+    manager
+        .replicas
+        .get_many(
+            namespace.into(),
+            Query::single_latest_per_key().build(),
+            sender,
+        )
+        .await?;
+
+    let mut entries = Vec::new();
+    while let Ok(entry) = receiver.recv().await {
+        entries.push(entry?);
+    }
+    Ok(entries)
+}
+
+/// Whether this node holds the secret of the namespace
+async fn is_writable(manager: &IrohManager, namespace: UNamespaceId) -> Result<bool> {
+    let mut namespaces = manager.docs.client().list().await?;
+    while let Some((id, kind)) = namespaces.try_next().await? {
+        if UNamespaceId::from(id) == namespace {
+            return Ok(matches!(kind, CapabilityKind::Write));
+        }
+    }
+    Err(SharedError::ReplicaMissing(namespace))
+}
+
+pub(crate) async fn backup_namespace(
+    manager: &IrohManager,
+    namespace: UNamespaceId,
+    archive_path: &Path,
+    include_secret: bool,
+) -> Result<BackupReport> {
+    let docs_client = manager.docs.client();
+    let blobs_client = manager.blobs.client();
+
+    let replica = docs_client
+        .open(namespace.into())
+        .await?
+        .ok_or(SharedError::ReplicaMissing(namespace))?;
+
+    // Read-only namespaces have no secret, they are backed up without it
+    let secret = if include_secret && is_writable(manager, namespace).await? {
+        let secret = manager.replicas.export_secret_key(namespace.into()).await?;
+        Some(secret.to_string())
+    } else {
+        None
+    };
+
+    // The replica stays open while its entries are read
+    let entries = latest_entries(manager, namespace).await?;
+
+    let mut blobs = Vec::new();
+    let mut seen_blobs = HashSet::new();
+    let mut authors = HashSet::new();
+    let mut missing_blobs = 0;
+    for entry in &entries {
+        let hash = entry.content_hash();
+        authors.insert(entry.author());
+
+        if seen_blobs.insert(hash) {
+            if blobs_client.has(hash).await? {
+                blobs.push(ManifestBlob {
+                    hash: hash.to_string(),
+                    len: entry.content_len(),
+                });
+            } else {
+                missing_blobs += 1;
+            }
+        }
+    }
+    drop(replica);
+
+    // Author secrets are as sensitive as the namespace secret
+    let mut author_secrets = Vec::new();
+    if secret.is_some() {
+        for author in authors {
+            if let Some(author) = docs_client.authors().export(author).await? {
+                author_secrets.push(author.to_string());
+            }
+        }
+    }
+
+    let manifest = Manifest {
+        namespace,
+        created_at_ms: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_millis() as u64),
+        secret,
+        authors: author_secrets,
+        entries,
+        blobs,
+    };
+    let serialized =
+        serde_json::to_vec(&manifest).map_err(|e| SharedError::Serde(e.to_string()))?;
+
+    // Written next to the destination first, so a failed backup never replaces a good one
+    let temp_path = PathBuf::from(format!("{}.partial", archive_path.display()));
+    let result = async {
+        let mut writer = ArchiveWriter::create(&temp_path, manager.encryption.clone()).await?;
+        writer
+            .write_all(&(serialized.len() as u32).to_le_bytes())
+            .await?;
+        writer.write_all(&serialized).await?;
+
+        let mut chunk = vec![0; COPY_CHUNK_SIZE];
+        for blob in &manifest.blobs {
+            let hash = Hash::from_str(&blob.hash).map_err(|e| SharedError::Iroh(e.to_string()))?;
+            let mut reader = blobs_client.read(hash).await?;
+            let size = reader.size();
+            writer.write_all(&size.to_le_bytes()).await?;
+
+            let mut copied = 0;
+            loop {
+                let read = reader.read(&mut chunk).await?;
+                if read == 0 {
+                    break;
+                }
+                writer.write_all(&chunk[..read]).await?;
+                copied += read as u64;
+            }
+            if copied != size {
+                return Err(SharedError::Storage(format!(
+                    "Blob {hash} is incomplete, copied {copied} of {size} bytes"
+                )));
+            }
+        }
+
+        writer.finish().await
+    }
+    .await;
+    if let Err(error) = result {
+        let _ = fs::remove_file(&temp_path).await;
+        return Err(error);
+    }
+    fs::rename(&temp_path, archive_path).await?;
+
+    let report = BackupReport {
+        entries: manifest.entries.len() as u64,
+        blobs: manifest.blobs.len() as u64,
+        missing_blobs,
+        includes_secret: manifest.secret.is_some(),
+    };
+    info!("[backup] namespace {namespace} backed up: {report:?}");
+
+    Ok(report)
+}
+
+pub(crate) async fn restore_namespace(
+    manager: &IrohManager,
+    archive_path: &Path,
+) -> Result<RestoreReport> {
+    let docs_client = manager.docs.client();
+    let blobs_client = manager.blobs.client();
+
+    let mut reader = ArchiveReader::open(archive_path, manager.encryption.clone()).await?;
+
+    let manifest_len = reader.read_u32_le().await?;
+    if manifest_len > MAX_MANIFEST_SIZE {
+        return Err(invalid_archive("manifest is too large"));
+    }
+    let manifest = reader.read_vec(manifest_len.into()).await?;
+    let manifest: Manifest =
+        serde_json::from_slice(&manifest).map_err(|e| SharedError::Serde(e.to_string()))?;
+
+    let namespace = manifest.namespace;
+    let capability = match &manifest.secret {
+        Some(secret) => {
+            let secret = NamespaceSecret::from_str(secret).map_err(invalid_archive)?;
+            if UNamespaceId::from(secret.id()) != namespace {
+                return Err(invalid_archive("secret belongs to another namespace"));
+            }
+            Capability::Write(secret)
+        }
+        None => Capability::Read(namespace.into()),
+    };
+    let writable = matches!(capability, Capability::Write(_));
+
+    // Blobs go first, so restored entries point at available content
+    let mut blobs = 0;
+    for blob in &manifest.blobs {
+        let len = reader.read_u64_le().await?;
+        if len != blob.len {
+            return Err(invalid_archive(format!(
+                "blob {} has wrong size",
+                blob.hash
+            )));
+        }
+
+        // Streamed into the store, blobs can be larger than the memory
+        let (blob_reader, blob_writer) = tokio::io::simplex(COPY_CHUNK_SIZE);
+        let progress = blobs_client
+            .add_reader(blob_reader, SetTagOption::Auto)
+            .await?;
+        let (copied, outcome) = tokio::join!(reader.copy_to(len, blob_writer), progress);
+        copied?;
+        let outcome = outcome?;
+        if outcome.hash.to_string() != blob.hash {
+            blobs_client.tags().delete(outcome.tag).await?;
+            return Err(invalid_archive(format!("blob {} is corrupted", blob.hash)));
+        }
+        blobs += 1;
+    }
+
+    for author in &manifest.authors {
+        let author = Author::from_str(author).map_err(invalid_archive)?;
+        docs_client.authors().import(author).await?;
+    }
+
+    let replica = docs_client.import_namespace(capability).await?;
+
+    // Entries are inserted as if a peer sent them, which checks their signatures and keeps
+    // their authors and timestamps. That requires the replica to accept synced entries.
+    let was_syncing = replica.status().await?.sync;
+    if !was_syncing {
+        manager.replicas.set_sync(namespace.into(), true).await?;
+    }
+    let inserted = insert_entries(manager, namespace, manifest.entries).await;
+    if !was_syncing {
+        manager.replicas.set_sync(namespace.into(), false).await?;
+    }
+    let (entries, skipped) = inserted?;
+
+    if !writable {
+        warn!("[backup] archive of namespace {namespace} has no secret, it's restored read-only");
+    }
+
+    let report = RestoreReport {
+        namespace,
+        entries,
+        skipped,
+        blobs,
+        writable,
+    };
+    info!("[backup] namespace {namespace} restored: {report:?}");
+
+    Ok(report)
+}
+
+/// Inserts the signed entries, returning how many were inserted
+/// and how many were skipped because the namespace has newer ones
+async fn insert_entries(
+    manager: &IrohManager,
+    namespace: UNamespaceId,
+    entries: Vec<SignedEntry>,
+) -> Result<(u64, u64)> {
+    let blobs_client = manager.blobs.client();
+    let node_id = *manager.router.endpoint().node_id().as_bytes();
+
+    let (mut inserted, mut skipped) = (0, 0);
+    for entry in entries {
+        if UNamespaceId::from(entry.namespace()) != namespace {
+            return Err(invalid_archive("entry belongs to another namespace"));
+        }
+
+        let content_status = if blobs_client.has(entry.content_hash()).await? {
+            ContentStatus::Complete
+        } else {
+            ContentStatus::Missing
+        };
+        let result = manager
+            .replicas
+            .insert_remote(namespace.into(), entry, node_id, content_status)
+            .await;
+
+        match result {
+            Ok(()) => inserted += 1,
+            Err(error)
+                if matches!(
+                    error.downcast_ref::<InsertError>(),
+                    Some(InsertError::NewerEntryExists)
+                ) =>
+            {
+                skipped += 1
+            }
+            Err(error) => return Err(invalid_archive(format!("{error:#}"))),
+        }
+    }
+
+    Ok((inserted, skipped))
+}
@@ -0,0 +1,274 @@
+use bao_tree::{
+    BaoTree, TreeNode,
+    io::{BaoContentItem, fsm::Outboard},
+};
+use bytes::Bytes;
+use iroh_blobs::{
+    BlobFormat, Hash, HashAndFormat, Tag, TempTag,
+    store::{
+        BaoBatchWriter, BaoBlobSize, ConsistencyCheckProgress, DbIter, EntryStatus, ExportMode,
+        ExportProgressCb, GcConfig, ImportMode, ImportProgress, Map, MapEntry, MapEntryMut, MapMut,
+        ReadableStore, Store, fs,
+    },
+    util::progress::{BoxedProgressSender, IdGenerator, ProgressSender},
+};
+use iroh_io::AsyncSliceReader;
+use std::{collections::BTreeSet, future::Future, io, path::PathBuf};
+use tokio_stream::Stream;
+
+use crate::encrypted_blobs::{EncryptedEntry, EncryptedStore};
+
+/// Blob store of a node, encrypted if the node was opened with encryption
+#[derive(Debug, Clone)]
+pub enum BlobStore {
+    Plain(fs::Store),
+    Encrypted(EncryptedStore),
+}
+
+/// Entries, readers and writers of either store
+#[derive(Debug, Clone)]
+pub enum Either<P, E> {
+    Plain(P),
+    Encrypted(E),
+}
+
+macro_rules! either {
+    ($value:expr, $inner:ident => $body:expr) => {
+        match $value {
+            Either::Plain($inner) => $body,
+            Either::Encrypted($inner) => $body,
+        }
+    };
+}
+
+macro_rules! store {
+    ($value:expr, $inner:ident => $body:expr) => {
+        match $value {
+            BlobStore::Plain($inner) => $body,
+            BlobStore::Encrypted($inner) => $body,
+        }
+    };
+}
+
+impl<P: MapEntry, E: MapEntry> MapEntry for Either<P, E> {
+    fn hash(&self) -> Hash {
+        either!(self, entry => entry.hash())
+    }
+
+    fn size(&self) -> BaoBlobSize {
+        either!(self, entry => entry.size())
+    }
+
+    fn is_complete(&self) -> bool {
+        either!(self, entry => entry.is_complete())
+    }
+
+    async fn outboard(&self) -> io::Result<impl Outboard> {
+        Ok(match self {
+            Self::Plain(entry) => Either::Plain(entry.outboard().await?),
+            Self::Encrypted(entry) => Either::Encrypted(entry.outboard().await?),
+        })
+    }
+
+    async fn data_reader(&self) -> io::Result<impl AsyncSliceReader> {
+        Ok(match self {
+            Self::Plain(entry) => Either::Plain(entry.data_reader().await?),
+            Self::Encrypted(entry) => Either::Encrypted(entry.data_reader().await?),
+        })
+    }
+}
+
+impl<P: MapEntryMut, E: MapEntryMut> MapEntryMut for Either<P, E> {
+    async fn batch_writer(&self) -> io::Result<impl BaoBatchWriter> {
+        Ok(match self {
+            Self::Plain(entry) => Either::Plain(entry.batch_writer().await?),
+            Self::Encrypted(entry) => Either::Encrypted(entry.batch_writer().await?),
+        })
+    }
+}
+
+impl<P: Outboard, E: Outboard> Outboard for Either<P, E> {
+    fn root(&self) -> blake3::Hash {
+        either!(self, outboard => outboard.root())
+    }
+
+    fn tree(&self) -> BaoTree {
+        either!(self, outboard => outboard.tree())
+    }
+
+    async fn load(&mut self, node: TreeNode) -> io::Result<Option<(blake3::Hash, blake3::Hash)>> {
+        either!(self, outboard => outboard.load(node).await)
+    }
+}
+
+impl<P: AsyncSliceReader, E: AsyncSliceReader> AsyncSliceReader for Either<P, E> {
+    async fn read_at(&mut self, offset: u64, len: usize) -> io::Result<Bytes> {
+        either!(self, reader => reader.read_at(offset, len).await)
+    }
+
+    async fn size(&mut self) -> io::Result<u64> {
+        either!(self, reader => reader.size().await)
+    }
+}
+
+impl<P: BaoBatchWriter, E: BaoBatchWriter> BaoBatchWriter for Either<P, E> {
+    async fn write_batch(&mut self, size: u64, batch: Vec<BaoContentItem>) -> io::Result<()> {
+        either!(self, writer => writer.write_batch(size, batch).await)
+    }
+
+    async fn sync(&mut self) -> io::Result<()> {
+        either!(self, writer => writer.sync().await)
+    }
+}
+
+impl Map for BlobStore {
+    type Entry = Either<fs::Entry, EncryptedEntry>;
+
+    async fn get(&self, hash: &Hash) -> io::Result<Option<Self::Entry>> {
+        Ok(match self {
+            Self::Plain(store) => store.get(hash).await?.map(Either::Plain),
+            Self::Encrypted(store) => store.get(hash).await?.map(Either::Encrypted),
+        })
+    }
+}
+
+impl MapMut for BlobStore {
+    type EntryMut = Either<fs::Entry, EncryptedEntry>;
+
+    async fn get_mut(&self, hash: &Hash) -> io::Result<Option<Self::EntryMut>> {
+        Ok(match self {
+            Self::Plain(store) => store.get_mut(hash).await?.map(Either::Plain),
+            Self::Encrypted(store) => store.get_mut(hash).await?.map(Either::Encrypted),
+        })
+    }
+
+    async fn get_or_create(&self, hash: Hash, size: u64) -> io::Result<Self::EntryMut> {
+        Ok(match self {
+            Self::Plain(store) => Either::Plain(store.get_or_create(hash, size).await?),
+            Self::Encrypted(store) => Either::Encrypted(store.get_or_create(hash, size).await?),
+        })
+    }
+
+    async fn entry_status(&self, hash: &Hash) -> io::Result<EntryStatus> {
+        store!(self, store => store.entry_status(hash).await)
+    }
+
+    fn entry_status_sync(&self, hash: &Hash) -> io::Result<EntryStatus> {
+        store!(self, store => store.entry_status_sync(hash))
+    }
+
+    async fn insert_complete(&self, entry: Self::EntryMut) -> io::Result<()> {
+        match (self, entry) {
+            (Self::Plain(store), Either::Plain(entry)) => store.insert_complete(entry).await,
+            (Self::Encrypted(store), Either::Encrypted(entry)) => {
+                store.insert_complete(entry).await
+            }
+            _ => Err(io::Error::other("Entry belongs to another store")),
+        }
+    }
+}
+
+impl ReadableStore for BlobStore {
+    async fn blobs(&self) -> io::Result<DbIter<Hash>> {
+        store!(self, store => store.blobs().await)
+    }
+
+    async fn tags(
+        &self,
+        from: Option<Tag>,
+        to: Option<Tag>,
+    ) -> io::Result<DbIter<(Tag, HashAndFormat)>> {
+        store!(self, store => store.tags(from, to).await)
+    }
+
+    fn temp_tags(&self) -> Box<dyn Iterator<Item = HashAndFormat> + Send + Sync + 'static> {
+        store!(self, store => store.temp_tags())
+    }
+
+    async fn consistency_check(
+        &self,
+        repair: bool,
+        tx: BoxedProgressSender<ConsistencyCheckProgress>,
+    ) -> io::Result<()> {
+        store!(self, store => store.consistency_check(repair, tx).await)
+    }
+
+    async fn partial_blobs(&self) -> io::Result<DbIter<Hash>> {
+        store!(self, store => store.partial_blobs().await)
+    }
+
+    async fn export(
+        &self,
+        hash: Hash,
+        target: PathBuf,
+        mode: ExportMode,
+        progress: ExportProgressCb,
+    ) -> io::Result<()> {
+        store!(self, store => store.export(hash, target, mode, progress).await)
+    }
+}
+
+impl Store for BlobStore {
+    async fn import_file(
+        &self,
+        data: PathBuf,
+        mode: ImportMode,
+        format: BlobFormat,
+        progress: impl ProgressSender<Msg = ImportProgress> + IdGenerator,
+    ) -> io::Result<(TempTag, u64)> {
+        store!(self, store => store.import_file(data, mode, format, progress).await)
+    }
+
+    async fn import_bytes(&self, bytes: Bytes, format: BlobFormat) -> io::Result<TempTag> {
+        store!(self, store => store.import_bytes(bytes, format).await)
+    }
+
+    async fn import_stream(
+        &self,
+        data: impl Stream<Item = io::Result<Bytes>> + Send + Unpin + 'static,
+        format: BlobFormat,
+        progress: impl ProgressSender<Msg = ImportProgress> + IdGenerator,
+    ) -> io::Result<(TempTag, u64)> {
+        store!(self, store => store.import_stream(data, format, progress).await)
+    }
+
+    async fn set_tag(&self, name: Tag, hash: HashAndFormat) -> io::Result<()> {
+        store!(self, store => store.set_tag(name, hash).await)
+    }
+
+    async fn rename_tag(&self, from: Tag, to: Tag) -> io::Result<()> {
+        store!(self, store => store.rename_tag(from, to).await)
+    }
+
+    async fn delete_tags(&self, from: Option<Tag>, to: Option<Tag>) -> io::Result<()> {
+        store!(self, store => store.delete_tags(from, to).await)
+    }
+
+    async fn create_tag(&self, hash: HashAndFormat) -> io::Result<Tag> {
+        store!(self, store => store.create_tag(hash).await)
+    }
+
+    fn temp_tag(&self, value: HashAndFormat) -> TempTag {
+        store!(self, store => store.temp_tag(value))
+    }
+
+    async fn gc_run<G, Gut>(&self, config: GcConfig, protected_cb: G)
+    where
+        G: Fn() -> Gut,
+        Gut: Future<Output = BTreeSet<Hash>> + Send,
+    {
+        store!(self, store => store.gc_run(config, protected_cb).await)
+    }
+
+    async fn delete(&self, hashes: Vec<Hash>) -> io::Result<()> {
+        store!(self, store => store.delete(hashes).await)
+    }
+
+    async fn shutdown(&self) {
+        store!(self, store => store.shutdown().await)
+    }
+
+    async fn sync(&self) -> io::Result<()> {
+        store!(self, store => store.sync().await)
+    }
+}
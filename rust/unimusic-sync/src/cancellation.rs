@@ -0,0 +1,71 @@
+use std::{future::Future, time::Duration};
+use tokio::sync::watch;
+
+use crate::errors::{Result, SharedError};
+
+/// Handle allowing to cancel long running operations, such as sync or import
+#[cfg_attr(feature = "default", derive(uniffi::Object))]
+#[derive(Debug)]
+pub struct CancellationHandle {
+    cancelled: watch::Sender<bool>,
+}
+
+impl Default for CancellationHandle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg_attr(feature = "default", uniffi::export)]
+impl CancellationHandle {
+    #[cfg_attr(feature = "default", uniffi::constructor)]
+    pub fn new() -> Self {
+        Self {
+            cancelled: watch::Sender::new(false),
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.send_replace(true);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        *self.cancelled.borrow()
+    }
+}
+
+impl CancellationHandle {
+    /// Resolves once the handle gets cancelled
+    pub async fn cancelled(&self) {
+        let mut receiver = self.cancelled.subscribe();
+        // Sender lives as long as self, so this can't fail
+        let _ = receiver.wait_for(|cancelled| *cancelled).await;
+    }
+}
+
+/// Runs the future until it finishes, the handle gets cancelled or the timeout passes
+pub async fn run_cancellable<T>(
+    cancellation: Option<&CancellationHandle>,
+    timeout_ms: Option<u64>,
+    future: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    let cancelled = async {
+        match cancellation {
+            Some(cancellation) => cancellation.cancelled().await,
+            None => std::future::pending().await,
+        }
+    };
+
+    let timed_out = async {
+        match timeout_ms {
+            Some(timeout_ms) => tokio::time::sleep(Duration::from_millis(timeout_ms)).await,
+            None => std::future::pending().await,
+        }
+    };
+
+    tokio::select! {
+        result = future => result,
+        _ = cancelled => Err(SharedError::Cancelled),
+        _ = timed_out => Err(SharedError::TimedOut(timeout_ms.unwrap_or_default())),
+    }
+}
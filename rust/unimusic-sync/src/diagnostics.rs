@@ -0,0 +1,236 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use iroh::{
+    Endpoint,
+    endpoint::{ConnectionType, DirectAddrType, RemoteInfo},
+    net_report::Report,
+};
+use serde::Serialize;
+
+use crate::{reconnect::PeerConnectionType, types::UNodeId};
+
+/// How an address of this node was found
+#[cfg_attr(feature = "default", derive(uniffi::Enum))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AddressKind {
+    /// Address of a local network interface
+    Local,
+    /// Public address reported by a STUN server
+    Stun,
+    /// Address mapped on the router through UPnP, PCP or NAT-PMP
+    Portmapped,
+    Unknown,
+}
+
+impl From<DirectAddrType> for AddressKind {
+    fn from(value: DirectAddrType) -> Self {
+        match value {
+            DirectAddrType::Local => Self::Local,
+            DirectAddrType::Stun | DirectAddrType::Stun4LocalPort => Self::Stun,
+            DirectAddrType::Portmapped => Self::Portmapped,
+            DirectAddrType::Unknown => Self::Unknown,
+        }
+    }
+}
+
+#[cfg_attr(feature = "default", derive(uniffi::Record))]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct NodeAddress {
+    pub addr: String,
+    pub kind: AddressKind,
+}
+
+/// What the last network report found out about the NAT in front of this node
+#[cfg_attr(feature = "default", derive(uniffi::Record))]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct NatDiagnostics {
+    /// Whether UDP got through to a STUN server
+    pub udp: bool,
+    pub ipv4: bool,
+    pub ipv6: bool,
+    pub public_ipv4: Option<String>,
+    pub public_ipv6: Option<String>,
+    /// Whether the public address depends on the destination. Direct connections rarely
+    /// work through such NATs, traffic goes through relays instead.
+    pub mapping_varies_by_destination: Option<bool>,
+    /// Whether devices on the LAN can reach each other through the public address
+    pub hair_pinning: Option<bool>,
+    pub captive_portal: Option<bool>,
+    /// Port mapping protocols offered by the router, `None` until they are probed
+    pub upnp: Option<bool>,
+    pub pcp: Option<bool>,
+    pub nat_pmp: Option<bool>,
+}
+
+impl From<&Report> for NatDiagnostics {
+    fn from(report: &Report) -> Self {
+        let portmap = report.portmap_probe.as_ref();
+        Self {
+            udp: report.udp,
+            ipv4: report.ipv4,
+            ipv6: report.ipv6,
+            public_ipv4: report.global_v4.map(|addr| addr.to_string()),
+            public_ipv6: report.global_v6.map(|addr| addr.to_string()),
+            mapping_varies_by_destination: report.mapping_varies_by_dest_ip,
+            hair_pinning: report.hair_pinning,
+            captive_portal: report.captive_portal,
+            upnp: portmap.map(|probe| probe.upnp),
+            pcp: portmap.map(|probe| probe.pcp),
+            nat_pmp: portmap.map(|probe| probe.nat_pmp),
+        }
+    }
+}
+
+#[cfg_attr(feature = "default", derive(uniffi::Record))]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PeerDiagnostics {
+    pub node_id: UNodeId,
+    pub connection_type: PeerConnectionType,
+    /// Direct address or relay URL the traffic goes through
+    pub path: Option<String>,
+    pub rtt_ms: Option<u64>,
+    /// Time since anything was sent to or received from the peer
+    pub last_used_ms: Option<u64>,
+}
+
+/// State of the network of the node, meant to be attached to bug reports
+#[cfg_attr(feature = "default", derive(uniffi::Record))]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Diagnostics {
+    pub node_id: UNodeId,
+    pub version: String,
+    /// Milliseconds since the UNIX epoch
+    pub created_at_ms: u64,
+    pub addresses: Vec<NodeAddress>,
+    pub home_relay: Option<String>,
+    /// `None` until the first network report finishes
+    pub nat: Option<NatDiagnostics>,
+    pub peers: Vec<PeerDiagnostics>,
+    /// Traffic of the whole endpoint since it started
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+}
+
+impl Diagnostics {
+    pub(crate) fn collect(endpoint: &Endpoint) -> Self {
+        let addresses = endpoint
+            .direct_addresses()
+            .get()
+            .ok()
+            .flatten()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|addr| NodeAddress {
+                addr: addr.addr.to_string(),
+                kind: addr.typ.into(),
+            })
+            .collect();
+
+        let mut peers: Vec<_> = endpoint
+            .remote_info_iter()
+            .map(PeerDiagnostics::from)
+            .collect();
+        peers.sort_by_key(|peer| peer.node_id.to_string());
+
+        let magicsock = &endpoint.metrics().magicsock;
+        let bytes_received = magicsock.recv_data_ipv4.get()
+            + magicsock.recv_data_ipv6.get()
+            + magicsock.recv_data_relay.get();
+
+        Self {
+            node_id: endpoint.node_id().into(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            created_at_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+            addresses,
+            home_relay: endpoint
+                .home_relay()
+                .get()
+                .ok()
+                .flatten()
+                .map(|url| url.to_string()),
+            nat: endpoint
+                .net_report()
+                .get()
+                .ok()
+                .flatten()
+                .map(|report| report.as_ref().into()),
+            peers,
+            bytes_sent: magicsock.send_data.get(),
+            bytes_received,
+        }
+    }
+}
+
+impl From<RemoteInfo> for PeerDiagnostics {
+    fn from(info: RemoteInfo) -> Self {
+        let path = match &info.conn_type {
+            ConnectionType::Direct(addr) | ConnectionType::Mixed(addr, _) => Some(addr.to_string()),
+            ConnectionType::Relay(url) => Some(url.to_string()),
+            ConnectionType::None => None,
+        };
+        Self {
+            node_id: info.node_id.into(),
+            connection_type: (&info.conn_type).into(),
+            path,
+            rtt_ms: info.latency.map(|latency| latency.as_millis() as u64),
+            last_used_ms: info.last_used.map(|last| last.as_millis() as u64),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::AddressKind;
+    use crate::{reconnect::PeerConnectionType, testing::TestNetwork};
+
+    type Result<T> = crate::testing::Result<T>;
+
+    #[tokio::test]
+    async fn test_diagnostics() -> Result<()> {
+        let network = TestNetwork::new(2).await?;
+        let (owner, peer) = (network.manager(0), network.manager(1));
+        let namespace = owner.create_namespace().await?;
+        owner
+            .write_file(namespace, "track.flac".into(), vec![7; 64 * 1024])
+            .await?;
+        peer.import(owner.share(namespace).await?).await?;
+        assert_eq!(
+            peer.read_file(namespace, "track.flac").await?.len(),
+            64 * 1024
+        );
+
+        let report = owner.diagnostics().await;
+        assert_eq!(report.node_id, owner.get_node_id().await);
+        assert_eq!(report.home_relay, None);
+        assert!(
+            report
+                .addresses
+                .iter()
+                .any(|addr| addr.kind == AddressKind::Local)
+        );
+        assert!(report.bytes_sent > 0 && report.bytes_received > 0);
+
+        let peer_id = peer.get_node_id().await;
+        let peer_report = report
+            .peers
+            .iter()
+            .find(|report| report.node_id == peer_id)
+            .expect("peer is reported");
+        assert_eq!(peer_report.connection_type, PeerConnectionType::Direct);
+        assert!(peer_report.rtt_ms.is_some());
+
+        let json: serde_json::Value = serde_json::from_str(&owner.diagnostics_json().await?)?;
+        assert_eq!(json["node_id"], report.node_id.to_string());
+        assert_eq!(
+            json["peers"].as_array().map(Vec::len),
+            Some(report.peers.len())
+        );
+
+        network.shutdown().await?;
+        Ok(())
+    }
+}
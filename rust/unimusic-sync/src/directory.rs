@@ -0,0 +1,552 @@
+use glob::{MatchOptions, Pattern};
+use iroh_blobs::Hash;
+use iroh_docs::store::Query;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    io::Read,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::UNIX_EPOCH,
+};
+use tokio::{fs, sync::OwnedMutexGuard};
+use walkdir::WalkDir;
+
+use crate::{
+    IrohManager, encryption,
+    errors::{Result, SharedError},
+    split,
+    types::UNamespaceId,
+};
+
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: false,
+    require_literal_leading_dot: false,
+};
+
+/// What was imported from a file last time
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct IndexedFile {
+    size: u64,
+    mtime_ms: u64,
+    hash: String,
+}
+
+/// Local index of files imported from one directory into a namespace, keyed by entry key
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(crate) struct DirectoryIndex {
+    files: HashMap<String, IndexedFile>,
+}
+
+/// Index held exclusively until it's dropped, so imports and mirrors of the same
+/// directory don't overwrite changes of each other
+#[derive(Debug)]
+pub(crate) struct LockedIndex {
+    pub(crate) index: DirectoryIndex,
+    path: PathBuf,
+    _guard: OwnedMutexGuard<()>,
+}
+
+impl LockedIndex {
+    pub(crate) async fn save(&self, manager: &IrohManager) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        let serialized =
+            serde_json::to_vec(&self.index).map_err(|e| SharedError::Serde(e.to_string()))?;
+        encryption::write(&self.path, &serialized, manager.encryption.as_ref()).await
+    }
+}
+
+/// Locks of the indexes, by their path
+#[derive(Debug, Clone, Default)]
+pub(crate) struct IndexLocks(Arc<Mutex<HashMap<PathBuf, Arc<tokio::sync::Mutex<()>>>>>);
+
+impl IndexLocks {
+    async fn lock(&self, path: &Path) -> OwnedMutexGuard<()> {
+        let lock = self
+            .0
+            .lock()
+            .unwrap()
+            .entry(path.to_path_buf())
+            .or_default()
+            .clone();
+        lock.lock_owned().await
+    }
+}
+
+impl DirectoryIndex {
+    pub(crate) fn hash(&self, key: &str) -> Option<&str> {
+        self.files.get(key).map(|file| file.hash.as_str())
+    }
+
+    /// Remembers a file written into the directory, so the next import doesn't read it
+    pub(crate) fn record(&mut self, key: String, metadata: &std::fs::Metadata, hash: Hash) {
+        self.files.insert(
+            key,
+            IndexedFile {
+                size: metadata.len(),
+                mtime_ms: mtime_ms(metadata),
+                hash: hash.to_string(),
+            },
+        );
+    }
+
+    /// Forgets a file removed from the directory, so the next import doesn't tombstone it
+    pub(crate) fn forget(&mut self, key: &str) {
+        self.files.remove(key);
+    }
+
+    /// Keys under the prefix whose files weren't found. Keys under paths which couldn't be read
+    /// are left out, their files might still exist.
+    fn missing(
+        &self,
+        key_prefix: &str,
+        seen_keys: &HashSet<String>,
+        unreadable: &[String],
+    ) -> Vec<String> {
+        let prefix = match key_prefix.trim_end_matches('/') {
+            "" => String::new(),
+            prefix => format!("{prefix}/"),
+        };
+        let is_unreadable = |key: &str| {
+            unreadable.iter().any(|unreadable| {
+                let unreadable = unreadable.trim_end_matches('/');
+                unreadable.is_empty()
+                    || key
+                        .strip_prefix(unreadable)
+                        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+            })
+        };
+
+        self.files
+            .keys()
+            .filter(|key| {
+                key.starts_with(&prefix) && !seen_keys.contains(*key) && !is_unreadable(key)
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg_attr(feature = "default", derive(uniffi::Record))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportFailure {
+    pub path: String,
+    pub error: String,
+}
+
+#[cfg_attr(feature = "default", derive(uniffi::Record))]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DirectoryImportReport {
+    /// Files written for the first time
+    pub added: u64,
+    /// Files written again, because their content changed
+    pub updated: u64,
+    pub unchanged: u64,
+    /// Files and directories matching one of the ignore globs
+    pub ignored: u64,
+    /// Keys deleted, because their file disappeared
+    pub tombstoned: u64,
+    pub failures: Vec<ImportFailure>,
+}
+
+#[derive(Debug)]
+struct FoundFile {
+    key: String,
+    path: PathBuf,
+    size: u64,
+    mtime_ms: u64,
+}
+
+fn namespace_indexes(manager: &IrohManager, namespace: &UNamespaceId) -> PathBuf {
+    manager.path.join("imports").join(namespace.to_string())
+}
+
+/// Each directory gets its own index, named by the hash of its canonical path
+fn index_path(manager: &IrohManager, namespace: &UNamespaceId, dir: &Path) -> PathBuf {
+    let dir_hash = blake3::hash(dir.as_os_str().as_encoded_bytes()).to_hex();
+    namespace_indexes(manager, namespace).join(format!("{}.json", &dir_hash[..32]))
+}
+
+async fn read_index(manager: &IrohManager, path: &Path) -> Result<DirectoryIndex> {
+    match encryption::read(path, manager.encryption.as_ref()).await? {
+        Some(file) => serde_json::from_slice(&file).map_err(|e| SharedError::Serde(e.to_string())),
+        None => Ok(DirectoryIndex::default()),
+    }
+}
+
+/// Reads the index of the directory without locking it
+pub(crate) async fn load_index(
+    manager: &IrohManager,
+    namespace: &UNamespaceId,
+    dir: &Path,
+) -> Result<DirectoryIndex> {
+    read_index(manager, &index_path(manager, namespace, dir)).await
+}
+
+/// Reads the index of the directory, which stays locked until the returned index is dropped
+pub(crate) async fn lock_index(
+    manager: &IrohManager,
+    namespace: &UNamespaceId,
+    dir: &Path,
+) -> Result<LockedIndex> {
+    let path = index_path(manager, namespace, dir);
+    let guard = manager.index_locks.lock(&path).await;
+    Ok(LockedIndex {
+        index: read_index(manager, &path).await?,
+        path,
+        _guard: guard,
+    })
+}
+
+/// Removes import indexes of the namespace
+pub(crate) async fn forget_namespace(manager: &IrohManager, namespace: &UNamespaceId) {
+    let _ = fs::remove_dir_all(namespace_indexes(manager, namespace)).await;
+}
+
+/// Joins the prefix and a relative path into a key, always separated by `/`
+pub(crate) fn make_key(key_prefix: &str, relative: &Path) -> String {
+    let relative = relative
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/");
+
+    match key_prefix.trim_end_matches('/') {
+        "" => relative,
+        prefix => format!("{prefix}/{relative}"),
+    }
+}
+
+pub(crate) fn is_ignored(patterns: &[Pattern], relative: &Path) -> bool {
+    let file_name = relative.file_name().map(Path::new);
+    patterns.iter().any(|pattern| {
+        pattern.matches_path_with(relative, MATCH_OPTIONS)
+            || file_name.is_some_and(|name| pattern.matches_path_with(name, MATCH_OPTIONS))
+    })
+}
+
+/// Walks the directory, returning files which aren't ignored and amount of ignored ones.
+/// Keys of paths which couldn't be read are added to `unreadable`.
+fn walk(
+    dir: &Path,
+    key_prefix: &str,
+    patterns: &[Pattern],
+    failures: &mut Vec<ImportFailure>,
+    unreadable: &mut Vec<String>,
+) -> (Vec<FoundFile>, u64) {
+    let mut files = Vec::new();
+    let mut ignored = 0;
+
+    let walker = WalkDir::new(dir).follow_links(false).into_iter();
+    let walker = walker.filter_entry(|entry| {
+        let Ok(relative) = entry.path().strip_prefix(dir) else {
+            return true;
+        };
+        let keep = relative.as_os_str().is_empty() || !is_ignored(patterns, relative);
+        if !keep {
+            ignored += 1;
+        }
+        keep
+    });
+
+    // Entries are collected first, the filter borrows the counter
+    let entries: Vec<_> = walker.collect();
+    for entry in entries {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(error) => {
+                // Without a path nothing under the directory is known to be missing
+                let relative = error.path().and_then(|path| path.strip_prefix(dir).ok());
+                unreadable.push(make_key(key_prefix, relative.unwrap_or(Path::new(""))));
+                failures.push(ImportFailure {
+                    path: error
+                        .path()
+                        .map(|path| path.display().to_string())
+                        .unwrap_or_default(),
+                    error: error.to_string(),
+                });
+                continue;
+            }
+        };
+        if !entry.file_type().is_file() {
+            continue;
+        }
+
+        let metadata = match entry.metadata() {
+            Ok(metadata) => metadata,
+            Err(error) => {
+                let relative = entry.path().strip_prefix(dir).unwrap_or(entry.path());
+                unreadable.push(make_key(key_prefix, relative));
+                failures.push(ImportFailure {
+                    path: entry.path().display().to_string(),
+                    error: error.to_string(),
+                });
+                continue;
+            }
+        };
+        let mtime_ms = mtime_ms(&metadata);
+
+        let relative = entry.path().strip_prefix(dir).unwrap_or(entry.path());
+        files.push(FoundFile {
+            key: make_key(key_prefix, relative),
+            path: entry.path().to_path_buf(),
+            size: metadata.len(),
+            mtime_ms,
+        });
+    }
+
+    (files, ignored)
+}
+
+fn mtime_ms(metadata: &std::fs::Metadata) -> u64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |duration| duration.as_millis() as u64)
+}
+
+pub(crate) fn compile_globs(globs: &[String]) -> Result<Vec<Pattern>> {
+    globs
+        .iter()
+        .map(|glob| {
+            Pattern::new(glob)
+                .map_err(|error| SharedError::InvalidInput(format!("Invalid glob {glob}: {error}")))
+        })
+        .collect()
+}
+
+pub(crate) fn hash_file(path: &Path) -> std::io::Result<Hash> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = blake3::Hasher::new();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hasher.finalize().into())
+}
+
+pub(crate) async fn import_directory(
+    manager: &IrohManager,
+    namespace: UNamespaceId,
+    dir: &Path,
+    key_prefix: &str,
+    ignore_globs: &[String],
+    tombstone_missing: bool,
+) -> Result<DirectoryImportReport> {
+    let replica = manager
+        .docs
+        .client()
+        .open(namespace.into())
+        .await?
+        .ok_or(SharedError::ReplicaMissing(namespace))?;
+
+    let patterns = compile_globs(ignore_globs)?;
+
+    // Importing files requires absolute paths
+    let dir = fs::canonicalize(dir).await?;
+    if !fs::metadata(&dir).await?.is_dir() {
+        return Err(SharedError::InvalidInput(format!(
+            "{} is not a directory",
+            dir.display()
+        )));
+    }
+
+    let mut report = DirectoryImportReport::default();
+    let (files, ignored, unreadable) = {
+        let dir = dir.clone();
+        let key_prefix = key_prefix.to_string();
+        let (files, ignored, failures, unreadable) = tokio::task::spawn_blocking(move || {
+            let (mut failures, mut unreadable) = (Vec::new(), Vec::new());
+            let (files, ignored) =
+                walk(&dir, &key_prefix, &patterns, &mut failures, &mut unreadable);
+            (files, ignored, failures, unreadable)
+        })
+        .await
+        .map_err(|error| SharedError::Iroh(error.to_string()))?;
+        report.failures = failures;
+        (files, ignored, unreadable)
+    };
+    report.ignored = ignored;
+
+    let mut locked = lock_index(manager, &namespace, &dir).await?;
+    let index = &mut locked.index;
+
+    let mut seen_keys = Vec::with_capacity(files.len());
+    for file in files {
+        seen_keys.push(file.key.clone());
+
+        let indexed = index.files.get(&file.key);
+        if indexed
+            .is_some_and(|indexed| indexed.size == file.size && indexed.mtime_ms == file.mtime_ms)
+        {
+            report.unchanged += 1;
+            continue;
+        }
+        let was_indexed = indexed.is_some();
+
+        let result: Result<bool> = async {
+            let path = file.path.clone();
+            let hash = tokio::task::spawn_blocking(move || hash_file(&path))
+                .await
+                .map_err(|error| SharedError::Iroh(error.to_string()))??;
+
+            // Touched files and files written by a previous import without an index are kept
+            let current = replica
+                .get_one(Query::single_latest_per_key().key_exact(&file.key))
+                .await?;
+            let current_hash = match current {
+                Some(entry) => {
+                    let (hash, _) =
+                        split::file_info(manager, entry.content_hash(), entry.content_len()).await;
+                    Some(hash)
+                }
+                None => None,
+            };
+            if current_hash == Some(hash) {
+                index.files.insert(
+                    file.key.clone(),
+                    IndexedFile {
+                        size: file.size,
+                        mtime_ms: file.mtime_ms,
+                        hash: hash.to_string(),
+                    },
+                );
+                return Ok(false);
+            }
+
+            let hash = manager
+                .write_file_from_path(
+                    namespace,
+                    file.key.clone(),
+                    file.path.to_string_lossy().into(),
+                )
+                .await?;
+
+            index.files.insert(
+                file.key.clone(),
+                IndexedFile {
+                    size: file.size,
+                    mtime_ms: file.mtime_ms,
+                    hash: hash.to_string(),
+                },
+            );
+
+            Ok(true)
+        }
+        .await;
+
+        match result {
+            Ok(true) if was_indexed => report.updated += 1,
+            Ok(true) => report.added += 1,
+            Ok(false) => report.unchanged += 1,
+            Err(error) => {
+                warn!("[import] failed to import {}: {error}", file.path.display());
+                report.failures.push(ImportFailure {
+                    path: file.path.display().to_string(),
+                    error: error.to_string(),
+                });
+            }
+        }
+    }
+
+    if tombstone_missing {
+        let seen_keys: HashSet<_> = seen_keys.into_iter().collect();
+        let missing = index.missing(key_prefix, &seen_keys, &unreadable);
+
+        for key in missing {
+            match manager.delete_file(namespace, key.clone()).await {
+                Ok(_) => {
+                    index.files.remove(&key);
+                    report.tombstoned += 1;
+                }
+                Err(error) => report.failures.push(ImportFailure {
+                    path: key,
+                    error: error.to_string(),
+                }),
+            }
+        }
+    }
+
+    locked.save(manager).await?;
+    info!(
+        "[import] imported {} into namespace {namespace}: {} added, {} updated, {} unchanged, {} tombstoned, {} failed",
+        dir.display(),
+        report.added,
+        report.updated,
+        report.unchanged,
+        report.tombstoned,
+        report.failures.len()
+    );
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{DirectoryIndex, IndexedFile};
+    use std::collections::HashSet;
+
+    fn index(keys: &[&str]) -> DirectoryIndex {
+        let mut index = DirectoryIndex::default();
+        for key in keys {
+            index.files.insert(
+                key.to_string(),
+                IndexedFile {
+                    size: 0,
+                    mtime_ms: 0,
+                    hash: String::new(),
+                },
+            );
+        }
+        index
+    }
+
+    fn missing(index: &DirectoryIndex, prefix: &str, unreadable: &[&str]) -> Vec<String> {
+        let seen_keys = HashSet::from(["library/kept.flac".to_string()]);
+        let unreadable: Vec<_> = unreadable.iter().map(|key| key.to_string()).collect();
+        let mut missing = index.missing(prefix, &seen_keys, &unreadable);
+        missing.sort();
+        missing
+    }
+
+    #[test]
+    fn test_missing_keys() {
+        let index = index(&[
+            "library/kept.flac",
+            "library/gone.flac",
+            "library/album/track.flac",
+            "library/albums.flac",
+            "other/track.flac",
+        ]);
+
+        assert_eq!(
+            missing(&index, "library", &[]),
+            [
+                "library/album/track.flac",
+                "library/albums.flac",
+                "library/gone.flac"
+            ]
+        );
+        // Files under a directory which couldn't be read aren't known to be gone
+        assert_eq!(
+            missing(&index, "library/", &["library/album"]),
+            ["library/albums.flac", "library/gone.flac"]
+        );
+        assert_eq!(
+            missing(&index, "library", &["library/gone.flac"]),
+            ["library/album/track.flac", "library/albums.flac"]
+        );
+        // Neither are any files if the directory itself couldn't be read
+        assert!(missing(&index, "library", &["library/"]).is_empty());
+        assert!(missing(&index, "", &[""]).is_empty());
+    }
+}
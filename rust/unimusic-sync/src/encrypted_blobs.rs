@@ -0,0 +1,1223 @@
+use bao_tree::{
+    BaoTree,
+    io::{BaoContentItem, fsm::Outboard, outboard::PreOrderOutboard, sync::CreateOutboard},
+};
+use bytes::Bytes;
+use iroh_blobs::{
+    BlobFormat, Hash, HashAndFormat, IROH_BLOCK_SIZE, Tag, TempTag,
+    hashseq::HashSeq,
+    store::{
+        BaoBatchWriter, BaoBlobSize, ConsistencyCheckProgress, DbIter, EntryStatus, ExportMode,
+        ExportProgressCb, GcConfig, ImportMode, ImportProgress, Map, MapEntry, MapEntryMut, MapMut,
+        ReadableStore, Store,
+    },
+    util::{
+        TagCounter, TagDrop,
+        progress::{BoxedProgressSender, IdGenerator, IgnoreProgressSender, ProgressSender},
+    },
+};
+use iroh_io::{AsyncSliceReader, AsyncSliceReaderExt};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, btree_map},
+    fmt::Display,
+    fs::{self, File, OpenOptions},
+    future::Future,
+    io::{self, BufReader, ErrorKind, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock},
+    time::SystemTime,
+};
+use tokio_stream::{Stream, StreamExt};
+
+use crate::encryption::{self, Encryption};
+
+/// Directory of the store, next to the files of the node
+pub const BLOBS_DIR: &str = "blobs-encrypted";
+/// Plaintext of data files is split into blocks of this size, each encrypted on its own,
+/// so any range of a blob can be read or written without touching the rest of it
+const DATA_BLOCK_SIZE: usize = 16 * 1024;
+/// Outboards are written one hash pair at a time while downloading, so their blocks are smaller
+const OUTBOARD_BLOCK_SIZE: usize = 1024;
+/// Kind of file, index and length of the block, encrypted along with its data
+const BLOCK_HEADER_LEN: usize = 13;
+/// Chunks read from files being imported
+const IMPORT_CHUNK_SIZE: usize = 1024 * 1024;
+const TEMP_DIR: &str = "temp";
+const TAGS_FILE: &str = "tags";
+const META_FILE: &str = "meta";
+
+/// Files of the blob store of nodes opened without encryption
+const PLAIN_STORE_FILES: &[&str] = &["blobs.db", "data", "temp"];
+
+/// Files kept for each blob
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FileKind {
+    Data,
+    Outboard,
+}
+
+impl FileKind {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Data => "data",
+            Self::Outboard => "outboard",
+        }
+    }
+
+    fn block_size(self) -> usize {
+        match self {
+            Self::Data => DATA_BLOCK_SIZE,
+            Self::Outboard => OUTBOARD_BLOCK_SIZE,
+        }
+    }
+
+    fn id(self) -> u8 {
+        match self {
+            Self::Data => 0,
+            Self::Outboard => 1,
+        }
+    }
+}
+
+fn invalid_data(error: impl Display) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, error.to_string())
+}
+
+/// Reads and decrypts a small file, `None` if it doesn't exist
+fn read_encrypted(path: &Path, encryption: &Encryption) -> io::Result<Option<Vec<u8>>> {
+    match fs::read(path) {
+        Ok(data) => encryption.decrypt(&data).map(Some).map_err(invalid_data),
+        Err(error) if error.kind() == ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(error),
+    }
+}
+
+/// Encrypts and replaces a small file, the same way as [`encryption::write`]
+fn write_encrypted(path: &Path, data: &[u8], encryption: &Encryption) -> io::Result<()> {
+    let data = encryption.encrypt(data).map_err(invalid_data)?;
+    let temp_path = path.with_extension("tmp");
+    let mut file = File::create(&temp_path)?;
+    file.write_all(&data)?;
+    file.sync_all()?;
+    drop(file);
+    fs::rename(temp_path, path)
+}
+
+/// File encrypted block by block. All blocks take the same space, whatever their length,
+/// and blocks which were skipped over read as zeros.
+struct BlockFile<'a> {
+    file: File,
+    kind: FileKind,
+    encryption: &'a Encryption,
+}
+
+impl<'a> BlockFile<'a> {
+    /// Opens the file, `None` if it doesn't exist
+    fn open(path: &Path, kind: FileKind, encryption: &'a Encryption) -> io::Result<Option<Self>> {
+        match File::open(path) {
+            Ok(file) => Ok(Some(Self {
+                file,
+                kind,
+                encryption,
+            })),
+            Err(error) if error.kind() == ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+
+    fn create(path: &Path, kind: FileKind, encryption: &'a Encryption) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        Ok(Self {
+            file,
+            kind,
+            encryption,
+        })
+    }
+
+    fn slot_len(&self) -> u64 {
+        (encryption::OVERHEAD + BLOCK_HEADER_LEN + self.kind.block_size()) as u64
+    }
+
+    fn blocks(&self) -> io::Result<u64> {
+        Ok(self.file.metadata()?.len() / self.slot_len())
+    }
+
+    /// Plaintext of the block, empty past the end of the file
+    fn read_block(&mut self, index: u64) -> io::Result<Vec<u8>> {
+        let block_size = self.kind.block_size();
+        let blocks = self.blocks()?;
+        if index >= blocks {
+            return Ok(Vec::new());
+        }
+
+        let mut slot = vec![0; self.slot_len() as usize];
+        self.file.seek(SeekFrom::Start(index * self.slot_len()))?;
+        self.file.read_exact(&mut slot)?;
+        if !encryption::is_encrypted(&slot) {
+            return Ok(vec![0; block_size]);
+        }
+
+        let mut block = self.encryption.decrypt(&slot).map_err(invalid_data)?;
+        if block.len() != BLOCK_HEADER_LEN + block_size
+            || block[0] != self.kind.id()
+            || block[1..9] != index.to_le_bytes()
+        {
+            return Err(invalid_data(format!(
+                "{} block {index} is corrupted",
+                self.kind.name()
+            )));
+        }
+        let len = u32::from_le_bytes(block[9..BLOCK_HEADER_LEN].try_into().unwrap()) as usize;
+        block.drain(..BLOCK_HEADER_LEN);
+        // Blocks followed by others were extended with zeros
+        block.truncate(if index + 1 < blocks { block_size } else { len });
+        Ok(block)
+    }
+
+    fn write_block(&mut self, index: u64, data: &[u8]) -> io::Result<()> {
+        let block_size = self.kind.block_size();
+        let mut block = Vec::with_capacity(BLOCK_HEADER_LEN + block_size);
+        block.push(self.kind.id());
+        block.extend_from_slice(&index.to_le_bytes());
+        block.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        block.extend_from_slice(data);
+        block.resize(BLOCK_HEADER_LEN + block_size, 0);
+
+        let slot = self.encryption.encrypt(&block).map_err(invalid_data)?;
+        self.file.seek(SeekFrom::Start(index * self.slot_len()))?;
+        self.file.write_all(&slot)
+    }
+
+    /// Length of the plaintext
+    fn len(&mut self) -> io::Result<u64> {
+        let blocks = self.blocks()?;
+        if blocks == 0 {
+            return Ok(0);
+        }
+        let last = self.read_block(blocks - 1)?;
+        Ok((blocks - 1) * self.kind.block_size() as u64 + last.len() as u64)
+    }
+
+    /// Reads up to `len` bytes, fewer at the end of the file
+    fn read_at(&mut self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        let block_size = self.kind.block_size() as u64;
+        let end = offset.saturating_add(len as u64);
+        let mut data = Vec::new();
+        let mut position = offset;
+        while position < end {
+            let block = self.read_block(position / block_size)?;
+            let start = (position % block_size) as usize;
+            if start >= block.len() {
+                break;
+            }
+            let until = block.len().min(start + (end - position) as usize);
+            data.extend_from_slice(&block[start..until]);
+            position += (until - start) as u64;
+        }
+        Ok(data)
+    }
+
+    fn write_at(&mut self, offset: u64, mut data: &[u8]) -> io::Result<()> {
+        let block_size = self.kind.block_size();
+        let mut position = offset;
+        while !data.is_empty() {
+            let index = position / block_size as u64;
+            let start = (position % block_size as u64) as usize;
+            let len = data.len().min(block_size - start);
+            let mut block = if len == block_size {
+                Vec::new()
+            } else {
+                self.read_block(index)?
+            };
+            if block.len() < start + len {
+                block.resize(start + len, 0);
+            }
+            block[start..start + len].copy_from_slice(&data[..len]);
+            self.write_block(index, &block)?;
+
+            data = &data[len..];
+            position += len as u64;
+        }
+        Ok(())
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        self.file.sync_data()
+    }
+}
+
+/// Reads the plaintext of a block file from the start
+struct BlockReader<'a> {
+    file: BlockFile<'a>,
+    index: u64,
+    block: Vec<u8>,
+    position: usize,
+}
+
+impl<'a> BlockReader<'a> {
+    fn new(file: BlockFile<'a>) -> Self {
+        Self {
+            file,
+            index: 0,
+            block: Vec::new(),
+            position: 0,
+        }
+    }
+}
+
+impl Read for BlockReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position == self.block.len() {
+            self.block = self.file.read_block(self.index)?;
+            self.index += 1;
+            self.position = 0;
+        }
+        let len = buf.len().min(self.block.len() - self.position);
+        buf[..len].copy_from_slice(&self.block[self.position..self.position + len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+struct EntryState {
+    size: u64,
+    /// Offset of the leaf which gave the size, leaves further in the blob give a more precise one
+    size_offset: u64,
+    complete: bool,
+}
+
+/// Encrypted metadata of a blob, whose directory is named after a keyed hash
+#[derive(Debug, Serialize, Deserialize)]
+struct Meta {
+    hash: Hash,
+    #[serde(flatten)]
+    state: EntryState,
+}
+
+/// Blob in an [`EncryptedStore`], complete or being downloaded
+#[derive(Debug, Clone)]
+pub struct EncryptedEntry(Arc<EntryInner>);
+
+#[derive(Debug)]
+struct EntryInner {
+    hash: Hash,
+    dir: PathBuf,
+    encryption: Encryption,
+    state: Mutex<EntryState>,
+    /// Held while files are written, so they are never read halfway through a write
+    files: RwLock<()>,
+}
+
+impl EncryptedEntry {
+    fn state(&self) -> EntryState {
+        *self.0.state.lock().unwrap()
+    }
+
+    fn path(&self, kind: FileKind) -> PathBuf {
+        self.0.dir.join(kind.name())
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let meta = Meta {
+            hash: self.0.hash,
+            state: self.state(),
+        };
+        let meta = serde_json::to_vec(&meta).map_err(invalid_data)?;
+        write_encrypted(&self.0.dir.join(META_FILE), &meta, &self.0.encryption)
+    }
+
+    fn read_at(&self, kind: FileKind, offset: u64, len: usize) -> io::Result<Bytes> {
+        let _guard = self.0.files.read().unwrap();
+        match BlockFile::open(&self.path(kind), kind, &self.0.encryption)? {
+            Some(mut file) => Ok(file.read_at(offset, len)?.into()),
+            None => Ok(Bytes::new()),
+        }
+    }
+
+    fn len(&self, kind: FileKind) -> io::Result<u64> {
+        let _guard = self.0.files.read().unwrap();
+        match BlockFile::open(&self.path(kind), kind, &self.0.encryption)? {
+            Some(mut file) => file.len(),
+            None => Ok(0),
+        }
+    }
+
+    fn write_batch(&self, size: u64, batch: &[BaoContentItem]) -> io::Result<()> {
+        let _guard = self.0.files.write().unwrap();
+        let encryption = &self.0.encryption;
+        let tree = BaoTree::new(size, IROH_BLOCK_SIZE);
+        let mut data = None;
+        let mut outboard = None;
+        for item in batch {
+            match item {
+                BaoContentItem::Parent(parent) => {
+                    let Some(offset) = tree.pre_order_offset(parent.node) else {
+                        continue;
+                    };
+                    let mut pair = [0; 64];
+                    pair[..32].copy_from_slice(parent.pair.0.as_bytes());
+                    pair[32..].copy_from_slice(parent.pair.1.as_bytes());
+                    if outboard.is_none() {
+                        let path = self.path(FileKind::Outboard);
+                        outboard = Some(BlockFile::create(&path, FileKind::Outboard, encryption)?);
+                    }
+                    outboard.as_mut().unwrap().write_at(offset * 64, &pair)?;
+                }
+                BaoContentItem::Leaf(leaf) => {
+                    {
+                        let mut state = self.0.state.lock().unwrap();
+                        if leaf.offset >= state.size_offset {
+                            state.size_offset = leaf.offset;
+                            state.size = size;
+                        }
+                    }
+                    if data.is_none() {
+                        let path = self.path(FileKind::Data);
+                        data = Some(BlockFile::create(&path, FileKind::Data, encryption)?);
+                    }
+                    data.as_mut().unwrap().write_at(leaf.offset, &leaf.data)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        {
+            let _guard = self.0.files.read().unwrap();
+            for kind in [FileKind::Data, FileKind::Outboard] {
+                if let Some(file) = BlockFile::open(&self.path(kind), kind, &self.0.encryption)? {
+                    file.sync()?;
+                }
+            }
+        }
+        self.save()
+    }
+}
+
+impl MapEntry for EncryptedEntry {
+    fn hash(&self) -> Hash {
+        self.0.hash
+    }
+
+    fn size(&self) -> BaoBlobSize {
+        let state = self.state();
+        BaoBlobSize::new(state.size, state.complete)
+    }
+
+    fn is_complete(&self) -> bool {
+        self.state().complete
+    }
+
+    async fn outboard(&self) -> io::Result<impl Outboard> {
+        Ok(PreOrderOutboard {
+            root: self.hash().into(),
+            tree: BaoTree::new(self.state().size, IROH_BLOCK_SIZE),
+            data: FileReader {
+                entry: self.clone(),
+                kind: FileKind::Outboard,
+            },
+        })
+    }
+
+    async fn data_reader(&self) -> io::Result<impl AsyncSliceReader> {
+        Ok(FileReader {
+            entry: self.clone(),
+            kind: FileKind::Data,
+        })
+    }
+}
+
+impl MapEntryMut for EncryptedEntry {
+    async fn batch_writer(&self) -> io::Result<impl BaoBatchWriter> {
+        Ok(BatchWriter(self.clone()))
+    }
+}
+
+/// Decrypts one of the files of a blob
+#[derive(Debug)]
+struct FileReader {
+    entry: EncryptedEntry,
+    kind: FileKind,
+}
+
+impl AsyncSliceReader for FileReader {
+    async fn read_at(&mut self, offset: u64, len: usize) -> io::Result<Bytes> {
+        let (entry, kind) = (self.entry.clone(), self.kind);
+        tokio::task::spawn_blocking(move || entry.read_at(kind, offset, len)).await?
+    }
+
+    async fn size(&mut self) -> io::Result<u64> {
+        let (entry, kind) = (self.entry.clone(), self.kind);
+        tokio::task::spawn_blocking(move || entry.len(kind)).await?
+    }
+}
+
+#[derive(Debug)]
+struct BatchWriter(EncryptedEntry);
+
+impl BaoBatchWriter for BatchWriter {
+    async fn write_batch(&mut self, size: u64, batch: Vec<BaoContentItem>) -> io::Result<()> {
+        let entry = self.0.clone();
+        tokio::task::spawn_blocking(move || entry.write_batch(size, &batch)).await?
+    }
+
+    async fn sync(&mut self) -> io::Result<()> {
+        let entry = self.0.clone();
+        tokio::task::spawn_blocking(move || entry.sync()).await?
+    }
+}
+
+/// Blob store keeping data and outboards encrypted at rest. Directories of the blobs are
+/// named after a keyed hash, so the files don't reveal which blobs are stored either.
+#[derive(Debug, Clone)]
+pub struct EncryptedStore(Arc<StoreInner>);
+
+#[derive(Debug)]
+struct StoreInner {
+    dir: PathBuf,
+    encryption: Encryption,
+    state: Mutex<StoreState>,
+}
+
+#[derive(Debug, Default)]
+struct StoreState {
+    /// Entries which have been used since the store was loaded
+    entries: HashMap<Hash, EncryptedEntry>,
+    tags: BTreeMap<Tag, HashAndFormat>,
+    /// Number of temporary tags of each blob
+    temp_tags: BTreeMap<HashAndFormat, u64>,
+}
+
+impl TagDrop for StoreInner {
+    fn on_drop(&self, inner: &HashAndFormat) {
+        let mut state = self.state.lock().unwrap();
+        if let btree_map::Entry::Occupied(mut count) = state.temp_tags.entry(*inner) {
+            *count.get_mut() -= 1;
+            if *count.get() == 0 {
+                count.remove();
+            }
+        }
+    }
+}
+
+impl TagCounter for StoreInner {
+    fn on_create(&self, inner: &HashAndFormat) {
+        *self
+            .state
+            .lock()
+            .unwrap()
+            .temp_tags
+            .entry(*inner)
+            .or_default() += 1;
+    }
+}
+
+impl EncryptedStore {
+    pub async fn load(dir: PathBuf, encryption: Encryption) -> io::Result<Self> {
+        tokio::task::spawn_blocking(move || {
+            fs::create_dir_all(dir.join(TEMP_DIR))?;
+            // Leftovers of imports interrupted by a crash
+            for file in fs::read_dir(dir.join(TEMP_DIR))? {
+                fs::remove_file(file?.path())?;
+            }
+
+            let tags = match read_encrypted(&dir.join(TAGS_FILE), &encryption)? {
+                Some(tags) => serde_json::from_slice::<Vec<(Tag, HashAndFormat)>>(&tags)
+                    .map_err(invalid_data)?
+                    .into_iter()
+                    .collect(),
+                None => BTreeMap::new(),
+            };
+
+            Ok(Self(Arc::new(StoreInner {
+                dir,
+                encryption,
+                state: Mutex::new(StoreState {
+                    tags,
+                    ..Default::default()
+                }),
+            })))
+        })
+        .await?
+    }
+
+    fn blob_dir(&self, hash: &Hash) -> PathBuf {
+        self.0
+            .dir
+            .join(self.0.encryption.file_name(hash.as_bytes()))
+    }
+
+    fn temp_path(&self) -> PathBuf {
+        self.0
+            .dir
+            .join(TEMP_DIR)
+            .join(format!("{:016x}", rand::random::<u64>()))
+    }
+
+    fn save_tags(&self, state: &StoreState) -> io::Result<()> {
+        let tags: Vec<_> = state.tags.iter().collect();
+        let tags = serde_json::to_vec(&tags).map_err(invalid_data)?;
+        write_encrypted(&self.0.dir.join(TAGS_FILE), &tags, &self.0.encryption)
+    }
+
+    /// Returns the cached entry, or caches the given one
+    fn cache_entry(&self, hash: Hash, state: EntryState) -> EncryptedEntry {
+        let entry = EncryptedEntry(Arc::new(EntryInner {
+            hash,
+            dir: self.blob_dir(&hash),
+            encryption: self.0.encryption.clone(),
+            state: Mutex::new(state),
+            files: RwLock::new(()),
+        }));
+        let mut store_state = self.0.state.lock().unwrap();
+        store_state.entries.entry(hash).or_insert(entry).clone()
+    }
+
+    /// Entry of the blob, `None` if the store has nothing of it
+    fn load_entry(&self, hash: &Hash) -> io::Result<Option<EncryptedEntry>> {
+        if let Some(entry) = self.0.state.lock().unwrap().entries.get(hash) {
+            return Ok(Some(entry.clone()));
+        }
+
+        let Some(meta) = read_encrypted(&self.blob_dir(hash).join(META_FILE), &self.0.encryption)?
+        else {
+            return Ok(None);
+        };
+        let meta: Meta = serde_json::from_slice(&meta).map_err(invalid_data)?;
+        if meta.hash != *hash {
+            return Err(invalid_data(format!(
+                "Metadata of blob {hash} is corrupted"
+            )));
+        }
+        Ok(Some(self.cache_entry(*hash, meta.state)))
+    }
+
+    fn list(&self, complete: bool) -> io::Result<DbIter<Hash>> {
+        let mut hashes = Vec::new();
+        for dir in fs::read_dir(&self.0.dir)? {
+            let dir = dir?;
+            if !dir.file_type()?.is_dir() || dir.file_name() == TEMP_DIR {
+                continue;
+            }
+            let Some(meta) = read_encrypted(&dir.path().join(META_FILE), &self.0.encryption)?
+            else {
+                continue;
+            };
+            let meta: Meta = serde_json::from_slice(&meta).map_err(invalid_data)?;
+            let state = match self.0.state.lock().unwrap().entries.get(&meta.hash) {
+                Some(entry) => entry.state(),
+                None => meta.state,
+            };
+            if state.complete == complete {
+                hashes.push(Ok(meta.hash));
+            }
+        }
+        Ok(Box::new(hashes.into_iter()))
+    }
+
+    /// Writes chunks into a new data file in the temporary directory, returning its length
+    fn write_temp(
+        &self,
+        path: &Path,
+        chunks: impl Iterator<Item = io::Result<Bytes>>,
+        mut on_progress: impl FnMut(u64),
+    ) -> io::Result<u64> {
+        let mut file = BlockFile::create(path, FileKind::Data, &self.0.encryption)?;
+        let mut block = Vec::with_capacity(DATA_BLOCK_SIZE);
+        let mut index = 0;
+        let mut size = 0;
+        for chunk in chunks {
+            let chunk = chunk?;
+            let mut chunk = &chunk[..];
+            size += chunk.len() as u64;
+            while !chunk.is_empty() {
+                let len = chunk.len().min(DATA_BLOCK_SIZE - block.len());
+                block.extend_from_slice(&chunk[..len]);
+                chunk = &chunk[len..];
+                if block.len() == DATA_BLOCK_SIZE {
+                    file.write_block(index, &block)?;
+                    index += 1;
+                    block.clear();
+                }
+            }
+            on_progress(size);
+        }
+        if !block.is_empty() {
+            file.write_block(index, &block)?;
+        }
+        file.sync()?;
+        Ok(size)
+    }
+
+    /// Moves a data file in place, next to the outboard computed from it.
+    /// The blob is tagged before it's stored, so it can't be collected in between.
+    fn store_complete(&self, temp: &Path, size: u64, format: BlobFormat) -> io::Result<TempTag> {
+        let file = BlockFile::open(temp, FileKind::Data, &self.0.encryption)?
+            .ok_or_else(|| io::Error::from(ErrorKind::NotFound))?;
+        let reader = BufReader::with_capacity(IMPORT_CHUNK_SIZE, BlockReader::new(file));
+        let outboard = PreOrderOutboard::<Vec<u8>>::create_sized(reader, size, IROH_BLOCK_SIZE)?;
+        let hash = Hash::from(outboard.root);
+        let tag = self.0.temp_tag(HashAndFormat { hash, format });
+        if self.entry_status_sync(&hash)? == EntryStatus::Complete {
+            return Ok(tag);
+        }
+
+        let entry = match self.load_entry(&hash)? {
+            Some(entry) => entry,
+            None => self.cache_entry(hash, EntryState::default()),
+        };
+        let _guard = entry.0.files.write().unwrap();
+        fs::create_dir_all(&entry.0.dir)?;
+
+        let outboard_path = self.temp_path();
+        let result = (|| {
+            let mut file =
+                BlockFile::create(&outboard_path, FileKind::Outboard, &self.0.encryption)?;
+            for (index, block) in outboard.data.chunks(OUTBOARD_BLOCK_SIZE).enumerate() {
+                file.write_block(index as u64, block)?;
+            }
+            file.sync()?;
+            fs::rename(&outboard_path, entry.path(FileKind::Outboard))
+        })();
+        if result.is_err() {
+            let _ = fs::remove_file(&outboard_path);
+        }
+        result?;
+        fs::rename(temp, entry.path(FileKind::Data))?;
+
+        *entry.0.state.lock().unwrap() = EntryState {
+            size,
+            size_offset: size,
+            complete: true,
+        };
+        entry.save()?;
+        Ok(tag)
+    }
+
+    /// Imports chunks of a blob, the temporary tag protects it until it's used
+    fn import_sync(
+        &self,
+        chunks: impl Iterator<Item = io::Result<Bytes>>,
+        format: BlobFormat,
+        progress: impl ProgressSender<Msg = ImportProgress> + IdGenerator,
+        name: String,
+    ) -> io::Result<(TempTag, u64)> {
+        let id = progress.new_id();
+        progress.blocking_send(ImportProgress::Found { id, name })?;
+
+        let temp = self.temp_path();
+        let result: io::Result<_> = (|| {
+            let size = self.write_temp(&temp, chunks, |offset| {
+                progress
+                    .try_send(ImportProgress::CopyProgress { id, offset })
+                    .ok();
+            })?;
+            progress.blocking_send(ImportProgress::Size { id, size })?;
+            let tag = self.store_complete(&temp, size, format)?;
+            Ok((tag, size))
+        })();
+        let _ = fs::remove_file(&temp);
+        let (tag, size) = result?;
+
+        let hash = *tag.hash();
+        progress.blocking_send(ImportProgress::OutboardDone { id, hash })?;
+        Ok((tag, size))
+    }
+
+    fn export_sync(
+        &self,
+        hash: Hash,
+        target: PathBuf,
+        progress: ExportProgressCb,
+    ) -> io::Result<()> {
+        if !target.is_absolute() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "Target path must be absolute",
+            ));
+        }
+        let entry = self
+            .load_entry(&hash)?
+            .filter(|entry| entry.is_complete())
+            .ok_or_else(|| io::Error::new(ErrorKind::NotFound, format!("Blob {hash} not found")))?;
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let _guard = entry.0.files.read().unwrap();
+        let mut target = File::create(target)?;
+        if let Some(file) = BlockFile::open(
+            &entry.path(FileKind::Data),
+            FileKind::Data,
+            &self.0.encryption,
+        )? {
+            let mut reader = BlockReader::new(file);
+            let mut buffer = vec![0; DATA_BLOCK_SIZE];
+            let mut offset = 0;
+            loop {
+                let len = reader.read(&mut buffer)?;
+                if len == 0 {
+                    break;
+                }
+                target.write_all(&buffer[..len])?;
+                offset += len as u64;
+                progress(offset)?;
+            }
+        }
+        target.sync_all()
+    }
+
+    fn delete_sync(&self, hashes: Vec<Hash>) -> io::Result<()> {
+        for hash in hashes {
+            {
+                let mut state = self.0.state.lock().unwrap();
+                if state.temp_tags.keys().any(|tag| tag.hash == hash) {
+                    continue;
+                }
+                state.entries.remove(&hash);
+            }
+            match fs::remove_dir_all(self.blob_dir(&hash)) {
+                Err(error) if error.kind() != ErrorKind::NotFound => return Err(error),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Deletes blobs which aren't protected, nor reachable from a tag
+    async fn collect_garbage(&self, mut live: BTreeSet<Hash>) -> io::Result<()> {
+        let roots: Vec<HashAndFormat> = {
+            let state = self.0.state.lock().unwrap();
+            let tags = state.tags.values();
+            tags.chain(state.temp_tags.keys()).copied().collect()
+        };
+        for root in roots {
+            live.insert(root.hash);
+            if !root.format.is_hash_seq() {
+                continue;
+            }
+            let Some(entry) = self.get(&root.hash).await? else {
+                continue;
+            };
+            if entry.is_complete() {
+                let children = entry.data_reader().await?.read_to_end().await?;
+                live.extend(HashSeq::try_from(children).map_err(invalid_data)?.iter());
+            }
+        }
+
+        let mut dead = Vec::new();
+        for hash in self.blobs().await?.chain(self.partial_blobs().await?) {
+            let hash = hash?;
+            if !live.contains(&hash) {
+                dead.push(hash);
+            }
+        }
+        if !dead.is_empty() {
+            info!("[encryption] deleting {} unused blobs", dead.len());
+            self.delete(dead).await?;
+        }
+        Ok(())
+    }
+}
+
+impl Map for EncryptedStore {
+    type Entry = EncryptedEntry;
+
+    async fn get(&self, hash: &Hash) -> io::Result<Option<EncryptedEntry>> {
+        let (store, hash) = (self.clone(), *hash);
+        tokio::task::spawn_blocking(move || store.load_entry(&hash)).await?
+    }
+}
+
+impl MapMut for EncryptedStore {
+    type EntryMut = EncryptedEntry;
+
+    async fn get_mut(&self, hash: &Hash) -> io::Result<Option<EncryptedEntry>> {
+        self.get(hash).await
+    }
+
+    async fn get_or_create(&self, hash: Hash, _size: u64) -> io::Result<EncryptedEntry> {
+        let store = self.clone();
+        tokio::task::spawn_blocking(move || {
+            if let Some(entry) = store.load_entry(&hash)? {
+                return Ok(entry);
+            }
+            // Saved right away, so partial blobs are listed while they are downloaded
+            let entry = store.cache_entry(hash, EntryState::default());
+            fs::create_dir_all(&entry.0.dir)?;
+            entry.save()?;
+            Ok(entry)
+        })
+        .await?
+    }
+
+    async fn entry_status(&self, hash: &Hash) -> io::Result<EntryStatus> {
+        let (store, hash) = (self.clone(), *hash);
+        tokio::task::spawn_blocking(move || store.entry_status_sync(&hash)).await?
+    }
+
+    fn entry_status_sync(&self, hash: &Hash) -> io::Result<EntryStatus> {
+        Ok(match self.load_entry(hash)? {
+            Some(entry) if entry.is_complete() => EntryStatus::Complete,
+            Some(_) => EntryStatus::Partial,
+            None => EntryStatus::NotFound,
+        })
+    }
+
+    async fn insert_complete(&self, entry: EncryptedEntry) -> io::Result<()> {
+        tokio::task::spawn_blocking(move || {
+            entry.0.state.lock().unwrap().complete = true;
+            entry.sync()
+        })
+        .await?
+    }
+}
+
+impl ReadableStore for EncryptedStore {
+    async fn blobs(&self) -> io::Result<DbIter<Hash>> {
+        let store = self.clone();
+        tokio::task::spawn_blocking(move || store.list(true)).await?
+    }
+
+    async fn tags(
+        &self,
+        from: Option<Tag>,
+        to: Option<Tag>,
+    ) -> io::Result<DbIter<(Tag, HashAndFormat)>> {
+        let state = self.0.state.lock().unwrap();
+        let tags: Vec<_> = state
+            .tags
+            .iter()
+            .filter(|(tag, _)| from.as_ref().is_none_or(|from| *tag >= from))
+            .filter(|(tag, _)| to.as_ref().is_none_or(|to| *tag < to))
+            .map(|(tag, value)| Ok((tag.clone(), *value)))
+            .collect();
+        Ok(Box::new(tags.into_iter()))
+    }
+
+    fn temp_tags(&self) -> Box<dyn Iterator<Item = HashAndFormat> + Send + Sync + 'static> {
+        let state = self.0.state.lock().unwrap();
+        let temp_tags: Vec<_> = state.temp_tags.keys().copied().collect();
+        Box::new(temp_tags.into_iter())
+    }
+
+    /// Every block is authenticated when it's read, so there is nothing to check
+    async fn consistency_check(
+        &self,
+        _repair: bool,
+        _tx: BoxedProgressSender<ConsistencyCheckProgress>,
+    ) -> io::Result<()> {
+        Ok(())
+    }
+
+    async fn partial_blobs(&self) -> io::Result<DbIter<Hash>> {
+        let store = self.clone();
+        tokio::task::spawn_blocking(move || store.list(false)).await?
+    }
+
+    async fn export(
+        &self,
+        hash: Hash,
+        target: PathBuf,
+        _mode: ExportMode,
+        progress: ExportProgressCb,
+    ) -> io::Result<()> {
+        let store = self.clone();
+        tokio::task::spawn_blocking(move || store.export_sync(hash, target, progress)).await?
+    }
+}
+
+impl Store for EncryptedStore {
+    async fn import_file(
+        &self,
+        path: PathBuf,
+        _mode: ImportMode,
+        format: BlobFormat,
+        progress: impl ProgressSender<Msg = ImportProgress> + IdGenerator,
+    ) -> io::Result<(TempTag, u64)> {
+        let store = self.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut file = File::open(&path)?;
+            let chunks = std::iter::from_fn(move || {
+                let mut chunk = vec![0; IMPORT_CHUNK_SIZE];
+                match file.read(&mut chunk) {
+                    Ok(0) => None,
+                    Ok(len) => {
+                        chunk.truncate(len);
+                        Some(Ok(chunk.into()))
+                    }
+                    Err(error) => Some(Err(error)),
+                }
+            });
+            let name = path.to_string_lossy().to_string();
+            store.import_sync(chunks, format, progress, name)
+        })
+        .await?
+    }
+
+    async fn import_bytes(&self, bytes: Bytes, format: BlobFormat) -> io::Result<TempTag> {
+        let store = self.clone();
+        tokio::task::spawn_blocking(move || {
+            let chunks = std::iter::once(Ok(bytes));
+            let progress = IgnoreProgressSender::default();
+            let (tag, _) = store.import_sync(chunks, format, progress, String::new())?;
+            Ok(tag)
+        })
+        .await?
+    }
+
+    async fn import_stream(
+        &self,
+        mut data: impl Stream<Item = io::Result<Bytes>> + Send + Unpin + 'static,
+        format: BlobFormat,
+        progress: impl ProgressSender<Msg = ImportProgress> + IdGenerator,
+    ) -> io::Result<(TempTag, u64)> {
+        let store = self.clone();
+        let (sender, mut receiver) = tokio::sync::mpsc::channel(4);
+        let import = tokio::task::spawn_blocking(move || {
+            let chunks = std::iter::from_fn(move || receiver.blocking_recv());
+            store.import_sync(chunks, format, progress, "stream".to_string())
+        });
+        while let Some(chunk) = data.next().await {
+            let failed = chunk.is_err();
+            if sender.send(chunk).await.is_err() || failed {
+                break;
+            }
+        }
+        drop(sender);
+        import.await?
+    }
+
+    async fn set_tag(&self, name: Tag, hash: HashAndFormat) -> io::Result<()> {
+        let mut state = self.0.state.lock().unwrap();
+        state.tags.insert(name, hash);
+        self.save_tags(&state)
+    }
+
+    async fn rename_tag(&self, from: Tag, to: Tag) -> io::Result<()> {
+        let mut state = self.0.state.lock().unwrap();
+        let value = state
+            .tags
+            .remove(&from)
+            .ok_or_else(|| io::Error::new(ErrorKind::NotFound, format!("Tag {from} not found")))?;
+        state.tags.insert(to, value);
+        self.save_tags(&state)
+    }
+
+    async fn delete_tags(&self, from: Option<Tag>, to: Option<Tag>) -> io::Result<()> {
+        let mut state = self.0.state.lock().unwrap();
+        state.tags.retain(|tag, _| {
+            from.as_ref().is_some_and(|from| tag < from) || to.as_ref().is_some_and(|to| tag >= to)
+        });
+        self.save_tags(&state)
+    }
+
+    async fn create_tag(&self, hash: HashAndFormat) -> io::Result<Tag> {
+        let mut state = self.0.state.lock().unwrap();
+        let tag = Tag::auto(SystemTime::now(), |tag| state.tags.contains_key(tag));
+        state.tags.insert(tag.clone(), hash);
+        self.save_tags(&state)?;
+        Ok(tag)
+    }
+
+    fn temp_tag(&self, value: HashAndFormat) -> TempTag {
+        self.0.temp_tag(value)
+    }
+
+    async fn gc_run<G, Gut>(&self, config: GcConfig, protected_cb: G)
+    where
+        G: Fn() -> Gut,
+        Gut: Future<Output = BTreeSet<Hash>> + Send,
+    {
+        loop {
+            tokio::time::sleep(config.period).await;
+            let live = protected_cb().await;
+            if let Err(error) = self.collect_garbage(live).await {
+                warn!("[encryption] failed to collect garbage: {error}");
+            }
+            if let Some(done) = &config.done_callback {
+                done();
+            }
+        }
+    }
+
+    async fn delete(&self, hashes: Vec<Hash>) -> io::Result<()> {
+        let store = self.clone();
+        tokio::task::spawn_blocking(move || store.delete_sync(hashes)).await?
+    }
+
+    /// Every write is done before its call returns
+    async fn shutdown(&self) {}
+
+    async fn sync(&self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Moves blobs and tags of the store written before encryption got enabled into the
+/// encrypted store, then deletes it
+pub(crate) async fn migrate(dir: &Path, store: &EncryptedStore) -> io::Result<()> {
+    if !tokio::fs::try_exists(dir.join(PLAIN_STORE_FILES[0])).await? {
+        return Ok(());
+    }
+
+    let plain = iroh_blobs::store::fs::Store::load(dir).await?;
+    let hashes = plain.blobs().await?.collect::<io::Result<Vec<_>>>()?;
+    // Keeps the blobs until they are tagged again
+    let mut temp_tags = Vec::with_capacity(hashes.len());
+    for hash in hashes {
+        // Copied next to the data of the plain store, which is deleted afterwards anyway
+        let copy =
+            std::path::absolute(dir.join(PLAIN_STORE_FILES[2]))?.join(format!("{hash}.migrate"));
+        let noop: ExportProgressCb = Box::new(|_| Ok(()));
+        plain
+            .export(hash, copy.clone(), ExportMode::Copy, noop)
+            .await?;
+        let imported = store
+            .import_file(
+                copy.clone(),
+                ImportMode::Copy,
+                BlobFormat::Raw,
+                IgnoreProgressSender::default(),
+            )
+            .await;
+        tokio::fs::remove_file(&copy).await?;
+        let (temp_tag, _) = imported?;
+        if *temp_tag.hash() != hash {
+            return Err(invalid_data(format!("Blob {hash} is corrupted")));
+        }
+        temp_tags.push(temp_tag);
+    }
+    for tag in plain.tags(None, None).await? {
+        let (name, value) = tag?;
+        store.set_tag(name, value).await?;
+    }
+    plain.shutdown().await;
+    drop(plain);
+
+    for file in PLAIN_STORE_FILES {
+        let path = dir.join(file);
+        let removed = match tokio::fs::metadata(&path).await {
+            Ok(metadata) if metadata.is_dir() => tokio::fs::remove_dir_all(&path).await,
+            Ok(_) => tokio::fs::remove_file(&path).await,
+            Err(error) if error.kind() == ErrorKind::NotFound => Ok(()),
+            Err(error) => Err(error),
+        };
+        removed?;
+    }
+    info!("[encryption] encrypted {} blobs", temp_tags.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{BlockFile, DATA_BLOCK_SIZE, EncryptedStore, FileKind};
+    use crate::encryption::{Encryption, EncryptionSecret};
+    use bytes::Bytes;
+    use iroh_blobs::{
+        BlobFormat, HashAndFormat, Tag,
+        store::{Map, MapEntry, ReadableStore, Store},
+    };
+    use iroh_io::AsyncSliceReader;
+
+    async fn encryption(dir: &std::path::Path) -> Encryption {
+        tokio::fs::create_dir_all(dir).await.unwrap();
+        Encryption::open(dir, &EncryptionSecret::Key([7; 32]))
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_block_file() {
+        let dir = std::env::temp_dir().join(format!("blocks_{}", rand::random::<u64>()));
+        let encryption = encryption(&dir).await;
+        let path = dir.join("data");
+
+        let mut file = BlockFile::create(&path, FileKind::Data, &encryption).unwrap();
+        let offset = DATA_BLOCK_SIZE as u64 * 2 + 10;
+        file.write_at(offset, b"far").unwrap();
+        file.write_at(5, b"near").unwrap();
+        assert_eq!(file.len().unwrap(), offset + 3);
+        assert_eq!(file.read_at(3, 8).unwrap(), b"\0\0near\0\0");
+        assert_eq!(file.read_at(offset - 2, 100).unwrap(), b"\0\0far");
+        assert!(file.read_at(offset + 3, 100).unwrap().is_empty());
+        drop(file);
+
+        let stored = std::fs::read(&path).unwrap();
+        assert!(!stored.windows(4).any(|window| window == b"near"));
+
+        // Blocks can't be moved around
+        let slot = stored.len() / 3;
+        let mut swapped = stored[slot * 2..].to_vec();
+        swapped.extend_from_slice(&stored[slot..]);
+        std::fs::write(&path, swapped).unwrap();
+        let mut file = BlockFile::open(&path, FileKind::Data, &encryption)
+            .unwrap()
+            .unwrap();
+        assert!(file.read_at(0, 10).is_err());
+
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+    }
+
+    #[tokio::test]
+    async fn test_store() {
+        let dir = std::env::temp_dir().join(format!("blobs_{}", rand::random::<u64>()));
+        let encryption = encryption(&dir).await;
+        let data = Bytes::from(b"plaintext blob ".repeat(5000));
+
+        let store = EncryptedStore::load(dir.join("blobs"), encryption.clone())
+            .await
+            .unwrap();
+        let temp_tag = store
+            .import_bytes(data.clone(), BlobFormat::Raw)
+            .await
+            .unwrap();
+        let hash = *temp_tag.hash();
+        assert_eq!(hash, iroh_blobs::Hash::new(&data));
+        store
+            .set_tag(Tag::from("music"), HashAndFormat::raw(hash))
+            .await
+            .unwrap();
+        drop(temp_tag);
+
+        let store = EncryptedStore::load(dir.join("blobs"), encryption)
+            .await
+            .unwrap();
+        let entry = store.get(&hash).await.unwrap().unwrap();
+        assert!(entry.is_complete());
+        assert_eq!(entry.size().value(), data.len() as u64);
+        let mut reader = entry.data_reader().await.unwrap();
+        assert_eq!(
+            reader.read_at(20_000, 15).await.unwrap(),
+            data[20_000..20_015]
+        );
+        assert_eq!(store.tags(None, None).await.unwrap().count(), 1);
+
+        for file in walkdir::WalkDir::new(&dir) {
+            let file = file.unwrap();
+            if file.file_type().is_file() {
+                let stored = std::fs::read(file.path()).unwrap();
+                assert!(
+                    !stored
+                        .windows(15)
+                        .any(|window| window == b"plaintext blob ")
+                );
+            }
+        }
+
+        store.delete(vec![hash]).await.unwrap();
+        assert!(store.get(&hash).await.unwrap().is_none());
+        assert_eq!(store.blobs().await.unwrap().count(), 0);
+
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+    }
+}
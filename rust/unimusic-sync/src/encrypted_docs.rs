@@ -0,0 +1,248 @@
+use iroh_docs::{
+    Author, AuthorId, Capability, CapabilityKind, NamespaceId, NamespaceSecret, SignedEntry,
+    actor::{OpenOpts, SyncHandle},
+    store::{DownloadPolicy, Query, Store},
+    sync::{ContentStatus, InsertError, PeerIdBytes},
+};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{
+        Arc, Weak,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+use tokio::{fs, sync::Mutex};
+
+use crate::{
+    encryption::{self, Encryption},
+    errors::{Result, SharedError},
+};
+
+/// Encrypted copy of the docs store, which itself only lives in memory
+pub const SNAPSHOT_FILE: &str = "docs.snapshot";
+/// Store of nodes opened without encryption
+const PLAIN_STORE_FILE: &str = "docs.redb";
+/// How often the store is saved, if it changed
+const SAVE_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Snapshot {
+    namespaces: Vec<NamespaceSnapshot>,
+    /// Secrets of the authors
+    authors: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct NamespaceSnapshot {
+    id: NamespaceId,
+    /// Secret of writable namespaces
+    secret: Option<String>,
+    /// Entries with the signatures of their authors
+    entries: Vec<SignedEntry>,
+    download_policy: DownloadPolicy,
+    sync_peers: Vec<PeerIdBytes>,
+}
+
+fn invalid_snapshot(error: impl std::fmt::Display) -> SharedError {
+    SharedError::Storage(format!("Docs snapshot is invalid: {error}"))
+}
+
+/// Opens the docs store in memory, filled from the encrypted snapshot. A store written
+/// before encryption got enabled is moved into it, then deleted.
+pub(crate) async fn load(dir: &Path, encryption: &Encryption) -> Result<Store> {
+    let snapshot_path = dir.join(SNAPSHOT_FILE);
+    let plain_path = dir.join(PLAIN_STORE_FILE);
+
+    let snapshot = match encryption::read(&snapshot_path, Some(encryption)).await? {
+        Some(snapshot) => postcard::from_bytes(&snapshot).map_err(invalid_snapshot)?,
+        None if fs::try_exists(&plain_path).await? => {
+            let replicas = SyncHandle::spawn(
+                Store::persistent(&plain_path)?,
+                None,
+                "migration".to_string(),
+            );
+            let snapshot = take_snapshot(&replicas).await;
+            replicas.shutdown().await?;
+            let snapshot = snapshot?;
+
+            let data = postcard::to_allocvec(&snapshot).map_err(invalid_snapshot)?;
+            encryption::write(&snapshot_path, &data, Some(encryption)).await?;
+            fs::remove_file(&plain_path).await?;
+            info!("[encryption] encrypted {PLAIN_STORE_FILE}");
+            snapshot
+        }
+        None => Snapshot::default(),
+    };
+
+    let mut store = Store::memory();
+    restore(&mut store, snapshot)?;
+    Ok(store)
+}
+
+fn restore(store: &mut Store, snapshot: Snapshot) -> Result<()> {
+    for author in snapshot.authors {
+        store.import_author(Author::from_str(&author).map_err(invalid_snapshot)?)?;
+    }
+
+    for namespace in snapshot.namespaces {
+        let capability = match namespace.secret {
+            Some(secret) => {
+                Capability::Write(NamespaceSecret::from_str(&secret).map_err(invalid_snapshot)?)
+            }
+            None => Capability::Read(namespace.id),
+        };
+        store.import_namespace(capability)?;
+
+        let mut entries = namespace.entries;
+        // Older entries first, so deletions of prefixes apply to what they deleted before
+        entries.sort_by_key(|entry| entry.timestamp());
+        let mut replica = store
+            .open_replica(&namespace.id)
+            .map_err(|error| SharedError::Storage(error.to_string()))?;
+        for entry in entries {
+            match replica.insert_remote_entry(entry, [0; 32], ContentStatus::Missing) {
+                Ok(_) | Err(InsertError::NewerEntryExists) => {}
+                Err(error) => return Err(invalid_snapshot(error)),
+            }
+        }
+        drop(replica);
+        store.close_replica(namespace.id);
+
+        store.set_download_policy(&namespace.id, namespace.download_policy)?;
+        for peer in namespace.sync_peers.into_iter().rev() {
+            store.register_useful_peer(namespace.id, peer)?;
+        }
+    }
+    Ok(())
+}
+
+async fn take_snapshot(replicas: &SyncHandle) -> Result<Snapshot> {
+    let mut snapshot = Snapshot::default();
+
+    let (sender, receiver) = async_channel::bounded(64);
+    replicas.list_authors(sender).await?;
+    let mut authors: Vec<AuthorId> = Vec::new();
+    while let Ok(author) = receiver.recv().await {
+        authors.push(author?);
+    }
+    for author in authors {
+        if let Some(author) = replicas.export_author(author).await? {
+            snapshot.authors.push(author.to_string());
+        }
+    }
+
+    let (sender, receiver) = async_channel::bounded(64);
+    replicas.list_replicas(sender).await?;
+    let mut namespaces = Vec::new();
+    while let Ok(namespace) = receiver.recv().await {
+        namespaces.push(namespace?);
+    }
+    for (id, kind) in namespaces {
+        // Entries and peers are only read from open replicas
+        replicas.open(id, OpenOpts::default()).await?;
+        let namespace = take_namespace_snapshot(replicas, id, kind).await;
+        replicas.close(id).await?;
+        snapshot.namespaces.push(namespace?);
+    }
+    Ok(snapshot)
+}
+
+async fn take_namespace_snapshot(
+    replicas: &SyncHandle,
+    id: NamespaceId,
+    kind: CapabilityKind,
+) -> Result<NamespaceSnapshot> {
+    let secret = match kind {
+        CapabilityKind::Write => Some(replicas.export_secret_key(id).await?.to_string()),
+        CapabilityKind::Read => None,
+    };
+
+    let (sender, receiver) = async_channel::bounded(64);
+    replicas.get_many(id, Query::all().build(), sender).await?;
+    let mut entries = Vec::new();
+    while let Ok(entry) = receiver.recv().await {
+        entries.push(entry?);
+    }
+
+    Ok(NamespaceSnapshot {
+        id,
+        secret,
+        entries,
+        download_policy: replicas.get_download_policy(id).await?,
+        sync_peers: replicas.get_sync_peers(id).await?.unwrap_or_default(),
+    })
+}
+
+/// Saves the in-memory docs store to its encrypted snapshot, every few seconds
+/// while it changes and when the node shuts down
+#[derive(Debug, Clone)]
+pub struct DocsSnapshot(Arc<SnapshotInner>);
+
+#[derive(Debug)]
+struct SnapshotInner {
+    path: PathBuf,
+    encryption: Encryption,
+    replicas: SyncHandle,
+    /// Hash of the last saved snapshot, held while saving
+    saved: Mutex<Option<blake3::Hash>>,
+    closed: AtomicBool,
+}
+
+impl DocsSnapshot {
+    pub(crate) fn spawn(dir: &Path, encryption: Encryption, replicas: SyncHandle) -> Self {
+        let snapshot = Self(Arc::new(SnapshotInner {
+            path: dir.join(SNAPSHOT_FILE),
+            encryption,
+            replicas,
+            saved: Mutex::new(None),
+            closed: AtomicBool::new(false),
+        }));
+
+        let weak = Arc::downgrade(&snapshot.0);
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(SAVE_INTERVAL).await;
+                let Some(inner) = Weak::upgrade(&weak) else {
+                    break;
+                };
+                if inner.closed.load(Ordering::Acquire) {
+                    break;
+                }
+                if let Err(error) = Self(inner).save().await {
+                    warn!("[encryption] failed to save {SNAPSHOT_FILE}: {error}");
+                }
+            }
+        });
+
+        snapshot
+    }
+
+    /// Writes the snapshot, unless nothing changed since it was last written
+    pub(crate) async fn save(&self) -> Result<()> {
+        let mut saved = self.0.saved.lock().await;
+        if self.0.closed.load(Ordering::Acquire) {
+            return Ok(());
+        }
+
+        let snapshot = take_snapshot(&self.0.replicas).await?;
+        let data = postcard::to_allocvec(&snapshot).map_err(invalid_snapshot)?;
+        let hash = blake3::hash(&data);
+        if *saved == Some(hash) {
+            return Ok(());
+        }
+        encryption::write(&self.0.path, &data, Some(&self.0.encryption)).await?;
+        *saved = Some(hash);
+        Ok(())
+    }
+
+    /// Saves the snapshot a last time, before the store shuts down
+    pub(crate) async fn close(&self) -> Result<()> {
+        self.save().await?;
+        self.0.closed.store(true, Ordering::Release);
+        Ok(())
+    }
+}
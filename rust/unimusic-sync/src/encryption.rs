@@ -0,0 +1,291 @@
+use crypto_secretbox::{
+    AeadCore, KeyInit, XSalsa20Poly1305,
+    aead::{Aead, OsRng},
+};
+use iroh::SecretKey;
+use log::info;
+use sha2::Sha256;
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::{fs, io::AsyncWriteExt};
+
+use crate::errors::{Result, SharedError};
+
+/// Prefix of encrypted files, followed by a nonce and the ciphertext
+const MAGIC: &[u8] = b"UMSENC1\0";
+const NONCE_LEN: usize = 24;
+const TAG_LEN: usize = 16;
+/// How much longer encrypted data is than its plaintext
+pub(crate) const OVERHEAD: usize = MAGIC.len() + NONCE_LEN + TAG_LEN;
+const KEY_LEN: usize = 32;
+const SALT_LEN: usize = 16;
+const PBKDF2_ITERATIONS: u32 = 600_000;
+
+/// Files in the store directory, which contain secrets or metadata about peers
+pub const SENSITIVE_FILES: &[&str] = &["secret.key", "nodes.json", "invitations.json"];
+/// Random salt used to derive the key from a passphrase, it isn't secret
+const SALT_FILE: &str = "encryption.salt";
+
+/// Key material given to [`crate::IrohFactory`]
+#[derive(Clone)]
+pub(crate) enum EncryptionSecret {
+    Key([u8; KEY_LEN]),
+    Passphrase(String),
+}
+
+impl std::fmt::Debug for EncryptionSecret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Key(_) => f.write_str("Key(..)"),
+            Self::Passphrase(_) => f.write_str("Passphrase(..)"),
+        }
+    }
+}
+
+impl EncryptionSecret {
+    pub(crate) fn key(key: &[u8]) -> Result<Self> {
+        let key = key.try_into().map_err(|_| {
+            SharedError::InvalidInput(format!(
+                "Encryption key must be {KEY_LEN} bytes long, got {}",
+                key.len()
+            ))
+        })?;
+        Ok(Self::Key(key))
+    }
+}
+
+/// Encrypts and decrypts files of the store
+#[derive(Clone)]
+pub struct Encryption {
+    cipher: Arc<XSalsa20Poly1305>,
+    /// Key naming files after what they contain, without revealing it
+    names_key: [u8; KEY_LEN],
+}
+
+impl std::fmt::Debug for Encryption {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Encryption(..)")
+    }
+}
+
+impl Encryption {
+    /// Prepares encryption of the store in given directory,
+    /// passphrase salt is created on the first use
+    pub(crate) async fn open(dir: &Path, secret: &EncryptionSecret) -> Result<Self> {
+        let key = match secret {
+            EncryptionSecret::Key(key) => *key,
+            EncryptionSecret::Passphrase(passphrase) => {
+                let salt_path = dir.join(SALT_FILE);
+                let salt = match fs::read(&salt_path).await {
+                    Ok(salt) => salt,
+                    Err(error) if error.kind() == ErrorKind::NotFound => {
+                        let salt: [u8; SALT_LEN] = rand::random();
+                        fs::create_dir_all(dir).await?;
+                        fs::write(&salt_path, salt).await?;
+                        salt.to_vec()
+                    }
+                    Err(error) => return Err(error.into()),
+                };
+
+                let passphrase = passphrase.clone();
+                tokio::task::spawn_blocking(move || {
+                    pbkdf2::pbkdf2_hmac_array::<Sha256, KEY_LEN>(
+                        passphrase.as_bytes(),
+                        &salt,
+                        PBKDF2_ITERATIONS,
+                    )
+                })
+                .await
+                .map_err(|error| SharedError::Iroh(error.to_string()))?
+            }
+        };
+
+        Ok(Self {
+            cipher: Arc::new(XSalsa20Poly1305::new(&key.into())),
+            names_key: blake3::derive_key("unimusic-sync file names", &key),
+        })
+    }
+
+    /// Name of the file holding the data with given id, which can't be linked back to the id
+    pub(crate) fn file_name(&self, id: &[u8]) -> String {
+        blake3::keyed_hash(&self.names_key, id).to_hex().to_string()
+    }
+
+    pub fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>> {
+        let nonce = XSalsa20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, plaintext)
+            .map_err(|_| SharedError::Storage("Failed to encrypt data".to_string()))?;
+
+        let mut data = Vec::with_capacity(MAGIC.len() + NONCE_LEN + ciphertext.len());
+        data.extend_from_slice(MAGIC);
+        data.extend_from_slice(&nonce);
+        data.extend_from_slice(&ciphertext);
+        Ok(data)
+    }
+
+    pub fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let data = data
+            .strip_prefix(MAGIC)
+            .filter(|data| data.len() >= NONCE_LEN)
+            .ok_or_else(|| SharedError::InvalidInput("Data is not encrypted".to_string()))?;
+        let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+
+        self.cipher.decrypt(nonce.into(), ciphertext).map_err(|_| {
+            SharedError::PermissionDenied(
+                "Failed to decrypt data, wrong key or corrupted file".to_string(),
+            )
+        })
+    }
+}
+
+pub fn is_encrypted(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+/// Reads the file, decrypting it if needed. Returns `None` if it doesn't exist.
+/// Unencrypted files are returned as they are, so stores created without encryption stay readable.
+pub async fn read(path: &Path, encryption: Option<&Encryption>) -> Result<Option<Vec<u8>>> {
+    let data = match fs::read(path).await {
+        Ok(data) => data,
+        Err(error) if error.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(error) => return Err(error.into()),
+    };
+
+    match (is_encrypted(&data), encryption) {
+        (true, Some(encryption)) => encryption.decrypt(&data).map(Some),
+        (true, None) => Err(SharedError::PermissionDenied(format!(
+            "{} is encrypted, a key or passphrase is required",
+            path.display()
+        ))),
+        (false, _) => Ok(Some(data)),
+    }
+}
+
+/// Writes the file, encrypting it if encryption is enabled.
+/// Data goes to a temporary file first, which then replaces the file, so a crash
+/// never leaves it truncated.
+pub async fn write(path: &Path, data: &[u8], encryption: Option<&Encryption>) -> Result<()> {
+    let data = match encryption {
+        Some(encryption) => encryption.encrypt(data)?,
+        None => data.to_vec(),
+    };
+
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    let temp_path = PathBuf::from(temp_path);
+
+    let mut file = fs::File::create(&temp_path).await?;
+    file.write_all(&data).await?;
+    file.sync_all().await?;
+    drop(file);
+    fs::rename(&temp_path, path).await?;
+    Ok(())
+}
+
+/// Encrypts sensitive files, which were written before encryption got enabled
+pub(crate) async fn migrate(dir: &Path, encryption: &Encryption) -> Result<()> {
+    for file in SENSITIVE_FILES {
+        let path = dir.join(file);
+        let Ok(data) = fs::read(&path).await else {
+            continue;
+        };
+        if is_encrypted(&data) {
+            continue;
+        }
+
+        let data = if *file == "secret.key" {
+            // Unencrypted keys are stored in OpenSSH format
+            let secret_key = iroh_blobs::util::fs::load_secret_key(path.clone()).await?;
+            secret_key.to_bytes().to_vec()
+        } else {
+            data
+        };
+
+        write(&path, &data, Some(encryption)).await?;
+        info!("[encryption] encrypted {file}");
+    }
+
+    Ok(())
+}
+
+/// Loads or generates the secret key of the node
+pub(crate) async fn load_secret_key(
+    path: PathBuf,
+    encryption: Option<&Encryption>,
+) -> Result<SecretKey> {
+    let Some(encryption) = encryption else {
+        // Fails with a clear error instead of a parsing one, if the key is encrypted
+        read(&path, None).await?;
+        return Ok(iroh_blobs::util::fs::load_secret_key(path).await?);
+    };
+
+    match read(&path, Some(encryption)).await? {
+        Some(bytes) => {
+            let bytes: [u8; 32] = bytes.as_slice().try_into().map_err(|_| {
+                SharedError::Storage(format!("{} is not a valid key", path.display()))
+            })?;
+            Ok(SecretKey::from_bytes(&bytes))
+        }
+        None => {
+            let secret_key = SecretKey::generate(rand::rngs::OsRng);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent).await?;
+            }
+            write(&path, &secret_key.to_bytes(), Some(encryption)).await?;
+            Ok(secret_key)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Encryption, EncryptionSecret, read, write};
+
+    #[tokio::test]
+    async fn test_files() {
+        let dir = std::env::temp_dir().join(format!("encryption_{}", rand::random::<u64>()));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+
+        let encryption = Encryption::open(&dir, &EncryptionSecret::Key([7; 32]))
+            .await
+            .unwrap();
+        let other = Encryption::open(&dir, &EncryptionSecret::Key([8; 32]))
+            .await
+            .unwrap();
+
+        // Unencrypted files stay readable
+        let plain = dir.join("plain.json");
+        write(&plain, b"plain", None).await.unwrap();
+        assert_eq!(
+            read(&plain, Some(&encryption)).await.unwrap().unwrap(),
+            b"plain"
+        );
+
+        let encrypted = dir.join("encrypted.json");
+        write(&encrypted, b"secret", Some(&encryption))
+            .await
+            .unwrap();
+        assert_ne!(tokio::fs::read(&encrypted).await.unwrap(), b"secret");
+        assert!(!dir.join("encrypted.json.tmp").exists());
+        assert_eq!(
+            read(&encrypted, Some(&encryption)).await.unwrap().unwrap(),
+            b"secret"
+        );
+        assert!(read(&encrypted, Some(&other)).await.is_err());
+        assert!(read(&encrypted, None).await.is_err());
+
+        assert!(
+            read(&dir.join("missing.json"), None)
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+    }
+}
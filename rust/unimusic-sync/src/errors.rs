@@ -4,8 +4,28 @@ use uniffi::deps::anyhow;
 
 pub type Result<T> = std::result::Result<T, SharedError>;
 
+/// Broad category of an error, allowing callers to decide how to handle it
+#[cfg_attr(feature = "default", derive(uniffi::Enum))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorCategory {
+    /// Peer unreachable, connection dropped, timeouts
+    Network,
+    /// Local store failed to read or write data
+    Storage,
+    /// Requested replica, entry or file does not exist
+    NotFound,
+    /// Operating system denied access
+    Permission,
+    /// Caller passed data which can't be used
+    InvalidInput,
+    /// Operation has been cancelled by the caller
+    Cancelled,
+    /// Anything that could not be categorized
+    Internal,
+}
+
 #[cfg_attr(feature = "default", derive(uniffi::Error))]
-#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+#[derive(Debug, Clone, thiserror::Error, PartialEq, Eq)]
 pub enum SharedError {
     #[error("Iroh error: {0}")]
     Iroh(String),
@@ -15,9 +35,22 @@ pub enum SharedError {
     #[error("Serde error: {0}")]
     Serde(String),
 
+    /// Deprecated, kept for existing bindings. I/O errors are returned by their category,
+    /// as [`Network`](Self::Network), [`Storage`](Self::Storage), [`NotFound`](Self::NotFound), ...
     #[error("I/O Error: {0}")]
     IO(String),
 
+    #[error("Network error: {0}")]
+    Network(String),
+    #[error("Storage error: {0}")]
+    Storage(String),
+    #[error("Not found: {0}")]
+    NotFound(String),
+    #[error("Permission denied: {0}")]
+    PermissionDenied(String),
+    #[error("Invalid input: {0}")]
+    InvalidInput(String),
+
     #[error("Tried to open replica, which does not exist: {0}")]
     ReplicaMissing(UNamespaceId),
     #[error("Tried to access entry, which does not exist:\nnamespace: {0}\npath: {1}")]
@@ -30,11 +63,101 @@ pub enum SharedError {
     InvalidNamespaceId(String),
     #[error("Sync failed: {0}")]
     SyncFailed(String),
+
+    #[error("Operation was cancelled")]
+    Cancelled,
+    #[error("Operation timed out after {0}ms")]
+    TimedOut(u64),
+}
+
+impl SharedError {
+    pub fn category(&self) -> ErrorCategory {
+        match self {
+            SharedError::Iroh(_) => ErrorCategory::Internal,
+            SharedError::IrohGossip(_) | SharedError::Network(_) => ErrorCategory::Network,
+            SharedError::SyncFailed(_) | SharedError::TimedOut(_) => ErrorCategory::Network,
+            SharedError::Serde(_) | SharedError::Storage(_) | SharedError::IO(_) => {
+                ErrorCategory::Storage
+            }
+            SharedError::NotFound(_)
+            | SharedError::ReplicaMissing(_)
+            | SharedError::EntryMissing(..)
+            | SharedError::EntryTombstoned(..) => ErrorCategory::NotFound,
+            SharedError::PermissionDenied(_) => ErrorCategory::Permission,
+            SharedError::InvalidInput(_) | SharedError::InvalidNamespaceId(_) => {
+                ErrorCategory::InvalidInput
+            }
+            SharedError::Cancelled => ErrorCategory::Cancelled,
+        }
+    }
+
+    /// Stable identifier of the error, it doesn't change between releases
+    pub fn code(&self) -> &'static str {
+        match self {
+            SharedError::Iroh(_) => "INTERNAL",
+            SharedError::IrohGossip(_) => "GOSSIP",
+            SharedError::Serde(_) => "SERIALIZATION",
+            SharedError::IO(_) => "IO",
+            SharedError::Network(_) => "NETWORK",
+            SharedError::Storage(_) => "STORAGE",
+            SharedError::NotFound(_) => "NOT_FOUND",
+            SharedError::PermissionDenied(_) => "PERMISSION_DENIED",
+            SharedError::InvalidInput(_) => "INVALID_INPUT",
+            SharedError::ReplicaMissing(_) => "REPLICA_MISSING",
+            SharedError::EntryMissing(..) => "ENTRY_MISSING",
+            SharedError::EntryTombstoned(..) => "ENTRY_TOMBSTONED",
+            SharedError::InvalidNamespaceId(_) => "INVALID_NAMESPACE_ID",
+            SharedError::SyncFailed(_) => "SYNC_FAILED",
+            SharedError::Cancelled => "CANCELLED",
+            SharedError::TimedOut(_) => "TIMED_OUT",
+        }
+    }
+
+    /// Whether repeating the operation later might succeed
+    pub fn is_retryable(&self) -> bool {
+        self.category() == ErrorCategory::Network
+    }
+}
+
+#[cfg_attr(feature = "default", uniffi::export)]
+pub fn error_category(error: SharedError) -> ErrorCategory {
+    error.category()
+}
+
+#[cfg_attr(feature = "default", uniffi::export)]
+pub fn error_code(error: SharedError) -> String {
+    error.code().to_string()
+}
+
+#[cfg_attr(feature = "default", uniffi::export)]
+pub fn error_is_retryable(error: SharedError) -> bool {
+    error.is_retryable()
 }
 
 impl From<anyhow::Error> for SharedError {
     fn from(value: anyhow::Error) -> Self {
-        SharedError::Iroh(value.to_string())
+        // Alternate formatting keeps the whole chain of causes
+        let message = format!("{value:#}");
+
+        for cause in value.chain() {
+            if let Some(error) = cause.downcast_ref::<SharedError>() {
+                return error.clone();
+            }
+
+            if let Some(error) = cause.downcast_ref::<std::io::Error>() {
+                return categorize_io_error(error.kind(), message);
+            }
+
+            if cause.is::<iroh::endpoint::ConnectionError>()
+                || cause.is::<iroh::endpoint::WriteError>()
+                || cause.is::<iroh::endpoint::ReadError>()
+                || cause.is::<tokio::time::error::Elapsed>()
+            {
+                return SharedError::Network(message);
+            }
+        }
+
+        SharedError::Iroh(message)
     }
 }
 
@@ -46,6 +169,185 @@ impl From<iroh_gossip::net::Error> for SharedError {
 
 impl From<std::io::Error> for SharedError {
     fn from(value: std::io::Error) -> Self {
-        SharedError::IO(value.to_string())
+        categorize_io_error(value.kind(), value.to_string())
+    }
+}
+
+fn categorize_io_error(kind: std::io::ErrorKind, message: String) -> SharedError {
+    use std::io::ErrorKind;
+
+    match kind {
+        ErrorKind::NotFound => SharedError::NotFound(message),
+        ErrorKind::PermissionDenied | ErrorKind::ReadOnlyFilesystem => {
+            SharedError::PermissionDenied(message)
+        }
+        ErrorKind::InvalidInput | ErrorKind::InvalidFilename => SharedError::InvalidInput(message),
+        ErrorKind::TimedOut
+        | ErrorKind::ConnectionRefused
+        | ErrorKind::ConnectionReset
+        | ErrorKind::ConnectionAborted
+        | ErrorKind::NotConnected
+        | ErrorKind::AddrNotAvailable
+        | ErrorKind::NetworkUnreachable
+        | ErrorKind::HostUnreachable
+        | ErrorKind::NetworkDown
+        | ErrorKind::BrokenPipe => SharedError::Network(message),
+        _ => SharedError::Storage(message),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashSet, io};
+
+    #[cfg(feature = "default")]
+    use uniffi::deps::anyhow;
+
+    use super::{ErrorCategory, SharedError};
+    use crate::types::UNamespaceId;
+
+    #[test]
+    fn test_codes_and_categories() {
+        let namespace = UNamespaceId::from(iroh_docs::NamespaceId::from([0; 32]));
+        let message = || "message".to_string();
+
+        let table = [
+            (
+                SharedError::Iroh(message()),
+                "INTERNAL",
+                ErrorCategory::Internal,
+            ),
+            (
+                SharedError::IrohGossip(message()),
+                "GOSSIP",
+                ErrorCategory::Network,
+            ),
+            (
+                SharedError::Serde(message()),
+                "SERIALIZATION",
+                ErrorCategory::Storage,
+            ),
+            (SharedError::IO(message()), "IO", ErrorCategory::Storage),
+            (
+                SharedError::Network(message()),
+                "NETWORK",
+                ErrorCategory::Network,
+            ),
+            (
+                SharedError::Storage(message()),
+                "STORAGE",
+                ErrorCategory::Storage,
+            ),
+            (
+                SharedError::NotFound(message()),
+                "NOT_FOUND",
+                ErrorCategory::NotFound,
+            ),
+            (
+                SharedError::PermissionDenied(message()),
+                "PERMISSION_DENIED",
+                ErrorCategory::Permission,
+            ),
+            (
+                SharedError::InvalidInput(message()),
+                "INVALID_INPUT",
+                ErrorCategory::InvalidInput,
+            ),
+            (
+                SharedError::ReplicaMissing(namespace),
+                "REPLICA_MISSING",
+                ErrorCategory::NotFound,
+            ),
+            (
+                SharedError::EntryMissing(namespace, message()),
+                "ENTRY_MISSING",
+                ErrorCategory::NotFound,
+            ),
+            (
+                SharedError::EntryTombstoned(namespace, message()),
+                "ENTRY_TOMBSTONED",
+                ErrorCategory::NotFound,
+            ),
+            (
+                SharedError::InvalidNamespaceId(message()),
+                "INVALID_NAMESPACE_ID",
+                ErrorCategory::InvalidInput,
+            ),
+            (
+                SharedError::SyncFailed(message()),
+                "SYNC_FAILED",
+                ErrorCategory::Network,
+            ),
+            (
+                SharedError::Cancelled,
+                "CANCELLED",
+                ErrorCategory::Cancelled,
+            ),
+            (
+                SharedError::TimedOut(100),
+                "TIMED_OUT",
+                ErrorCategory::Network,
+            ),
+        ];
+
+        for (error, code, category) in &table {
+            assert_eq!(error.code(), *code, "{error:?}");
+            assert_eq!(error.category(), *category, "{error:?}");
+            assert_eq!(
+                error.is_retryable(),
+                *category == ErrorCategory::Network,
+                "{error:?}"
+            );
+        }
+
+        let codes: HashSet<_> = table.iter().map(|(error, ..)| error.code()).collect();
+        assert_eq!(codes.len(), table.len());
+    }
+
+    #[test]
+    fn test_categorize_io_errors() {
+        let table = [
+            (io::ErrorKind::NotFound, ErrorCategory::NotFound),
+            (io::ErrorKind::PermissionDenied, ErrorCategory::Permission),
+            (io::ErrorKind::ReadOnlyFilesystem, ErrorCategory::Permission),
+            (io::ErrorKind::InvalidInput, ErrorCategory::InvalidInput),
+            (io::ErrorKind::ConnectionReset, ErrorCategory::Network),
+            (io::ErrorKind::TimedOut, ErrorCategory::Network),
+            (io::ErrorKind::StorageFull, ErrorCategory::Storage),
+            (io::ErrorKind::UnexpectedEof, ErrorCategory::Storage),
+        ];
+
+        for (kind, category) in table {
+            let error = SharedError::from(io::Error::from(kind));
+            assert_eq!(error.category(), category, "{kind:?}");
+
+            // Causes deeper in the chain are found too
+            let error = anyhow::Error::from(io::Error::from(kind)).context("reading file");
+            let error = SharedError::from(error);
+            assert_eq!(error.category(), category, "{kind:?}");
+            assert!(error.to_string().contains("reading file"), "{error}");
+        }
+    }
+
+    #[test]
+    fn test_from_anyhow() {
+        let error = anyhow::Error::from(SharedError::Cancelled).context("syncing");
+        assert_eq!(SharedError::from(error), SharedError::Cancelled);
+
+        let error = anyhow::anyhow!("unknown").context("syncing");
+        assert_eq!(
+            SharedError::from(error),
+            SharedError::Iroh("syncing: unknown".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_from_elapsed() {
+        let elapsed = tokio::time::timeout(std::time::Duration::ZERO, std::future::pending::<()>())
+            .await
+            .unwrap_err();
+        let error = SharedError::from(anyhow::Error::from(elapsed));
+        assert_eq!(error.category(), ErrorCategory::Network);
+        assert!(error.is_retryable());
     }
 }
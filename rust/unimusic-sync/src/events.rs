@@ -0,0 +1,163 @@
+use iroh_blobs::Hash;
+use iroh_docs::{ContentStatus, Entry, engine::LiveEvent};
+use log::warn;
+use tokio::{
+    sync::{Mutex, mpsc},
+    task::JoinHandle,
+};
+use tokio_stream::StreamExt;
+
+use crate::{
+    IrohManager, TOMBSTONE,
+    errors::{Result, SharedError},
+    types::{UHash, UNamespaceId, UNodeId},
+};
+
+/// Events buffered for a subscriber which doesn't keep up
+const EVENT_BUFFER: usize = 256;
+
+/// Change of a namespace or of a peer syncing it
+#[cfg_attr(feature = "default", derive(uniffi::Enum))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyncEvent {
+    /// Entry written on this node
+    InsertLocal {
+        key: String,
+        hash: UHash,
+        deleted: bool,
+    },
+    /// Entry received from the peer, its content might not be downloaded yet
+    InsertRemote {
+        key: String,
+        hash: UHash,
+        deleted: bool,
+        from: UNodeId,
+        content_ready: bool,
+    },
+    /// Content of a remote entry got downloaded
+    ContentReady {
+        hash: UHash,
+    },
+    /// Content of every entry received since the last sync got downloaded
+    PendingContentReady,
+    NeighborUp {
+        node_id: UNodeId,
+    },
+    NeighborDown {
+        node_id: UNodeId,
+    },
+    /// Sync with the peer finished, it failed if there's an error
+    SyncFinished {
+        node_id: UNodeId,
+        error: Option<String>,
+    },
+}
+
+impl SyncEvent {
+    fn from_live_event(event: LiveEvent) -> Self {
+        fn key_of(entry: &Entry) -> String {
+            String::from_utf8_lossy(entry.key()).into_owned()
+        }
+
+        fn is_tombstone(entry: &Entry) -> bool {
+            entry.content_hash() == Hash::new(TOMBSTONE)
+        }
+
+        match event {
+            LiveEvent::InsertLocal { entry } => Self::InsertLocal {
+                key: key_of(&entry),
+                hash: entry.content_hash().into(),
+                deleted: is_tombstone(&entry),
+            },
+            LiveEvent::InsertRemote {
+                from,
+                entry,
+                content_status,
+            } => Self::InsertRemote {
+                key: key_of(&entry),
+                hash: entry.content_hash().into(),
+                deleted: is_tombstone(&entry),
+                from: from.into(),
+                content_ready: content_status == ContentStatus::Complete,
+            },
+            LiveEvent::ContentReady { hash } => Self::ContentReady { hash: hash.into() },
+            LiveEvent::PendingContentReady => Self::PendingContentReady,
+            LiveEvent::NeighborUp(node_id) => Self::NeighborUp {
+                node_id: node_id.into(),
+            },
+            LiveEvent::NeighborDown(node_id) => Self::NeighborDown {
+                node_id: node_id.into(),
+            },
+            LiveEvent::SyncFinished(event) => Self::SyncFinished {
+                node_id: event.peer.into(),
+                error: event.result.err(),
+            },
+        }
+    }
+}
+
+/// Events of a namespace, which stop once the subscription is dropped
+#[cfg_attr(feature = "default", derive(uniffi::Object))]
+#[derive(Debug)]
+pub struct EventSubscription {
+    namespace: UNamespaceId,
+    events: Mutex<mpsc::Receiver<SyncEvent>>,
+    task: JoinHandle<()>,
+}
+
+impl Drop for EventSubscription {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+#[cfg_attr(feature = "default", uniffi::export(async_runtime = "tokio"))]
+impl EventSubscription {
+    pub fn namespace(&self) -> UNamespaceId {
+        self.namespace
+    }
+
+    /// Waits for the next event, returns nothing once the namespace is closed
+    #[cfg_attr(feature = "default", uniffi::method(async_runtime = "tokio"))]
+    pub async fn next(&self) -> Option<SyncEvent> {
+        self.events.lock().await.recv().await
+    }
+}
+
+impl EventSubscription {
+    pub(crate) async fn subscribe(manager: &IrohManager, namespace: UNamespaceId) -> Result<Self> {
+        let replica = manager
+            .docs
+            .client()
+            .open(namespace.into())
+            .await?
+            .ok_or(SharedError::ReplicaMissing(namespace))?;
+        let mut live_events = replica.subscribe().await?;
+
+        let (sender, events) = mpsc::channel(EVENT_BUFFER);
+        let task = tokio::spawn(async move {
+            // Events stop once the replica is closed
+            let _replica = replica;
+            while let Some(event) = live_events.next().await {
+                match event {
+                    Ok(event) => {
+                        if sender
+                            .send(SyncEvent::from_live_event(event))
+                            .await
+                            .is_err()
+                        {
+                            break;
+                        }
+                    }
+                    Err(error) => warn!("[events] namespace {namespace} event error: {error}"),
+                }
+            }
+        });
+
+        Ok(Self {
+            namespace,
+            events: Mutex::new(events),
+            task,
+        })
+    }
+}
@@ -0,0 +1,361 @@
+use iroh_blobs::{
+    Hash,
+    store::{ExportFormat, ExportMode},
+};
+use iroh_docs::store::Query;
+use log::{info, warn};
+use serde::Serialize;
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::{fs, sync::watch, task::JoinSet};
+use tokio_stream::StreamExt;
+
+use crate::{
+    IrohManager, RESERVED_PREFIX, TOMBSTONE, directory,
+    errors::{Result, SharedError},
+    split,
+    types::UNamespaceId,
+};
+
+/// How many files are exported at the same time
+const PARALLEL_EXPORTS: usize = 4;
+/// Suffix of files being exported, they are renamed once complete
+pub(crate) const PARTIAL_SUFFIX: &str = ".unimusic-partial";
+/// Characters which aren't allowed in file names on at least one supported platform
+const INVALID_CHARACTERS: &[char] = &['<', '>', ':', '"', '\\', '|', '?', '*'];
+/// File names reserved on Windows, with or without an extension
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+#[cfg_attr(feature = "default", derive(uniffi::Record))]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExportProgress {
+    /// Files to export, known once entries of the namespace are listed
+    pub total_files: u64,
+    /// Files exported, skipped or failed so far
+    pub processed_files: u64,
+    pub total_bytes: u64,
+    pub processed_bytes: u64,
+}
+
+/// Handle allowing to follow progress of [`IrohManager::export_all`]
+#[cfg_attr(feature = "default", derive(uniffi::Object))]
+#[derive(Debug)]
+pub struct ExportProgressHandle {
+    progress: watch::Sender<ExportProgress>,
+}
+
+impl Default for ExportProgressHandle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg_attr(feature = "default", uniffi::export)]
+impl ExportProgressHandle {
+    #[cfg_attr(feature = "default", uniffi::constructor)]
+    pub fn new() -> Self {
+        Self {
+            progress: watch::Sender::new(ExportProgress::default()),
+        }
+    }
+
+    pub fn progress(&self) -> ExportProgress {
+        self.progress.borrow().clone()
+    }
+}
+
+impl ExportProgressHandle {
+    /// Receiver notified whenever the progress changes
+    pub fn subscribe(&self) -> watch::Receiver<ExportProgress> {
+        self.progress.subscribe()
+    }
+}
+
+#[cfg_attr(feature = "default", derive(uniffi::Record))]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ExportFailure {
+    pub key: String,
+    pub error: String,
+}
+
+#[cfg_attr(feature = "default", derive(uniffi::Record))]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ExportReport {
+    pub exported: u64,
+    /// Files whose destination already had the same content
+    pub unchanged: u64,
+    /// Deleted keys, they have nothing to export
+    pub tombstoned: u64,
+    pub exported_bytes: u64,
+    pub failures: Vec<ExportFailure>,
+}
+
+#[derive(Debug)]
+struct ExportTask {
+    key: String,
+    path: PathBuf,
+    hash: Hash,
+    /// Hash and length of the file, which differ from those of the blob for split files
+    file_hash: Hash,
+    len: u64,
+}
+
+/// Turns a key into a relative path, replacing characters and names which aren't valid file names.
+/// Returns `None` for keys without any usable component.
+pub(crate) fn sanitize_key(key: &str) -> Option<PathBuf> {
+    let path: PathBuf = key
+        .split('/')
+        .filter(|component| !component.is_empty())
+        .map(sanitize_component)
+        .collect();
+
+    (!path.as_os_str().is_empty()).then_some(path)
+}
+
+fn sanitize_component(component: &str) -> String {
+    let mut name: String = component
+        .chars()
+        .map(|c| {
+            if c.is_control() || INVALID_CHARACTERS.contains(&c) {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect();
+
+    // Windows drops trailing dots and spaces, which could make two names collide
+    let trimmed = name.trim_end_matches(['.', ' ']).len();
+    if trimmed != name.len() {
+        name.truncate(trimmed);
+        name.push('_');
+    }
+
+    let stem = name.split('.').next().unwrap_or_default();
+    if name.is_empty()
+        || RESERVED_NAMES
+            .iter()
+            .any(|reserved| reserved.eq_ignore_ascii_case(stem))
+    {
+        name.insert(0, '_');
+    }
+
+    name
+}
+
+/// Exports the blob into a temporary file next to the destination, then moves it into place,
+/// so the destination never holds partial content
+pub(crate) async fn export_blob(manager: &IrohManager, hash: Hash, path: &Path) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(PARTIAL_SUFFIX);
+    let partial = PathBuf::from(partial);
+
+    let result = async {
+        match split::load_manifest(manager, hash).await? {
+            Some(manifest) => split::export_parts(manager, &manifest, &partial).await?,
+            None => {
+                manager
+                    .blobs
+                    .client()
+                    .export(hash, partial.clone(), ExportFormat::Blob, ExportMode::Copy)
+                    .await?
+                    .await?;
+            }
+        }
+        fs::rename(&partial, path).await?;
+        Ok(())
+    }
+    .await;
+
+    if result.is_err() {
+        let _ = fs::remove_file(&partial).await;
+    }
+    result
+}
+
+/// Whether the file already has the given content
+async fn is_unchanged(path: &Path, hash: Hash, len: u64) -> bool {
+    match fs::metadata(path).await {
+        Ok(metadata) if metadata.is_file() && metadata.len() == len => {}
+        _ => return false,
+    }
+
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || directory::hash_file(&path))
+        .await
+        .is_ok_and(|result| result.is_ok_and(|file_hash| file_hash == hash))
+}
+
+pub(crate) async fn export_all(
+    manager: &IrohManager,
+    namespace: UNamespaceId,
+    prefix: &str,
+    destination_dir: &Path,
+    progress: Option<Arc<ExportProgressHandle>>,
+) -> Result<ExportReport> {
+    let replica = manager
+        .docs
+        .client()
+        .open(namespace.into())
+        .await?
+        .ok_or(SharedError::ReplicaMissing(namespace))?;
+
+    // Prefix is a folder, so `music` exports `music/...` but not `musicals/...`
+    let prefix = match prefix.trim_end_matches('/') {
+        "" => String::new(),
+        prefix => format!("{prefix}/"),
+    };
+    let query = if prefix.is_empty() {
+        Query::single_latest_per_key()
+    } else {
+        Query::single_latest_per_key().key_prefix(&prefix)
+    };
+
+    let mut report = ExportReport::default();
+    let mut tasks = Vec::new();
+    let mut paths = HashSet::new();
+
+    let mut entries = replica.get_many(query).await?;
+    while let Some(entry) = entries.try_next().await? {
+        let key = String::from_utf8_lossy(entry.key()).into_owned();
+        if key.starts_with(RESERVED_PREFIX) {
+            continue;
+        }
+        if entry.content_hash() == Hash::new(TOMBSTONE) {
+            report.tombstoned += 1;
+            continue;
+        }
+
+        let relative = key.strip_prefix(&prefix).and_then(sanitize_key);
+        let Some(relative) = relative else {
+            report.failures.push(ExportFailure {
+                key,
+                error: "Key has no valid file name".to_string(),
+            });
+            continue;
+        };
+
+        // Sanitised keys can collide, the first one wins
+        let path = destination_dir.join(relative);
+        if !paths.insert(path.clone()) {
+            report.failures.push(ExportFailure {
+                error: format!("{} is already exported from another key", path.display()),
+                key,
+            });
+            continue;
+        }
+
+        let (file_hash, len) =
+            split::file_info(manager, entry.content_hash(), entry.content_len()).await;
+        tasks.push(ExportTask {
+            key,
+            path,
+            hash: entry.content_hash(),
+            file_hash,
+            len,
+        });
+    }
+
+    if let Some(progress) = &progress {
+        progress.progress.send_modify(|progress| {
+            progress.total_files = tasks.len() as u64;
+            progress.total_bytes = tasks.iter().map(|task| task.len).sum();
+        });
+    }
+
+    fs::create_dir_all(destination_dir).await?;
+
+    let mut tasks = tasks.into_iter();
+    let mut running = JoinSet::new();
+    loop {
+        while running.len() < PARALLEL_EXPORTS {
+            let Some(task) = tasks.next() else {
+                break;
+            };
+
+            let manager = manager.clone();
+            running.spawn(async move {
+                let result = if is_unchanged(&task.path, task.file_hash, task.len).await {
+                    Ok(false)
+                } else {
+                    export_blob(&manager, task.hash, &task.path)
+                        .await
+                        .map(|_| true)
+                };
+                (task, result)
+            });
+        }
+
+        let Some(finished) = running.join_next().await else {
+            break;
+        };
+        let (task, result) = finished.map_err(|error| SharedError::Iroh(error.to_string()))?;
+
+        match result {
+            Ok(true) => {
+                report.exported += 1;
+                report.exported_bytes += task.len;
+            }
+            Ok(false) => report.unchanged += 1,
+            Err(error) => {
+                warn!("[export] failed to export {}: {error}", task.key);
+                report.failures.push(ExportFailure {
+                    key: task.key,
+                    error: error.to_string(),
+                });
+            }
+        }
+
+        if let Some(progress) = &progress {
+            progress.progress.send_modify(|progress| {
+                progress.processed_files += 1;
+                progress.processed_bytes += task.len;
+            });
+        }
+    }
+
+    info!(
+        "[export] exported namespace {namespace} into {}: {} exported, {} unchanged, {} failed",
+        destination_dir.display(),
+        report.exported,
+        report.unchanged,
+        report.failures.len()
+    );
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod test {
+    use super::sanitize_key;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_sanitize_key() {
+        assert_eq!(
+            sanitize_key("album/01 - intro.flac"),
+            Some(PathBuf::from("album/01 - intro.flac"))
+        );
+        assert_eq!(
+            sanitize_key("//what?/a:b*c.mp3"),
+            Some(PathBuf::from("what_/a_b_c.mp3"))
+        );
+        assert_eq!(sanitize_key("../up/./x"), Some(PathBuf::from("_/up/_/x")));
+        assert_eq!(
+            sanitize_key("con.txt/aux"),
+            Some(PathBuf::from("_con.txt/_aux"))
+        );
+        assert_eq!(sanitize_key("trailing. "), Some(PathBuf::from("trailing_")));
+        assert_eq!(sanitize_key("///"), None);
+    }
+}
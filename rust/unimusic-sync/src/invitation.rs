@@ -0,0 +1,465 @@
+use iroh::{Endpoint, NodeAddr, NodeId, endpoint::Connection, protocol::ProtocolHandler};
+use iroh_base::ticket::Ticket;
+use iroh_docs::{
+    DocTicket,
+    protocol::Docs,
+    rpc::{AddrInfoOptions, client::docs::ShareMode},
+};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    future::Future,
+    path::PathBuf,
+    pin::Pin,
+    str::FromStr,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::RwLock;
+
+#[cfg(feature = "default")]
+use uniffi::deps::anyhow;
+
+use crate::{
+    PersistentStore,
+    encryption::{self, Encryption},
+    errors::{Result, SharedError},
+    node_storage::NodeStorage,
+    ticket::{TICKET_URI_PREFIX, uri_param},
+    types::UNamespaceId,
+};
+
+/// Protocol used to exchange an invitation for a namespace capability
+pub const INVITATION_ALPN: &[u8] = b"/unimusic/invitation/0";
+const INVITATION_URI_PARAM: &str = "invite";
+const MAX_MESSAGE_SIZE: usize = 16 * 1024;
+
+/// Ticket of an invitation, it carries neither the capability nor the namespace id,
+/// which is a read capability by itself. Both are handed out by the sharer only if
+/// the invitation is still valid.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InvitationTicket {
+    pub node: NodeAddr,
+    token: [u8; 32],
+}
+
+impl InvitationTicket {
+    /// Identifier of the invitation, which can be used to revoke it
+    pub fn id(&self) -> String {
+        self.token
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect()
+    }
+}
+
+impl Ticket for InvitationTicket {
+    const KIND: &'static str = "unimusicinvite";
+
+    fn to_bytes(&self) -> Vec<u8> {
+        postcard::to_stdvec(self).expect("postcard serialization failed")
+    }
+
+    fn from_bytes(bytes: &[u8]) -> std::result::Result<Self, iroh_base::ticket::Error> {
+        Ok(postcard::from_bytes(bytes)?)
+    }
+}
+
+impl Display for InvitationTicket {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", Ticket::serialize(self))
+    }
+}
+
+impl FromStr for InvitationTicket {
+    type Err = SharedError;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let s = s.trim();
+        let ticket = match s.strip_prefix(TICKET_URI_PREFIX) {
+            Some(query) => uri_param(query, INVITATION_URI_PARAM).ok_or_else(|| {
+                SharedError::InvalidInput(format!("Invitation URI is missing the ticket: {s}"))
+            })?,
+            None => s,
+        };
+
+        Ticket::deserialize(ticket)
+            .map_err(|error| SharedError::InvalidInput(format!("Invalid invitation: {error}")))
+    }
+}
+
+impl From<InvitationTicket> for String {
+    fn from(value: InvitationTicket) -> Self {
+        value.to_string()
+    }
+}
+
+#[cfg(feature = "default")]
+uniffi::custom_type!(InvitationTicket, String, {
+    lower: |item| item.to_string(),
+    try_lift: |string| Ok(InvitationTicket::from_str(&string)?)
+});
+
+/// Returns `unimusic://join?invite=...` URI of the invitation
+#[cfg_attr(feature = "default", uniffi::export)]
+pub fn invitation_to_uri(ticket: InvitationTicket) -> String {
+    format!("{TICKET_URI_PREFIX}{INVITATION_URI_PARAM}={ticket}")
+}
+
+/// Invitation created by this node
+#[cfg_attr(feature = "default", derive(uniffi::Record))]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Invitation {
+    pub id: String,
+    pub namespace: UNamespaceId,
+    pub ticket: InvitationTicket,
+    /// Unix timestamp in milliseconds
+    pub created_at_ms: u64,
+    /// Unix timestamp in milliseconds, `None` never expires
+    pub expires_at_ms: Option<u64>,
+    /// Invitation can be accepted only once
+    pub single_use: bool,
+    /// Invited nodes get only a read capability, so they can't change the namespace
+    #[serde(default)]
+    pub read_only: bool,
+    /// How many times the invitation has been accepted
+    pub uses: u32,
+    pub revoked: bool,
+}
+
+impl Invitation {
+    /// Checks whether the invitation can be accepted at given time
+    pub fn check(&self, now_ms: u64) -> std::result::Result<(), InvitationRejection> {
+        if self.revoked {
+            Err(InvitationRejection::Revoked)
+        } else if self
+            .expires_at_ms
+            .is_some_and(|expires_at| now_ms >= expires_at)
+        {
+            Err(InvitationRejection::Expired)
+        } else if self.single_use && self.uses > 0 {
+            Err(InvitationRejection::AlreadyUsed)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Reason why the sharer refused an invitation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, thiserror::Error)]
+pub enum InvitationRejection {
+    #[error("invitation does not exist")]
+    Unknown,
+    #[error("invitation has expired")]
+    Expired,
+    #[error("invitation has been revoked")]
+    Revoked,
+    #[error("invitation has already been used")]
+    AlreadyUsed,
+    #[error("namespace is no longer shared")]
+    NamespaceMissing,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct InvitationRequest {
+    token: [u8; 32],
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+enum InvitationResponse {
+    Accepted { ticket: Box<DocTicket> },
+    Rejected { reason: InvitationRejection },
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_millis() as u64)
+}
+
+/// Local list of invitations, which also answers invitation requests of other nodes
+#[derive(Debug, Clone)]
+pub struct Invitations {
+    path: PathBuf,
+    invitations: Arc<RwLock<HashMap<String, Invitation>>>,
+    node_storage: Arc<RwLock<NodeStorage>>,
+    docs: Docs<PersistentStore>,
+    encryption: Option<Encryption>,
+}
+
+impl Invitations {
+    /// Load invitations from given path, or start with an empty list if it doesn't exist
+    pub(crate) async fn load(
+        path: PathBuf,
+        node_storage: Arc<RwLock<NodeStorage>>,
+        docs: Docs<PersistentStore>,
+        encryption: Option<Encryption>,
+    ) -> Result<Self> {
+        let invitations = match encryption::read(&path, encryption.as_ref()).await? {
+            Some(file) => {
+                serde_json::from_slice(&file).map_err(|e| SharedError::Serde(e.to_string()))?
+            }
+            None => HashMap::new(),
+        };
+
+        Ok(Self {
+            path,
+            invitations: Arc::new(RwLock::new(invitations)),
+            node_storage,
+            docs,
+            encryption,
+        })
+    }
+
+    async fn save(&self, invitations: &HashMap<String, Invitation>) -> Result<()> {
+        let serialized = serde_json::to_vec_pretty(invitations)
+            .map_err(|e| SharedError::Serde(e.to_string()))?;
+        encryption::write(&self.path, &serialized, self.encryption.as_ref()).await
+    }
+
+    pub(crate) async fn create(
+        &self,
+        node: NodeAddr,
+        namespace: UNamespaceId,
+        expires_in_ms: Option<u64>,
+        single_use: bool,
+        read_only: bool,
+    ) -> Result<Invitation> {
+        let ticket = InvitationTicket {
+            node,
+            token: rand::random(),
+        };
+
+        let created_at_ms = now_ms();
+        let expires_at_ms =
+            expires_in_ms.map(|expires_in| created_at_ms.saturating_add(expires_in));
+        let invitation = Invitation {
+            id: ticket.id(),
+            namespace,
+            ticket,
+            created_at_ms,
+            expires_at_ms,
+            single_use,
+            read_only,
+            uses: 0,
+            revoked: false,
+        };
+
+        let mut invitations = self.invitations.write().await;
+        invitations.insert(invitation.id.clone(), invitation.clone());
+        self.save(&invitations).await?;
+        info!("[invitation] created invitation for namespace {namespace}");
+
+        Ok(invitation)
+    }
+
+    /// Returns invitations of the namespace, or all of them
+    pub async fn list(&self, namespace: Option<UNamespaceId>) -> Vec<Invitation> {
+        let invitations = self.invitations.read().await;
+        let mut list: Vec<Invitation> = invitations
+            .values()
+            .filter(|invitation| {
+                namespace.is_none_or(|namespace| invitation.namespace == namespace)
+            })
+            .cloned()
+            .collect();
+        list.sort_by_key(|invitation| invitation.created_at_ms);
+        list
+    }
+
+    pub(crate) async fn revoke(&self, id: &str) -> Result<()> {
+        let mut invitations = self.invitations.write().await;
+        let invitation = invitations
+            .get_mut(id)
+            .ok_or_else(|| SharedError::NotFound(format!("Invitation {id}")))?;
+        invitation.revoked = true;
+        info!(
+            "[invitation] revoked invitation for namespace {}",
+            invitation.namespace
+        );
+        self.save(&invitations).await
+    }
+
+    /// Removes all invitations of the namespace
+    pub(crate) async fn forget_namespace(&self, namespace: &UNamespaceId) -> Result<()> {
+        let mut invitations = self.invitations.write().await;
+        invitations.retain(|_, invitation| invitation.namespace != *namespace);
+        self.save(&invitations).await
+    }
+
+    /// Validates the invitation and returns the capability, counting it as used
+    async fn redeem(
+        &self,
+        token: &[u8; 32],
+        node_id: NodeId,
+    ) -> Result<std::result::Result<DocTicket, InvitationRejection>> {
+        let id: String = token.iter().map(|byte| format!("{byte:02x}")).collect();
+
+        // Lock is held until the use is saved, so a single use invitation can't be redeemed twice
+        let mut invitations = self.invitations.write().await;
+        let Some(invitation) = invitations.get_mut(&id) else {
+            return Ok(Err(InvitationRejection::Unknown));
+        };
+        if let Err(rejection) = invitation.check(now_ms()) {
+            return Ok(Err(rejection));
+        }
+
+        let namespace = invitation.namespace;
+        let Some(replica) = self.docs.client().open(namespace.into()).await? else {
+            return Ok(Err(InvitationRejection::NamespaceMissing));
+        };
+        let mode = if invitation.read_only {
+            ShareMode::Read
+        } else {
+            ShareMode::Write
+        };
+        let ticket = replica
+            .share(mode, AddrInfoOptions::RelayAndAddresses)
+            .await?;
+
+        invitation.uses = invitation.uses.saturating_add(1);
+        self.save(&invitations).await?;
+
+        self.node_storage
+            .write()
+            .await
+            .associate_node(namespace, node_id);
+        info!(
+            "[invitation] node {} joined namespace {namespace}",
+            node_id.fmt_short()
+        );
+
+        Ok(Ok(ticket))
+    }
+
+    async fn handle(&self, connection: Connection) -> anyhow::Result<()> {
+        let node_id = connection.remote_node_id()?;
+        let (mut send, mut recv) = connection.accept_bi().await?;
+
+        let request: InvitationRequest =
+            serde_json::from_slice(&recv.read_to_end(MAX_MESSAGE_SIZE).await?)?;
+
+        let response = match self.redeem(&request.token, node_id).await? {
+            Ok(ticket) => InvitationResponse::Accepted {
+                ticket: Box::new(ticket),
+            },
+            Err(reason) => {
+                warn!(
+                    "[invitation] rejected node {}: {reason}",
+                    node_id.fmt_short()
+                );
+                InvitationResponse::Rejected { reason }
+            }
+        };
+
+        send.write_all(&serde_json::to_vec(&response)?).await?;
+        send.finish()?;
+        connection.closed().await;
+
+        Ok(())
+    }
+}
+
+impl ProtocolHandler for Invitations {
+    fn accept(
+        &self,
+        connection: Connection,
+    ) -> Pin<Box<dyn Future<Output = anyhow::Result<()>> + Send + 'static>> {
+        let this = self.clone();
+        Box::pin(async move { this.handle(connection).await })
+    }
+}
+
+/// Asks the sharer to exchange the invitation for a namespace capability
+pub(crate) async fn request_capability(
+    endpoint: &Endpoint,
+    ticket: &InvitationTicket,
+) -> Result<DocTicket> {
+    let response = async {
+        let connection = endpoint
+            .connect(ticket.node.clone(), INVITATION_ALPN)
+            .await?;
+        let (mut send, mut recv) = connection.open_bi().await?;
+
+        let request = InvitationRequest {
+            token: ticket.token,
+        };
+        send.write_all(&serde_json::to_vec(&request)?).await?;
+        send.finish()?;
+
+        let response: InvitationResponse =
+            serde_json::from_slice(&recv.read_to_end(MAX_MESSAGE_SIZE).await?)?;
+        connection.close(0u32.into(), b"done");
+
+        anyhow::Ok(response)
+    }
+    .await?;
+
+    match response {
+        InvitationResponse::Accepted { ticket } => Ok(*ticket),
+        InvitationResponse::Rejected { reason } => Err(SharedError::PermissionDenied(format!(
+            "Invitation rejected: {reason}"
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Invitation, InvitationRejection, InvitationTicket, invitation_to_uri};
+
+    use iroh::{NodeAddr, SecretKey};
+    use iroh_docs::NamespaceSecret;
+    use std::str::FromStr;
+
+    fn mock_invitation() -> Invitation {
+        let mut rng = rand::thread_rng();
+        let namespace = NamespaceSecret::new(&mut rng).id();
+        let ticket = InvitationTicket {
+            node: NodeAddr::new(SecretKey::generate(&mut rng).public()),
+            token: rand::random(),
+        };
+
+        Invitation {
+            id: ticket.id(),
+            namespace: namespace.into(),
+            ticket,
+            created_at_ms: 1_000,
+            expires_at_ms: Some(2_000),
+            single_use: true,
+            read_only: false,
+            uses: 0,
+            revoked: false,
+        }
+    }
+
+    #[test]
+    fn test_ticket_roundtrip() {
+        let ticket = mock_invitation().ticket;
+
+        let parsed = InvitationTicket::from_str(&ticket.to_string()).unwrap();
+        assert_eq!(parsed, ticket);
+
+        let parsed = InvitationTicket::from_str(&invitation_to_uri(ticket.clone())).unwrap();
+        assert_eq!(parsed, ticket);
+    }
+
+    #[test]
+    fn test_check() {
+        let mut invitation = mock_invitation();
+        assert_eq!(invitation.check(1_500), Ok(()));
+        assert_eq!(invitation.check(2_000), Err(InvitationRejection::Expired));
+
+        invitation.uses = 1;
+        assert_eq!(
+            invitation.check(1_500),
+            Err(InvitationRejection::AlreadyUsed)
+        );
+
+        invitation.single_use = false;
+        assert_eq!(invitation.check(1_500), Ok(()));
+
+        invitation.revoked = true;
+        assert_eq!(invitation.check(1_500), Err(InvitationRejection::Revoked));
+    }
+}
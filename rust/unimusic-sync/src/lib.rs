@@ -7,27 +7,106 @@ use types::{UAuthorId, UDocTicket, UEntry, UHash, UNamespaceId, UNodeId};
 pub mod node_storage;
 use node_storage::NodeStorage;
 
+pub mod ticket;
+
+pub mod directory;
+use directory::{DirectoryImportReport, IndexLocks};
+
+pub mod backup;
+use backup::{BackupReport, RestoreReport};
+
+pub mod export;
+use export::{ExportProgressHandle, ExportReport};
+
+pub mod mirror;
+
+pub mod split;
+
+pub mod metadata;
+
+pub mod artwork;
+use metadata::TrackMetadata;
+use mirror::{Mirror, MirrorInfo};
+
+pub mod search;
+use search::{LiveSearchIndex, SearchResult};
+
+pub mod streaming;
+use streaming::{StreamingServer, StreamingServerInfo};
+
+#[cfg(test)]
+mod simulation;
+#[cfg(test)]
+mod testing;
+
+pub mod encryption;
+use encryption::{Encryption, EncryptionSecret};
+
+pub mod encrypted_blobs;
+use encrypted_blobs::EncryptedStore;
+
+pub mod encrypted_docs;
+use encrypted_docs::DocsSnapshot;
+
+pub mod blob_store;
+use blob_store::BlobStore;
+
+pub mod invitation;
+use invitation::{INVITATION_ALPN, Invitation, InvitationTicket, Invitations};
+
+pub mod backoff;
+use backoff::Backoff;
+
+pub mod scheduler;
+use scheduler::{AutoSync, AutoSyncConfig};
+
+pub mod cancellation;
+use cancellation::{CancellationHandle, run_cancellable};
+
+pub mod events;
+use events::EventSubscription;
+
+pub mod relay;
+
+pub mod diagnostics;
+use diagnostics::Diagnostics;
+
+pub mod reconnect;
+use reconnect::{
+    PeerConnectionType, PeerReconnectResult, RECONNECT_TIMEOUT_MS, ReconnectOutcome,
+    ReconnectReport,
+};
+
 use log::{info, warn};
 
-use std::{borrow::Cow, fmt::Debug, path::PathBuf, sync::Arc};
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fmt::Debug,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, Weak},
+    time::{Duration, Instant},
+};
 
-use iroh::{Endpoint, NodeAddr, node_info::NodeData, protocol::Router};
+use iroh::{Endpoint, NodeAddr, RelayMap, RelayMode, node_info::NodeData, protocol::Router};
 use iroh_blobs::{
-    ALPN as BLOBS_ALPN, Hash,
+    ALPN as BLOBS_ALPN, BlobFormat, Hash,
     net_protocol::Blobs,
-    store::{ExportFormat, ExportMode},
+    rpc::client::blobs::{DownloadMode, DownloadOptions},
+    util::SetTagOption,
 };
 use iroh_docs::{
     ALPN as DOCS_ALPN, DocTicket,
-    engine::LiveEvent,
+    actor::SyncHandle,
+    engine::{DefaultAuthorStorage, Engine, LiveEvent},
     protocol::Docs,
     rpc::{AddrInfoOptions, client::docs::ShareMode},
     store::Query,
 };
 use iroh_gossip::{ALPN as GOSSIP_ALPN, net::Gossip};
 
-use tokio::{fs, sync::RwLock};
-use tokio_stream::StreamExt;
+use tokio::{sync::RwLock, task::JoinSet};
+use tokio_stream::{Stream, StreamExt};
 
 #[cfg(feature = "default")]
 use {std::sync::LazyLock, tokio::runtime::Runtime};
@@ -44,48 +123,150 @@ pub static TOKIO_RUNTIME: LazyLock<Runtime> = LazyLock::new(|| {
     runtime
 });
 
+/// Creates [`IrohManager`]s.
+///
+/// With a key or passphrase, everything the node stores is encrypted at rest: its secret key,
+/// the files describing peers and invitations, blobs and entries. Blobs go to an
+/// [`EncryptedStore`] and entries are kept in memory, saved to an encrypted snapshot by
+/// [`DocsSnapshot`]. Files of a store created without encryption are still read and get
+/// encrypted when it's opened with encryption for the first time.
 #[cfg_attr(feature = "default", derive(uniffi::Object))]
 #[derive(Debug)]
-pub struct IrohFactory;
+pub struct IrohFactory {
+    encryption: Option<EncryptionSecret>,
+    discovery: bool,
+    relay_mode: RelayMode,
+}
+
+impl Default for IrohFactory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 #[cfg_attr(feature = "default", uniffi::export(async_runtime = "tokio"))]
 impl IrohFactory {
     #[cfg_attr(feature = "default", uniffi::constructor)]
     pub fn new() -> Self {
-        Self {}
+        Self {
+            encryption: None,
+            discovery: true,
+            relay_mode: RelayMode::Default,
+        }
+    }
+
+    /// Encrypts the store with a 32 bytes long key
+    #[cfg_attr(feature = "default", uniffi::constructor)]
+    pub fn with_encryption_key(key: Vec<u8>) -> Result<Self> {
+        Ok(Self {
+            encryption: Some(EncryptionSecret::key(&key)?),
+            ..Self::new()
+        })
+    }
+
+    /// Encrypts the store with a key derived from the passphrase
+    #[cfg_attr(feature = "default", uniffi::constructor)]
+    pub fn with_passphrase(passphrase: String) -> Self {
+        Self {
+            encryption: Some(EncryptionSecret::Passphrase(passphrase)),
+            ..Self::new()
+        }
+    }
+
+    /// Copy of the factory whose nodes use only the given relays instead of the default ones
+    #[cfg_attr(feature = "default", uniffi::method)]
+    pub fn with_relays(self: Arc<Self>, relay_urls: Vec<String>) -> Result<Arc<Self>> {
+        let relay_map = relay::parse_relay_map(&relay_urls)?;
+        Ok(Arc::new(Self {
+            encryption: self.encryption.clone(),
+            discovery: self.discovery,
+            relay_mode: RelayMode::Custom(relay_map),
+        }))
     }
 
     #[cfg_attr(feature = "default", uniffi::method(async_runtime = "tokio"))]
     pub async fn iroh_manager(&self, path: &str) -> Result<IrohManager> {
         let path = PathBuf::from(path);
 
+        let encryption = match &self.encryption {
+            Some(secret) => {
+                let encryption = Encryption::open(&path, secret).await?;
+                encryption::migrate(&path, &encryption).await?;
+                info!("[encryption] store is encrypted");
+                Some(encryption)
+            }
+            None => None,
+        };
+
         // Load or generate secret key to preserve the NodeId
-        let secret_key = iroh_blobs::util::fs::load_secret_key(path.join("secret.key")).await?;
+        let secret_key =
+            encryption::load_secret_key(path.join("secret.key"), encryption.as_ref()).await?;
 
-        let endpoint = Endpoint::builder()
+        let mut endpoint = Endpoint::builder()
             .secret_key(secret_key)
-            .discovery_n0()
-            .discovery_local_network()
-            .discovery_dht()
-            .bind()
-            .await?;
+            .relay_mode(self.relay_mode.clone());
+        if self.discovery {
+            endpoint = endpoint
+                .discovery_n0()
+                .discovery_local_network()
+                .discovery_dht();
+        }
+        let endpoint = endpoint.bind().await?;
+
+        let (blob_store, docs_store) = match &encryption {
+            Some(encryption) => {
+                let blob_store =
+                    EncryptedStore::load(path.join(encrypted_blobs::BLOBS_DIR), encryption.clone())
+                        .await?;
+                encrypted_blobs::migrate(&path, &blob_store).await?;
+                let docs_store = encrypted_docs::load(&path, encryption).await?;
+                (BlobStore::Encrypted(blob_store), docs_store)
+            }
+            None => (
+                BlobStore::Plain(iroh_blobs::store::fs::Store::load(&path).await?),
+                iroh_docs::store::Store::persistent(path.join("docs.redb"))?,
+            ),
+        };
 
-        let blobs = Blobs::persistent(&path).await?.build(&endpoint);
+        let blobs = Blobs::builder(blob_store).build(&endpoint);
         let gossip = Gossip::builder().spawn(endpoint.clone()).await?;
-        let docs = Docs::persistent(path.clone())
-            .spawn(&blobs, &gossip)
-            .await?;
+        // Same as `Docs::persistent`, but keeps a handle of the replica store, which reads and
+        // inserts entries with their signatures, unlike the RPC client
+        let engine = Engine::spawn(
+            endpoint.clone(),
+            gossip.clone(),
+            docs_store,
+            blobs.store().clone(),
+            blobs.downloader().clone(),
+            DefaultAuthorStorage::Persistent(path.join("default-author")),
+            blobs.rt().clone(),
+        )
+        .await?;
+        let replicas = engine.sync.clone();
+        let docs = Docs::new(engine);
+        let docs_snapshot = encryption
+            .as_ref()
+            .map(|encryption| DocsSnapshot::spawn(&path, encryption.clone(), replicas.clone()));
+
+        let node_storage = NodeStorage::load(path.join("nodes.json"), encryption.as_ref()).await?;
+
+        let node_storage = Arc::new(RwLock::new(node_storage));
+
+        let invitations = Invitations::load(
+            path.join("invitations.json"),
+            node_storage.clone(),
+            docs.clone(),
+            encryption.clone(),
+        )
+        .await?;
 
         let router = Router::builder(endpoint.clone())
             .accept(BLOBS_ALPN, blobs.clone())
             .accept(GOSSIP_ALPN, gossip.clone())
             .accept(DOCS_ALPN, docs.clone())
+            .accept(INVITATION_ALPN, invitations.clone())
             .spawn();
 
-        let node_storage = NodeStorage::load(path.join("nodes.json")).await?;
-
-        let node_storage = Arc::new(RwLock::new(node_storage));
-
         {
             let node_storage = node_storage.clone();
             tokio::spawn(async move {
@@ -111,28 +292,164 @@ impl IrohFactory {
             path,
             router,
             node_storage,
+            invitations,
+            encryption,
+            reconnect_backoff: Default::default(),
+            auto_sync: Default::default(),
+            mirrors: Default::default(),
+            search_indexes: Default::default(),
+            streaming_server: Default::default(),
+            index_locks: Default::default(),
 
             blobs,
             gossip,
             docs,
+            replicas,
+            docs_snapshot,
         })
     }
 }
 
-type PersistentStore = iroh_blobs::store::fs::Store;
+impl IrohFactory {
+    /// Turns off DNS, DHT and local network discovery,
+    /// so peers are only reachable through addresses they are given
+    pub fn without_discovery(mut self) -> Self {
+        self.discovery = false;
+        self
+    }
+
+    /// Uses only the given relays instead of the default ones
+    pub fn with_relay_map(mut self, relay_map: RelayMap) -> Self {
+        self.relay_mode = RelayMode::Custom(relay_map);
+        self
+    }
+
+    /// Turns off relays, so peers are only reachable through their direct addresses
+    pub fn without_relay(mut self) -> Self {
+        self.relay_mode = RelayMode::Disabled;
+        self
+    }
+}
+
+pub(crate) type PersistentStore = BlobStore;
 
-const TOMBSTONE: &[u8] = b"\00";
+const TOMBSTONE: &[u8] = b"\x000";
+/// Keys used internally, they aren't listed as files
+const RESERVED_PREFIX: &str = ".unimusic/";
+
+/// Rejects keys which files can't be written to
+fn check_writable_key(key: &str) -> Result<()> {
+    if key.starts_with(RESERVED_PREFIX) {
+        return Err(SharedError::InvalidInput(format!(
+            "Keys starting with {RESERVED_PREFIX} are reserved"
+        )));
+    }
+    Ok(())
+}
+
+/// Which peers should be contacted when syncing a namespace
+#[cfg_attr(feature = "default", derive(uniffi::Enum))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SyncScope {
+    /// Only peers known to participate in the namespace, none if no peer has been recorded yet
+    #[default]
+    Namespace,
+    /// Every known peer
+    AllKnown,
+}
 
 #[cfg_attr(feature = "default", derive(uniffi::Object))]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct IrohManager {
     pub path: PathBuf,
     pub router: Router,
     pub node_storage: Arc<RwLock<NodeStorage>>,
+    pub invitations: Invitations,
+    pub encryption: Option<Encryption>,
+    pub reconnect_backoff: Arc<Mutex<Backoff<UNodeId>>>,
+    pub auto_sync: Arc<Mutex<AutoSync>>,
+    pub mirrors: Arc<Mutex<HashMap<UNamespaceId, Mirror>>>,
+    pub search_indexes: Arc<Mutex<HashMap<UNamespaceId, Arc<LiveSearchIndex>>>>,
+    pub streaming_server: Arc<Mutex<Option<StreamingServer>>>,
+    pub(crate) index_locks: IndexLocks,
 
     pub blobs: Blobs<PersistentStore>,
     pub gossip: Gossip,
     pub docs: Docs<PersistentStore>,
+    pub(crate) replicas: SyncHandle,
+    pub(crate) docs_snapshot: Option<DocsSnapshot>,
+}
+
+/// Manager held by its own background tasks. The state owning those tasks is referenced
+/// weakly, so the tasks stop once every [`IrohManager`] is dropped instead of keeping it alive.
+#[derive(Debug, Clone)]
+pub(crate) struct WeakIrohManager {
+    path: PathBuf,
+    router: Router,
+    node_storage: Arc<RwLock<NodeStorage>>,
+    invitations: Invitations,
+    encryption: Option<Encryption>,
+    reconnect_backoff: Arc<Mutex<Backoff<UNodeId>>>,
+    auto_sync: Weak<Mutex<AutoSync>>,
+    mirrors: Weak<Mutex<HashMap<UNamespaceId, Mirror>>>,
+    search_indexes: Weak<Mutex<HashMap<UNamespaceId, Arc<LiveSearchIndex>>>>,
+    streaming_server: Weak<Mutex<Option<StreamingServer>>>,
+    index_locks: IndexLocks,
+
+    blobs: Blobs<PersistentStore>,
+    gossip: Gossip,
+    docs: Docs<PersistentStore>,
+    replicas: SyncHandle,
+    docs_snapshot: Option<DocsSnapshot>,
+}
+
+impl WeakIrohManager {
+    /// Returns the manager unless it has been dropped
+    pub(crate) fn upgrade(&self) -> Option<IrohManager> {
+        Some(IrohManager {
+            path: self.path.clone(),
+            router: self.router.clone(),
+            node_storage: self.node_storage.clone(),
+            invitations: self.invitations.clone(),
+            encryption: self.encryption.clone(),
+            reconnect_backoff: self.reconnect_backoff.clone(),
+            auto_sync: self.auto_sync.upgrade()?,
+            mirrors: self.mirrors.upgrade()?,
+            search_indexes: self.search_indexes.upgrade()?,
+            streaming_server: self.streaming_server.upgrade()?,
+            index_locks: self.index_locks.clone(),
+
+            blobs: self.blobs.clone(),
+            gossip: self.gossip.clone(),
+            docs: self.docs.clone(),
+            replicas: self.replicas.clone(),
+            docs_snapshot: self.docs_snapshot.clone(),
+        })
+    }
+}
+
+impl IrohManager {
+    pub(crate) fn downgrade(&self) -> WeakIrohManager {
+        WeakIrohManager {
+            path: self.path.clone(),
+            router: self.router.clone(),
+            node_storage: self.node_storage.clone(),
+            invitations: self.invitations.clone(),
+            encryption: self.encryption.clone(),
+            reconnect_backoff: self.reconnect_backoff.clone(),
+            auto_sync: Arc::downgrade(&self.auto_sync),
+            mirrors: Arc::downgrade(&self.mirrors),
+            search_indexes: Arc::downgrade(&self.search_indexes),
+            streaming_server: Arc::downgrade(&self.streaming_server),
+            index_locks: self.index_locks.clone(),
+
+            blobs: self.blobs.clone(),
+            gossip: self.gossip.clone(),
+            docs: self.docs.clone(),
+            replicas: self.replicas.clone(),
+            docs_snapshot: self.docs_snapshot.clone(),
+        }
+    }
 }
 
 #[cfg_attr(feature = "default", uniffi::export(async_runtime = "tokio"))]
@@ -140,33 +457,168 @@ impl IrohManager {
     // TODO: Add channel/lock which notifies storage to stop locking so shutdown doesn't get starved
     #[cfg_attr(feature = "default", uniffi::method(async_runtime = "tokio"))]
     pub async fn shutdown(&self) -> Result<()> {
+        self.stop_auto_sync();
+        self.mirrors.lock().unwrap().clear();
+        self.search_indexes.lock().unwrap().clear();
+        self.stop_streaming_server();
+
+        if let Some(docs_snapshot) = &self.docs_snapshot {
+            docs_snapshot.close().await?;
+        }
+
         let node_storage = self.node_storage.read().await;
         let (shutdown, save) = tokio::join!(
             self.router.shutdown(),
-            node_storage.save(self.path.join("nodes.json"))
+            node_storage.save(self.path.join("nodes.json"), self.encryption.as_ref())
         );
         shutdown?;
         save?;
         Ok(())
     }
 
-    pub async fn reconnect(&self) {
-        for (node_id, node_data) in self.node_storage.read().await.nodes.iter() {
-            let node_addr = NodeAddr::from_parts(
-                (*node_id).into(),
-                node_data.relay_url.clone(),
-                node_data.direct_addresses.clone(),
-            );
+    #[cfg_attr(feature = "default", uniffi::method(async_runtime = "tokio"))]
+    pub async fn reconnect(&self) -> ReconnectReport {
+        self.reconnect_with_timeout(RECONNECT_TIMEOUT_MS).await
+    }
 
-            match self.router.endpoint().connect(node_addr, DOCS_ALPN).await {
-                Ok(_) => {
-                    info!("[reconnect] Connected to {node_id}");
-                }
-                Err(error) => {
-                    warn!("[reconnect] Failed to establish a connection with {node_id}: {error}")
+    /// Dials all known nodes in parallel, giving each of them `timeout_ms` to connect.
+    /// Nodes which failed recently are skipped until their backoff expires.
+    #[cfg_attr(feature = "default", uniffi::method(async_runtime = "tokio"))]
+    pub async fn reconnect_with_timeout(&self, timeout_ms: u64) -> ReconnectReport {
+        let timeout = Duration::from_millis(timeout_ms);
+        let mut report = ReconnectReport::default();
+
+        let node_addrs: Vec<(UNodeId, NodeAddr)> = {
+            let node_storage = self.node_storage.read().await;
+            let backoff = self.reconnect_backoff.lock().unwrap();
+
+            node_storage
+                .nodes
+                .iter()
+                .filter_map(|(node_id, node_data)| {
+                    if let Some(remaining) = backoff.remaining(node_id) {
+                        info!("[reconnect] Skipping {node_id}, backing off for {remaining:?}");
+                        report.peers.push(PeerReconnectResult {
+                            node_id: *node_id,
+                            outcome: ReconnectOutcome::BackingOff {
+                                retry_in_ms: remaining.as_millis() as u64,
+                            },
+                            connection_type: PeerConnectionType::None,
+                            latency_ms: None,
+                        });
+                        return None;
+                    }
+
+                    let node_addr = NodeAddr::from_parts(
+                        (*node_id).into(),
+                        node_data.relay_url.clone(),
+                        node_data.direct_addresses.clone(),
+                    );
+                    Some((*node_id, node_addr))
+                })
+                .collect()
+        };
+
+        let mut set = JoinSet::new();
+        for (node_id, node_addr) in node_addrs {
+            let endpoint = self.router.endpoint().clone();
+            set.spawn(async move {
+                let started = Instant::now();
+                let connection =
+                    tokio::time::timeout(timeout, endpoint.connect(node_addr, DOCS_ALPN)).await;
+
+                let outcome = match connection {
+                    Ok(Ok(connection)) => {
+                        let latency = connection.rtt().min(started.elapsed());
+                        let connection_type = endpoint
+                            .remote_info(node_id.into())
+                            .map_or(PeerConnectionType::None, |info| (&info.conn_type).into());
+
+                        info!(
+                            "[reconnect] Connected to {node_id} ({connection_type:?}, {latency:?})"
+                        );
+                        return PeerReconnectResult {
+                            node_id,
+                            outcome: ReconnectOutcome::Connected,
+                            connection_type,
+                            latency_ms: Some(latency.as_millis() as u64),
+                        };
+                    }
+                    Ok(Err(error)) => {
+                        warn!(
+                            "[reconnect] Failed to establish a connection with {node_id}: {error}"
+                        );
+                        ReconnectOutcome::Failed {
+                            reason: error.to_string(),
+                        }
+                    }
+                    Err(_) => {
+                        warn!("[reconnect] Connecting to {node_id} timed out after {timeout:?}");
+                        ReconnectOutcome::TimedOut
+                    }
+                };
+
+                PeerReconnectResult {
+                    node_id,
+                    outcome,
+                    connection_type: PeerConnectionType::None,
+                    latency_ms: None,
                 }
+            });
+        }
+
+        for result in set.join_all().await {
+            let mut backoff = self.reconnect_backoff.lock().unwrap();
+            if result.outcome == ReconnectOutcome::Connected {
+                backoff.succeeded(&result.node_id);
+            } else {
+                let delay = backoff.failed(result.node_id);
+                info!(
+                    "[reconnect] Next attempt to {} in {delay:?}",
+                    result.node_id
+                );
             }
+            report.peers.push(result);
         }
+
+        report
+    }
+
+    /// Starts syncing auto-synced namespaces in the background.
+    /// Namespaces get synced periodically, after local writes and whenever their peers come back.
+    #[cfg_attr(feature = "default", uniffi::method(async_runtime = "tokio"))]
+    pub async fn start_auto_sync(&self, config: AutoSyncConfig) {
+        self.auto_sync.lock().unwrap().start(self, config);
+    }
+
+    #[cfg_attr(feature = "default", uniffi::method(async_runtime = "tokio"))]
+    pub async fn start_auto_sync_default(&self) {
+        self.start_auto_sync(AutoSyncConfig::default()).await;
+    }
+
+    #[cfg_attr(feature = "default", uniffi::method)]
+    pub fn stop_auto_sync(&self) {
+        self.auto_sync.lock().unwrap().stop();
+    }
+
+    #[cfg_attr(feature = "default", uniffi::method)]
+    pub fn is_auto_sync_running(&self) -> bool {
+        self.auto_sync.lock().unwrap().is_running()
+    }
+
+    #[cfg_attr(feature = "default", uniffi::method(async_runtime = "tokio"))]
+    pub async fn add_auto_sync_namespace(&self, namespace: UNamespaceId) {
+        self.auto_sync.lock().unwrap().add(self, namespace);
+    }
+
+    #[cfg_attr(feature = "default", uniffi::method)]
+    pub fn remove_auto_sync_namespace(&self, namespace: UNamespaceId) {
+        self.auto_sync.lock().unwrap().remove(&namespace);
+    }
+
+    #[cfg_attr(feature = "default", uniffi::method)]
+    pub fn get_auto_sync_namespaces(&self) -> Vec<UNamespaceId> {
+        self.auto_sync.lock().unwrap().namespaces()
     }
 
     #[cfg_attr(feature = "default", uniffi::method(async_runtime = "tokio"))]
@@ -180,6 +632,14 @@ impl IrohManager {
             .collect()
     }
 
+    #[cfg_attr(feature = "default", uniffi::method(async_runtime = "tokio"))]
+    pub async fn get_namespace_nodes(&self, namespace: UNamespaceId) -> Vec<UNodeId> {
+        self.node_storage
+            .read()
+            .await
+            .get_namespace_nodes(&namespace)
+    }
+
     #[cfg_attr(feature = "default", uniffi::method(async_runtime = "tokio"))]
     pub async fn get_author(&self) -> Result<UAuthorId> {
         let docs_client = self.docs.client();
@@ -194,6 +654,30 @@ impl IrohManager {
         node_id.into()
     }
 
+    /// Addresses, relay, NAT state and connections of this node
+    #[cfg_attr(feature = "default", uniffi::method(async_runtime = "tokio"))]
+    pub async fn diagnostics(&self) -> Diagnostics {
+        Diagnostics::collect(self.router.endpoint())
+    }
+
+    /// [`diagnostics`](Self::diagnostics) as pretty-printed JSON, to be attached to support tickets
+    #[cfg_attr(feature = "default", uniffi::method(async_runtime = "tokio"))]
+    pub async fn diagnostics_json(&self) -> Result<String> {
+        serde_json::to_string_pretty(&self.diagnostics().await)
+            .map_err(|e| SharedError::Serde(e.to_string()))
+    }
+
+    /// Namespaces stored on this node, including read-only ones
+    #[cfg_attr(feature = "default", uniffi::method(async_runtime = "tokio"))]
+    pub async fn get_namespaces(&self) -> Result<Vec<UNamespaceId>> {
+        let mut namespaces = Vec::new();
+        let mut list = self.docs.client().list().await?;
+        while let Some((namespace, _)) = list.try_next().await? {
+            namespaces.push(namespace.into());
+        }
+        Ok(namespaces)
+    }
+
     #[cfg_attr(feature = "default", uniffi::method(async_runtime = "tokio"))]
     pub async fn create_namespace(&self) -> Result<UNamespaceId> {
         let docs_client = self.docs.client();
@@ -204,7 +688,13 @@ impl IrohManager {
     #[cfg_attr(feature = "default", uniffi::method(async_runtime = "tokio"))]
     pub async fn delete_namespace(&self, namespace: UNamespaceId) -> Result<()> {
         let docs_client = self.docs.client();
+        self.remove_auto_sync_namespace(namespace);
+        self.stop_mirror(namespace);
+        self.search_indexes.lock().unwrap().remove(&namespace);
         docs_client.drop_doc(namespace.into()).await?;
+        self.node_storage.write().await.forget_namespace(&namespace);
+        self.invitations.forget_namespace(&namespace).await?;
+        directory::forget_namespace(self, &namespace).await;
         Ok(())
     }
 
@@ -222,11 +712,14 @@ impl IrohManager {
         let mut files = Vec::new();
         let tombstone_hash = Hash::new(TOMBSTONE);
         while let Some(file) = entries.try_next().await? {
-            if file.content_hash() == tombstone_hash {
+            if file.content_hash() == tombstone_hash
+                || file.key().starts_with(RESERVED_PREFIX.as_bytes())
+            {
                 continue;
             }
 
-            files.push(Arc::new(file.into()))
+            let (_, len) = split::file_info(self, file.content_hash(), file.content_len()).await;
+            files.push(Arc::new(UEntry::from(file).with_file_len(len)))
         }
 
         Ok(files)
@@ -234,8 +727,7 @@ impl IrohManager {
 
     #[cfg_attr(feature = "default", uniffi::method(async_runtime = "tokio"))]
     pub async fn delete_file(&self, namespace: UNamespaceId, path: String) -> Result<UHash> {
-        let tombstone_hash = self.write_file(namespace, path, TOMBSTONE.to_vec()).await;
-        return tombstone_hash;
+        self.write_file(namespace, path, TOMBSTONE.to_vec()).await
     }
 
     #[cfg_attr(feature = "default", uniffi::method(async_runtime = "tokio"))]
@@ -245,6 +737,7 @@ impl IrohManager {
         path: String,
         data: Vec<u8>,
     ) -> Result<UHash> {
+        check_writable_key(&path)?;
         let docs_client = self.docs.client();
 
         let authors = docs_client.authors();
@@ -255,11 +748,167 @@ impl IrohManager {
             .await?
             .ok_or(SharedError::ReplicaMissing(namespace))?;
 
-        let hash = replica.set_bytes(author, path, data).await?;
+        let replaced = split::parts_of(self, namespace, &path).await?;
+        // Parsing tags of a large file would stall the runtime
+        let key = path.clone();
+        let (metadata, data) =
+            tokio::task::spawn_blocking(move || (metadata::extract_from_bytes(&key, &data), data))
+                .await
+                .map_err(|error| SharedError::Iroh(error.to_string()))?;
+        let hash = replica.set_bytes(author, path.clone(), data).await?;
+        split::release_parts(self, namespace, replaced).await;
+        metadata::store_logged(self, namespace, &path, metadata).await;
 
         Ok(hash.into())
     }
 
+    /// Same as [`IrohManager::write_file`], but streams the file at `source_path` into the store
+    /// instead of holding it in memory
+    #[cfg_attr(feature = "default", uniffi::method(async_runtime = "tokio"))]
+    pub async fn write_file_from_path(
+        &self,
+        namespace: UNamespaceId,
+        path: String,
+        source_path: String,
+    ) -> Result<UHash> {
+        check_writable_key(&path)?;
+        let docs_client = self.docs.client();
+
+        let authors = docs_client.authors();
+        let author = authors.default().await?;
+
+        let replica = docs_client
+            .open(namespace.into())
+            .await?
+            .ok_or(SharedError::ReplicaMissing(namespace))?;
+
+        // The store only imports absolute paths
+        let source_path = std::path::absolute(&source_path)?;
+        let replaced = split::parts_of(self, namespace, &path).await?;
+        let outcome = replica
+            .import_file(
+                author,
+                path.clone().into_bytes().into(),
+                &source_path,
+                false,
+            )
+            .await?
+            .finish()
+            .await?;
+        split::release_parts(self, namespace, replaced).await;
+
+        let key = path.clone();
+        let metadata =
+            tokio::task::spawn_blocking(move || metadata::extract_from_path(&key, &source_path))
+                .await
+                .ok()
+                .flatten();
+        metadata::store_logged(self, namespace, &path, metadata).await;
+
+        Ok(outcome.hash.into())
+    }
+
+    /// Same as [`IrohManager::write_file`], but stores tags of MP3 and FLAC files separately
+    /// from their audio frames. Editing a tag then only syncs the new tags, not the whole track.
+    /// Files are rebuilt when they are read or exported, other formats are stored as they are.
+    /// The entry points at a small manifest, so its content length isn't the length of the file.
+    /// Parts no file references anymore are deleted when the file is overwritten or deleted.
+    #[cfg_attr(feature = "default", uniffi::method(async_runtime = "tokio"))]
+    pub async fn write_file_split(
+        &self,
+        namespace: UNamespaceId,
+        path: String,
+        data: Vec<u8>,
+    ) -> Result<UHash> {
+        Ok(split::write_split(self, namespace, path, data)
+            .await?
+            .into())
+    }
+
+    /// Returns tags and properties of the track, which were indexed when it was written.
+    /// Works without downloading the track itself.
+    #[cfg_attr(feature = "default", uniffi::method(async_runtime = "tokio"))]
+    pub async fn get_track_metadata(
+        &self,
+        namespace: UNamespaceId,
+        path: String,
+    ) -> Result<Option<TrackMetadata>> {
+        metadata::get_track(self, namespace, &path).await
+    }
+
+    /// Returns metadata of indexed tracks, optionally only of given artist and album, ignoring case
+    #[cfg_attr(feature = "default", uniffi::method(async_runtime = "tokio"))]
+    pub async fn get_tracks(
+        &self,
+        namespace: UNamespaceId,
+        artist: Option<String>,
+        album: Option<String>,
+    ) -> Result<Vec<TrackMetadata>> {
+        metadata::get_tracks(self, namespace, artist.as_deref(), album.as_deref()).await
+    }
+
+    /// Returns cover art referenced by track metadata. With a size, the smallest thumbnail
+    /// at least that large is returned, which is a JPEG of at most 512 pixels.
+    /// Without one the original image is returned.
+    #[cfg_attr(feature = "default", uniffi::method(async_runtime = "tokio"))]
+    pub async fn get_artwork(
+        &self,
+        namespace: UNamespaceId,
+        hash: UHash,
+        size: Option<u32>,
+    ) -> Result<Option<Vec<u8>>> {
+        artwork::get(self, namespace, hash.into(), size).await
+    }
+
+    /// Searches keys and track metadata of the namespace, best matches first.
+    /// Every word of the query has to match the start of a word, ignoring case and diacritics.
+    /// The index is built on the first search and then kept up to date in the background.
+    #[cfg_attr(feature = "default", uniffi::method(async_runtime = "tokio"))]
+    pub async fn search(
+        &self,
+        namespace: UNamespaceId,
+        query: String,
+        limit: u32,
+    ) -> Result<Vec<SearchResult>> {
+        let existing = self.search_indexes.lock().unwrap().get(&namespace).cloned();
+        let index = match existing {
+            Some(index) => index,
+            None => {
+                let index = Arc::new(LiveSearchIndex::build(self, namespace).await?);
+                // Concurrent first searches might build it twice, the first one is kept
+                self.search_indexes
+                    .lock()
+                    .unwrap()
+                    .entry(namespace)
+                    .or_insert(index)
+                    .clone()
+            }
+        };
+        Ok(index.search(&query, limit as usize))
+    }
+
+    /// Returns the latest entry of the key, without needing its content to be stored locally
+    #[cfg_attr(feature = "default", uniffi::method(async_runtime = "tokio"))]
+    pub async fn get_file(&self, namespace: UNamespaceId, path: &str) -> Result<Arc<UEntry>> {
+        let replica = self
+            .docs
+            .client()
+            .open(namespace.into())
+            .await?
+            .ok_or(SharedError::ReplicaMissing(namespace))?;
+
+        let entry = replica
+            .get_one(Query::single_latest_per_key().key_exact(path))
+            .await?
+            .ok_or_else(|| SharedError::EntryMissing(namespace, path.to_string()))?;
+        if entry.content_hash() == Hash::new(TOMBSTONE) {
+            return Err(SharedError::EntryTombstoned(namespace, path.to_string()));
+        }
+
+        let (_, len) = split::file_info(self, entry.content_hash(), entry.content_len()).await;
+        Ok(Arc::new(UEntry::from(entry).with_file_len(len)))
+    }
+
     #[cfg_attr(feature = "default", uniffi::method(async_runtime = "tokio"))]
     pub async fn read_file(&self, namespace: UNamespaceId, path: &str) -> Result<Vec<u8>> {
         let docs_client = self.docs.client();
@@ -270,7 +919,7 @@ impl IrohManager {
             .ok_or(SharedError::ReplicaMissing(namespace))?;
 
         let entry = replica
-            .get_one(Query::key_exact(path))
+            .get_one(Query::single_latest_per_key().key_exact(path))
             .await?
             .ok_or_else(|| SharedError::EntryMissing(namespace, path.to_string()))?;
 
@@ -286,9 +935,7 @@ impl IrohManager {
 
     #[cfg_attr(feature = "default", uniffi::method(async_runtime = "tokio"))]
     pub async fn read_file_hash(&self, hash: UHash) -> Result<Vec<u8>> {
-        let blobs_client = self.blobs.client();
-        let bytes = blobs_client.read_to_bytes(hash.into()).await?;
-        Ok(bytes.to_vec())
+        split::read_content(self, hash.into()).await
     }
 
     #[cfg_attr(feature = "default", uniffi::method(async_runtime = "tokio"))]
@@ -306,7 +953,7 @@ impl IrohManager {
             .ok_or(SharedError::ReplicaMissing(namespace))?;
 
         let entry = replica
-            .get_one(Query::key_exact(path))
+            .get_one(Query::single_latest_per_key().key_exact(path))
             .await?
             .ok_or_else(|| SharedError::EntryMissing(namespace, path.to_string()))?;
 
@@ -316,26 +963,31 @@ impl IrohManager {
         Ok(())
     }
 
+    /// Exports latest entries under `prefix` into `destination_dir`, recreating the key hierarchy
+    /// as folders. Key components which aren't valid file names get sanitised, deleted keys are
+    /// skipped and so are files which already have the same content. Files are exported in
+    /// parallel, `progress` can be polled while it runs.
     #[cfg_attr(feature = "default", uniffi::method(async_runtime = "tokio"))]
-    pub async fn export_hash(&self, hash: UHash, destination: &str) -> Result<()> {
-        let blobs_client = self.blobs.client();
-
-        let destination = PathBuf::from(destination);
-        if destination.exists() {
-            fs::remove_file(&destination).await?;
-        }
-
-        blobs_client
-            .export(
-                hash.into(),
-                destination,
-                ExportFormat::Blob,
-                ExportMode::Copy,
-            )
-            .await?
-            .await?;
+    pub async fn export_all(
+        &self,
+        namespace: UNamespaceId,
+        prefix: String,
+        destination_dir: String,
+        progress: Option<Arc<ExportProgressHandle>>,
+    ) -> Result<ExportReport> {
+        export::export_all(
+            self,
+            namespace,
+            &prefix,
+            Path::new(&destination_dir),
+            progress,
+        )
+        .await
+    }
 
-        Ok(())
+    #[cfg_attr(feature = "default", uniffi::method(async_runtime = "tokio"))]
+    pub async fn export_hash(&self, hash: UHash, destination: &str) -> Result<()> {
+        export::export_blob(self, hash.into(), Path::new(destination)).await
     }
 
     #[cfg_attr(feature = "default", uniffi::method(async_runtime = "tokio"))]
@@ -355,76 +1007,119 @@ impl IrohManager {
 
     #[cfg_attr(feature = "default", uniffi::method(async_runtime = "tokio"))]
     pub async fn sync(&self, namespace: UNamespaceId) -> Result<()> {
+        self.sync_with(namespace, SyncScope::Namespace).await
+    }
+
+    #[cfg_attr(feature = "default", uniffi::method(async_runtime = "tokio"))]
+    pub async fn sync_with(&self, namespace: UNamespaceId, scope: SyncScope) -> Result<()> {
         let docs_client = self.docs.client();
         let replica = docs_client
             .open(namespace.into())
             .await?
             .ok_or(SharedError::ReplicaMissing(namespace))?;
 
-        let node_addrs: Vec<NodeAddr> = {
-            let node_storage = self.node_storage.read().await;
-            node_storage
-                .nodes
-                .iter()
-                .map(|(node_id, node_data)| {
-                    NodeAddr::from_parts(
-                        (*node_id).into(),
-                        node_data.relay_url.clone(),
-                        node_data.direct_addresses.clone(),
-                    )
-                })
-                .collect()
+        let node_addrs = match scope {
+            SyncScope::Namespace => self.peer_addrs(Some(namespace)).await,
+            SyncScope::AllKnown => self.peer_addrs(None).await,
         };
 
         if node_addrs.is_empty() {
+            info!("[namespace {namespace}] no peers to sync with");
             return Ok(());
         }
 
         replica.start_sync(node_addrs).await?;
 
-        let mut event_stream = replica.subscribe().await?;
-        while let Some(event) = event_stream.try_next().await? {
-            match event {
-                LiveEvent::SyncFinished(event) => {
-                    if let Err(err_message) = event.result {
-                        return Err(SharedError::SyncFailed(err_message));
-                    }
-                    info!("[namespace {namespace}] sync finished");
-                }
-                LiveEvent::ContentReady { hash } => {
-                    info!("[namespace {namespace}] Downloaded: {hash}")
-                }
-                LiveEvent::InsertLocal { entry } => {
-                    info!("[namespace {namespace}] Locally inserted: {entry:?}");
-                }
-                LiveEvent::InsertRemote {
-                    from,
-                    entry,
-                    content_status,
-                } => {
-                    info!(
-                        "[namespace {namespace}] {} inserted: {} (available: {content_status:?})",
-                        from.fmt_short(),
-                        entry.content_hash().fmt_short()
-                    );
-                }
-                LiveEvent::PendingContentReady => {
-                    info!("[namespace {namespace}] content ready");
-                    break;
-                }
-                LiveEvent::NeighborDown(key) => {
-                    info!("[namespace {namespace}] {key} disconnected");
-                }
-                LiveEvent::NeighborUp(key) => info!("[namespace {namespace}] {key} connected"),
+        let event_stream = replica.subscribe().await?;
+        self.process_live_events(namespace, event_stream).await
+    }
+
+    /// Starts live sync of the namespace without waiting for it, so peers can sync with this
+    /// node whenever they connect. Known peers of the namespace are contacted in the background.
+    #[cfg_attr(feature = "default", uniffi::method(async_runtime = "tokio"))]
+    pub async fn start_live_sync(&self, namespace: UNamespaceId) -> Result<()> {
+        let replica = self
+            .docs
+            .client()
+            .open(namespace.into())
+            .await?
+            .ok_or(SharedError::ReplicaMissing(namespace))?;
+
+        replica
+            .start_sync(self.peer_addrs(Some(namespace)).await)
+            .await?;
+        Ok(())
+    }
+
+    /// Subscribes to changes of the namespace. Changes made by peers only arrive while
+    /// the namespace syncs live.
+    #[cfg_attr(feature = "default", uniffi::method(async_runtime = "tokio"))]
+    pub async fn subscribe(&self, namespace: UNamespaceId) -> Result<Arc<EventSubscription>> {
+        Ok(Arc::new(
+            EventSubscription::subscribe(self, namespace).await?,
+        ))
+    }
+
+    /// Downloads content of the latest entries which isn't stored locally. Live sync only
+    /// fetches content from the peer an entry came from, which might not have had it yet.
+    /// Returns how many blobs were downloaded.
+    #[cfg_attr(feature = "default", uniffi::method(async_runtime = "tokio"))]
+    pub async fn download_missing(&self, namespace: UNamespaceId) -> Result<u32> {
+        let replica = self
+            .docs
+            .client()
+            .open(namespace.into())
+            .await?
+            .ok_or(SharedError::ReplicaMissing(namespace))?;
+        let blobs_client = self.blobs.client();
+
+        let mut missing = Vec::new();
+        let tombstone_hash = Hash::new(TOMBSTONE);
+        let mut entries = replica.get_many(Query::single_latest_per_key()).await?;
+        while let Some(entry) = entries.try_next().await? {
+            let hash = entry.content_hash();
+            if hash != tombstone_hash && !blobs_client.has(hash).await? {
+                missing.push(hash);
             }
         }
 
-        Ok(())
+        if missing.is_empty() {
+            return Ok(0);
+        }
+
+        // Peers of the namespace are tried first, any other known one might have the content too
+        let mut nodes = self.peer_addrs(Some(namespace)).await;
+        for node in self.peer_addrs(None).await {
+            if !nodes.iter().any(|known| known.node_id == node.node_id) {
+                nodes.push(node);
+            }
+        }
+
+        let mut downloaded = 0;
+        for hash in missing {
+            let options = DownloadOptions {
+                format: BlobFormat::Raw,
+                nodes: nodes.clone(),
+                tag: SetTagOption::Auto,
+                mode: DownloadMode::Direct,
+            };
+            let result = match blobs_client.download_with_opts(hash, options).await {
+                Ok(progress) => progress.finish().await.map(|_| ()),
+                Err(error) => Err(error),
+            };
+            match result {
+                Ok(()) => downloaded += 1,
+                Err(error) => warn!("[namespace {namespace}] failed to fetch {hash}: {error:#}"),
+            }
+        }
+        info!("[namespace {namespace}] downloaded {downloaded} missing blobs");
+        Ok(downloaded)
     }
 
     #[cfg_attr(feature = "default", uniffi::method(async_runtime = "tokio"))]
     pub async fn import(&self, ticket: UDocTicket) -> Result<UNamespaceId> {
         let ticket: DocTicket = ticket.into();
+        let namespace: UNamespaceId = ticket.capability.id().into();
 
         let docs_client = self.docs.client();
 
@@ -435,20 +1130,341 @@ impl IrohManager {
                 let node_data =
                     NodeData::new(node.relay_url.clone(), node.direct_addresses.clone());
                 node_storage.upsert_node(node.node_id, Cow::Owned(node_data));
+                node_storage.associate_node(namespace, node.node_id);
             }
         }
 
         info!("[ticket] importing {ticket}");
-        let (replica, mut event_stream) = docs_client.import_and_subscribe(ticket).await?;
-        let namespace = replica.id();
+        let (replica, event_stream) = docs_client.import_and_subscribe(ticket).await?;
 
         info!("[ticket] syncing namespace {namespace}");
+        self.process_live_events(namespace, event_stream).await?;
+        info!("[ticket] imported namespace {namespace}");
+
+        Ok(replica.id().into())
+    }
+
+    /// Writes every file in the directory tree into the namespace, keyed by its path
+    /// relative to `dir` under `key_prefix`. Files matching one of `ignore_globs`, by their
+    /// relative path or name, are skipped. Size and modification time of imported files
+    /// are remembered, so files which didn't change since the last import aren't read again.
+    /// With `tombstone_missing`, keys previously imported from the same directory whose files
+    /// disappeared get deleted.
+    #[cfg_attr(feature = "default", uniffi::method(async_runtime = "tokio"))]
+    pub async fn import_directory(
+        &self,
+        namespace: UNamespaceId,
+        dir: String,
+        key_prefix: String,
+        ignore_globs: Vec<String>,
+        tombstone_missing: bool,
+    ) -> Result<DirectoryImportReport> {
+        directory::import_directory(
+            self,
+            namespace,
+            Path::new(&dir),
+            &key_prefix,
+            &ignore_globs,
+            tombstone_missing,
+        )
+        .await
+    }
+
+    /// Keeps `dir` and keys of the namespace under `key_prefix` in sync in both directions.
+    /// Local file changes are imported as they happen, deleted files get tombstoned, and entries
+    /// written by peers are exported into the directory once their content is downloaded.
+    /// Each namespace can be mirrored into one directory, starting a mirror replaces the previous one.
+    #[cfg_attr(feature = "default", uniffi::method(async_runtime = "tokio"))]
+    pub async fn start_mirror(
+        &self,
+        namespace: UNamespaceId,
+        dir: String,
+        key_prefix: String,
+        ignore_globs: Vec<String>,
+    ) -> Result<()> {
+        let mirror =
+            Mirror::start(self, namespace, Path::new(&dir), key_prefix, ignore_globs).await?;
+        self.mirrors.lock().unwrap().insert(namespace, mirror);
+        Ok(())
+    }
+
+    #[cfg_attr(feature = "default", uniffi::method)]
+    pub fn stop_mirror(&self, namespace: UNamespaceId) {
+        if self.mirrors.lock().unwrap().remove(&namespace).is_some() {
+            info!("[mirror] stopped mirroring namespace {namespace}");
+        }
+    }
+
+    #[cfg_attr(feature = "default", uniffi::method)]
+    pub fn get_mirrors(&self) -> Vec<MirrorInfo> {
+        self.mirrors
+            .lock()
+            .unwrap()
+            .values()
+            .map(|mirror| mirror.info.clone())
+            .collect()
+    }
+
+    /// Starts an HTTP server on 127.0.0.1 serving entries and blobs to media players,
+    /// which fetches content missing locally from peers. Port 0 picks an unused port.
+    /// If the server is already running, its info is returned instead.
+    #[cfg_attr(feature = "default", uniffi::method(async_runtime = "tokio"))]
+    pub async fn start_streaming_server(&self, port: u16) -> Result<StreamingServerInfo> {
+        if let Some(info) = self.get_streaming_server() {
+            return Ok(info);
+        }
+
+        let server = StreamingServer::start(self.downgrade(), port).await?;
+        let mut running = self.streaming_server.lock().unwrap();
+        // Concurrent starts keep the first server
+        Ok(running.get_or_insert(server).info.clone())
+    }
+
+    #[cfg_attr(feature = "default", uniffi::method)]
+    pub fn stop_streaming_server(&self) {
+        if self.streaming_server.lock().unwrap().take().is_some() {
+            info!("[streaming] stopped");
+        }
+    }
+
+    #[cfg_attr(feature = "default", uniffi::method)]
+    pub fn get_streaming_server(&self) -> Option<StreamingServerInfo> {
+        self.streaming_server
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|server| server.info.clone())
+    }
+
+    /// URL of the entry on the streaming server, including the access token
+    #[cfg_attr(feature = "default", uniffi::method)]
+    pub fn get_stream_url(&self, namespace: UNamespaceId, path: String) -> Result<String> {
+        self.streaming_server
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|server| server.entry_url(namespace, &path))
+            .ok_or_else(|| SharedError::NotFound("Streaming server isn't running".to_string()))
+    }
+
+    /// URL of the blob on the streaming server, including the access token
+    #[cfg_attr(feature = "default", uniffi::method)]
+    pub fn get_blob_stream_url(&self, hash: UHash) -> Result<String> {
+        self.streaming_server
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|server| server.blob_url(hash.into()))
+            .ok_or_else(|| SharedError::NotFound("Streaming server isn't running".to_string()))
+    }
+
+    /// Writes latest entries of the namespace and their content into a single archive.
+    /// With `include_secret`, the archive also holds the namespace and author secrets,
+    /// so it has to be kept as safe as the device itself. Read-only namespaces have no secret,
+    /// they are backed up without it. Stores with encryption enabled encrypt the archive
+    /// with the same key.
+    #[cfg_attr(feature = "default", uniffi::method(async_runtime = "tokio"))]
+    pub async fn backup_namespace(
+        &self,
+        namespace: UNamespaceId,
+        archive_path: String,
+        include_secret: bool,
+    ) -> Result<BackupReport> {
+        backup::backup_namespace(self, namespace, Path::new(&archive_path), include_secret).await
+    }
+
+    /// Rebuilds the namespace from an archive created by [`IrohManager::backup_namespace`],
+    /// without contacting any peer.
+    /// Entries are restored as they were signed, keeping their authors and times, so entries
+    /// which are newer in the namespace or on peers win. Archives without the secret restore
+    /// the namespace read-only.
+    #[cfg_attr(feature = "default", uniffi::method(async_runtime = "tokio"))]
+    pub async fn restore_namespace(&self, archive_path: String) -> Result<RestoreReport> {
+        backup::restore_namespace(self, Path::new(&archive_path)).await
+    }
+
+    /// Creates an invitation to the namespace, which can expire or be used only once.
+    /// Unlike a ticket, it doesn't contain the capability nor the namespace id, so it can be
+    /// revoked until it's accepted. With `read_only`, invited nodes can't change the namespace.
+    #[cfg_attr(feature = "default", uniffi::method(async_runtime = "tokio"))]
+    pub async fn create_invitation(
+        &self,
+        namespace: UNamespaceId,
+        expires_in_ms: Option<u64>,
+        single_use: bool,
+        read_only: bool,
+    ) -> Result<Invitation> {
+        self.docs
+            .client()
+            .open(namespace.into())
+            .await?
+            .ok_or(SharedError::ReplicaMissing(namespace))?;
+
+        let node_addr = self.router.endpoint().node_addr().await?;
+        self.invitations
+            .create(node_addr, namespace, expires_in_ms, single_use, read_only)
+            .await
+    }
+
+    /// Returns invitations created by this node, optionally only of given namespace
+    #[cfg_attr(feature = "default", uniffi::method(async_runtime = "tokio"))]
+    pub async fn get_invitations(&self, namespace: Option<UNamespaceId>) -> Vec<Invitation> {
+        self.invitations.list(namespace).await
+    }
+
+    #[cfg_attr(feature = "default", uniffi::method(async_runtime = "tokio"))]
+    pub async fn revoke_invitation(&self, id: String) -> Result<()> {
+        self.invitations.revoke(&id).await
+    }
+
+    /// Exchanges the invitation for a ticket with the sharer and imports the namespace
+    #[cfg_attr(feature = "default", uniffi::method(async_runtime = "tokio"))]
+    pub async fn accept_invitation(&self, ticket: InvitationTicket) -> Result<UNamespaceId> {
+        info!(
+            "[invitation] accepting invitation from {}",
+            ticket.node.node_id.fmt_short()
+        );
+        let doc_ticket = invitation::request_capability(self.router.endpoint(), &ticket).await?;
+
+        self.import(doc_ticket.into()).await
+    }
+
+    /// Same as [`IrohManager::sync_with`], but can be cancelled and limited in time.
+    /// Live sync started by this call is stopped when it gets cancelled or times out,
+    /// live sync which was running before is left alone.
+    #[cfg_attr(feature = "default", uniffi::method(async_runtime = "tokio"))]
+    pub async fn sync_cancellable(
+        &self,
+        namespace: UNamespaceId,
+        scope: SyncScope,
+        cancellation: Option<Arc<CancellationHandle>>,
+        timeout_ms: Option<u64>,
+    ) -> Result<()> {
+        let was_syncing = self.is_syncing(namespace).await;
+        let result = run_cancellable(
+            cancellation.as_deref(),
+            timeout_ms,
+            self.sync_with(namespace, scope),
+        )
+        .await;
+
+        if !was_syncing
+            && matches!(
+                result,
+                Err(SharedError::Cancelled | SharedError::TimedOut(_))
+            )
+        {
+            self.leave(namespace).await;
+        }
+
+        result
+    }
+
+    /// Same as [`IrohManager::import`], but can be cancelled and limited in time.
+    /// Live sync started by this call is stopped when it gets cancelled or times out,
+    /// live sync which was running before is left alone. A namespace which didn't exist
+    /// before the call is deleted again, so that no partial copy of it is left behind.
+    #[cfg_attr(feature = "default", uniffi::method(async_runtime = "tokio"))]
+    pub async fn import_cancellable(
+        &self,
+        ticket: UDocTicket,
+        cancellation: Option<Arc<CancellationHandle>>,
+        timeout_ms: Option<u64>,
+    ) -> Result<UNamespaceId> {
+        let namespace: UNamespaceId = DocTicket::from(ticket.clone()).capability.id().into();
+
+        let existed = self.get_namespaces().await?.contains(&namespace);
+        let was_syncing = self.is_syncing(namespace).await;
+        let result =
+            run_cancellable(cancellation.as_deref(), timeout_ms, self.import(ticket)).await;
+
+        if matches!(
+            result,
+            Err(SharedError::Cancelled | SharedError::TimedOut(_))
+        ) {
+            if !existed {
+                if let Err(error) = self.delete_namespace(namespace).await {
+                    warn!("[namespace {namespace}] failed to delete partial import: {error}");
+                }
+            } else if !was_syncing {
+                self.leave(namespace).await;
+            }
+        }
+
+        result
+    }
+}
+
+impl IrohManager {
+    /// Addresses of peers known to participate in the namespace.
+    /// Without a namespace all known peers are returned.
+    pub(crate) async fn peer_addrs(&self, namespace: Option<UNamespaceId>) -> Vec<NodeAddr> {
+        let node_storage = self.node_storage.read().await;
+
+        // Peers which never shared the namespace must not learn about it
+        let node_ids = match namespace {
+            Some(namespace) => node_storage.get_namespace_nodes(&namespace),
+            None => node_storage.nodes.keys().copied().collect(),
+        };
+
+        node_ids
+            .into_iter()
+            .filter_map(|node_id| {
+                let node_data = node_storage.get_unode_data(&node_id)?;
+                Some(NodeAddr::from_parts(
+                    node_id.into(),
+                    node_data.relay_url.clone(),
+                    node_data.direct_addresses.clone(),
+                ))
+            })
+            .collect()
+    }
+
+    /// Whether the namespace exists and syncs live, started by anyone
+    async fn is_syncing(&self, namespace: UNamespaceId) -> bool {
+        match self.docs.client().open(namespace.into()).await {
+            Ok(Some(replica)) => replica.status().await.is_ok_and(|status| status.sync),
+            _ => false,
+        }
+    }
+
+    /// Stops live sync of the namespace, if it exists
+    async fn leave(&self, namespace: UNamespaceId) {
+        let docs_client = self.docs.client();
+
+        match docs_client.open(namespace.into()).await {
+            Ok(Some(replica)) => {
+                if let Err(error) = replica.leave().await {
+                    warn!("[namespace {namespace}] failed to stop live sync: {error}");
+                } else {
+                    info!("[namespace {namespace}] stopped live sync");
+                }
+            }
+            Ok(None) => {}
+            Err(error) => warn!("[namespace {namespace}] failed to open: {error}"),
+        }
+    }
+
+    /// Logs live events of the namespace until all pending content is ready,
+    /// remembering every peer that took part in the exchange
+    async fn process_live_events<E>(
+        &self,
+        namespace: UNamespaceId,
+        mut event_stream: impl Stream<Item = std::result::Result<LiveEvent, E>> + Unpin,
+    ) -> Result<()>
+    where
+        SharedError: From<E>,
+    {
         while let Some(event) = event_stream.try_next().await? {
             match event {
                 LiveEvent::SyncFinished(event) => {
                     if let Err(err_message) = event.result {
                         return Err(SharedError::SyncFailed(err_message));
                     }
+                    self.node_storage
+                        .write()
+                        .await
+                        .associate_node(namespace, event.peer);
                     info!("[namespace {namespace}] sync finished");
                 }
                 LiveEvent::ContentReady { hash } => {
@@ -462,6 +1478,10 @@ impl IrohManager {
                     entry,
                     content_status,
                 } => {
+                    self.node_storage
+                        .write()
+                        .await
+                        .associate_node(namespace, from);
                     info!(
                         "[namespace {namespace}] {} inserted: {} (available: {content_status:?})",
                         from.fmt_short(),
@@ -480,9 +1500,8 @@ impl IrohManager {
                 }
             }
         }
-        info!("[ticket] imported namespace {namespace}");
 
-        Ok(replica.id().into())
+        Ok(())
     }
 }
 
@@ -490,15 +1509,20 @@ impl IrohManager {
 mod test {
     use crate::errors::SharedError;
 
-    use super::{IrohFactory, IrohManager};
-    use log::{error, info, warn};
-    use std::{
-        error::Error,
-        path::{Path, PathBuf},
-        sync::Arc,
-        time::{Duration, SystemTime},
+    use super::{
+        IrohFactory, IrohManager, SyncScope, TOMBSTONE, artwork,
+        cancellation::CancellationHandle,
+        events::SyncEvent,
+        export::ExportProgressHandle,
+        split,
+        testing::{TempDir, TestNetwork, wait_for},
+        types::{UEntry, UHash},
     };
-    use tokio::task::{JoinHandle, JoinSet};
+    use iroh_docs::store::Query;
+    use log::info;
+    use std::{error::Error, path::PathBuf, sync::Arc, time::Duration};
+    use tokio::task::JoinSet;
+    use tokio_stream::StreamExt;
 
     type Result<T> = std::result::Result<T, Box<dyn Error + Sync + Send>>;
 
@@ -550,26 +1574,6 @@ mod test {
         ),
     ];
 
-    struct TempDir(PathBuf);
-    impl TempDir {
-        pub fn new() -> Self {
-            let sys_time = std::time::SystemTime::now()
-                .duration_since(SystemTime::UNIX_EPOCH)
-                .unwrap()
-                .as_secs();
-            Self(std::env::temp_dir().join(sys_time.to_string()))
-        }
-
-        pub fn subpath<P: AsRef<Path>>(&self, path: P) -> PathBuf {
-            self.0.join(path)
-        }
-    }
-    impl Drop for TempDir {
-        fn drop(&mut self) {
-            let _ = std::fs::remove_dir_all(&self.0);
-        }
-    }
-
     async fn mock_client(dir: PathBuf) -> Result<IrohManager> {
         let iroh_manager = IrohFactory::new()
             .iroh_manager(&dir.to_string_lossy())
@@ -579,10 +1583,9 @@ mod test {
 
     #[tokio::test]
     async fn test_connection() -> Result<()> {
-        env_logger::init();
-        let temp_dir = TempDir::new();
-
-        let provider = mock_client(temp_dir.subpath("provider")).await?;
+        let _ = env_logger::try_init();
+        let mut network = TestNetwork::new(6).await?;
+        let provider = network.manager(0).clone();
 
         info!("[provider] create namespace");
         let namespace = provider.create_namespace().await?;
@@ -603,55 +1606,57 @@ mod test {
 
         info!("[provider] share ticket");
         let ticket = provider.share(namespace).await?;
-
-        let mut set = JoinSet::new();
-        let ticket = Arc::new(ticket);
-        let file_hashes = Arc::new(file_hashes);
+        let provider_id = provider.get_node_id().await;
 
         info!("[receivers] test 5 concurrent connections");
-        for i in 0..5 {
+        let mut set = JoinSet::new();
+        for i in 1..6 {
+            let receiver = network.manager(i).clone();
             let ticket = ticket.clone();
             let file_hashes = file_hashes.clone();
-            let receiver_path = temp_dir.subpath(format!("receiver_{i}"));
-            let task: JoinHandle<Result<()>> = tokio::spawn(async move {
-                let receiver = mock_client(receiver_path).await?;
-
-                info!("[receiver {i}]: make sure files are empty before import:");
-                info!("[receiver {i}]: via hash");
-                for file_hash in file_hashes.iter() {
+            set.spawn(async move {
+                info!("[receiver {i}]: make sure files are empty before import");
+                for file_hash in &file_hashes {
                     assert!(receiver.read_file_hash(*file_hash).await.is_err());
                 }
-                info!("[receiver {i}]: via path");
                 for (file_path, _) in TEST_FILES {
                     assert!(receiver.read_file(namespace, file_path).await.is_err());
                 }
 
                 info!("[receiver {i}]: import ticket");
-                let imported_namespace = receiver.import((*ticket).clone()).await?;
+                let imported_namespace = receiver.import(ticket).await?;
                 assert_eq!(namespace, imported_namespace);
-
-                info!("[receiver {i}]: imported ticket, waiting 5 seconds for it to propagate...");
-                tokio::time::sleep(Duration::from_secs(5)).await;
-
-                info!("[receiver {i}]: make sure files are got properly imported");
-                for (j, (path, contents)) in TEST_FILES.into_iter().enumerate() {
-                    info!("[receiver {i}]: make sure {path} gets properly imported");
-                    assert_eq!(&receiver.read_file_hash(file_hashes[j]).await?, contents);
-                    assert_eq!(&receiver.read_file(namespace, path).await?, contents);
-                }
-
-                info!("[receiver {i}]: shutdown");
-                receiver.shutdown().await?;
-                Ok(())
+                assert!(
+                    receiver
+                        .get_namespace_nodes(namespace)
+                        .await
+                        .contains(&provider_id)
+                );
+                Ok::<_, Box<dyn Error + Sync + Send>>(())
             });
-            set.spawn(task);
         }
-
         for result in set.join_all().await {
-            result.unwrap()?;
+            result?;
+        }
+
+        network.wait_until_converged(namespace).await?;
+        for i in 1..6 {
+            let receiver = network.manager(i);
+            for (j, (path, contents)) in TEST_FILES.into_iter().enumerate() {
+                assert_eq!(&receiver.read_file_hash(file_hashes[j]).await?, contents);
+                assert_eq!(&receiver.read_file(namespace, path).await?, contents);
+            }
+        }
+
+        info!("[receivers]: shutdown");
+        for i in 1..6 {
+            network.stop(i).await?;
         }
 
-        info!("[provider]: modify {}", MODIFIED_FILE.0);
+        info!(
+            "[provider]: modify {} and delete {}",
+            MODIFIED_FILE.0, DELETED_FILE
+        );
         provider
             .write_file(
                 namespace,
@@ -659,12 +1664,6 @@ mod test {
                 MODIFIED_FILE.1.to_vec(),
             )
             .await?;
-        assert_eq!(
-            provider.read_file(namespace, MODIFIED_FILE.0).await?,
-            MODIFIED_FILE.1
-        );
-
-        info!("[provider]: delete {}", DELETED_FILE);
         provider
             .delete_file(namespace, DELETED_FILE.to_string())
             .await?;
@@ -673,89 +1672,1330 @@ mod test {
             Err(SharedError::EntryTombstoned(..))
         ));
 
-        info!(
-            "[provider]: modified {} and deleted {}, waiting 5 seconds to propagate...",
-            MODIFIED_FILE.0, DELETED_FILE
-        );
-        tokio::time::sleep(Duration::from_secs(5)).await;
-
-        let mut set = JoinSet::new();
-        for i in 0..5 {
-            let file_hashes = file_hashes.clone();
-            let provider_id = provider.router.endpoint().node_id().into();
-            let receiver_path = temp_dir.subpath(format!("receiver_{i}"));
-            let task: JoinHandle<Result<IrohManager>> = tokio::spawn(async move {
-                info!("[receiver {i}]: recreate");
-                let receiver = mock_client(receiver_path).await?;
-
-                info!("[receiver {i}]: make sure files are still there");
-                for (j, (path, contents)) in TEST_FILES.iter().enumerate() {
-                    assert_eq!(&receiver.read_file_hash(file_hashes[j]).await?, contents);
-                    assert_eq!(&receiver.read_file(namespace, path).await?, contents);
-                }
+        for i in 1..6 {
+            info!("[receiver {i}]: restart, files are still there");
+            let receiver = network.start(i).await?;
+            for (j, (path, contents)) in TEST_FILES.iter().enumerate() {
+                assert_eq!(&receiver.read_file_hash(file_hashes[j]).await?, contents);
+                assert_eq!(&receiver.read_file(namespace, path).await?, contents);
+            }
+        }
 
-                info!("[receiver {i}]: reconnect");
-                receiver.reconnect().await;
-                let mut retries = 0u32;
-                while !receiver.get_known_nodes().await.contains(&provider_id) {
-                    if retries > 15 {
-                        error!("[receiver {i}]: didn't find provider after 15 tries, giving up");
-                        break;
-                    }
+        // Restarted receivers listen on new addresses, syncing connects them again
+        network.introduce_all().await?;
 
-                    warn!("[receiver {i}]: didn't find provider, retrying in 2s...");
-                    tokio::time::sleep(Duration::from_secs(2)).await;
-                    receiver.reconnect().await;
+        // Receivers reject syncs until they sync the namespace themselves, so all start at once
+        network.heal(namespace).await?;
+        for i in 1..6 {
+            let receiver = network.manager(i);
+            assert_eq!(
+                &receiver.read_file(namespace, MODIFIED_FILE.0).await?,
+                MODIFIED_FILE.1
+            );
+            assert!(matches!(
+                receiver.read_file(namespace, DELETED_FILE).await,
+                Err(SharedError::EntryTombstoned(..))
+            ));
+        }
 
-                    retries += 1;
-                }
+        network.shutdown().await?;
+        Ok(())
+    }
 
-                info!("[receiver {i}]: sync");
-                retries = 0;
-                while let Err(_) = receiver.sync(namespace).await {
-                    if retries > 15 {
-                        error!("[receiver {i}]: failed sync 15 times, giving up");
-                        break;
-                    }
+    #[tokio::test]
+    async fn test_read_latest_across_authors() -> Result<()> {
+        let network = TestNetwork::new(2).await?;
+        let namespace = network.shared_namespace().await?;
+        let (owner, peer) = (network.manager(0), network.manager(1));
 
-                    warn!("[receiver {i}]: sync failed, retrying in 2s...");
-                    tokio::time::sleep(Duration::from_secs(2)).await;
+        owner
+            .write_file(namespace, "track".into(), b"first".to_vec())
+            .await?;
+        network.heal(namespace).await?;
+        peer.write_file(namespace, "track".into(), b"second".to_vec())
+            .await?;
+        network.wait_until_converged(namespace).await?;
 
-                    retries += 1
-                }
+        // The key has an entry of each author, the newer one is read
+        for manager in [owner, peer] {
+            assert_eq!(manager.read_file(namespace, "track").await?, b"second");
+        }
+        let destination = TempDir::new().subpath("track");
+        owner
+            .export(namespace, "track", &destination.to_string_lossy())
+            .await?;
+        assert_eq!(std::fs::read(&destination)?, b"second");
 
-                info!("[receiver {i}]: synced, wait 5 seconds for it to propagate...");
-                tokio::time::sleep(Duration::from_secs(5)).await;
+        network.shutdown().await?;
+        Ok(())
+    }
 
-                info!("[receiver {i}]: make sure file got properly synced");
-                assert_eq!(
-                    &receiver.read_file(namespace, MODIFIED_FILE.0).await?,
-                    MODIFIED_FILE.1
-                );
+    #[tokio::test]
+    async fn test_download_missing() -> Result<()> {
+        let network = TestNetwork::new(2).await?;
+        network.introduce_all().await?;
+        let (owner, peer) = (network.manager(0), network.manager(1));
+        let namespace = owner.create_namespace().await?;
+        for (path, contents) in TEST_FILES {
+            owner
+                .write_file(namespace, path.to_string(), contents.to_vec())
+                .await?;
+        }
 
-                info!("[receiver {i}]: make sure file got properly deleted");
-                assert!(matches!(
-                    receiver.read_file(namespace, DELETED_FILE).await,
-                    Err(SharedError::EntryTombstoned(..))
-                ));
+        // The peer syncs entries, but none of their content
+        let ticket: iroh_docs::DocTicket = owner.share(namespace).await?.into();
+        let replica = peer
+            .docs
+            .client()
+            .import_namespace(ticket.capability)
+            .await?;
+        replica
+            .set_download_policy(iroh_docs::store::DownloadPolicy::NothingExcept(vec![]))
+            .await?;
+        replica.start_sync(ticket.nodes).await?;
+        wait_for(async || {
+            peer.get_files(namespace)
+                .await
+                .is_ok_and(|files| files.len() == TEST_FILES.len())
+        })
+        .await;
+        assert!(peer.read_file(namespace, TEST_FILES[0].0).await.is_err());
+        let entry = peer.get_file(namespace, TEST_FILES[0].0).await?;
+        assert_eq!(entry.content_len(), TEST_FILES[0].1.len() as u64);
 
-                Ok(receiver)
-            });
-            set.spawn(task);
+        assert_eq!(
+            peer.download_missing(namespace).await?,
+            TEST_FILES.len() as u32
+        );
+        for (path, contents) in TEST_FILES {
+            assert_eq!(peer.read_file(namespace, path).await?, contents);
         }
+        assert_eq!(peer.download_missing(namespace).await?, 0);
 
-        let mut receivers = Vec::new();
-        for result in set.join_all().await {
-            receivers.push(result.unwrap()?);
-        }
+        network.shutdown().await?;
+        Ok(())
+    }
 
-        info!("[receivers]: shutdown");
-        for receiver in receivers {
-            receiver.shutdown().await?;
-        }
-        info!("[provider]: shutdown");
-        provider.shutdown().await?;
+    #[tokio::test]
+    async fn test_sync_scope() -> Result<()> {
+        let network = TestNetwork::new(2).await?;
+        let owner = network.manager(0);
+
+        // The nodes know each other, but the namespace hasn't been shared with anyone
+        let namespace = owner.create_namespace().await?;
+        assert!(owner.peer_addrs(Some(namespace)).await.is_empty());
+        assert_eq!(owner.peer_addrs(None).await.len(), 1);
+
+        owner.sync(namespace).await?;
+        assert!(owner.get_namespace_nodes(namespace).await.is_empty());
+
+        network.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_start_live_sync() -> Result<()> {
+        let network = TestNetwork::new(2).await?;
+        let namespace = network.shared_namespace().await?;
+        network.partition(namespace).await;
+
+        // The receiver doesn't contact anyone, it only accepts the sync of the provider
+        let (provider, receiver) = (network.manager(0), network.manager(1));
+        receiver.start_live_sync(namespace).await?;
+        provider.start_live_sync(namespace).await?;
+
+        let (key, data) = TEST_FILES[0];
+        provider
+            .write_file(namespace, key.to_string(), data.to_vec())
+            .await?;
+        wait_for(async || receiver.read_file(namespace, key).await.is_ok()).await;
+        assert_eq!(receiver.read_file(namespace, key).await?, data);
+
+        network.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sync_timeout_keeps_live_sync() -> Result<()> {
+        let mut network = TestNetwork::new(2).await?;
+        let namespace = network.shared_namespace().await?;
+        network.partition(namespace).await;
+        // The peer is gone, so syncing with it never finishes
+        network.stop(1).await?;
+        let owner = network.manager(0);
+
+        let result = owner
+            .sync_cancellable(namespace, SyncScope::AllKnown, None, Some(200))
+            .await;
+        assert!(matches!(result, Err(SharedError::TimedOut(200))));
+        assert!(!owner.is_syncing(namespace).await);
+
+        // Live sync started by someone else keeps running
+        owner.start_live_sync(namespace).await?;
+        let result = owner
+            .sync_cancellable(namespace, SyncScope::AllKnown, None, Some(200))
+            .await;
+        assert!(matches!(result, Err(SharedError::TimedOut(200))));
+        assert!(owner.is_syncing(namespace).await);
+
+        network.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_cancel_import() -> Result<()> {
+        let mut network = TestNetwork::new(2).await?;
+        let owner = network.manager(0);
+        let namespace = owner.create_namespace().await?;
+        for (path, contents) in TEST_FILES {
+            owner
+                .write_file(namespace, path.to_string(), contents.to_vec())
+                .await?;
+        }
+        let ticket = owner.share(namespace).await?;
+        // The owner is gone, so the import never finishes
+        network.stop(0).await?;
+        let peer = network.manager(1);
+
+        let cancellation = Arc::new(CancellationHandle::new());
+        let cancel = {
+            let cancellation = cancellation.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_millis(500)).await;
+                cancellation.cancel();
+            })
+        };
+        let result = peer
+            .import_cancellable(ticket, Some(cancellation.clone()), None)
+            .await;
+        cancel.await?;
+        assert!(matches!(result, Err(SharedError::Cancelled)));
+        assert!(cancellation.is_cancelled());
+
+        assert!(!peer.get_namespaces().await?.contains(&namespace));
+        assert!(peer.get_files(namespace).await.is_err());
+        assert!(peer.get_namespace_nodes(namespace).await.is_empty());
+
+        network.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_subscribe() -> Result<()> {
+        let network = TestNetwork::new(2).await?;
+        let namespace = network.shared_namespace().await?;
+        let (provider, receiver) = (network.manager(0), network.manager(1));
+
+        let local = provider.subscribe(namespace).await?;
+        let remote = receiver.subscribe(namespace).await?;
+
+        let (key, data) = TEST_FILES[0];
+        let hash = provider
+            .write_file(namespace, key.to_string(), data.to_vec())
+            .await?;
+        assert_eq!(
+            local.next().await,
+            Some(SyncEvent::InsertLocal {
+                key: key.to_string(),
+                hash,
+                deleted: false,
+            })
+        );
+
+        let provider_id = provider.get_node_id().await;
+        let event = tokio::time::timeout(Duration::from_secs(30), async {
+            loop {
+                match remote.next().await {
+                    Some(SyncEvent::InsertRemote { key, from, .. }) => break Some((key, from)),
+                    Some(_) => continue,
+                    None => break None,
+                }
+            }
+        })
+        .await?;
+        assert_eq!(event, Some((key.to_string(), provider_id)));
+
+        provider.delete_file(namespace, key.to_string()).await?;
+        assert!(matches!(
+            local.next().await,
+            Some(SyncEvent::InsertLocal { deleted: true, .. })
+        ));
+
+        drop((local, remote));
+        network.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_read_only_invitation() -> Result<()> {
+        let network = TestNetwork::new(2).await?;
+        let (owner, guest) = (network.manager(0), network.manager(1));
+        let namespace = owner.create_namespace().await?;
+        let (key, data) = TEST_FILES[0];
+        owner
+            .write_file(namespace, key.to_string(), data.to_vec())
+            .await?;
+
+        let invitation = owner.create_invitation(namespace, None, true, true).await?;
+        // The namespace id alone allows reading, so only the sharer hands it out
+        let namespace_id = iroh_docs::NamespaceId::from(namespace);
+        assert!(
+            !iroh_base::ticket::Ticket::to_bytes(&invitation.ticket)
+                .windows(32)
+                .any(|window| window == namespace_id.as_bytes())
+        );
+
+        assert_eq!(
+            guest.accept_invitation(invitation.ticket.clone()).await?,
+            namespace
+        );
+        assert_eq!(guest.read_file(namespace, key).await?, data);
+        assert!(
+            guest
+                .write_file(namespace, "guest.flac".into(), b"guest".to_vec())
+                .await
+                .is_err()
+        );
+
+        assert!(matches!(
+            guest.accept_invitation(invitation.ticket).await,
+            Err(SharedError::PermissionDenied(_))
+        ));
+
+        network.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_backup_restore() -> Result<()> {
+        let temp_dir = TempDir::new();
+        std::fs::create_dir_all(temp_dir.subpath(""))?;
+
+        let provider = mock_client(temp_dir.subpath("backup_provider")).await?;
+        let namespace = provider.create_namespace().await?;
+        for (path, data) in &TEST_FILES {
+            provider
+                .write_file(namespace, path.to_string(), data.to_vec())
+                .await?;
+        }
+        provider
+            .delete_file(namespace, DELETED_FILE.to_string())
+            .await?;
+
+        let timestamps = |files: Vec<Arc<UEntry>>| {
+            let mut timestamps: Vec<_> = files
+                .iter()
+                .map(|file| (file.key(), file.author().to_string(), file.timestamp()))
+                .collect();
+            timestamps.sort_by(|a, b| a.0.cmp(&b.0));
+            timestamps
+        };
+        let original = timestamps(provider.get_files(namespace).await?);
+
+        let archive = temp_dir.subpath("namespace.backup");
+        let report = provider
+            .backup_namespace(namespace, archive.to_string_lossy().into(), true)
+            .await?;
+        assert_eq!(report.entries, TEST_FILES.len() as u64);
+        assert!(report.includes_secret);
+        provider.shutdown().await?;
+
+        let restored = mock_client(temp_dir.subpath("backup_restored")).await?;
+        let report = restored
+            .restore_namespace(archive.to_string_lossy().into())
+            .await?;
+        assert_eq!(report.namespace, namespace);
+        assert_eq!(report.entries, TEST_FILES.len() as u64);
+        assert!(report.writable);
+
+        for (path, data) in TEST_FILES.iter().filter(|(path, _)| *path != DELETED_FILE) {
+            assert_eq!(&restored.read_file(namespace, path).await?, data);
+        }
+        assert!(matches!(
+            restored.read_file(namespace, DELETED_FILE).await,
+            Err(SharedError::EntryTombstoned(..))
+        ));
+        // Entries keep their authors and times, so they don't win over newer ones of peers
+        assert_eq!(timestamps(restored.get_files(namespace).await?), original);
+
+        let (key, _) = TEST_FILES[0];
+        restored
+            .write_file(namespace, key.to_string(), MODIFIED_FILE.1.to_vec())
+            .await?;
+        let report = restored
+            .restore_namespace(archive.to_string_lossy().into())
+            .await?;
+        assert_eq!(report.entries, 0);
+        assert_eq!(report.skipped, TEST_FILES.len() as u64);
+        assert_eq!(restored.read_file(namespace, key).await?, MODIFIED_FILE.1);
+        restored.shutdown().await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_encrypted_backup() -> Result<()> {
+        let temp_dir = TempDir::new();
+        let key = vec![7; 32];
+        let provider = IrohFactory::with_encryption_key(key.clone())?
+            .iroh_manager(&temp_dir.subpath("provider").to_string_lossy())
+            .await?;
+        let namespace = provider.create_namespace().await?;
+        let (path, data) = TEST_FILES[0];
+        provider
+            .write_file(namespace, path.to_string(), data.to_vec())
+            .await?;
+
+        let archive = temp_dir.subpath("namespace.backup");
+        provider
+            .backup_namespace(namespace, archive.to_string_lossy().into(), true)
+            .await?;
+        provider.shutdown().await?;
+        let bytes = std::fs::read(&archive)?;
+        assert!(!bytes.windows(data.len()).any(|window| window == data));
+
+        let plain = mock_client(temp_dir.subpath("plain")).await?;
+        assert!(matches!(
+            plain
+                .restore_namespace(archive.to_string_lossy().into())
+                .await,
+            Err(SharedError::PermissionDenied(_))
+        ));
+        plain.shutdown().await?;
+
+        let restored = IrohFactory::with_encryption_key(key)?
+            .iroh_manager(&temp_dir.subpath("restored").to_string_lossy())
+            .await?;
+        let report = restored
+            .restore_namespace(archive.to_string_lossy().into())
+            .await?;
+        assert_eq!(report.entries, 1);
+        assert_eq!(restored.read_file(namespace, path).await?, data);
+        restored.shutdown().await?;
+
+        Ok(())
+    }
+
+    /// Whether any file under the directory contains the bytes
+    fn contains_plaintext(dir: &std::path::Path, plaintext: &[u8]) -> Result<bool> {
+        for file in walkdir::WalkDir::new(dir) {
+            let file = file?;
+            if file.file_type().is_file()
+                && std::fs::read(file.path())?
+                    .windows(plaintext.len())
+                    .any(|window| window == plaintext)
+            {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    #[tokio::test]
+    async fn test_encrypted_store() -> Result<()> {
+        let key = "music/plaintext key.mp3";
+        let data = b"plaintext music ".repeat(5000);
+        let mut network = TestNetwork::encrypted(2, vec![7; 32]).await?;
+        let namespace = network.shared_namespace().await?;
+        network
+            .manager(0)
+            .write_file(namespace, key.to_string(), data.clone())
+            .await?;
+        network.wait_until_converged(namespace).await?;
+        assert_eq!(network.manager(1).read_file(namespace, key).await?, data);
+
+        // Entries, secrets and blobs are read back from the encrypted files
+        network.stop(1).await?;
+        let guest = network.start(1).await?;
+        assert_eq!(guest.read_file(namespace, key).await?, data);
+        guest
+            .write_file(namespace, "after_restart".to_string(), b"guest".to_vec())
+            .await?;
+
+        network.stop(0).await?;
+        network.stop(1).await?;
+        for i in 0..2 {
+            assert!(!contains_plaintext(&network.path(i), key.as_bytes())?);
+            assert!(!contains_plaintext(&network.path(i), &data[..32])?);
+        }
+        network.shutdown().await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_encrypt_existing_store() -> Result<()> {
+        let temp_dir = TempDir::new();
+        let path = temp_dir.subpath("store");
+        let key = "music/plaintext key.mp3";
+        let data = b"plaintext music ".repeat(5000);
+
+        let plain = mock_client(path.clone()).await?;
+        let namespace = plain.create_namespace().await?;
+        plain
+            .write_file(namespace, key.to_string(), data.clone())
+            .await?;
+        plain.shutdown().await?;
+        assert!(contains_plaintext(&path, &data[..32])?);
+
+        let encrypted = IrohFactory::with_encryption_key(vec![7; 32])?
+            .iroh_manager(&path.to_string_lossy())
+            .await?;
+        assert_eq!(encrypted.read_file(namespace, key).await?, data);
+        assert!(!path.join("docs.redb").exists());
+        assert!(!path.join("blobs.db").exists());
+        encrypted.shutdown().await?;
+        assert!(!contains_plaintext(&path, key.as_bytes())?);
+        assert!(!contains_plaintext(&path, &data[..32])?);
+
+        assert!(matches!(
+            IrohFactory::new()
+                .iroh_manager(&path.to_string_lossy())
+                .await,
+            Err(SharedError::PermissionDenied(_))
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_backup_read_only() -> Result<()> {
+        let temp_dir = TempDir::new();
+        std::fs::create_dir_all(temp_dir.subpath(""))?;
+        let network = TestNetwork::new(2).await?;
+        let (owner, guest) = (network.manager(0), network.manager(1));
+        let namespace = owner.create_namespace().await?;
+        let (key, data) = TEST_FILES[0];
+        owner
+            .write_file(namespace, key.to_string(), data.to_vec())
+            .await?;
+        let invitation = owner.create_invitation(namespace, None, true, true).await?;
+        guest.accept_invitation(invitation.ticket).await?;
+        assert_eq!(guest.read_file(namespace, key).await?, data);
+
+        // The secret is asked for, but a read-only namespace has none
+        let archive = temp_dir.subpath("read_only.backup");
+        let report = guest
+            .backup_namespace(namespace, archive.to_string_lossy().into(), true)
+            .await?;
+        assert_eq!(report.entries, 1);
+        assert!(!report.includes_secret);
+        network.shutdown().await?;
+
+        let restored = mock_client(temp_dir.subpath("restored")).await?;
+        let report = restored
+            .restore_namespace(archive.to_string_lossy().into())
+            .await?;
+        assert_eq!(report.entries, 1);
+        assert!(!report.writable);
+        assert_eq!(restored.read_file(namespace, key).await?, data);
+        restored.shutdown().await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_import_directory() -> Result<()> {
+        let temp_dir = TempDir::new();
+        let music = temp_dir.subpath("music");
+        std::fs::create_dir_all(music.join("album"))?;
+        for (path, data) in &TEST_FILES {
+            std::fs::write(music.join("album").join(path), data)?;
+        }
+        std::fs::write(music.join("cover.tmp"), b"ignored")?;
+
+        let client = mock_client(temp_dir.subpath("import_client")).await?;
+        let namespace = client.create_namespace().await?;
+        let import = || {
+            client.import_directory(
+                namespace,
+                music.to_string_lossy().into(),
+                "library".to_string(),
+                vec!["*.tmp".to_string()],
+                true,
+            )
+        };
+
+        let report = import().await?;
+        assert_eq!(report.added, TEST_FILES.len() as u64);
+        assert_eq!(report.ignored, 1);
+        assert!(report.failures.is_empty());
+        let key = format!("library/album/{}", TEST_FILES[0].0);
+        assert_eq!(client.read_file(namespace, &key).await?, TEST_FILES[0].1);
+
+        let report = import().await?;
+        assert_eq!(report.added + report.updated, 0);
+        assert_eq!(report.unchanged, TEST_FILES.len() as u64);
+
+        std::fs::write(music.join("album").join(MODIFIED_FILE.0), MODIFIED_FILE.1)?;
+        std::fs::remove_file(music.join("album").join(DELETED_FILE))?;
+        let report = import().await?;
+        assert_eq!(report.updated, 1);
+        assert_eq!(report.tombstoned, 1);
+        assert_eq!(client.read_file(namespace, &key).await?, MODIFIED_FILE.1);
+        assert!(matches!(
+            client
+                .read_file(namespace, &format!("library/album/{DELETED_FILE}"))
+                .await,
+            Err(SharedError::EntryTombstoned(..))
+        ));
+
+        // Another directory under the same prefix leaves keys of the first one alone
+        let other = temp_dir.subpath("other");
+        std::fs::create_dir_all(&other)?;
+        std::fs::write(other.join("other.flac"), b"other")?;
+        let report = client
+            .import_directory(
+                namespace,
+                other.to_string_lossy().into(),
+                "library".to_string(),
+                vec![],
+                true,
+            )
+            .await?;
+        assert_eq!(report.added, 1);
+        assert_eq!(report.tombstoned, 0);
+        assert_eq!(client.read_file(namespace, &key).await?, MODIFIED_FILE.1);
+
+        // Importing over a split file releases its parts
+        let live_parts = async || -> Result<usize> {
+            let replica = client.docs.client().open(namespace.into()).await?.unwrap();
+            let parts = replica
+                .get_many(Query::single_latest_per_key().key_prefix(split::PARTS_PREFIX))
+                .await?
+                .collect::<Vec<_>>()
+                .await;
+            let tombstone = iroh_blobs::Hash::new(TOMBSTONE);
+            Ok(parts
+                .into_iter()
+                .filter(|entry| entry.as_ref().is_ok_and(|e| e.content_hash() != tombstone))
+                .count())
+        };
+        client
+            .write_file_split(namespace, "library/split.mp3".into(), tagged_mp3("Split"))
+            .await?;
+        assert_eq!(live_parts().await?, 2);
+        std::fs::write(music.join("split.mp3"), b"plain")?;
+        let report = import().await?;
+        assert_eq!(report.added, 1);
+        assert_eq!(live_parts().await?, 0);
+        assert_eq!(
+            client.read_file(namespace, "library/split.mp3").await?,
+            b"plain"
+        );
+
+        client.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_background_tasks_release_manager() -> Result<()> {
+        let temp_dir = TempDir::new();
+        let music = temp_dir.subpath("music");
+        std::fs::create_dir_all(&music)?;
+        std::fs::write(music.join(TEST_FILES[0].0), TEST_FILES[0].1)?;
+
+        let client = mock_client(temp_dir.subpath("client")).await?;
+        let namespace = client.create_namespace().await?;
+        client.add_auto_sync_namespace(namespace).await;
+        client.start_auto_sync_default().await;
+        client
+            .start_mirror(
+                namespace,
+                music.to_string_lossy().to_string(),
+                String::new(),
+                vec![],
+            )
+            .await?;
+        client.search(namespace, "track".into(), 10).await?;
+        client.start_streaming_server(0).await?;
+
+        let auto_sync = Arc::downgrade(&client.auto_sync);
+        let mirrors = Arc::downgrade(&client.mirrors);
+        let search_indexes = Arc::downgrade(&client.search_indexes);
+        let streaming_server = Arc::downgrade(&client.streaming_server);
+        let router = client.router.clone();
+        drop(client);
+
+        // Tasks in the middle of some work finish it before noticing
+        wait_for(async || {
+            auto_sync.strong_count() == 0
+                && mirrors.strong_count() == 0
+                && search_indexes.strong_count() == 0
+                && streaming_server.strong_count() == 0
+        })
+        .await;
+
+        router.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_mirror() -> Result<()> {
+        let temp_dir = TempDir::new();
+        let music = temp_dir.subpath("mirror");
+        std::fs::create_dir_all(&music)?;
+        std::fs::write(music.join(TEST_FILES[0].0), TEST_FILES[0].1)?;
+
+        let client = mock_client(temp_dir.subpath("mirror_client")).await?;
+        let namespace = client.create_namespace().await?;
+        client
+            .write_file(
+                namespace,
+                format!("library/{}", TEST_FILES[1].0),
+                TEST_FILES[1].1.to_vec(),
+            )
+            .await?;
+
+        client
+            .start_mirror(
+                namespace,
+                music.to_string_lossy().into(),
+                "library".to_string(),
+                vec![],
+            )
+            .await?;
+        assert_eq!(client.get_mirrors().len(), 1);
+
+        // Both sides are reconciled when the mirror starts
+        let exported = music.join(TEST_FILES[1].0);
+        wait_for(async || std::fs::read(&exported).is_ok_and(|data| data == TEST_FILES[1].1)).await;
+        let key = format!("library/{}", TEST_FILES[0].0);
+        assert_eq!(client.read_file(namespace, &key).await?, TEST_FILES[0].1);
+
+        // Local changes reach the namespace
+        std::fs::write(music.join(MODIFIED_FILE.0), MODIFIED_FILE.1)?;
+        let modified_key = format!("library/{}", MODIFIED_FILE.0);
+        wait_for(async || {
+            client
+                .read_file(namespace, &modified_key)
+                .await
+                .is_ok_and(|data| data == MODIFIED_FILE.1)
+        })
+        .await;
+
+        // Deleted entries are removed from the directory
+        client
+            .delete_file(namespace, format!("library/{}", TEST_FILES[1].0))
+            .await?;
+        wait_for(async || !exported.exists()).await;
+
+        client.stop_mirror(namespace);
+        assert!(client.get_mirrors().is_empty());
+
+        client.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_export_all() -> Result<()> {
+        let temp_dir = TempDir::new();
+        let destination = temp_dir.subpath("exported");
+
+        let client = mock_client(temp_dir.subpath("export_client")).await?;
+        let namespace = client.create_namespace().await?;
+        for (path, data) in &TEST_FILES {
+            client
+                .write_file(namespace, format!("library/{path}"), data.to_vec())
+                .await?;
+        }
+        client
+            .write_file(namespace, "library/what?.txt".to_string(), b"odd".to_vec())
+            .await?;
+        client
+            .write_file(
+                namespace,
+                "other/skipped.txt".to_string(),
+                b"other".to_vec(),
+            )
+            .await?;
+        client
+            .delete_file(namespace, format!("library/{DELETED_FILE}"))
+            .await?;
+
+        let progress = Arc::new(ExportProgressHandle::new());
+        let export = || {
+            client.export_all(
+                namespace,
+                "library".to_string(),
+                destination.to_string_lossy().into(),
+                Some(progress.clone()),
+            )
+        };
+
+        let report = export().await?;
+        assert_eq!(report.exported, TEST_FILES.len() as u64);
+        assert_eq!(report.tombstoned, 1);
+        assert!(report.failures.is_empty());
+        assert_eq!(std::fs::read(destination.join("what_.txt"))?, b"odd");
+        assert_eq!(
+            std::fs::read(destination.join(TEST_FILES[0].0))?,
+            TEST_FILES[0].1
+        );
+        assert!(!destination.join(DELETED_FILE).exists());
+        assert!(!destination.join("skipped.txt").exists());
+
+        let progress = progress.progress();
+        assert_eq!(progress.total_files, TEST_FILES.len() as u64);
+        assert_eq!(progress.processed_files, progress.total_files);
+
+        let report = export().await?;
+        assert_eq!(report.exported, 0);
+        assert_eq!(report.unchanged, TEST_FILES.len() as u64);
+
+        client.shutdown().await?;
+        Ok(())
+    }
+
+    fn syncsafe(len: usize) -> [u8; 4] {
+        [21, 14, 7, 0].map(|shift| (len >> shift) as u8 & 0x7f)
+    }
+
+    /// MP3 with an ID3v2.4 tag holding the title and optionally a front cover
+    fn tagged_mp3_with_art(title: &str, image: Option<&[u8]>) -> Vec<u8> {
+        let mut frames = vec![(b"TIT2", [&[3], title.as_bytes()].concat())];
+        if let Some(image) = image {
+            // Latin-1 MIME type, front cover, empty description
+            frames.push((b"APIC", [b"\x00image/png\x00\x03\x00", image].concat()));
+        }
+
+        let mut tag = Vec::new();
+        for (id, body) in frames {
+            tag.extend(id);
+            tag.extend(syncsafe(body.len()));
+            tag.extend([0, 0]);
+            tag.extend(body);
+        }
+
+        let mut data = b"ID3\x04\x00\x00".to_vec();
+        data.extend(syncsafe(tag.len()));
+        data.extend(tag);
+        // MPEG-1 Layer III frames, 128 kbps at 44.1 kHz
+        for i in 0..160 {
+            data.extend([0xff, 0xfb, 0x90, 0x64]);
+            data.extend([i as u8; 413]);
+        }
+        data
+    }
+
+    fn tagged_mp3(title: &str) -> Vec<u8> {
+        tagged_mp3_with_art(title, None)
+    }
+
+    #[tokio::test]
+    async fn test_write_file_split() -> Result<()> {
+        let temp_dir = TempDir::new();
+        let client = mock_client(temp_dir.subpath("split_client")).await?;
+        let namespace = client.create_namespace().await?;
+        let key = "library/track.mp3".to_string();
+
+        let original = tagged_mp3("Original");
+        client
+            .write_file_split(namespace, key.clone(), original.clone())
+            .await?;
+        assert_eq!(client.read_file(namespace, &key).await?, original);
+
+        let edited = tagged_mp3("Edited title");
+        let hash = client
+            .write_file_split(namespace, key.clone(), edited.clone())
+            .await?;
+        assert_eq!(client.read_file(namespace, &key).await?, edited);
+        assert_eq!(client.read_file_hash(hash).await?, edited);
+        let track = client
+            .get_track_metadata(namespace, key.clone())
+            .await?
+            .unwrap();
+        assert_eq!(track.title.as_deref(), Some("Edited title"));
+
+        // Audio is stored once, the replaced tags are released
+        let files = client.get_files(namespace).await?;
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].file_len(), edited.len() as u64);
+        let live_parts = async || -> Result<usize> {
+            let replica = client.docs.client().open(namespace.into()).await?.unwrap();
+            let parts = replica
+                .get_many(Query::single_latest_per_key().key_prefix(split::PARTS_PREFIX))
+                .await?
+                .collect::<Vec<_>>()
+                .await;
+            let tombstone = iroh_blobs::Hash::new(TOMBSTONE);
+            Ok(parts
+                .into_iter()
+                .filter(|entry| entry.as_ref().is_ok_and(|e| e.content_hash() != tombstone))
+                .count())
+        };
+        assert_eq!(live_parts().await?, 2);
+
+        let destination = temp_dir.subpath("track.mp3");
+        client
+            .export(namespace, &key, &destination.to_string_lossy())
+            .await?;
+        assert_eq!(std::fs::read(&destination)?, edited);
+
+        // Exported split files are recognised as unchanged
+        let export_dir = temp_dir
+            .subpath("split_export")
+            .to_string_lossy()
+            .to_string();
+        let progress = Arc::new(ExportProgressHandle::new());
+        let report = client
+            .export_all(
+                namespace,
+                "library".into(),
+                export_dir.clone(),
+                Some(progress.clone()),
+            )
+            .await?;
+        assert_eq!(report.exported, 1);
+        assert_eq!(report.exported_bytes, edited.len() as u64);
+        let progress = progress.progress();
+        assert_eq!(progress.total_bytes, edited.len() as u64);
+        assert_eq!(progress.processed_bytes, edited.len() as u64);
+        let report = client
+            .export_all(namespace, "library".into(), export_dir, None)
+            .await?;
+        assert_eq!(report.unchanged, 1);
+
+        // Released parts are internal entries, not deleted files
+        let report = client
+            .export_all(
+                namespace,
+                String::new(),
+                temp_dir.subpath("full_export").to_string_lossy().into(),
+                None,
+            )
+            .await?;
+        assert_eq!(report.exported, 1);
+        assert_eq!(report.tombstoned, 0);
+
+        // Parts still referenced by another file are kept
+        client
+            .write_file_split(namespace, "library/copy.mp3".into(), tagged_mp3("Copy"))
+            .await?;
+        assert_eq!(live_parts().await?, 3);
+        client.delete_file(namespace, key.clone()).await?;
+        assert_eq!(live_parts().await?, 2);
+        assert!(matches!(
+            client.get_file(namespace, &key).await,
+            Err(SharedError::EntryTombstoned(..))
+        ));
+        let copy = client.get_file(namespace, "library/copy.mp3").await?;
+        assert_eq!(copy.file_len(), tagged_mp3("Copy").len() as u64);
+        client
+            .write_file(namespace, "library/copy.mp3".into(), b"plain".to_vec())
+            .await?;
+        assert_eq!(live_parts().await?, 0);
+
+        // Other formats are stored as they are
+        client
+            .write_file_split(namespace, "notes.txt".to_string(), b"notes".to_vec())
+            .await?;
+        assert_eq!(client.read_file(namespace, "notes.txt").await?, b"notes");
+
+        // Internal keys can't be overwritten
+        let part = format!("{}{}", split::PARTS_PREFIX, hash);
+        assert!(matches!(
+            client
+                .write_file(namespace, part.clone(), b"part".to_vec())
+                .await,
+            Err(SharedError::InvalidInput(_))
+        ));
+        assert!(matches!(
+            client.delete_file(namespace, part.clone()).await,
+            Err(SharedError::InvalidInput(_))
+        ));
+        assert!(matches!(
+            client
+                .write_file_split(namespace, part, b"part".to_vec())
+                .await,
+            Err(SharedError::InvalidInput(_))
+        ));
+
+        client.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_file_from_path() -> Result<()> {
+        let temp_dir = TempDir::new();
+        let client = mock_client(temp_dir.subpath("path_client")).await?;
+        let namespace = client.create_namespace().await?;
+        let key = "library/track.mp3".to_string();
+
+        let source = temp_dir.subpath("source.mp3");
+        let data = tagged_mp3("From path");
+        std::fs::write(&source, &data)?;
+
+        let hash = client
+            .write_file_from_path(namespace, key.clone(), source.to_string_lossy().into())
+            .await?;
+        assert_eq!(client.read_file(namespace, &key).await?, data);
+        assert_eq!(client.read_file_hash(hash).await?, data);
+
+        let track = client.get_track_metadata(namespace, key).await?.unwrap();
+        assert_eq!(track.title.as_deref(), Some("From path"));
+
+        client.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_track_metadata() -> Result<()> {
+        let temp_dir = TempDir::new();
+        let client = mock_client(temp_dir.subpath("metadata_client")).await?;
+        let namespace = client.create_namespace().await?;
+        let key = "library/track.mp3".to_string();
+
+        client
+            .write_file(namespace, key.clone(), tagged_mp3("Indexed"))
+            .await?;
+        client
+            .write_file(namespace, "notes.txt".to_string(), b"notes".to_vec())
+            .await?;
+
+        let track = client
+            .get_track_metadata(namespace, key.clone())
+            .await?
+            .unwrap();
+        assert_eq!(track.key, key);
+        assert_eq!(track.title.as_deref(), Some("Indexed"));
+        assert_eq!(track.codec, "mp3");
+        assert!(
+            client
+                .get_track_metadata(namespace, "notes.txt".to_string())
+                .await?
+                .is_none()
+        );
+
+        assert_eq!(client.get_tracks(namespace, None, None).await?, vec![track]);
+        assert!(
+            client
+                .get_tracks(namespace, Some("Nobody".to_string()), None)
+                .await?
+                .is_empty()
+        );
+        // Sidecar entries aren't files
+        assert_eq!(client.get_files(namespace).await?.len(), 2);
+
+        client.delete_file(namespace, key.clone()).await?;
+        assert!(client.get_track_metadata(namespace, key).await?.is_none());
+        assert!(client.get_tracks(namespace, None, None).await?.is_empty());
+
+        client.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_search() -> Result<()> {
+        let temp_dir = TempDir::new();
+        let client = mock_client(temp_dir.subpath("search_client")).await?;
+        let namespace = client.create_namespace().await?;
+
+        client
+            .write_file(
+                namespace,
+                "library/01.mp3".to_string(),
+                tagged_mp3("Café del Mar"),
+            )
+            .await?;
+        client
+            .write_file(namespace, "notes/cafe.txt".to_string(), b"notes".to_vec())
+            .await?;
+
+        // Title match ranks above the key match, diacritics are ignored
+        let results = client.search(namespace, "CAFE".to_string(), 10).await?;
+        let keys: Vec<_> = results.iter().map(|result| result.key.as_str()).collect();
+        assert_eq!(keys, ["library/01.mp3", "notes/cafe.txt"]);
+        assert_eq!(
+            results[0].track.as_ref().unwrap().title.as_deref(),
+            Some("Café del Mar")
+        );
+        assert_eq!(
+            client.search(namespace, "caf".to_string(), 1).await?.len(),
+            1
+        );
+
+        // Index follows later changes
+        client
+            .write_file(namespace, "library/02.mp3".to_string(), tagged_mp3("Marée"))
+            .await?;
+        client
+            .delete_file(namespace, "notes/cafe.txt".to_string())
+            .await?;
+        let search = async |query: &str| -> Vec<String> {
+            client
+                .search(namespace, query.to_string(), 10)
+                .await
+                .unwrap_or_default()
+                .into_iter()
+                .map(|result| result.key)
+                .collect()
+        };
+        wait_for(async || search("mar").await.len() == 2 && search("notes").await.is_empty()).await;
+        assert_eq!(search("mar").await, ["library/01.mp3", "library/02.mp3"]);
+        assert!(search("notes").await.is_empty());
+
+        client.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_artwork() -> Result<()> {
+        let temp_dir = TempDir::new();
+        let client = mock_client(temp_dir.subpath("artwork_client")).await?;
+        let namespace = client.create_namespace().await?;
+
+        let mut cover = Vec::new();
+        image::RgbImage::from_pixel(600, 600, image::Rgb([200, 40, 40]))
+            .write_to(
+                &mut std::io::Cursor::new(&mut cover),
+                image::ImageFormat::Png,
+            )
+            .unwrap();
+
+        // Both tracks of the album embed the same cover
+        for (key, title) in [("album/01.mp3", "First"), ("album/02.mp3", "Second")] {
+            client
+                .write_file(
+                    namespace,
+                    key.to_string(),
+                    tagged_mp3_with_art(title, Some(&cover)),
+                )
+                .await?;
+        }
+
+        let first = client
+            .get_track_metadata(namespace, "album/01.mp3".to_string())
+            .await?
+            .unwrap();
+        let second = client
+            .get_track_metadata(namespace, "album/02.mp3".to_string())
+            .await?
+            .unwrap();
+        let hash = first.artwork.clone().unwrap();
+        assert_eq!(second.artwork, Some(hash.clone()));
+
+        // Stored once, with a thumbnail per size
+        let replica = client.docs.client().open(namespace.into()).await?.unwrap();
+        let art_entries = replica
+            .get_many(Query::single_latest_per_key().key_prefix(artwork::ARTWORK_PREFIX))
+            .await?
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(art_entries.len(), 1 + artwork::THUMBNAIL_SIZES.len());
+
+        let hash: UHash = hash.parse()?;
+        assert_eq!(
+            client.get_artwork(namespace, hash, None).await?,
+            Some(cover)
+        );
+        let thumbnail = client
+            .get_artwork(namespace, hash, Some(200))
+            .await?
+            .unwrap();
+        let thumbnail = image::load_from_memory(&thumbnail).unwrap();
+        assert_eq!((thumbnail.width(), thumbnail.height()), (256, 256));
+
+        // Tracks without art don't reference any
+        client
+            .write_file(namespace, "plain.mp3".to_string(), tagged_mp3("Plain"))
+            .await?;
+        let plain = client
+            .get_track_metadata(namespace, "plain.mp3".to_string())
+            .await?
+            .unwrap();
+        assert_eq!(plain.artwork, None);
+
+        client.shutdown().await?;
+        Ok(())
+    }
+
+    /// Sends a request to the local server, returning the status line, headers and body
+    async fn http_request(
+        port: u16,
+        method: &str,
+        target: &str,
+        headers: &[(&str, &str)],
+    ) -> Result<(u16, Vec<(String, String)>, Vec<u8>)> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let mut stream = tokio::net::TcpStream::connect(("127.0.0.1", port)).await?;
+        let mut request =
+            format!("{method} {target} HTTP/1.1\r\nHost: 127.0.0.1\r\nConnection: close\r\n");
+        for (name, value) in headers {
+            request.push_str(&format!("{name}: {value}\r\n"));
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes()).await?;
+
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await?;
+        let split = response
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .ok_or("incomplete response")?;
+        let head = String::from_utf8(response[..split].to_vec())?;
+        let body = response[split + 4..].to_vec();
+
+        let mut lines = head.lines();
+        let status = lines
+            .next()
+            .unwrap_or_default()
+            .split(' ')
+            .nth(1)
+            .unwrap_or_default()
+            .parse()?;
+        let headers = lines
+            .filter_map(|line| line.split_once(": "))
+            .map(|(name, value)| (name.to_lowercase(), value.to_string()))
+            .collect();
+        Ok((status, headers, body))
+    }
+
+    fn header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+        headers
+            .iter()
+            .find(|(header, _)| header == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    #[tokio::test]
+    async fn test_streaming_server() -> Result<()> {
+        let temp_dir = TempDir::new();
+        let client = mock_client(temp_dir.subpath("streaming_client")).await?;
+        let namespace = client.create_namespace().await?;
+
+        let key = "album/01 intro.mp3".to_string();
+        let track = tagged_mp3("Intro");
+        client
+            .write_file_split(namespace, key.clone(), track.clone())
+            .await?;
+        let notes_hash = client
+            .write_file(namespace, "notes".to_string(), b"plain notes".to_vec())
+            .await?;
+
+        assert!(client.get_stream_url(namespace, key.clone()).is_err());
+        let info = client.start_streaming_server(0).await?;
+        assert_eq!(client.start_streaming_server(0).await?, info);
+
+        let url = client.get_stream_url(namespace, key.clone())?;
+        assert!(url.starts_with(&info.base_url));
+        let target = url.strip_prefix(&info.base_url).unwrap();
+        assert!(target.contains("/album/01%20intro.mp3?token="));
+
+        // Split files are served whole
+        let (status, headers, body) = http_request(info.port, "GET", target, &[]).await?;
+        assert_eq!(status, 200);
+        assert_eq!(header(&headers, "content-type"), Some("audio/mpeg"));
+        assert_eq!(header(&headers, "accept-ranges"), Some("bytes"));
+        assert_eq!(
+            header(&headers, "content-length"),
+            Some(track.len().to_string().as_str())
+        );
+        assert_eq!(body, track);
+
+        // Range spanning the tags and the audio frames
+        let (status, headers, body) =
+            http_request(info.port, "GET", target, &[("Range", "bytes=10-2009")]).await?;
+        assert_eq!(status, 206);
+        assert_eq!(
+            header(&headers, "content-range"),
+            Some(format!("bytes 10-2009/{}", track.len()).as_str())
+        );
+        assert_eq!(body, track[10..2010]);
+
+        let (status, headers, _) =
+            http_request(info.port, "GET", target, &[("Range", "bytes=999999-")]).await?;
+        assert_eq!(status, 416);
+        assert_eq!(
+            header(&headers, "content-range"),
+            Some(format!("bytes */{}", track.len()).as_str())
+        );
+
+        let (status, headers, body) = http_request(info.port, "HEAD", target, &[]).await?;
+        assert_eq!(status, 200);
+        assert_eq!(
+            header(&headers, "content-length"),
+            Some(track.len().to_string().as_str())
+        );
+        assert!(body.is_empty());
+
+        // Blobs can be fetched by hash, with the token as a bearer token
+        let blob_url = client.get_blob_stream_url(notes_hash)?;
+        let blob_path = blob_url
+            .strip_prefix(&info.base_url)
+            .unwrap()
+            .split('?')
+            .next()
+            .unwrap();
+        let bearer = format!("Bearer {}", info.token);
+        let (status, _, body) =
+            http_request(info.port, "GET", blob_path, &[("Authorization", &bearer)]).await?;
+        assert_eq!(status, 200);
+        assert_eq!(body, b"plain notes");
+
+        // Requests without the token are rejected
+        let (status, _, _) = http_request(info.port, "GET", blob_path, &[]).await?;
+        assert_eq!(status, 401);
+        let (status, _, _) =
+            http_request(info.port, "GET", &format!("{blob_path}?token=wrong"), &[]).await?;
+        assert_eq!(status, 401);
+
+        let missing = format!("/ns/{namespace}/missing?token={}", info.token);
+        let (status, _, _) = http_request(info.port, "GET", &missing, &[]).await?;
+        assert_eq!(status, 404);
+
+        client.stop_streaming_server();
+        assert!(client.get_streaming_server().is_none());
+
+        client.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_streaming_fetches_from_peers() -> Result<()> {
+        let network = TestNetwork::new(2).await?;
+        network.introduce_all().await?;
+        let (owner, peer) = (network.manager(0), network.manager(1));
+        let namespace = owner.create_namespace().await?;
+
+        let data: Vec<u8> = (0..4 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+        let hash = owner
+            .write_file(namespace, "large.bin".into(), data.clone())
+            .await?;
+        let track = tagged_mp3("Fetched");
+        let track_hash = owner
+            .write_file_split(namespace, "track.mp3".into(), track.clone())
+            .await?;
+
+        // Ranges are served while the rest of the blob is still downloaded
+        let info = peer.start_streaming_server(0).await?;
+        let target = format!("/blob/{hash}?token={}", info.token);
+        let (status, headers, body) = http_request(
+            info.port,
+            "GET",
+            &target,
+            &[("Range", "bytes=1000000-1000999")],
+        )
+        .await?;
+        assert_eq!(status, 206);
+        assert_eq!(
+            header(&headers, "content-range"),
+            Some("bytes 1000000-1000999/4194304")
+        );
+        assert_eq!(body, &data[1_000_000..1_001_000]);
+
+        let (status, _, body) = http_request(info.port, "GET", &target, &[]).await?;
+        assert_eq!(status, 200);
+        assert_eq!(body, data);
+
+        // Parts of split files are fetched too
+        let target = format!("/blob/{track_hash}?token={}", info.token);
+        let (status, _, body) = http_request(info.port, "GET", &target, &[]).await?;
+        assert_eq!(status, 200);
+        assert_eq!(body, track);
 
+        network.shutdown().await?;
         Ok(())
     }
 }
@@ -0,0 +1,261 @@
+use iroh_blobs::Hash;
+use iroh_docs::store::Query;
+use lofty::{
+    config::ParseOptions,
+    file::FileType,
+    mp4::{Mp4Codec, Mp4File},
+    prelude::*,
+    probe::Probe,
+    tag::Tag,
+};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::{
+    io::{BufReader, Cursor, Read, Seek, SeekFrom},
+    path::Path,
+};
+use tokio_stream::StreamExt;
+
+use crate::{
+    IrohManager, RESERVED_PREFIX, TOMBSTONE, artwork,
+    errors::{Result, SharedError},
+    types::UNamespaceId,
+};
+
+/// Keys of sidecar entries holding metadata of tracks, followed by the key of the track
+pub const METADATA_PREFIX: &str = ".unimusic/meta/";
+
+/// Tags and properties of an audio file, readable without downloading the file
+#[cfg_attr(feature = "default", derive(uniffi::Record))]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrackMetadata {
+    /// Key of the audio file
+    pub key: String,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub track_number: Option<u32>,
+    pub duration_ms: u64,
+    /// Lowercase codec name, such as `mp3`, `flac`, `aac`, `alac`, `vorbis` or `opus`
+    pub codec: String,
+    /// Hash of the embedded cover art, see [`IrohManager::get_artwork`]
+    #[serde(default)]
+    pub artwork: Option<String>,
+}
+
+/// Metadata of a track with its embedded cover art, which is stored separately
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ExtractedTrack {
+    pub track: TrackMetadata,
+    pub artwork: Option<Vec<u8>>,
+}
+
+fn parse_options() -> ParseOptions {
+    ParseOptions::new().read_cover_art(true)
+}
+
+/// Parses tags and properties of MP3, FLAC, MP4 and Ogg files, `None` for anything else
+fn extract<R: Read + Seek>(key: &str, mut reader: R) -> Option<ExtractedTrack> {
+    let probe = Probe::new(&mut reader)
+        .options(parse_options())
+        .guess_file_type()
+        .ok()?;
+
+    let codec = match probe.file_type()? {
+        FileType::Mpeg => "mp3",
+        FileType::Flac => "flac",
+        FileType::Vorbis => "vorbis",
+        FileType::Opus => "opus",
+        FileType::Speex => "speex",
+        FileType::Mp4 => {
+            reader.seek(SeekFrom::Start(0)).ok()?;
+            let file =
+                Mp4File::read_from(&mut reader, ParseOptions::new().read_cover_art(false)).ok()?;
+            match file.properties().codec() {
+                Some(Mp4Codec::AAC) => "aac",
+                Some(Mp4Codec::ALAC) => "alac",
+                Some(Mp4Codec::MP3) => "mp3",
+                Some(Mp4Codec::FLAC) => "flac",
+                _ => "mp4",
+            }
+        }
+        _ => return None,
+    };
+
+    reader.seek(SeekFrom::Start(0)).ok()?;
+    let file = Probe::new(&mut reader)
+        .options(parse_options())
+        .guess_file_type()
+        .ok()?
+        .read()
+        .ok()?;
+    let tag: Option<&Tag> = file.primary_tag().or_else(|| file.first_tag());
+
+    let track = TrackMetadata {
+        key: key.to_string(),
+        title: tag
+            .and_then(|tag| tag.title())
+            .map(|title| title.into_owned()),
+        artist: tag
+            .and_then(|tag| tag.artist())
+            .map(|artist| artist.into_owned()),
+        album: tag
+            .and_then(|tag| tag.album())
+            .map(|album| album.into_owned()),
+        track_number: tag.and_then(|tag| tag.track()),
+        duration_ms: file.properties().duration().as_millis() as u64,
+        codec: codec.to_string(),
+        artwork: None,
+    };
+    Some(ExtractedTrack {
+        track,
+        artwork: tag.and_then(artwork::from_tag),
+    })
+}
+
+pub(crate) fn extract_from_bytes(key: &str, data: &[u8]) -> Option<ExtractedTrack> {
+    extract(key, Cursor::new(data))
+}
+
+pub(crate) fn extract_from_path(key: &str, path: &Path) -> Option<ExtractedTrack> {
+    let file = std::fs::File::open(path).ok()?;
+    extract(key, BufReader::new(file))
+}
+
+fn metadata_key(key: &str) -> String {
+    format!("{METADATA_PREFIX}{key}")
+}
+
+/// Writes the sidecar entry of the key, or deletes it if the key no longer holds a track
+pub(crate) async fn store(
+    manager: &IrohManager,
+    namespace: UNamespaceId,
+    key: &str,
+    metadata: Option<TrackMetadata>,
+) -> Result<()> {
+    if key.starts_with(RESERVED_PREFIX) {
+        return Ok(());
+    }
+
+    let docs_client = manager.docs.client();
+    let replica = docs_client
+        .open(namespace.into())
+        .await?
+        .ok_or(SharedError::ReplicaMissing(namespace))?;
+
+    let sidecar_key = metadata_key(key);
+    let current = replica
+        .get_one(Query::single_latest_per_key().key_exact(&sidecar_key))
+        .await?
+        .map(|entry| entry.content_hash());
+
+    let content = match metadata {
+        Some(metadata) => {
+            serde_json::to_vec(&metadata).map_err(|e| SharedError::Serde(e.to_string()))?
+        }
+        None if current.is_some_and(|hash| hash != Hash::new(TOMBSTONE)) => TOMBSTONE.to_vec(),
+        None => return Ok(()),
+    };
+
+    // Writing the same metadata again would only cause sync traffic
+    if current == Some(Hash::new(&content)) {
+        return Ok(());
+    }
+
+    let author = docs_client.authors().default().await?;
+    replica.set_bytes(author, sidecar_key, content).await?;
+    Ok(())
+}
+
+/// Stores the cover art of the track and then its metadata referencing it.
+/// Only logs failures, so they don't fail the write of the file itself.
+pub(crate) async fn store_logged(
+    manager: &IrohManager,
+    namespace: UNamespaceId,
+    key: &str,
+    extracted: Option<ExtractedTrack>,
+) {
+    let metadata = match extracted {
+        Some(ExtractedTrack {
+            mut track,
+            artwork: Some(image),
+        }) => {
+            let hash = artwork::store_logged(manager, namespace, image).await;
+            track.artwork = hash.map(|hash| hash.to_string());
+            Some(track)
+        }
+        Some(ExtractedTrack { track, .. }) => Some(track),
+        None => None,
+    };
+    if let Err(error) = store(manager, namespace, key, metadata).await {
+        warn!("[metadata] failed to index {key}: {error}");
+    }
+}
+
+pub(crate) async fn read_metadata(manager: &IrohManager, hash: Hash) -> Option<TrackMetadata> {
+    if hash == Hash::new(TOMBSTONE) {
+        return None;
+    }
+    // Sidecars of peers might not be downloaded yet
+    let bytes = manager.blobs.client().read_to_bytes(hash).await.ok()?;
+    serde_json::from_slice(&bytes).ok()
+}
+
+pub(crate) async fn get_track(
+    manager: &IrohManager,
+    namespace: UNamespaceId,
+    key: &str,
+) -> Result<Option<TrackMetadata>> {
+    let replica = manager
+        .docs
+        .client()
+        .open(namespace.into())
+        .await?
+        .ok_or(SharedError::ReplicaMissing(namespace))?;
+
+    let Some(entry) = replica
+        .get_one(Query::single_latest_per_key().key_exact(metadata_key(key)))
+        .await?
+    else {
+        return Ok(None);
+    };
+    Ok(read_metadata(manager, entry.content_hash()).await)
+}
+
+/// Returns metadata of all indexed tracks, optionally only of given artist or album.
+/// Filters ignore case.
+pub(crate) async fn get_tracks(
+    manager: &IrohManager,
+    namespace: UNamespaceId,
+    artist: Option<&str>,
+    album: Option<&str>,
+) -> Result<Vec<TrackMetadata>> {
+    let replica = manager
+        .docs
+        .client()
+        .open(namespace.into())
+        .await?
+        .ok_or(SharedError::ReplicaMissing(namespace))?;
+
+    let matches = |filter: Option<&str>, value: &Option<String>| match filter {
+        Some(filter) => value
+            .as_deref()
+            .is_some_and(|value| value.to_lowercase() == filter.to_lowercase()),
+        None => true,
+    };
+
+    let mut tracks = Vec::new();
+    let mut entries = replica
+        .get_many(Query::single_latest_per_key().key_prefix(METADATA_PREFIX))
+        .await?;
+    while let Some(entry) = entries.try_next().await? {
+        let Some(track) = read_metadata(manager, entry.content_hash()).await else {
+            continue;
+        };
+        if matches(artist, &track.artist) && matches(album, &track.album) {
+            tracks.push(track);
+        }
+    }
+
+    Ok(tracks)
+}
@@ -0,0 +1,364 @@
+use glob::Pattern;
+use iroh_blobs::Hash;
+use iroh_docs::{ContentStatus, engine::LiveEvent, store::Query};
+use log::{info, warn};
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::{
+    collections::HashMap,
+    path::{Component, Path, PathBuf},
+    time::Duration,
+};
+use tokio::{fs, sync::mpsc, task::JoinHandle, time::Instant};
+use tokio_stream::StreamExt;
+
+use crate::{
+    IrohManager, RESERVED_PREFIX, TOMBSTONE, WeakIrohManager, directory,
+    errors::{Result, SharedError},
+    export::{self, PARTIAL_SUFFIX},
+    types::UNamespaceId,
+};
+
+/// How long to wait for more filesystem changes before importing them
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+#[cfg_attr(feature = "default", derive(uniffi::Record))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MirrorInfo {
+    pub namespace: UNamespaceId,
+    pub dir: String,
+    pub key_prefix: String,
+}
+
+/// Directory kept in sync with a namespace in both directions
+#[derive(Debug)]
+pub struct Mirror {
+    pub info: MirrorInfo,
+    task: JoinHandle<()>,
+    _watcher: RecommendedWatcher,
+}
+
+impl Drop for Mirror {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+#[derive(Debug, Clone)]
+struct MirrorContext {
+    manager: WeakIrohManager,
+    namespace: UNamespaceId,
+    dir: PathBuf,
+    key_prefix: String,
+    ignore_globs: Vec<String>,
+    patterns: Vec<Pattern>,
+}
+
+impl Mirror {
+    /// Watches the directory and starts mirroring it.
+    /// Files of the directory are imported first, then entries missing in it are exported.
+    pub(crate) async fn start(
+        manager: &IrohManager,
+        namespace: UNamespaceId,
+        dir: &Path,
+        key_prefix: String,
+        mut ignore_globs: Vec<String>,
+    ) -> Result<Self> {
+        manager
+            .docs
+            .client()
+            .open(namespace.into())
+            .await?
+            .ok_or(SharedError::ReplicaMissing(namespace))?;
+
+        fs::create_dir_all(dir).await?;
+        let dir = fs::canonicalize(dir).await?;
+
+        // Files being exported must never be imported
+        ignore_globs.push(format!("*{PARTIAL_SUFFIX}"));
+        let patterns = directory::compile_globs(&ignore_globs)?;
+
+        let (changes, receiver) = mpsc::unbounded_channel();
+        let mut watcher =
+            notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+                let Ok(event) = event else {
+                    return;
+                };
+                let relevant = matches!(
+                    event.kind,
+                    EventKind::Create(_)
+                        | EventKind::Modify(_)
+                        | EventKind::Remove(_)
+                        | EventKind::Any
+                ) && !event
+                    .paths
+                    .iter()
+                    .all(|path| path.to_string_lossy().ends_with(PARTIAL_SUFFIX));
+
+                if relevant {
+                    let _ = changes.send(());
+                }
+            })
+            .map_err(|error| SharedError::Storage(format!("Failed to watch directory: {error}")))?;
+        watcher
+            .watch(&dir, RecursiveMode::Recursive)
+            .map_err(|error| SharedError::Storage(format!("Failed to watch directory: {error}")))?;
+
+        let info = MirrorInfo {
+            namespace,
+            dir: dir.to_string_lossy().into(),
+            key_prefix: key_prefix.clone(),
+        };
+        let context = MirrorContext {
+            manager: manager.downgrade(),
+            namespace,
+            dir,
+            key_prefix,
+            ignore_globs,
+            patterns,
+        };
+        info!("[mirror] mirroring namespace {namespace} to {}", info.dir);
+
+        Ok(Self {
+            info,
+            task: tokio::spawn(run_mirror(context, receiver)),
+            _watcher: watcher,
+        })
+    }
+}
+
+async fn run_mirror(context: MirrorContext, mut changes: mpsc::UnboundedReceiver<()>) {
+    let namespace = context.namespace;
+    let Ok(manager) = context.manager() else {
+        return;
+    };
+    let replica = match manager.docs.client().open(namespace.into()).await {
+        Ok(Some(replica)) => replica,
+        Ok(None) => {
+            warn!("[mirror] namespace {namespace} does not exist, stopping");
+            return;
+        }
+        Err(error) => {
+            warn!("[mirror] failed to open namespace {namespace}: {error}");
+            return;
+        }
+    };
+
+    // Subscribed before the initial export, so no remote entry slips between them
+    let mut events = match replica.subscribe().await {
+        Ok(events) => events,
+        Err(error) => {
+            warn!("[mirror] failed to subscribe to namespace {namespace}: {error}");
+            return;
+        }
+    };
+    drop(manager);
+
+    if let Err(error) = context.import().await {
+        warn!("[mirror] initial import of namespace {namespace} failed: {error}");
+    }
+    if let Err(error) = context.export_all().await {
+        warn!("[mirror] initial export of namespace {namespace} failed: {error}");
+    }
+
+    let mut deadline: Option<Instant> = None;
+    // Keys waiting for their content to be downloaded
+    let mut pending: HashMap<Hash, Vec<String>> = HashMap::new();
+
+    loop {
+        let due = async {
+            match deadline {
+                Some(deadline) => tokio::time::sleep_until(deadline).await,
+                None => std::future::pending().await,
+            }
+        };
+
+        tokio::select! {
+            Some(()) = changes.recv() => {
+                deadline.get_or_insert_with(|| Instant::now() + DEBOUNCE);
+            }
+            _ = due => {
+                deadline = None;
+                if let Err(error) = context.import().await {
+                    warn!("[mirror] import of namespace {namespace} failed: {error}");
+                }
+            }
+            event = events.next() => {
+                let keys = match event {
+                    // Entries the mirror imported itself are skipped by export_key, the index has their hash
+                    Some(Ok(LiveEvent::InsertLocal { entry })) => {
+                        vec![String::from_utf8_lossy(entry.key()).into_owned()]
+                    }
+                    Some(Ok(LiveEvent::InsertRemote { entry, content_status, .. })) => {
+                        let key = String::from_utf8_lossy(entry.key()).into_owned();
+                        if context.relative_path(&key).is_none() {
+                            continue;
+                        }
+                        if content_status == ContentStatus::Complete {
+                            vec![key]
+                        } else {
+                            pending.entry(entry.content_hash()).or_default().push(key);
+                            continue;
+                        }
+                    }
+                    Some(Ok(LiveEvent::ContentReady { hash })) => {
+                        pending.remove(&hash).unwrap_or_default()
+                    }
+                    Some(Ok(_)) => continue,
+                    Some(Err(error)) => {
+                        warn!("[mirror] namespace {namespace} event error: {error}");
+                        continue;
+                    }
+                    None => {
+                        warn!("[mirror] namespace {namespace} events ended, stopping");
+                        break;
+                    }
+                };
+
+                for key in keys {
+                    if let Err(error) = context.export_key(&key).await {
+                        warn!("[mirror] failed to export {key}: {error}");
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl MirrorContext {
+    /// Fails once the manager is dropped, which stops the mirror as well
+    fn manager(&self) -> Result<IrohManager> {
+        self.manager.upgrade().ok_or(SharedError::Cancelled)
+    }
+
+    async fn import(&self) -> Result<()> {
+        let report = directory::import_directory(
+            &self.manager()?,
+            self.namespace,
+            &self.dir,
+            &self.key_prefix,
+            &self.ignore_globs,
+            true,
+        )
+        .await?;
+
+        for failure in report.failures {
+            warn!(
+                "[mirror] failed to import {}: {}",
+                failure.path, failure.error
+            );
+        }
+        Ok(())
+    }
+
+    /// Returns path of the key inside the directory, if the key belongs to the mirror
+    /// and can be safely written there
+    fn relative_path(&self, key: &str) -> Option<PathBuf> {
+        if key.starts_with(RESERVED_PREFIX) {
+            return None;
+        }
+
+        let relative = match self.key_prefix.trim_end_matches('/') {
+            "" => key,
+            prefix => key.strip_prefix(prefix)?.strip_prefix('/')?,
+        };
+
+        let relative = PathBuf::from(relative);
+        let safe = relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)));
+        if !safe || relative.as_os_str().is_empty() {
+            return None;
+        }
+
+        (!directory::is_ignored(&self.patterns, &relative)).then_some(relative)
+    }
+
+    /// Exports every entry which differs from the directory
+    async fn export_all(&self) -> Result<()> {
+        let manager = self.manager()?;
+        let replica = manager
+            .docs
+            .client()
+            .open(self.namespace.into())
+            .await?
+            .ok_or(SharedError::ReplicaMissing(self.namespace))?;
+        let index = directory::load_index(&manager, &self.namespace, &self.dir).await?;
+
+        let mut keys = Vec::new();
+        let mut entries = replica.get_many(Query::single_latest_per_key()).await?;
+        while let Some(entry) = entries.try_next().await? {
+            let key = String::from_utf8_lossy(entry.key()).into_owned();
+            let Some(relative) = self.relative_path(&key) else {
+                continue;
+            };
+
+            let exists = fs::try_exists(self.dir.join(&relative))
+                .await
+                .unwrap_or(false);
+            let is_tombstone = entry.content_hash() == Hash::new(TOMBSTONE);
+            let up_to_date = index.hash(&key) == Some(entry.content_hash().to_string().as_str());
+
+            if (is_tombstone && exists) || (!is_tombstone && !(exists && up_to_date)) {
+                keys.push(key);
+            }
+        }
+
+        for key in keys {
+            if let Err(error) = self.export_key(&key).await {
+                warn!("[mirror] failed to export {key}: {error}");
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the latest entry of the key into the directory, deleting the file for tombstones
+    async fn export_key(&self, key: &str) -> Result<()> {
+        let Some(relative) = self.relative_path(key) else {
+            return Ok(());
+        };
+        let path = self.dir.join(&relative);
+
+        let manager = self.manager()?;
+        let replica = manager
+            .docs
+            .client()
+            .open(self.namespace.into())
+            .await?
+            .ok_or(SharedError::ReplicaMissing(self.namespace))?;
+        let Some(entry) = replica
+            .get_one(Query::single_latest_per_key().key_exact(key))
+            .await?
+        else {
+            return Ok(());
+        };
+        let hash = entry.content_hash();
+
+        let mut locked = directory::lock_index(&manager, &self.namespace, &self.dir).await?;
+        let index = &mut locked.index;
+
+        if hash == Hash::new(TOMBSTONE) {
+            match fs::remove_file(&path).await {
+                Ok(()) => info!("[mirror] deleted {}", path.display()),
+                Err(error) if error.kind() == std::io::ErrorKind::NotFound => {}
+                Err(error) => return Err(error.into()),
+            }
+            // Otherwise the next import would tombstone the key again
+            index.forget(key);
+        } else {
+            if index.hash(key) == Some(hash.to_string().as_str())
+                && fs::try_exists(&path).await.unwrap_or(false)
+            {
+                return Ok(());
+            }
+
+            export::export_blob(&manager, hash, &path).await?;
+
+            // Recorded, so the watcher doesn't import the file back
+            let metadata = fs::metadata(&path).await?;
+            index.record(key.to_string(), &metadata, hash);
+            info!("[mirror] exported {}", path.display());
+        }
+
+        locked.save(&manager).await
+    }
+}
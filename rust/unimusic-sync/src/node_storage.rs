@@ -1,21 +1,28 @@
 use iroh::{NodeId, node_info::NodeData};
 use log::info;
 use serde::{Deserialize, Serialize};
-use std::{borrow::Cow, collections::HashMap, path::Path};
-use tokio::fs;
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    path::Path,
+};
 
+use crate::encryption::{self, Encryption};
 use crate::errors::{Result, SharedError};
-use crate::types::{UNodeData, UNodeId};
+use crate::types::{UNamespaceId, UNodeData, UNodeId};
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct NodeStorage {
     pub nodes: HashMap<UNodeId, UNodeData>,
+    /// Peers known to participate in given namespace
+    #[serde(default)]
+    pub namespaces: HashMap<UNamespaceId, HashSet<UNodeId>>,
 }
 
 impl NodeStorage {
     /// Load NodeStorage from given path, or create a new instance if it doesn't exist
-    pub async fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
-        if let Ok(file) = fs::read(path).await {
+    pub async fn load<P: AsRef<Path>>(path: P, encryption: Option<&Encryption>) -> Result<Self> {
+        if let Some(file) = encryption::read(path.as_ref(), encryption).await? {
             let deserialized: Self =
                 serde_json::from_slice(&file).map_err(|e| SharedError::Serde(e.to_string()))?;
             info!("[node_storage]: loaded {} nodes", deserialized.nodes.len());
@@ -26,11 +33,15 @@ impl NodeStorage {
     }
 
     /// Saves NodeStorage to given path
-    pub async fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+    pub async fn save<P: AsRef<Path>>(
+        &self,
+        path: P,
+        encryption: Option<&Encryption>,
+    ) -> Result<()> {
         let serialized =
             serde_json::to_vec_pretty(self).map_err(|e| SharedError::Serde(e.to_string()))?;
         info!("[node_storage]: saving {} nodes", self.nodes.len());
-        fs::write(path, serialized).await?;
+        encryption::write(path.as_ref(), &serialized, encryption).await?;
         info!("[node_storage]: saved {} nodes", self.nodes.len());
         Ok(())
     }
@@ -50,6 +61,35 @@ impl NodeStorage {
             .or_insert_with(|| new_data.into_owned().into());
     }
 
+    /// Remember that given node participates in the namespace
+    pub fn associate_node(&mut self, namespace: UNamespaceId, id: NodeId) {
+        let inserted = self
+            .namespaces
+            .entry(namespace)
+            .or_default()
+            .insert(id.into());
+
+        if inserted {
+            info!(
+                "[node_storage]: associated node {} with namespace {namespace}",
+                id.fmt_short()
+            );
+        }
+    }
+
+    /// Forget all peers associated with the namespace
+    pub fn forget_namespace(&mut self, namespace: &UNamespaceId) {
+        self.namespaces.remove(namespace);
+    }
+
+    /// Returns nodes associated with the namespace
+    pub fn get_namespace_nodes(&self, namespace: &UNamespaceId) -> Vec<UNodeId> {
+        self.namespaces
+            .get(namespace)
+            .map(|nodes| nodes.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
     pub fn get_node_data(&self, id: NodeId) -> Option<&UNodeData> {
         self.nodes.get(&id.into())
     }
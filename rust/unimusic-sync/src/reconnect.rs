@@ -0,0 +1,128 @@
+use iroh::endpoint::ConnectionType;
+use serde::Serialize;
+
+use crate::types::UNodeId;
+
+/// Default time given to a single peer to establish a connection
+pub const RECONNECT_TIMEOUT_MS: u64 = 10_000;
+
+/// How the connection to a peer is routed
+#[cfg_attr(feature = "default", derive(uniffi::Enum))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PeerConnectionType {
+    /// Direct UDP connection
+    Direct,
+    /// Connection over a relay server
+    Relay,
+    /// Both direct and relay paths are in use, direct one is not confirmed yet
+    Mixed,
+    /// No verified connection
+    None,
+}
+
+impl From<&ConnectionType> for PeerConnectionType {
+    fn from(value: &ConnectionType) -> Self {
+        match value {
+            ConnectionType::Direct(_) => Self::Direct,
+            ConnectionType::Relay(_) => Self::Relay,
+            ConnectionType::Mixed(..) => Self::Mixed,
+            ConnectionType::None => Self::None,
+        }
+    }
+}
+
+#[cfg_attr(feature = "default", derive(uniffi::Enum))]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReconnectOutcome {
+    Connected,
+    Failed {
+        reason: String,
+    },
+    TimedOut,
+    /// Peer failed recently, it wasn't dialed this time
+    BackingOff {
+        retry_in_ms: u64,
+    },
+}
+
+#[cfg_attr(feature = "default", derive(uniffi::Record))]
+#[derive(Debug, Clone, Serialize)]
+pub struct PeerReconnectResult {
+    pub node_id: UNodeId,
+    pub outcome: ReconnectOutcome,
+    pub connection_type: PeerConnectionType,
+    pub latency_ms: Option<u64>,
+}
+
+#[cfg_attr(feature = "default", derive(uniffi::Record))]
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ReconnectReport {
+    pub peers: Vec<PeerReconnectResult>,
+}
+
+impl ReconnectReport {
+    /// Returns ids of peers which connected successfully
+    pub fn connected(&self) -> impl Iterator<Item = UNodeId> + '_ {
+        self.peers
+            .iter()
+            .filter(|peer| peer.outcome == ReconnectOutcome::Connected)
+            .map(|peer| peer.node_id)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{PeerConnectionType, ReconnectOutcome};
+    use crate::{
+        backoff::{Backoff, BackoffPolicy},
+        testing::TestNetwork,
+    };
+
+    type Result<T> = crate::testing::Result<T>;
+
+    #[tokio::test]
+    async fn test_reconnect_with_timeout() -> Result<()> {
+        let mut network = TestNetwork::new(3).await?;
+        let online = network.manager(1).get_node_id().await;
+        let offline = network.manager(2).get_node_id().await;
+        network.stop(2).await?;
+
+        let manager = network.manager(0);
+        *manager.reconnect_backoff.lock().unwrap() = Backoff::new(BackoffPolicy {
+            initial_delay_ms: 60_000,
+            max_delay_ms: 60_000,
+        });
+
+        let report = manager.reconnect_with_timeout(2_000).await;
+        assert_eq!(report.peers.len(), 2);
+        assert_eq!(report.connected().collect::<Vec<_>>(), vec![online]);
+
+        let peer = report.peers.iter().find(|peer| peer.node_id == online);
+        let peer = peer.expect("online peer is reported");
+        assert_eq!(peer.connection_type, PeerConnectionType::Direct);
+        assert!(peer.latency_ms.is_some());
+
+        let peer = report.peers.iter().find(|peer| peer.node_id == offline);
+        let peer = peer.expect("offline peer is reported");
+        assert!(matches!(
+            peer.outcome,
+            ReconnectOutcome::Failed { .. } | ReconnectOutcome::TimedOut
+        ));
+        assert_eq!(peer.connection_type, PeerConnectionType::None);
+        assert_eq!(peer.latency_ms, None);
+
+        // The offline peer isn't dialed again until its backoff expires
+        let report = manager.reconnect_with_timeout(2_000).await;
+        assert_eq!(report.connected().collect::<Vec<_>>(), vec![online]);
+        let peer = report.peers.iter().find(|peer| peer.node_id == offline);
+        assert!(matches!(
+            peer.map(|peer| &peer.outcome),
+            Some(ReconnectOutcome::BackingOff { retry_in_ms }) if *retry_in_ms > 0
+        ));
+
+        network.shutdown().await?;
+        Ok(())
+    }
+}
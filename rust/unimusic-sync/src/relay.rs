@@ -0,0 +1,162 @@
+#[cfg(any(test, feature = "relay-server"))]
+use std::net::SocketAddr;
+
+#[cfg(any(test, feature = "relay-server"))]
+use iroh::RelayNode;
+use iroh::{RelayMap, RelayUrl};
+#[cfg(any(test, feature = "relay-server"))]
+use iroh_relay::server::{AccessConfig, RelayConfig, Server, ServerConfig, StunConfig};
+#[cfg(any(test, feature = "relay-server"))]
+use log::info;
+
+use crate::errors::{Result, SharedError};
+
+/// Parses relay URLs given by the user into a map of relays the endpoint connects to
+pub fn parse_relay_map<S: AsRef<str>>(urls: &[S]) -> Result<RelayMap> {
+    if urls.is_empty() {
+        return Err(SharedError::InvalidInput("No relay URLs given".into()));
+    }
+    urls.iter()
+        .map(|url| {
+            url.as_ref()
+                .parse::<RelayUrl>()
+                .map_err(|error| SharedError::InvalidInput(format!("{}: {error}", url.as_ref())))
+        })
+        .collect()
+}
+
+/// Relay and STUN server running inside the process, meant for local testing.
+///
+/// It serves plain HTTP without TLS, so it should only be reachable from trusted networks.
+#[cfg(any(test, feature = "relay-server"))]
+#[derive(Debug)]
+pub struct LocalRelay {
+    server: Server,
+    node: RelayNode,
+}
+
+#[cfg(any(test, feature = "relay-server"))]
+impl LocalRelay {
+    /// Starts the relay on the address and the STUN server on the same IP with any free port
+    pub async fn spawn(http_addr: SocketAddr) -> Result<Self> {
+        let server = Server::spawn(ServerConfig::<(), ()> {
+            relay: Some(RelayConfig {
+                http_bind_addr: http_addr,
+                tls: None,
+                limits: Default::default(),
+                key_cache_capacity: None,
+                access: AccessConfig::Everyone,
+            }),
+            stun: Some(StunConfig {
+                bind_addr: SocketAddr::new(http_addr.ip(), 0),
+            }),
+            quic: None,
+            metrics_addr: None,
+        })
+        .await?;
+
+        let http_addr = server
+            .http_addr()
+            .ok_or_else(|| SharedError::Network("Relay server is not listening".into()))?;
+        let url: RelayUrl = format!("http://{http_addr}")
+            .parse()
+            .map_err(|error| SharedError::Iroh(format!("{error}")))?;
+        let node = RelayNode {
+            url,
+            stun_only: false,
+            stun_port: server.stun_addr().map(|addr| addr.port()).unwrap_or(0),
+            // QUIC address discovery is not served
+            quic: None,
+        };
+        info!(
+            "[relay] serving {} with STUN on {:?}",
+            node.url,
+            server.stun_addr()
+        );
+
+        Ok(Self { server, node })
+    }
+
+    pub fn url(&self) -> &RelayUrl {
+        &self.node.url
+    }
+
+    /// Map for [`crate::IrohFactory::with_relay_map`] making endpoints use only this relay
+    pub fn relay_map(&self) -> RelayMap {
+        self.node.clone().into()
+    }
+
+    pub async fn shutdown(self) -> Result<()> {
+        self.server.shutdown().await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{LocalRelay, parse_relay_map};
+    use crate::{IrohFactory, IrohManager, testing::TempDir, types::UNodeId};
+    use iroh::NodeAddr;
+    use iroh_docs::DocTicket;
+    use std::time::Duration;
+
+    type Result<T> = crate::testing::Result<T>;
+
+    #[test]
+    fn test_parse_relay_map() {
+        let map = parse_relay_map(&["https://relay.example.com", "http://127.0.0.1:3340"]).unwrap();
+        assert_eq!(map.len(), 2);
+
+        assert!(parse_relay_map::<&str>(&[]).is_err());
+        assert!(parse_relay_map(&["not a url"]).is_err());
+    }
+
+    async fn relayed_manager(dir: &TempDir, name: &str, relay: &LocalRelay) -> Result<IrohManager> {
+        let manager = IrohFactory::new()
+            .without_discovery()
+            .with_relay_map(relay.relay_map())
+            .iroh_manager(&dir.subpath(name).to_string_lossy())
+            .await?;
+        let home_relay = tokio::time::timeout(
+            Duration::from_secs(10),
+            manager.router.endpoint().home_relay().initialized(),
+        )
+        .await??;
+        assert_eq!(&home_relay, relay.url());
+        Ok(manager)
+    }
+
+    #[tokio::test]
+    async fn test_relay_only_sync() -> Result<()> {
+        let relay = LocalRelay::spawn("127.0.0.1:0".parse()?).await?;
+        let dir = TempDir::new();
+        let owner = relayed_manager(&dir, "owner", &relay).await?;
+        let peer = relayed_manager(&dir, "peer", &relay).await?;
+
+        let namespace = owner.create_namespace().await?;
+        owner
+            .write_file(namespace, "track.flac".into(), b"relayed".to_vec())
+            .await?;
+
+        // The peer learns only the relay of the owner, not its direct addresses
+        let ticket: DocTicket = owner.share(namespace).await?.into();
+        let node_id = owner.router.endpoint().node_id();
+        let ticket = DocTicket::new(
+            ticket.capability,
+            vec![NodeAddr::new(node_id).with_relay_url(relay.url().clone())],
+        );
+        peer.import(ticket.into()).await?;
+
+        assert_eq!(peer.read_file(namespace, "track.flac").await?, b"relayed");
+        assert!(
+            peer.get_namespace_nodes(namespace)
+                .await
+                .contains(&UNodeId::from(node_id))
+        );
+
+        owner.shutdown().await?;
+        peer.shutdown().await?;
+        relay.shutdown().await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,422 @@
+use iroh::NodeId;
+use iroh_docs::engine::LiveEvent;
+use log::{info, warn};
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
+use tokio::{
+    sync::mpsc,
+    task::{JoinHandle, JoinSet},
+    time::{Instant, MissedTickBehavior},
+};
+use tokio_stream::StreamExt;
+
+use crate::{
+    IrohManager, SyncScope, WeakIrohManager,
+    backoff::{Backoff, BackoffPolicy},
+    errors::Result,
+    types::UNamespaceId,
+};
+
+#[cfg_attr(feature = "default", derive(uniffi::Record))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AutoSyncConfig {
+    /// How often every namespace gets re-synced
+    pub interval_ms: u64,
+    /// How long to wait for more changes after a local write or a peer coming back
+    pub debounce_ms: u64,
+    /// Time after which a single sync is considered failed
+    pub sync_timeout_ms: u64,
+    /// How many times a failed sync gets retried before waiting for the next trigger,
+    /// `None` retries until it succeeds
+    pub max_retries: Option<u32>,
+    pub backoff: BackoffPolicy,
+}
+
+impl Default for AutoSyncConfig {
+    fn default() -> Self {
+        Self {
+            interval_ms: 5 * 60 * 1_000,
+            debounce_ms: 2_000,
+            sync_timeout_ms: 60_000,
+            max_retries: Some(8),
+            backoff: BackoffPolicy::default(),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Trigger {
+    /// Something changed in the namespace
+    Namespace(UNamespaceId),
+    /// Peer has been discovered
+    Node(NodeId),
+}
+
+#[derive(Debug)]
+struct Scheduler {
+    triggers: mpsc::UnboundedSender<Trigger>,
+    tasks: Vec<JoinHandle<()>>,
+    watchers: HashMap<UNamespaceId, JoinHandle<()>>,
+}
+
+impl Drop for Scheduler {
+    fn drop(&mut self) {
+        for task in self.tasks.iter().chain(self.watchers.values()) {
+            task.abort();
+        }
+    }
+}
+
+/// Keeps chosen namespaces synced in the background
+#[derive(Debug, Default)]
+pub struct AutoSync {
+    namespaces: HashSet<UNamespaceId>,
+    scheduler: Option<Scheduler>,
+}
+
+impl AutoSync {
+    pub fn is_running(&self) -> bool {
+        self.scheduler.is_some()
+    }
+
+    pub fn namespaces(&self) -> Vec<UNamespaceId> {
+        self.namespaces.iter().copied().collect()
+    }
+
+    /// Starts the scheduler, replacing the previous one if it was running
+    pub(crate) fn start(&mut self, manager: &IrohManager, config: AutoSyncConfig) {
+        self.stop();
+
+        let (triggers, receiver) = mpsc::unbounded_channel();
+        info!("[auto_sync] starting with {config:?}");
+
+        let scheduler = tokio::spawn(run_scheduler(manager.downgrade(), config, receiver));
+
+        let discovery = {
+            let endpoint = manager.router.endpoint().clone();
+            let triggers = triggers.clone();
+            tokio::spawn(async move {
+                let mut discovery_stream = endpoint.discovery_stream();
+                while let Some(item) = discovery_stream.next().await {
+                    let Ok(item) = item else {
+                        continue;
+                    };
+
+                    if triggers.send(Trigger::Node(item.node_id())).is_err() {
+                        break;
+                    }
+                }
+            })
+        };
+
+        let mut scheduler = Scheduler {
+            triggers,
+            tasks: vec![scheduler, discovery],
+            watchers: HashMap::new(),
+        };
+        for namespace in &self.namespaces {
+            spawn_watcher(&mut scheduler, manager, *namespace);
+        }
+
+        self.scheduler = Some(scheduler);
+    }
+
+    pub(crate) fn stop(&mut self) {
+        if self.scheduler.take().is_some() {
+            info!("[auto_sync] stopped");
+        }
+    }
+
+    pub(crate) fn add(&mut self, manager: &IrohManager, namespace: UNamespaceId) {
+        if !self.namespaces.insert(namespace) {
+            return;
+        }
+
+        if let Some(scheduler) = &mut self.scheduler {
+            spawn_watcher(scheduler, manager, namespace);
+            let _ = scheduler.triggers.send(Trigger::Namespace(namespace));
+        }
+    }
+
+    pub(crate) fn remove(&mut self, namespace: &UNamespaceId) {
+        self.namespaces.remove(namespace);
+
+        if let Some(watcher) = self
+            .scheduler
+            .as_mut()
+            .and_then(|scheduler| scheduler.watchers.remove(namespace))
+        {
+            watcher.abort();
+        }
+    }
+}
+
+/// Forwards local writes and new neighbors of the namespace to the scheduler
+fn spawn_watcher(scheduler: &mut Scheduler, manager: &IrohManager, namespace: UNamespaceId) {
+    let docs = manager.docs.clone();
+    let triggers = scheduler.triggers.clone();
+
+    let watcher = tokio::spawn(async move {
+        let replica = match docs.client().open(namespace.into()).await {
+            Ok(Some(replica)) => replica,
+            Ok(None) => {
+                warn!("[auto_sync] namespace {namespace} does not exist, not watching it");
+                return;
+            }
+            Err(error) => {
+                warn!("[auto_sync] failed to open namespace {namespace}: {error}");
+                return;
+            }
+        };
+
+        let mut event_stream = match replica.subscribe().await {
+            Ok(event_stream) => event_stream,
+            Err(error) => {
+                warn!("[auto_sync] failed to subscribe to namespace {namespace}: {error}");
+                return;
+            }
+        };
+
+        while let Some(event) = event_stream.next().await {
+            match event {
+                Ok(LiveEvent::InsertLocal { .. } | LiveEvent::NeighborUp(_)) => {
+                    if triggers.send(Trigger::Namespace(namespace)).is_err() {
+                        break;
+                    }
+                }
+                Ok(_) => {}
+                Err(error) => warn!("[auto_sync] namespace {namespace} event error: {error}"),
+            }
+        }
+    });
+
+    if let Some(previous) = scheduler.watchers.insert(namespace, watcher) {
+        previous.abort();
+    }
+}
+
+/// Runs until the manager is dropped, which drops the [`AutoSync`] stopping it
+async fn run_scheduler(
+    manager: WeakIrohManager,
+    config: AutoSyncConfig,
+    mut receiver: mpsc::UnboundedReceiver<Trigger>,
+) {
+    let interval = Duration::from_millis(config.interval_ms);
+    let debounce = Duration::from_millis(config.debounce_ms);
+
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    let mut backoff = Backoff::new(config.backoff);
+    let mut seen_nodes: HashMap<NodeId, Instant> = HashMap::new();
+    let mut pending: HashSet<UNamespaceId> = HashSet::new();
+    let mut deadline: Option<Instant> = None;
+    let mut syncs: JoinSet<(UNamespaceId, Result<()>)> = JoinSet::new();
+    let mut in_flight: HashSet<UNamespaceId> = HashSet::new();
+    let mut retries: JoinSet<UNamespaceId> = JoinSet::new();
+
+    loop {
+        let due = async {
+            match deadline {
+                Some(deadline) => tokio::time::sleep_until(deadline).await,
+                None => std::future::pending().await,
+            }
+        };
+
+        tokio::select! {
+            _ = ticker.tick() => {
+                let Some(manager) = manager.upgrade() else {
+                    break;
+                };
+                // Nodes seen before the last interval would trigger a sync again anyway
+                let now = Instant::now();
+                seen_nodes.retain(|_, last_seen| now - *last_seen < interval);
+
+                for namespace in manager.auto_sync.lock().unwrap().namespaces() {
+                    if backoff.remaining(&namespace).is_none() {
+                        pending.insert(namespace);
+                    }
+                }
+                deadline = Some(Instant::now());
+            }
+            Some(trigger) = receiver.recv() => {
+                let queued = match trigger {
+                    Trigger::Namespace(namespace) => {
+                        if backoff.remaining(&namespace).is_some() {
+                            continue;
+                        }
+                        vec![namespace]
+                    }
+                    Trigger::Node(node_id) => {
+                        let now = Instant::now();
+                        let last_seen = seen_nodes.insert(node_id, now);
+                        if last_seen.is_some_and(|last_seen| now - last_seen < interval) {
+                            continue;
+                        }
+
+                        let Some(manager) = manager.upgrade() else {
+                            break;
+                        };
+                        info!("[auto_sync] peer {} came back", node_id.fmt_short());
+                        let namespaces = manager.auto_sync.lock().unwrap().namespaces();
+                        let node_storage = manager.node_storage.read().await;
+                        // Only namespaces the peer is known to have, others aren't announced to it
+                        namespaces
+                            .into_iter()
+                            .filter(|namespace| {
+                                node_storage
+                                    .get_namespace_nodes(namespace)
+                                    .contains(&node_id.into())
+                            })
+                            .collect()
+                    }
+                };
+
+                if !queued.is_empty() {
+                    pending.extend(queued);
+                    deadline.get_or_insert_with(|| Instant::now() + debounce);
+                }
+            }
+            Some(result) = syncs.join_next(), if !syncs.is_empty() => {
+                let Ok((namespace, result)) = result else {
+                    continue;
+                };
+                in_flight.remove(&namespace);
+
+                match result {
+                    Ok(()) => {
+                        info!("[auto_sync] synced namespace {namespace}");
+                        backoff.succeeded(&namespace);
+                    }
+                    Err(error) => {
+                        let delay = backoff.failed(namespace);
+                        let failures = backoff.failures(&namespace);
+
+                        if config.max_retries.is_some_and(|max_retries| failures > max_retries) {
+                            warn!("[auto_sync] namespace {namespace} failed {failures} times, waiting for the next trigger: {error}");
+                            backoff.succeeded(&namespace);
+                        } else {
+                            warn!("[auto_sync] namespace {namespace} failed, retrying in {delay:?}: {error}");
+                            retries.spawn(async move {
+                                tokio::time::sleep(delay).await;
+                                namespace
+                            });
+                        }
+                    }
+                }
+
+                if pending.contains(&namespace) {
+                    deadline.get_or_insert_with(Instant::now);
+                }
+            }
+            Some(Ok(namespace)) = retries.join_next(), if !retries.is_empty() => {
+                pending.insert(namespace);
+                deadline.get_or_insert_with(Instant::now);
+            }
+            _ = due => {
+                deadline = None;
+
+                let Some(manager) = manager.upgrade() else {
+                    break;
+                };
+                let namespaces = manager.auto_sync.lock().unwrap().namespaces();
+                pending.retain(|namespace| namespaces.contains(namespace));
+
+                let ready: Vec<UNamespaceId> = pending
+                    .iter()
+                    .filter(|namespace| !in_flight.contains(namespace))
+                    .copied()
+                    .collect();
+
+                for namespace in ready {
+                    pending.remove(&namespace);
+                    in_flight.insert(namespace);
+
+                    let manager = manager.clone();
+                    syncs.spawn(async move {
+                        let result = manager
+                            .sync_cancellable(
+                                namespace,
+                                SyncScope::Namespace,
+                                None,
+                                Some(config.sync_timeout_ms),
+                            )
+                            .await;
+                        (namespace, result)
+                    });
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::AutoSyncConfig;
+    use crate::{
+        backoff::BackoffPolicy,
+        testing::{TestNetwork, wait_for},
+    };
+
+    type Result<T> = crate::testing::Result<T>;
+
+    #[tokio::test]
+    async fn test_auto_sync() -> Result<()> {
+        let network = TestNetwork::new(2).await?;
+        let (owner, peer) = (network.manager(0), network.manager(1));
+        let namespace = owner.create_namespace().await?;
+        owner
+            .write_file(namespace, "first".into(), vec![1; 100])
+            .await?;
+        let ticket: iroh_docs::DocTicket = owner.share(namespace).await?.into();
+
+        // The peer doesn't have the namespace yet, so syncs fail until it gets imported
+        let config = AutoSyncConfig {
+            interval_ms: 60 * 60 * 1_000,
+            debounce_ms: 50,
+            sync_timeout_ms: 10_000,
+            max_retries: None,
+            backoff: BackoffPolicy {
+                initial_delay_ms: 200,
+                max_delay_ms: 200,
+            },
+        };
+        peer.add_auto_sync_namespace(namespace).await;
+        peer.start_auto_sync(config).await;
+        tokio::time::sleep(Duration::from_millis(500)).await;
+
+        peer.node_storage
+            .write()
+            .await
+            .associate_node(namespace, owner.router.endpoint().node_id());
+        peer.docs
+            .client()
+            .import_namespace(ticket.capability)
+            .await?;
+        // Only a retry syncs it now, the next interval is an hour away
+        wait_for(async || peer.read_file(namespace, "first").await.is_ok()).await;
+
+        peer.start_auto_sync(AutoSyncConfig {
+            interval_ms: 500,
+            ..config
+        })
+        .await;
+        // Syncs of the first ticks are done by then
+        tokio::time::sleep(Duration::from_secs(1)).await;
+
+        // Nothing but the next interval starts live sync again
+        peer.leave(namespace).await;
+        owner
+            .write_file(namespace, "second".into(), vec![2; 100])
+            .await?;
+        wait_for(async || peer.read_file(namespace, "second").await.is_ok()).await;
+
+        peer.stop_auto_sync();
+        network.shutdown().await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,383 @@
+use iroh_blobs::Hash;
+use iroh_docs::{Entry, engine::LiveEvent, store::Query};
+use log::{info, warn};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::{Arc, RwLock},
+};
+use tokio::task::JoinHandle;
+use tokio_stream::StreamExt;
+use unicode_normalization::{UnicodeNormalization, char::is_combining_mark};
+
+use crate::{
+    IrohManager, RESERVED_PREFIX, TOMBSTONE, WeakIrohManager,
+    errors::{Result, SharedError},
+    metadata::{self, METADATA_PREFIX, TrackMetadata},
+    types::UNamespaceId,
+};
+
+#[cfg_attr(feature = "default", derive(uniffi::Record))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchResult {
+    pub key: String,
+    /// Higher is better, only meaningful relative to other results of the same search
+    pub score: u32,
+    /// Metadata of the track, if the key holds an indexed one
+    pub track: Option<TrackMetadata>,
+}
+
+/// Where a term was found, matches in titles rank higher than matches in keys
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Key,
+    Album,
+    Artist,
+    Title,
+}
+
+impl Field {
+    fn weight(self) -> u32 {
+        match self {
+            Self::Key => 1,
+            Self::Album | Self::Artist => 2,
+            Self::Title => 3,
+        }
+    }
+}
+
+/// Lowercases the text and removes diacritics, so `Beyoncé` matches `beyonce`
+pub(crate) fn normalize(text: &str) -> String {
+    text.nfd()
+        .filter(|c| !is_combining_mark(*c))
+        .flat_map(char::to_lowercase)
+        .flat_map(|c| {
+            // Letters which don't decompose into a base letter and a mark
+            let replacement = match c {
+                'ß' => "ss",
+                'æ' => "ae",
+                'œ' => "oe",
+                'ø' => "o",
+                'ł' => "l",
+                'đ' => "d",
+                'þ' => "th",
+                _ => return vec![c],
+            };
+            replacement.chars().collect()
+        })
+        .collect()
+}
+
+pub(crate) fn tokenize(text: &str) -> Vec<String> {
+    normalize(text)
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|token| !token.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+#[derive(Debug, Default)]
+struct Document {
+    terms: Vec<(String, Field)>,
+}
+
+/// Inverted index of keys and track metadata of a namespace
+#[derive(Debug, Default)]
+pub(crate) struct SearchIndex {
+    documents: HashMap<String, Document>,
+    /// Sorted, so terms starting with a prefix are next to each other
+    terms: BTreeMap<String, HashSet<String>>,
+    tracks: HashMap<String, TrackMetadata>,
+}
+
+impl SearchIndex {
+    /// Indexes the key, replacing what was indexed for it before
+    pub(crate) fn insert(&mut self, key: &str) {
+        self.remove_terms(key);
+
+        let mut terms: Vec<(String, Field)> = tokenize(key)
+            .into_iter()
+            .map(|term| (term, Field::Key))
+            .collect();
+        if let Some(track) = self.tracks.get(key) {
+            for (value, field) in [
+                (&track.title, Field::Title),
+                (&track.artist, Field::Artist),
+                (&track.album, Field::Album),
+            ] {
+                if let Some(value) = value {
+                    terms.extend(tokenize(value).into_iter().map(|term| (term, field)));
+                }
+            }
+        }
+
+        for (term, _) in &terms {
+            self.terms
+                .entry(term.clone())
+                .or_default()
+                .insert(key.to_string());
+        }
+        self.documents.insert(key.to_string(), Document { terms });
+    }
+
+    pub(crate) fn remove(&mut self, key: &str) {
+        self.remove_terms(key);
+        self.documents.remove(key);
+    }
+
+    /// Updates metadata of the track, which might be indexed before or after its key
+    pub(crate) fn set_track(&mut self, key: &str, track: Option<TrackMetadata>) {
+        match track {
+            Some(track) => self.tracks.insert(key.to_string(), track),
+            None => self.tracks.remove(key),
+        };
+        if self.documents.contains_key(key) {
+            self.insert(key);
+        }
+    }
+
+    fn remove_terms(&mut self, key: &str) {
+        let Some(document) = self.documents.get(key) else {
+            return;
+        };
+        for (term, _) in &document.terms {
+            if let Some(keys) = self.terms.get_mut(term) {
+                keys.remove(key);
+                if keys.is_empty() {
+                    self.terms.remove(term);
+                }
+            }
+        }
+    }
+
+    /// Returns keys matching every word of the query, either exactly or by prefix.
+    /// Exact matches score twice as much as prefix ones.
+    pub(crate) fn search(&self, query: &str, limit: usize) -> Vec<SearchResult> {
+        let words = tokenize(query);
+        if words.is_empty() {
+            return Vec::new();
+        }
+
+        let mut scores: Option<HashMap<&str, u32>> = None;
+        for word in &words {
+            let mut word_scores: HashMap<&str, u32> = HashMap::new();
+            let matching = self
+                .terms
+                .range(word.clone()..)
+                .take_while(|(term, _)| term.starts_with(word.as_str()));
+
+            for (term, keys) in matching {
+                let exact = term == word;
+                for key in keys {
+                    let Some(document) = self.documents.get(key) else {
+                        continue;
+                    };
+                    let score = document
+                        .terms
+                        .iter()
+                        .filter(|(document_term, _)| document_term == term)
+                        .map(|(_, field)| field.weight() * if exact { 2 } else { 1 })
+                        .max()
+                        .unwrap_or_default();
+
+                    let best = word_scores.entry(key.as_str()).or_default();
+                    *best = (*best).max(score);
+                }
+            }
+
+            scores = Some(match scores {
+                None => word_scores,
+                Some(scores) => scores
+                    .into_iter()
+                    .filter_map(|(key, score)| {
+                        word_scores
+                            .get(key)
+                            .map(|word_score| (key, score + word_score))
+                    })
+                    .collect(),
+            });
+        }
+
+        let mut results: Vec<_> = scores.unwrap_or_default().into_iter().collect();
+        results.sort_by(|(a_key, a_score), (b_key, b_score)| {
+            b_score.cmp(a_score).then_with(|| a_key.cmp(b_key))
+        });
+
+        results
+            .into_iter()
+            .take(limit)
+            .map(|(key, score)| SearchResult {
+                key: key.to_string(),
+                score,
+                track: self.tracks.get(key).cloned(),
+            })
+            .collect()
+    }
+}
+
+/// Search index of a namespace, kept up to date by live events
+#[derive(Debug)]
+pub struct LiveSearchIndex {
+    index: Arc<RwLock<SearchIndex>>,
+    task: JoinHandle<()>,
+}
+
+impl Drop for LiveSearchIndex {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl LiveSearchIndex {
+    /// Builds the index from entries of the namespace and starts following its changes
+    pub(crate) async fn build(manager: &IrohManager, namespace: UNamespaceId) -> Result<Self> {
+        let replica = manager
+            .docs
+            .client()
+            .open(namespace.into())
+            .await?
+            .ok_or(SharedError::ReplicaMissing(namespace))?;
+
+        // Subscribed first, so changes made while building aren't missed
+        let mut events = replica.subscribe().await?;
+
+        let index = Arc::new(RwLock::new(SearchIndex::default()));
+        let mut updater = IndexUpdater {
+            manager: manager.downgrade(),
+            index: index.clone(),
+            pending: HashMap::new(),
+        };
+
+        let mut entries = replica.get_many(Query::single_latest_per_key()).await?;
+        while let Some(entry) = entries.try_next().await? {
+            updater.apply(&entry).await;
+        }
+        info!(
+            "[search] indexed {} keys of namespace {namespace}",
+            index.read().unwrap().documents.len()
+        );
+
+        let task = tokio::spawn(async move {
+            // Events stop once the replica is closed
+            let _replica = replica;
+            while let Some(event) = events.next().await {
+                match event {
+                    Ok(LiveEvent::InsertLocal { entry })
+                    | Ok(LiveEvent::InsertRemote { entry, .. }) => updater.apply(&entry).await,
+                    Ok(LiveEvent::ContentReady { hash }) => updater.content_ready(hash).await,
+                    Ok(_) => {}
+                    Err(error) => warn!("[search] namespace {namespace} event error: {error}"),
+                }
+            }
+        });
+
+        Ok(Self { index, task })
+    }
+
+    pub(crate) fn search(&self, query: &str, limit: usize) -> Vec<SearchResult> {
+        self.index.read().unwrap().search(query, limit)
+    }
+}
+
+struct IndexUpdater {
+    manager: WeakIrohManager,
+    index: Arc<RwLock<SearchIndex>>,
+    /// Metadata sidecars of peers waiting for their content, by hash
+    pending: HashMap<Hash, String>,
+}
+
+impl IndexUpdater {
+    async fn read_metadata(&self, hash: Hash) -> Option<TrackMetadata> {
+        metadata::read_metadata(&self.manager.upgrade()?, hash).await
+    }
+
+    async fn apply(&mut self, entry: &Entry) {
+        let key = String::from_utf8_lossy(entry.key()).into_owned();
+        let hash = entry.content_hash();
+        let is_tombstone = hash == Hash::new(TOMBSTONE);
+
+        if let Some(track_key) = key.strip_prefix(METADATA_PREFIX) {
+            let track = if is_tombstone {
+                None
+            } else {
+                match self.read_metadata(hash).await {
+                    Some(track) => Some(track),
+                    None => {
+                        self.pending.insert(hash, track_key.to_string());
+                        return;
+                    }
+                }
+            };
+            self.index.write().unwrap().set_track(track_key, track);
+        } else if key.starts_with(RESERVED_PREFIX) {
+        } else if is_tombstone {
+            self.index.write().unwrap().remove(&key);
+        } else {
+            self.index.write().unwrap().insert(&key);
+        }
+    }
+
+    async fn content_ready(&mut self, hash: Hash) {
+        let Some(track_key) = self.pending.remove(&hash) else {
+            return;
+        };
+        if let Some(track) = self.read_metadata(hash).await {
+            self.index
+                .write()
+                .unwrap()
+                .set_track(&track_key, Some(track));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{SearchIndex, normalize, tokenize};
+    use crate::metadata::TrackMetadata;
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize("Beyoncé"), "beyonce");
+        assert_eq!(normalize("Sigur Rós"), "sigur ros");
+        assert_eq!(normalize("Straße"), "strasse");
+        assert_eq!(
+            tokenize("library/Mötley Crüe - Kickstart.mp3"),
+            ["library", "motley", "crue", "kickstart", "mp3"]
+        );
+    }
+
+    #[test]
+    fn test_search() {
+        let mut index = SearchIndex::default();
+        index.insert("library/01.mp3");
+        index.insert("library/crue.txt");
+        index.set_track(
+            "library/01.mp3",
+            Some(TrackMetadata {
+                key: "library/01.mp3".to_string(),
+                title: Some("Kickstart My Heart".to_string()),
+                artist: Some("Mötley Crüe".to_string()),
+                album: None,
+                track_number: Some(1),
+                duration_ms: 0,
+                codec: "mp3".to_string(),
+                artwork: None,
+            }),
+        );
+
+        // Artist match ranks above the key match
+        let results = index.search("Crue", 10);
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].key, "library/01.mp3");
+        assert!(results[0].track.is_some());
+
+        // Every word has to match, the last one by prefix
+        let results = index.search("motley kick", 10);
+        assert_eq!(results.len(), 1);
+        assert!(index.search("motley nothing", 10).is_empty());
+        assert_eq!(index.search("lib", 1).len(), 1);
+
+        index.remove("library/01.mp3");
+        assert_eq!(index.search("crue", 10).len(), 1);
+        assert!(index.search("kickstart", 10).is_empty());
+    }
+}
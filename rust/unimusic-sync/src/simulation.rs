@@ -0,0 +1,182 @@
+use log::info;
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use std::collections::BTreeMap;
+
+use crate::{
+    testing::{ReplicaState, Result, TestNetwork, replica_state},
+    types::UNamespaceId,
+};
+
+/// Few keys, so operations of different sides hit the same ones
+const KEYS: usize = 12;
+
+struct Simulation {
+    network: TestNetwork,
+    namespace: UNamespaceId,
+    rng: StdRng,
+    /// Expected content of every key, `None` once deleted. Operations run one after another,
+    /// so the last one applied to a key has the latest timestamp and wins.
+    expected: BTreeMap<String, Option<Vec<u8>>>,
+}
+
+impl Simulation {
+    async fn new(seed: u64, nodes: usize) -> Result<Self> {
+        let network = TestNetwork::new(nodes).await?;
+        let namespace = network.shared_namespace().await?;
+        Ok(Self {
+            network,
+            namespace,
+            rng: StdRng::seed_from_u64(seed),
+            expected: BTreeMap::new(),
+        })
+    }
+
+    /// Applies random writes, overwrites and deletes on the node
+    async fn apply_random(&mut self, node: usize, count: usize) -> Result<()> {
+        let manager = self.network.manager(node).clone();
+        for _ in 0..count {
+            let key = format!("library/track_{:02}.bin", self.rng.gen_range(0..KEYS));
+            let exists = matches!(self.expected.get(&key), Some(Some(_)));
+
+            if exists && self.rng.gen_bool(0.3) {
+                manager.delete_file(self.namespace, key.clone()).await?;
+                self.expected.insert(key, None);
+            } else {
+                let len = self.rng.gen_range(1..4096);
+                let data: Vec<u8> = (0..len).map(|_| self.rng.r#gen()).collect();
+                manager
+                    .write_file(self.namespace, key.clone(), data.clone())
+                    .await?;
+                self.expected.insert(key, Some(data));
+            }
+        }
+        Ok(())
+    }
+
+    /// Partitions the network into the sides, which change independently for a few rounds
+    async fn run_partitioned(&mut self, sides: &[&[usize]], rounds: usize) -> Result<()> {
+        self.network.partition(self.namespace).await;
+
+        for round in 0..rounds {
+            for side in sides {
+                for &node in *side {
+                    let count = self.rng.gen_range(1..8);
+                    self.apply_random(node, count).await?;
+                }
+            }
+
+            // Sides sync one at a time, so stored peers of the other sides reject them
+            for side in sides {
+                let outside = self.outside_states(side).await?;
+
+                self.network.connect(self.namespace, side).await?;
+                self.network.partition(self.namespace).await;
+
+                assert_eq!(
+                    self.outside_states(side).await?,
+                    outside,
+                    "side {side:?} leaked changes in round {round}"
+                );
+            }
+            info!("[simulation] round {round} done");
+        }
+        Ok(())
+    }
+
+    async fn outside_states(&self, side: &[usize]) -> Result<Vec<Option<ReplicaState>>> {
+        let mut states = Vec::new();
+        for (i, manager) in self.network.managers().iter().enumerate() {
+            if !side.contains(&i) {
+                states.push(replica_state(manager, self.namespace).await?);
+            }
+        }
+        Ok(states)
+    }
+
+    /// Heals the network and checks every replica lists and reads the expected files
+    async fn heal_and_check(&self) -> Result<()> {
+        self.network.heal(self.namespace).await?;
+
+        let expected: BTreeMap<_, _> = self
+            .expected
+            .iter()
+            .filter_map(|(key, data)| Some((key.clone(), data.clone()?)))
+            .collect();
+
+        for (i, manager) in self.network.managers().iter().enumerate() {
+            let files: Vec<String> = manager
+                .get_files(self.namespace)
+                .await?
+                .iter()
+                .map(|entry| entry.key())
+                .collect();
+            assert_eq!(
+                files,
+                expected.keys().cloned().collect::<Vec<_>>(),
+                "files of node {i}"
+            );
+
+            for (key, data) in &expected {
+                assert_eq!(
+                    &manager.read_file(self.namespace, key).await?,
+                    data,
+                    "{key} on node {i}"
+                );
+            }
+        }
+        Ok(())
+    }
+
+    async fn shutdown(self) -> Result<()> {
+        self.network.shutdown().await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Result, Simulation};
+
+    #[tokio::test]
+    async fn test_two_sides() -> Result<()> {
+        let _ = env_logger::try_init();
+        let mut simulation = Simulation::new(1, 4).await?;
+
+        simulation.apply_random(0, 8).await?;
+        simulation.heal_and_check().await?;
+
+        simulation.run_partitioned(&[&[0, 1], &[2, 3]], 3).await?;
+        simulation.heal_and_check().await?;
+
+        simulation.shutdown().await
+    }
+
+    #[tokio::test]
+    async fn test_isolated_nodes() -> Result<()> {
+        let _ = env_logger::try_init();
+        let mut simulation = Simulation::new(2, 3).await?;
+
+        // Each node is a side of its own, so nothing syncs until the network heals
+        simulation.run_partitioned(&[&[0], &[1], &[2]], 2).await?;
+        simulation.heal_and_check().await?;
+
+        simulation.shutdown().await
+    }
+
+    #[tokio::test]
+    async fn test_repeated_partitions() -> Result<()> {
+        let _ = env_logger::try_init();
+        let mut simulation = Simulation::new(3, 5).await?;
+
+        simulation
+            .run_partitioned(&[&[0], &[1, 2, 3, 4]], 2)
+            .await?;
+        simulation.heal_and_check().await?;
+
+        simulation
+            .run_partitioned(&[&[0, 2], &[1, 3, 4]], 2)
+            .await?;
+        simulation.heal_and_check().await?;
+
+        simulation.shutdown().await
+    }
+}
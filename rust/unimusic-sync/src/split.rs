@@ -0,0 +1,466 @@
+use iroh_blobs::Hash;
+use iroh_docs::store::Query;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, ops::Range, path::Path};
+use tokio::{
+    fs::File,
+    io::{AsyncWriteExt, BufWriter},
+};
+use tokio_stream::StreamExt;
+
+use crate::{
+    IrohManager, RESERVED_PREFIX, TOMBSTONE, check_writable_key,
+    errors::{Result, SharedError},
+    metadata,
+    types::UNamespaceId,
+};
+
+/// Manifest blob starts with this prefix, followed by the postcard encoded manifest
+const MAGIC: &[u8] = b"UMSSPLIT1\0";
+/// Manifests are tiny, larger blobs are never parsed
+pub(crate) const MAX_MANIFEST_LEN: u64 = 16 * 1024;
+/// Keys of entries referencing part blobs, followed by the hash of the part
+pub const PARTS_PREFIX: &str = ".unimusic/parts/";
+
+const ID3V2_HEADER_LEN: usize = 10;
+const ID3V1_LEN: usize = 128;
+const APE_FOOTER_LEN: usize = 32;
+const FLAC_MARKER: &[u8] = b"fLaC";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum PartKind {
+    Tags,
+    Audio,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Part {
+    pub kind: PartKind,
+    pub hash: Hash,
+    pub len: u64,
+}
+
+/// Parts which concatenated in order give back the original file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct SplitManifest {
+    /// Hash of the whole file, so it can be compared with files on disk
+    pub file_hash: Hash,
+    pub file_len: u64,
+    pub parts: Vec<Part>,
+}
+
+impl SplitManifest {
+    fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(postcard::to_allocvec(self).map_err(|e| SharedError::Serde(e.to_string()))?);
+        Ok(bytes)
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let manifest: Self = postcard::from_bytes(bytes.strip_prefix(MAGIC)?).ok()?;
+        // Files which just happen to start with the prefix are read as they are
+        let len: u64 = manifest.parts.iter().map(|part| part.len).sum();
+        (len == manifest.file_len).then_some(manifest)
+    }
+}
+
+fn syncsafe(bytes: &[u8]) -> usize {
+    bytes
+        .iter()
+        .fold(0, |size, byte| (size << 7) | (*byte & 0x7f) as usize)
+}
+
+/// Length of ID3v2 tags at the start of the data
+fn id3v2_len(data: &[u8]) -> usize {
+    let mut offset = 0;
+    // Some taggers prepend a new tag without removing the old one
+    while let Some(header) = data.get(offset..offset + ID3V2_HEADER_LEN) {
+        if &header[..3] != b"ID3" {
+            break;
+        }
+        let has_footer = header[5] & 0x10 != 0;
+        let len = ID3V2_HEADER_LEN + syncsafe(&header[6..10]) + if has_footer { 10 } else { 0 };
+        if offset + len > data.len() {
+            break;
+        }
+        offset += len;
+    }
+    offset
+}
+
+/// Start of the APEv2 and ID3v1 tags at the end of the data
+fn mp3_tail_start(data: &[u8], audio_start: usize) -> usize {
+    let mut end = data.len();
+
+    if end >= audio_start + ID3V1_LEN && &data[end - ID3V1_LEN..end - ID3V1_LEN + 3] == b"TAG" {
+        end -= ID3V1_LEN;
+    }
+
+    if end >= audio_start + APE_FOOTER_LEN {
+        let footer = &data[end - APE_FOOTER_LEN..end];
+        if &footer[..8] == b"APETAGEX" {
+            // Size includes the items and the footer, but not the optional header
+            let size = u32::from_le_bytes(footer[12..16].try_into().unwrap()) as usize;
+            let flags = u32::from_le_bytes(footer[20..24].try_into().unwrap());
+            let has_header = flags & (1 << 31) != 0;
+            let len = size + if has_header { APE_FOOTER_LEN } else { 0 };
+            if len >= APE_FOOTER_LEN && end >= audio_start + len {
+                end -= len;
+            }
+        }
+    }
+
+    end
+}
+
+/// End of FLAC metadata blocks, which start at `start`
+fn flac_metadata_end(data: &[u8], start: usize) -> Option<usize> {
+    let mut offset = start + FLAC_MARKER.len();
+    loop {
+        let header = data.get(offset..offset + 4)?;
+        let is_last = header[0] & 0x80 != 0;
+        let len = u32::from_be_bytes([0, header[1], header[2], header[3]]) as usize;
+        offset += 4 + len;
+        if offset > data.len() {
+            return None;
+        }
+        if is_last {
+            return Some(offset);
+        }
+    }
+}
+
+fn is_mp3_frame(data: &[u8]) -> bool {
+    data.len() >= 2 && data[0] == 0xff && data[1] & 0xe0 == 0xe0
+}
+
+/// Finds ranges of tags and audio in supported formats, `None` for anything else
+pub(crate) fn split_ranges(data: &[u8]) -> Option<Vec<(PartKind, Range<usize>)>> {
+    let head = id3v2_len(data);
+    let rest = &data[head..];
+
+    let (tags_end, audio_end) = if rest.starts_with(FLAC_MARKER) {
+        (flac_metadata_end(data, head)?, data.len())
+    } else if head > 0 || is_mp3_frame(rest) {
+        (head, mp3_tail_start(data, head))
+    } else {
+        return None;
+    };
+
+    let ranges = [
+        (PartKind::Tags, 0..tags_end),
+        (PartKind::Audio, tags_end..audio_end),
+        (PartKind::Tags, audio_end..data.len()),
+    ];
+    Some(
+        ranges
+            .into_iter()
+            .filter(|(_, range)| !range.is_empty())
+            .collect(),
+    )
+}
+
+/// Writes the file as separate tag and audio blobs, falling back to a single blob
+/// for formats which can't be split
+pub(crate) async fn write_split(
+    manager: &IrohManager,
+    namespace: UNamespaceId,
+    path: String,
+    data: Vec<u8>,
+) -> Result<Hash> {
+    check_writable_key(&path)?;
+
+    let docs_client = manager.docs.client();
+    let blobs_client = manager.blobs.client();
+    let author = docs_client.authors().default().await?;
+    let replica = docs_client
+        .open(namespace.into())
+        .await?
+        .ok_or(SharedError::ReplicaMissing(namespace))?;
+
+    let previous = parts_of(manager, namespace, &path).await?;
+    let key = path.clone();
+    let (extracted, data) =
+        tokio::task::spawn_blocking(move || (metadata::extract_from_bytes(&key, &data), data))
+            .await
+            .map_err(|error| SharedError::Iroh(error.to_string()))?;
+
+    let Some(ranges) = split_ranges(&data) else {
+        let hash = replica.set_bytes(author, path.clone(), data).await?;
+        release_parts(manager, namespace, previous).await;
+        metadata::store_logged(manager, namespace, &path, extracted).await;
+        return Ok(hash);
+    };
+
+    let mut parts = Vec::with_capacity(ranges.len());
+    for (kind, range) in ranges {
+        let len = range.len() as u64;
+        let outcome = blobs_client.add_bytes(data[range].to_vec()).await?;
+
+        // Unchanged parts already have their entry, rewriting it would only cause sync traffic
+        let key = format!("{PARTS_PREFIX}{}", outcome.hash);
+        let existing = replica
+            .get_one(Query::single_latest_per_key().key_exact(&key))
+            .await?;
+        if existing.is_none_or(|entry| entry.content_hash() != outcome.hash) {
+            replica.set_hash(author, key, outcome.hash, len).await?;
+        }
+
+        parts.push(Part {
+            kind,
+            hash: outcome.hash,
+            len,
+        });
+    }
+
+    let manifest = SplitManifest {
+        file_hash: Hash::new(&data),
+        file_len: data.len() as u64,
+        parts,
+    };
+    let hash = replica
+        .set_bytes(author, path.clone(), manifest.to_bytes()?)
+        .await?;
+    info!(
+        "[split] stored {} bytes as {} parts",
+        manifest.file_len,
+        manifest.parts.len()
+    );
+
+    let replaced = previous
+        .into_iter()
+        .filter(|hash| !manifest.parts.iter().any(|part| part.hash == *hash))
+        .collect();
+    release_parts(manager, namespace, replaced).await;
+    metadata::store_logged(manager, namespace, &path, extracted).await;
+
+    Ok(hash)
+}
+
+/// Hashes of the parts of the file stored under the key, empty unless it is split
+pub(crate) async fn parts_of(
+    manager: &IrohManager,
+    namespace: UNamespaceId,
+    key: &str,
+) -> Result<Vec<Hash>> {
+    let replica = manager
+        .docs
+        .client()
+        .open(namespace.into())
+        .await?
+        .ok_or(SharedError::ReplicaMissing(namespace))?;
+    let Some(entry) = replica
+        .get_one(Query::single_latest_per_key().key_exact(key))
+        .await?
+    else {
+        return Ok(Vec::new());
+    };
+    if entry.content_len() > MAX_MANIFEST_LEN {
+        return Ok(Vec::new());
+    }
+
+    let manifest = load_manifest(manager, entry.content_hash())
+        .await
+        .ok()
+        .flatten();
+    Ok(manifest
+        .map(|manifest| manifest.parts.iter().map(|part| part.hash).collect())
+        .unwrap_or_default())
+}
+
+/// Tombstones entries of the parts which no file of the namespace references anymore,
+/// so peers stop fetching them. Only logs failures, the file itself is already written.
+pub(crate) async fn release_parts(
+    manager: &IrohManager,
+    namespace: UNamespaceId,
+    parts: Vec<Hash>,
+) {
+    if parts.is_empty() {
+        return;
+    }
+    if let Err(error) =
+        tombstone_unreferenced(manager, namespace, parts.into_iter().collect()).await
+    {
+        warn!("[split] failed to release parts in namespace {namespace}: {error}");
+    }
+}
+
+async fn tombstone_unreferenced(
+    manager: &IrohManager,
+    namespace: UNamespaceId,
+    mut parts: HashSet<Hash>,
+) -> Result<()> {
+    let docs_client = manager.docs.client();
+    let replica = docs_client
+        .open(namespace.into())
+        .await?
+        .ok_or(SharedError::ReplicaMissing(namespace))?;
+
+    // Only blobs small enough to be manifests can reference parts
+    let tombstone = Hash::new(TOMBSTONE);
+    let mut candidates = Vec::new();
+    let mut entries = replica.get_many(Query::single_latest_per_key()).await?;
+    while let Some(entry) = entries.try_next().await? {
+        if !entry.key().starts_with(RESERVED_PREFIX.as_bytes())
+            && entry.content_hash() != tombstone
+            && entry.content_len() <= MAX_MANIFEST_LEN
+        {
+            candidates.push(entry.content_hash());
+        }
+    }
+
+    for hash in candidates {
+        // Files of peers which aren't downloaded yet might reference the parts too
+        let Some(manifest) = load_manifest(manager, hash).await.ok() else {
+            return Ok(());
+        };
+        for part in manifest.iter().flat_map(|manifest| &manifest.parts) {
+            parts.remove(&part.hash);
+        }
+        if parts.is_empty() {
+            return Ok(());
+        }
+    }
+
+    let author = docs_client.authors().default().await?;
+    for hash in &parts {
+        replica
+            .set_bytes(author, format!("{PARTS_PREFIX}{hash}"), TOMBSTONE.to_vec())
+            .await?;
+    }
+    info!("[split] released {} parts", parts.len());
+    Ok(())
+}
+
+/// Returns the manifest, if the blob is one
+pub(crate) async fn load_manifest(
+    manager: &IrohManager,
+    hash: Hash,
+) -> Result<Option<SplitManifest>> {
+    let mut reader = manager.blobs.client().read(hash).await?;
+    if reader.size() > MAX_MANIFEST_LEN {
+        return Ok(None);
+    }
+
+    let bytes = reader.read_to_bytes().await?;
+    Ok(SplitManifest::from_bytes(&bytes))
+}
+
+/// Hash and length of the file stored in the blob, which differ from those of the blob
+/// for split files. Blobs which aren't available locally are assumed not to be split.
+pub(crate) async fn file_info(manager: &IrohManager, hash: Hash, len: u64) -> (Hash, u64) {
+    if len > MAX_MANIFEST_LEN {
+        return (hash, len);
+    }
+    match load_manifest(manager, hash).await {
+        Ok(Some(manifest)) => (manifest.file_hash, manifest.file_len),
+        _ => (hash, len),
+    }
+}
+
+/// Reads the content of the blob, rebuilding split files
+pub(crate) async fn read_content(manager: &IrohManager, hash: Hash) -> Result<Vec<u8>> {
+    let blobs_client = manager.blobs.client();
+    let bytes = blobs_client.read_to_bytes(hash).await?;
+
+    if bytes.len() as u64 > MAX_MANIFEST_LEN {
+        return Ok(bytes.to_vec());
+    }
+    let Some(manifest) = SplitManifest::from_bytes(&bytes) else {
+        return Ok(bytes.to_vec());
+    };
+
+    let mut data = Vec::with_capacity(manifest.file_len as usize);
+    for part in &manifest.parts {
+        data.extend_from_slice(&blobs_client.read_to_bytes(part.hash).await?);
+    }
+    Ok(data)
+}
+
+/// Writes parts of the split file into the path
+pub(crate) async fn export_parts(
+    manager: &IrohManager,
+    manifest: &SplitManifest,
+    path: &Path,
+) -> Result<()> {
+    let blobs_client = manager.blobs.client();
+    let mut writer = BufWriter::new(File::create(path).await?);
+
+    for part in &manifest.parts {
+        let mut reader = blobs_client.read(part.hash).await?;
+        let copied = tokio::io::copy(&mut reader, &mut writer).await?;
+        if copied != part.len {
+            return Err(SharedError::Storage(format!(
+                "Part {} is incomplete, copied {copied} of {} bytes",
+                part.hash, part.len
+            )));
+        }
+    }
+
+    writer.flush().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{PartKind, split_ranges};
+
+    fn id3v2(body_len: usize) -> Vec<u8> {
+        let mut tag = b"ID3\x04\x00\x00".to_vec();
+        tag.extend([0, 0, (body_len >> 7) as u8 & 0x7f, body_len as u8 & 0x7f]);
+        tag.extend(vec![1; body_len]);
+        tag
+    }
+
+    #[test]
+    fn test_split_mp3() {
+        let mut data = id3v2(200);
+        data.extend([0xff, 0xfb, 0x90, 0x64]);
+        data.extend(vec![0; 1000]);
+        let mut id3v1 = b"TAG".to_vec();
+        id3v1.resize(128, b' ');
+        data.extend(id3v1);
+
+        let ranges = split_ranges(&data).unwrap();
+        assert_eq!(
+            ranges,
+            vec![
+                (PartKind::Tags, 0..210),
+                (PartKind::Audio, 210..1214),
+                (PartKind::Tags, 1214..1342),
+            ]
+        );
+
+        // Untagged files are split too, so tags can be added cheaply later
+        assert_eq!(
+            split_ranges(&data[210..1214]).unwrap(),
+            vec![(PartKind::Audio, 0..1004)]
+        );
+    }
+
+    #[test]
+    fn test_split_flac() {
+        let mut data = b"fLaC".to_vec();
+        // STREAMINFO followed by the last block, a vorbis comment
+        data.extend([0x00, 0, 0, 34]);
+        data.extend([0; 34]);
+        data.extend([0x84, 0, 0, 10]);
+        data.extend([2; 10]);
+        data.extend([0xff, 0xf8, 0x00, 0x00]);
+        data.extend(vec![3; 500]);
+
+        assert_eq!(
+            split_ranges(&data).unwrap(),
+            vec![(PartKind::Tags, 0..56), (PartKind::Audio, 56..560)]
+        );
+    }
+
+    #[test]
+    fn test_split_unsupported() {
+        assert!(split_ranges(b"OggS\x00\x02").is_none());
+        assert!(split_ranges(b"plain text").is_none());
+        // Truncated metadata isn't split
+        assert!(split_ranges(b"fLaC\x00\x00\x00\x22").is_none());
+    }
+}
@@ -0,0 +1,662 @@
+use http_body_util::{BodyExt, Empty, Full, StreamBody, combinators::BoxBody};
+use hyper::{
+    Method, Request, Response, StatusCode,
+    body::{Bytes, Frame, Incoming},
+    header,
+    server::conn::http1,
+    service::service_fn,
+};
+use hyper_util::rt::TokioIo;
+use iroh_blobs::{
+    BlobFormat, Hash,
+    get::db::DownloadProgress,
+    net_protocol::Blobs,
+    rpc::client::blobs::{DownloadMode, DownloadOptions, ReadAtLen},
+    util::SetTagOption,
+};
+use iroh_docs::store::Query;
+use log::{info, warn};
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC, percent_decode_str, utf8_percent_encode};
+use std::{
+    convert::Infallible,
+    io,
+    net::{Ipv4Addr, SocketAddr},
+    ops::Range,
+    sync::Arc,
+};
+use tokio::{
+    net::TcpListener,
+    sync::{mpsc, watch},
+    task::JoinHandle,
+    task::JoinSet,
+};
+use tokio_stream::{StreamExt, wrappers::ReceiverStream};
+
+use crate::{
+    IrohManager, PersistentStore, TOMBSTONE, WeakIrohManager,
+    errors::{Result, SharedError},
+    split,
+    types::UNamespaceId,
+};
+
+/// Characters escaped in key components of URLs
+const PATH_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+/// Bytes sniffed to guess content types of blobs
+const SNIFF_LEN: u64 = 16;
+
+type Body = BoxBody<Bytes, io::Error>;
+
+#[cfg_attr(feature = "default", derive(uniffi::Record))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamingServerInfo {
+    pub port: u16,
+    /// Has to be passed as the `token` query parameter or as a bearer token
+    pub token: String,
+    /// Such as `http://127.0.0.1:port`, without a trailing slash
+    pub base_url: String,
+}
+
+/// HTTP server on the loopback interface serving entries and blobs to media players
+#[derive(Debug)]
+pub struct StreamingServer {
+    pub info: StreamingServerInfo,
+    task: JoinHandle<()>,
+}
+
+impl Drop for StreamingServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Blobs which concatenated give the content, split files have more than one
+struct Content {
+    segments: Vec<Segment>,
+    len: u64,
+    content_type: &'static str,
+}
+
+/// How much of a blob is stored, it grows while the blob is downloaded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Available {
+    /// Bytes before the offset
+    Prefix(u64),
+    Complete,
+    Failed,
+}
+
+/// Blob of the content, which can be read while it is still downloaded
+#[derive(Debug, Clone)]
+struct Segment {
+    hash: Hash,
+    len: u64,
+    available: watch::Receiver<Available>,
+}
+
+impl Segment {
+    /// Waits until the byte at the offset is stored, returns the end of the stored bytes
+    async fn readable_from(&mut self, offset: u64) -> io::Result<u64> {
+        let available = *self
+            .available
+            .wait_for(|available| match available {
+                Available::Prefix(end) => *end > offset,
+                _ => true,
+            })
+            .await
+            .map_err(io::Error::other)?;
+        match available {
+            Available::Prefix(end) => Ok(end.min(self.len)),
+            Available::Complete => Ok(self.len),
+            Available::Failed => Err(io::Error::other(format!("Failed to fetch {}", self.hash))),
+        }
+    }
+}
+
+impl StreamingServer {
+    /// Binds to the port on 127.0.0.1, an unused one is picked for port 0
+    pub(crate) async fn start(manager: WeakIrohManager, port: u16) -> Result<Self> {
+        let listener = TcpListener::bind(SocketAddr::from((Ipv4Addr::LOCALHOST, port))).await?;
+        let port = listener.local_addr()?.port();
+
+        let token: String = rand::random::<[u8; 32]>()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect();
+        let info = StreamingServerInfo {
+            port,
+            token: token.clone(),
+            base_url: format!("http://127.0.0.1:{port}"),
+        };
+        info!("[streaming] listening on {}", info.base_url);
+
+        let token: Arc<str> = token.into();
+        let task = tokio::spawn(async move {
+            // Connections are aborted together with the server
+            let mut connections = JoinSet::new();
+            loop {
+                tokio::select! {
+                    accepted = listener.accept() => {
+                        let stream = match accepted {
+                            Ok((stream, _)) => stream,
+                            Err(error) => {
+                                warn!("[streaming] failed to accept connection: {error}");
+                                continue;
+                            }
+                        };
+
+                        let (manager, token) = (manager.clone(), token.clone());
+                        let service = service_fn(move |request| {
+                            handle(manager.clone(), token.clone(), request)
+                        });
+                        connections.spawn(async move {
+                            let connection = http1::Builder::new()
+                                .serve_connection(TokioIo::new(stream), service);
+                            if let Err(error) = connection.await {
+                                info!("[streaming] connection closed: {error}");
+                            }
+                        });
+                    }
+                    Some(_) = connections.join_next(), if !connections.is_empty() => {}
+                }
+            }
+        });
+
+        Ok(Self { info, task })
+    }
+
+    pub(crate) fn entry_url(&self, namespace: UNamespaceId, key: &str) -> String {
+        let key = key
+            .split('/')
+            .map(|component| utf8_percent_encode(component, PATH_SEGMENT).to_string())
+            .collect::<Vec<_>>()
+            .join("/");
+        format!(
+            "{}/ns/{namespace}/{key}?token={}",
+            self.info.base_url, self.info.token
+        )
+    }
+
+    pub(crate) fn blob_url(&self, hash: Hash) -> String {
+        format!(
+            "{}/blob/{hash}?token={}",
+            self.info.base_url, self.info.token
+        )
+    }
+}
+
+/// Compares in constant time, so the token can't be guessed byte by byte
+fn token_matches(expected: &str, given: &str) -> bool {
+    expected.len() == given.len()
+        && expected
+            .bytes()
+            .zip(given.bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+fn is_authorized<B>(request: &Request<B>, token: &str) -> bool {
+    let from_query = request.uri().query().is_some_and(|query| {
+        query
+            .split('&')
+            .filter_map(|pair| pair.strip_prefix("token="))
+            .any(|given| token_matches(token, given))
+    });
+    let from_header = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|given| token_matches(token, given));
+
+    from_query || from_header
+}
+
+/// Parses a single range of the `Range` header. Returns `None` when the whole content
+/// should be served and `Some(Err)` when the range can't be satisfied.
+pub(crate) fn parse_range(header: &str, len: u64) -> Option<std::result::Result<Range<u64>, ()>> {
+    let range = header.trim().strip_prefix("bytes=")?;
+    // Multiple ranges are rarely used by players, serving everything is allowed
+    if range.contains(',') {
+        return None;
+    }
+    let (start, end) = range.split_once('-')?;
+    let (start, end) = (start.trim(), end.trim());
+
+    let range = match (start, end) {
+        ("", suffix) => {
+            let suffix: u64 = suffix.parse().ok()?;
+            if suffix == 0 || len == 0 {
+                return Some(Err(()));
+            }
+            len.saturating_sub(suffix)..len
+        }
+        (start, end) => {
+            let start: u64 = start.parse().ok()?;
+            let end = match end {
+                "" => len,
+                end => end.parse::<u64>().ok()?.saturating_add(1).min(len),
+            };
+            if start >= len {
+                return Some(Err(()));
+            }
+            if start >= end {
+                return None;
+            }
+            start..end
+        }
+    };
+    Some(Ok(range))
+}
+
+/// Guesses the content type from the extension of the key, or from the first bytes
+pub(crate) fn content_type(key: Option<&str>, head: &[u8]) -> &'static str {
+    let extension = key
+        .and_then(|key| key.rsplit('/').next())
+        .and_then(|name| name.rsplit_once('.'))
+        .map(|(_, extension)| extension.to_ascii_lowercase());
+
+    let by_extension = match extension.as_deref() {
+        Some("mp3") => Some("audio/mpeg"),
+        Some("flac") => Some("audio/flac"),
+        Some("m4a" | "m4b" | "mp4" | "alac") => Some("audio/mp4"),
+        Some("aac") => Some("audio/aac"),
+        Some("ogg" | "oga" | "opus") => Some("audio/ogg"),
+        Some("wav") => Some("audio/wav"),
+        Some("aif" | "aiff") => Some("audio/aiff"),
+        Some("jpg" | "jpeg") => Some("image/jpeg"),
+        Some("png") => Some("image/png"),
+        Some("json") => Some("application/json"),
+        Some("txt" | "lrc") => Some("text/plain; charset=utf-8"),
+        _ => None,
+    };
+    if let Some(content_type) = by_extension {
+        return content_type;
+    }
+
+    match head {
+        [b'I', b'D', b'3', ..] => "audio/mpeg",
+        [0xff, second, ..] if second & 0xe0 == 0xe0 => "audio/mpeg",
+        [b'f', b'L', b'a', b'C', ..] => "audio/flac",
+        [b'O', b'g', b'g', b'S', ..] => "audio/ogg",
+        [
+            b'R',
+            b'I',
+            b'F',
+            b'F',
+            _,
+            _,
+            _,
+            _,
+            b'W',
+            b'A',
+            b'V',
+            b'E',
+            ..,
+        ] => "audio/wav",
+        [_, _, _, _, b'f', b't', b'y', b'p', ..] => "audio/mp4",
+        [0x89, b'P', b'N', b'G', ..] => "image/png",
+        [0xff, 0xd8, 0xff, ..] => "image/jpeg",
+        _ => "application/octet-stream",
+    }
+}
+
+async fn handle(
+    manager: WeakIrohManager,
+    token: Arc<str>,
+    request: Request<Incoming>,
+) -> std::result::Result<Response<Body>, Infallible> {
+    if request.method() != Method::GET && request.method() != Method::HEAD {
+        return Ok(status_response(StatusCode::METHOD_NOT_ALLOWED));
+    }
+    if !is_authorized(&request, &token) {
+        return Ok(status_response(StatusCode::UNAUTHORIZED));
+    }
+
+    // The server stops once the manager is dropped
+    let Some(manager) = manager.upgrade() else {
+        return Ok(status_response(StatusCode::SERVICE_UNAVAILABLE));
+    };
+    match serve(&manager, &request).await {
+        Ok(response) => Ok(response),
+        Err(error) => {
+            let status = match &error {
+                SharedError::ReplicaMissing(_)
+                | SharedError::EntryMissing(..)
+                | SharedError::EntryTombstoned(..)
+                | SharedError::NotFound(_) => StatusCode::NOT_FOUND,
+                SharedError::InvalidInput(_) | SharedError::InvalidNamespaceId(_) => {
+                    StatusCode::BAD_REQUEST
+                }
+                SharedError::Network(_) => StatusCode::BAD_GATEWAY,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            warn!("[streaming] {} failed: {error}", request.uri().path());
+            Ok(status_response(status))
+        }
+    }
+}
+
+fn status_response(status: StatusCode) -> Response<Body> {
+    let mut response = Response::new(
+        Full::new(Bytes::from(status.canonical_reason().unwrap_or_default()))
+            .map_err(|never| match never {})
+            .boxed(),
+    );
+    *response.status_mut() = status;
+    response
+}
+
+async fn serve<B>(manager: &IrohManager, request: &Request<B>) -> Result<Response<Body>> {
+    let path = request.uri().path();
+    let content = if let Some(rest) = path.strip_prefix("/ns/") {
+        let (namespace, key) = rest
+            .split_once('/')
+            .ok_or_else(|| SharedError::InvalidInput("Missing key".to_string()))?;
+        let namespace: UNamespaceId = namespace
+            .parse()
+            .map_err(|_| SharedError::InvalidNamespaceId(namespace.to_string()))?;
+        let key = percent_decode_str(key)
+            .decode_utf8()
+            .map_err(|error| SharedError::InvalidInput(error.to_string()))?;
+        entry_content(manager, namespace, &key).await?
+    } else if let Some(hash) = path.strip_prefix("/blob/") {
+        let hash: Hash = hash
+            .parse()
+            .map_err(|_| SharedError::InvalidInput(format!("Invalid hash {hash}")))?;
+        load_content(manager, hash, None, None).await?
+    } else {
+        return Err(SharedError::NotFound(path.to_string()));
+    };
+
+    let range = request
+        .headers()
+        .get(header::RANGE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| parse_range(value, content.len));
+
+    let mut response = Response::builder()
+        .header(header::CONTENT_TYPE, content.content_type)
+        .header(header::ACCEPT_RANGES, "bytes");
+    let range = match range {
+        Some(Err(())) => {
+            return Response::builder()
+                .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                .header(header::CONTENT_RANGE, format!("bytes */{}", content.len))
+                .body(Empty::new().map_err(|never| match never {}).boxed())
+                .map_err(|error| SharedError::Iroh(error.to_string()));
+        }
+        Some(Ok(range)) => {
+            response = response.status(StatusCode::PARTIAL_CONTENT).header(
+                header::CONTENT_RANGE,
+                format!("bytes {}-{}/{}", range.start, range.end - 1, content.len),
+            );
+            range
+        }
+        None => 0..content.len,
+    };
+    response = response.header(header::CONTENT_LENGTH, range.end - range.start);
+
+    let body = if request.method() == Method::HEAD {
+        Empty::new().map_err(|never| match never {}).boxed()
+    } else {
+        stream_body(manager.blobs.clone(), content.segments, range)
+    };
+    response
+        .body(body)
+        .map_err(|error| SharedError::Iroh(error.to_string()))
+}
+
+async fn entry_content(
+    manager: &IrohManager,
+    namespace: UNamespaceId,
+    key: &str,
+) -> Result<Content> {
+    let replica = manager
+        .docs
+        .client()
+        .open(namespace.into())
+        .await?
+        .ok_or(SharedError::ReplicaMissing(namespace))?;
+
+    let entry = replica
+        .get_one(Query::single_latest_per_key().key_exact(key))
+        .await?
+        .ok_or_else(|| SharedError::EntryMissing(namespace, key.to_string()))?;
+    if entry.content_hash() == Hash::new(TOMBSTONE) {
+        return Err(SharedError::EntryTombstoned(namespace, key.to_string()));
+    }
+
+    load_content(manager, entry.content_hash(), Some(namespace), Some(key)).await
+}
+
+/// Starts downloading the blob from peers, if it isn't stored locally yet.
+/// Returns once its size is known, its data can be read as it arrives.
+async fn fetch(
+    manager: &IrohManager,
+    hash: Hash,
+    namespace: Option<UNamespaceId>,
+) -> Result<Segment> {
+    let blobs_client = manager.blobs.client();
+    if blobs_client.has(hash).await? {
+        return Ok(Segment {
+            hash,
+            len: blobs_client.read(hash).await?.size(),
+            available: watch::channel(Available::Complete).1,
+        });
+    }
+
+    let nodes = manager.peer_addrs(namespace).await;
+    if nodes.is_empty() {
+        return Err(SharedError::NotFound(format!(
+            "Blob {hash} isn't stored locally and no peers are known"
+        )));
+    }
+
+    info!("[streaming] fetching {hash} from {} peers", nodes.len());
+    let mut progress = blobs_client
+        .download_with_opts(
+            hash,
+            DownloadOptions {
+                format: BlobFormat::Raw,
+                nodes,
+                tag: SetTagOption::Auto,
+                mode: DownloadMode::Direct,
+            },
+        )
+        .await?;
+    let failed = |error: String| SharedError::Network(format!("Failed to fetch {hash}: {error}"));
+
+    let len = loop {
+        match progress.next().await {
+            Some(Ok(DownloadProgress::Found { size, .. })) => break size,
+            // Another download completed it in the meantime
+            Some(Ok(DownloadProgress::AllDone(_))) => {
+                return Ok(Segment {
+                    hash,
+                    len: blobs_client.read(hash).await?.size(),
+                    available: watch::channel(Available::Complete).1,
+                });
+            }
+            Some(Ok(DownloadProgress::Abort(error))) => return Err(failed(error.to_string())),
+            Some(Ok(_)) => {}
+            Some(Err(error)) => return Err(failed(format!("{error:#}"))),
+            None => return Err(failed("download stopped".to_string())),
+        }
+    };
+
+    // Ranges are fetched in order, so everything before the offset of a write is stored
+    let (sender, available) = watch::channel(Available::Prefix(0));
+    tokio::spawn(async move {
+        while let Some(event) = progress.next().await {
+            match event {
+                Ok(DownloadProgress::Progress { offset, .. }) => {
+                    sender.send_modify(|available| {
+                        if let Available::Prefix(end) = available {
+                            *end = (*end).max(offset);
+                        }
+                    });
+                }
+                Ok(DownloadProgress::AllDone(_)) => {
+                    sender.send_replace(Available::Complete);
+                    return;
+                }
+                Ok(DownloadProgress::Abort(error)) => {
+                    warn!("[streaming] failed to fetch {hash}: {error}");
+                    break;
+                }
+                Ok(_) => {}
+                Err(error) => {
+                    warn!("[streaming] failed to fetch {hash}: {error:#}");
+                    break;
+                }
+            }
+        }
+        sender.send_replace(Available::Failed);
+    });
+
+    Ok(Segment {
+        hash,
+        len,
+        available,
+    })
+}
+
+async fn load_content(
+    manager: &IrohManager,
+    hash: Hash,
+    namespace: Option<UNamespaceId>,
+    key: Option<&str>,
+) -> Result<Content> {
+    let mut segment = fetch(manager, hash, namespace).await?;
+
+    // Only small blobs can be manifests, they are needed whole to tell
+    let manifest = if segment.len <= split::MAX_MANIFEST_LEN {
+        if segment.len > 0 {
+            segment.readable_from(segment.len - 1).await?;
+        }
+        split::load_manifest(manager, hash).await?
+    } else {
+        None
+    };
+    let mut segments = match manifest {
+        Some(manifest) => {
+            let mut segments = Vec::with_capacity(manifest.parts.len());
+            for part in &manifest.parts {
+                segments.push(fetch(manager, part.hash, namespace).await?);
+            }
+            segments
+        }
+        None => vec![segment],
+    };
+    let len = segments.iter().map(|segment| segment.len).sum();
+
+    let head = match segments.first_mut() {
+        Some(segment) if segment.len > 0 => {
+            let readable = segment.readable_from(0).await?;
+            manager
+                .blobs
+                .client()
+                .read_at_to_bytes(segment.hash, 0, ReadAtLen::Exact(readable.min(SNIFF_LEN)))
+                .await?
+        }
+        _ => Bytes::new(),
+    };
+
+    Ok(Content {
+        segments,
+        len,
+        content_type: content_type(key, &head),
+    })
+}
+
+/// Streams the range of the content, reading only the blobs it covers
+/// as soon as their data is stored
+fn stream_body(blobs: Blobs<PersistentStore>, segments: Vec<Segment>, range: Range<u64>) -> Body {
+    let (sender, receiver) = mpsc::channel(4);
+
+    tokio::spawn(async move {
+        let blobs_client = blobs.client();
+        let mut segment_start = 0;
+        for mut segment in segments {
+            let segment_end = segment_start + segment.len;
+            let start = range.start.max(segment_start);
+            let end = range.end.min(segment_end);
+            let offset = segment_start;
+            segment_start = segment_end;
+            if start >= end {
+                continue;
+            }
+
+            let (mut position, end) = (start - offset, end - offset);
+            while position < end {
+                let readable = match segment.readable_from(position).await {
+                    Ok(readable) => readable.min(end),
+                    Err(error) => {
+                        let _ = sender.send(Err(error)).await;
+                        return;
+                    }
+                };
+
+                let reader = blobs_client
+                    .read_at(
+                        segment.hash,
+                        position,
+                        ReadAtLen::Exact(readable - position),
+                    )
+                    .await;
+                let mut reader = match reader {
+                    Ok(reader) => reader,
+                    Err(error) => {
+                        let _ = sender.send(Err(io::Error::other(error))).await;
+                        return;
+                    }
+                };
+
+                while let Some(chunk) = reader.next().await {
+                    let failed = chunk.is_err();
+                    // Fails once the client disconnects
+                    if sender.send(chunk.map(Frame::data)).await.is_err() || failed {
+                        return;
+                    }
+                }
+                position = readable;
+            }
+        }
+    });
+
+    BodyExt::boxed(StreamBody::new(ReceiverStream::new(receiver)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{content_type, parse_range};
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range("bytes=0-99", 1000), Some(Ok(0..100)));
+        assert_eq!(parse_range("bytes=500-", 1000), Some(Ok(500..1000)));
+        assert_eq!(parse_range("bytes=-100", 1000), Some(Ok(900..1000)));
+        // End past the content is clamped
+        assert_eq!(parse_range("bytes=900-5000", 1000), Some(Ok(900..1000)));
+        assert_eq!(parse_range("bytes=1000-", 1000), Some(Err(())));
+        assert_eq!(parse_range("bytes=-0", 1000), Some(Err(())));
+        // Malformed and multiple ranges are ignored
+        assert_eq!(parse_range("bytes=abc", 1000), None);
+        assert_eq!(parse_range("items=0-1", 1000), None);
+        assert_eq!(parse_range("bytes=0-1,5-6", 1000), None);
+    }
+
+    #[test]
+    fn test_content_type() {
+        assert_eq!(content_type(Some("album/01.MP3"), b""), "audio/mpeg");
+        assert_eq!(content_type(Some("a.flac"), b"ID3"), "audio/flac");
+        assert_eq!(content_type(None, b"fLaC\0\0\0\x22"), "audio/flac");
+        assert_eq!(content_type(Some("no_extension"), b"ID3\x04"), "audio/mpeg");
+        assert_eq!(content_type(None, b"\0\0\0\x20ftypM4A "), "audio/mp4");
+        assert_eq!(content_type(None, b"hello"), "application/octet-stream");
+    }
+}
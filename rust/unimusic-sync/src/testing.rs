@@ -0,0 +1,349 @@
+use iroh::node_info::NodeData;
+use iroh_blobs::Hash;
+use iroh_docs::store::Query;
+use log::info;
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    error::Error,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+use tokio::{sync::mpsc, task::JoinSet, time::Instant};
+use tokio_stream::StreamExt;
+
+use crate::{IrohFactory, IrohManager, TOMBSTONE, types::UNamespaceId};
+
+pub(crate) type Result<T> = std::result::Result<T, Box<dyn Error + Sync + Send>>;
+
+/// How long replicas are given to converge, generous for slow CI machines
+const CONVERGENCE_TIMEOUT: Duration = Duration::from_secs(60);
+/// How long replicas may stay unchanged before missing content is fetched explicitly
+const QUIET_PERIOD: Duration = Duration::from_secs(3);
+
+pub(crate) struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new() -> Self {
+        let sys_time = std::time::SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        Self(std::env::temp_dir().join(sys_time.to_string()))
+    }
+
+    pub fn subpath<P: AsRef<Path>>(&self, path: P) -> PathBuf {
+        self.0.join(path)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Managers in one process, reaching each other only through direct addresses.
+/// Discovery and relays are turned off, so tests run offline.
+pub(crate) struct TestNetwork {
+    /// `None` while the manager is stopped
+    managers: Vec<Option<IrohManager>>,
+    dir: TempDir,
+    /// Key the stores of the managers are encrypted with
+    encryption_key: Option<Vec<u8>>,
+}
+
+impl TestNetwork {
+    /// Starts managers which know addresses of each other
+    pub async fn new(count: usize) -> Result<Self> {
+        Self::start_network(count, None).await
+    }
+
+    /// Starts managers whose stores are encrypted with the key
+    pub async fn encrypted(count: usize, key: Vec<u8>) -> Result<Self> {
+        Self::start_network(count, Some(key)).await
+    }
+
+    async fn start_network(count: usize, encryption_key: Option<Vec<u8>>) -> Result<Self> {
+        let mut network = Self {
+            managers: Vec::with_capacity(count),
+            dir: TempDir::new(),
+            encryption_key,
+        };
+        for i in 0..count {
+            let manager = network.spawn(i).await?;
+            network.managers.push(Some(manager));
+        }
+        network.introduce_all().await?;
+        Ok(network)
+    }
+
+    async fn spawn(&self, i: usize) -> Result<IrohManager> {
+        let path = self.path(i);
+        let factory = match &self.encryption_key {
+            Some(key) => IrohFactory::with_encryption_key(key.clone())?,
+            None => IrohFactory::new(),
+        };
+        Ok(factory
+            .without_discovery()
+            .without_relay()
+            .iroh_manager(&path.to_string_lossy())
+            .await?)
+    }
+
+    /// Directory holding the store of the manager
+    pub fn path(&self, i: usize) -> PathBuf {
+        self.dir.subpath(format!("node_{i}"))
+    }
+
+    pub fn manager(&self, i: usize) -> &IrohManager {
+        self.managers[i].as_ref().expect("manager is stopped")
+    }
+
+    /// Running managers
+    pub fn managers(&self) -> Vec<IrohManager> {
+        self.managers.iter().flatten().cloned().collect()
+    }
+
+    /// Makes every running manager know direct addresses of every other one
+    pub async fn introduce_all(&self) -> Result<()> {
+        let managers = self.managers();
+        for manager in &managers {
+            let addr = manager.router.endpoint().node_addr().await?;
+            let data = NodeData::new(addr.relay_url.clone(), addr.direct_addresses.clone());
+
+            for other in &managers {
+                if other.router.endpoint().node_id() == addr.node_id {
+                    continue;
+                }
+                other.router.endpoint().add_node_addr(addr.clone())?;
+                other
+                    .node_storage
+                    .write()
+                    .await
+                    .upsert_node(addr.node_id, Cow::Borrowed(&data));
+            }
+        }
+        Ok(())
+    }
+
+    /// Creates a namespace on the first manager, which every other one joins through a ticket
+    pub async fn shared_namespace(&self) -> Result<UNamespaceId> {
+        let owner = self.manager(0);
+        let namespace = owner.create_namespace().await?;
+        let ticket = owner.share(namespace).await?;
+        for manager in &self.managers()[1..] {
+            manager.import(ticket.clone()).await?;
+        }
+        Ok(namespace)
+    }
+
+    /// Shuts the manager down, keeping its storage
+    pub async fn stop(&mut self, i: usize) -> Result<()> {
+        if let Some(manager) = self.managers[i].take() {
+            manager.shutdown().await?;
+        }
+        Ok(())
+    }
+
+    /// Starts a stopped manager again from its storage. It knows peers it knew before,
+    /// but they don't learn its new address.
+    pub async fn start(&mut self, i: usize) -> Result<&IrohManager> {
+        if self.managers[i].is_none() {
+            let manager = self.spawn(i).await?;
+            self.managers[i] = Some(manager);
+        }
+        Ok(self.manager(i))
+    }
+
+    /// Stops live sync of the namespace on every running manager. They reject sync
+    /// requests for it until they [`connect`](Self::connect) again.
+    pub async fn partition(&self, namespace: UNamespaceId) {
+        for manager in self.managers() {
+            manager.leave(namespace).await;
+        }
+    }
+
+    /// Starts live sync of the namespace between the managers and waits until they converge
+    pub async fn connect(&self, namespace: UNamespaceId, members: &[usize]) -> Result<()> {
+        let mut addrs = Vec::with_capacity(members.len());
+        for &i in members {
+            addrs.push(self.manager(i).router.endpoint().node_addr().await?);
+        }
+
+        let managers: Vec<_> = members.iter().map(|&i| self.manager(i).clone()).collect();
+        for manager in &managers {
+            let node_id = manager.router.endpoint().node_id();
+            let peers: Vec<_> = addrs
+                .iter()
+                .filter(|addr| addr.node_id != node_id)
+                .cloned()
+                .collect();
+            manager
+                .docs
+                .client()
+                .open(namespace.into())
+                .await?
+                .ok_or("replica is missing")?
+                .start_sync(peers)
+                .await?;
+        }
+        wait_until_converged(&managers, namespace).await
+    }
+
+    /// Connects every running manager
+    pub async fn heal(&self, namespace: UNamespaceId) -> Result<()> {
+        let members: Vec<_> = (0..self.managers.len())
+            .filter(|&i| self.managers[i].is_some())
+            .collect();
+        self.connect(namespace, &members).await
+    }
+
+    pub async fn wait_until_converged(&self, namespace: UNamespaceId) -> Result<()> {
+        wait_until_converged(&self.managers(), namespace).await
+    }
+
+    pub async fn shutdown(self) -> Result<()> {
+        for manager in self.managers.iter().flatten() {
+            manager.shutdown().await?;
+        }
+        Ok(())
+    }
+}
+
+/// Waits until the condition holds, for changes made by tasks in the background
+pub(crate) async fn wait_for(mut condition: impl AsyncFnMut() -> bool) {
+    for _ in 0..100 {
+        if condition().await {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("condition wasn't met in time");
+}
+
+/// Latest content hash of every key
+pub(crate) type ReplicaState = BTreeMap<Vec<u8>, Hash>;
+
+/// State of the replica, or `None` when it is missing some content
+pub(crate) async fn replica_state(
+    manager: &IrohManager,
+    namespace: UNamespaceId,
+) -> Result<Option<ReplicaState>> {
+    let replica = manager
+        .docs
+        .client()
+        .open(namespace.into())
+        .await?
+        .ok_or("replica is missing")?;
+    let blobs_client = manager.blobs.client();
+
+    let mut state = BTreeMap::new();
+    let mut entries = replica.get_many(Query::single_latest_per_key()).await?;
+    while let Some(entry) = entries.try_next().await? {
+        let hash = entry.content_hash();
+        if hash != Hash::new(TOMBSTONE) && !blobs_client.has(hash).await? {
+            return Ok(None);
+        }
+        state.insert(entry.key().to_vec(), hash);
+    }
+    Ok(Some(state))
+}
+
+/// Waits until every replica of the namespace has the same latest entries and all of their
+/// content. Replicas are compared again after each of their live events, not periodically.
+pub(crate) async fn wait_until_converged(
+    managers: &[IrohManager],
+    namespace: UNamespaceId,
+) -> Result<()> {
+    let (sender, mut events) = mpsc::unbounded_channel();
+    let mut subscriptions = JoinSet::new();
+    for manager in managers {
+        let replica = manager
+            .docs
+            .client()
+            .open(namespace.into())
+            .await?
+            .ok_or("replica is missing")?;
+        let mut stream = replica.subscribe().await?;
+        let sender = sender.clone();
+        subscriptions.spawn(async move {
+            // Events stop once the replica is closed
+            let _replica = replica;
+            while let Some(event) = stream.next().await {
+                if sender.send(event.is_ok()).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+    drop(sender);
+
+    let deadline = Instant::now() + CONVERGENCE_TIMEOUT;
+    loop {
+        let mut states = Vec::with_capacity(managers.len());
+        for manager in managers {
+            states.push(replica_state(manager, namespace).await?);
+        }
+        if states[0].is_some() && states.iter().all(|state| *state == states[0]) {
+            info!("[test] {} replicas converged", managers.len());
+            return Ok(());
+        }
+
+        let quiet_until = (Instant::now() + QUIET_PERIOD).min(deadline);
+        match tokio::time::timeout_at(quiet_until, events.recv()).await {
+            Ok(Some(_)) => {
+                // Bursts of events are handled by a single comparison
+                while events.try_recv().is_ok() {}
+            }
+            Ok(None) => return Err("live events ended before replicas converged".into()),
+            Err(_) if quiet_until < deadline => {
+                // Content announced by peers which didn't have it yet isn't fetched again
+                for (manager, state) in managers.iter().zip(&states) {
+                    if state.is_none() {
+                        manager.download_missing(namespace).await?;
+                    }
+                }
+            }
+            Err(_) => {
+                let summary: Vec<_> = states
+                    .iter()
+                    .map(|state| match state {
+                        Some(state) => format!("{} keys", state.len()),
+                        None => "missing content".to_string(),
+                    })
+                    .collect();
+                return Err(format!(
+                    "replicas didn't converge in {CONVERGENCE_TIMEOUT:?}: {summary:?}"
+                )
+                .into());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Result, TestNetwork};
+
+    #[tokio::test]
+    async fn test_wait_until_converged() -> Result<()> {
+        let network = TestNetwork::new(3).await?;
+        let namespace = network.shared_namespace().await?;
+
+        for (i, manager) in network.managers().iter().enumerate() {
+            manager
+                .write_file(namespace, format!("from_{i}"), vec![i as u8; 100])
+                .await?;
+        }
+        network.wait_until_converged(namespace).await?;
+
+        for manager in network.managers() {
+            assert_eq!(manager.get_files(namespace).await?.len(), 3);
+            assert_eq!(manager.read_file(namespace, "from_2").await?, vec![2; 100]);
+        }
+
+        network.shutdown().await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,127 @@
+use iroh::NodeAddr;
+use iroh_docs::DocTicket;
+use std::str::FromStr;
+
+use crate::{
+    errors::{Result, SharedError},
+    types::UDocTicket,
+};
+
+/// Prefix of ticket URIs, which can be used as deep links
+pub const TICKET_URI_PREFIX: &str = "unimusic://join?";
+const TICKET_URI_PARAM: &str = "ticket";
+
+/// Returns value of the parameter in the query part of the URI
+pub(crate) fn uri_param<'a>(query: &'a str, name: &str) -> Option<&'a str> {
+    query
+        .split('&')
+        .filter_map(|param| param.split_once('='))
+        .find_map(|(key, value)| (key == name).then_some(value))
+}
+
+/// Parses a ticket from its plain, compact or URI form
+pub fn parse_ticket(input: &str) -> Result<DocTicket> {
+    let input = input.trim();
+
+    let ticket = match input.strip_prefix(TICKET_URI_PREFIX) {
+        Some(query) => uri_param(query, TICKET_URI_PARAM).ok_or_else(|| {
+            SharedError::InvalidInput(format!("Ticket URI is missing the ticket: {input}"))
+        })?,
+        None => input,
+    };
+
+    DocTicket::from_str(ticket)
+        .map_err(|error| SharedError::InvalidInput(format!("Invalid ticket: {error}")))
+}
+
+/// Strips direct addresses of the nodes from the ticket, making it short enough for QR codes.
+/// Nodes are then resolved through discovery or their relay when importing.
+#[cfg_attr(feature = "default", uniffi::export)]
+pub fn compact_ticket(ticket: UDocTicket) -> UDocTicket {
+    let ticket: DocTicket = ticket.into();
+
+    let nodes = ticket
+        .nodes
+        .into_iter()
+        .map(|node| {
+            let mut compact = NodeAddr::new(node.node_id);
+            if let Some(relay_url) = node.relay_url {
+                compact = compact.with_relay_url(relay_url);
+            }
+            compact
+        })
+        .collect();
+
+    DocTicket::new(ticket.capability, nodes).into()
+}
+
+/// Returns `unimusic://join?ticket=...` URI of the ticket
+#[cfg_attr(feature = "default", uniffi::export)]
+pub fn ticket_to_uri(ticket: UDocTicket) -> String {
+    format!("{TICKET_URI_PREFIX}{TICKET_URI_PARAM}={ticket}")
+}
+
+#[cfg(test)]
+mod test {
+    use super::{TICKET_URI_PREFIX, compact_ticket, ticket_to_uri};
+    use crate::types::UDocTicket;
+
+    use iroh::{NodeAddr, SecretKey};
+    use iroh_docs::{Capability, DocTicket, NamespaceSecret};
+    use std::{net::SocketAddr, str::FromStr};
+
+    fn mock_ticket() -> UDocTicket {
+        let mut rng = rand::thread_rng();
+
+        let capability = Capability::Write(NamespaceSecret::new(&mut rng));
+        let node_addr = NodeAddr::new(SecretKey::generate(&mut rng).public())
+            .with_relay_url("https://relay.example.com./".parse().unwrap())
+            .with_direct_addresses([
+                SocketAddr::from(([192, 168, 1, 12], 52000)),
+                SocketAddr::from(([10, 0, 0, 3], 52001)),
+                "[2001:db8::1]:52002".parse().unwrap(),
+            ]);
+
+        DocTicket::new(capability, vec![node_addr]).into()
+    }
+
+    #[test]
+    fn test_uri_roundtrip() {
+        let ticket = mock_ticket();
+
+        let uri = ticket_to_uri(ticket.clone());
+        assert!(uri.starts_with(TICKET_URI_PREFIX));
+
+        let parsed = UDocTicket::from_str(&uri).unwrap();
+        assert_eq!(parsed.to_string(), ticket.to_string());
+    }
+
+    #[test]
+    fn test_compact_roundtrip() {
+        let ticket = mock_ticket();
+
+        let compact = compact_ticket(ticket.clone());
+        assert!(compact.to_string().len() < ticket.to_string().len());
+
+        let parsed: DocTicket = UDocTicket::from_str(&compact.to_string()).unwrap().into();
+        let original: DocTicket = ticket.into();
+        assert_eq!(parsed.capability.id(), original.capability.id());
+        assert!(matches!(parsed.capability, Capability::Write(_)));
+        assert_eq!(parsed.nodes.len(), original.nodes.len());
+        for (parsed, original) in parsed.nodes.iter().zip(&original.nodes) {
+            assert_eq!(parsed.node_id, original.node_id);
+            assert_eq!(parsed.relay_url, original.relay_url);
+            assert!(parsed.direct_addresses.is_empty());
+        }
+
+        let compact_uri = ticket_to_uri(compact.clone());
+        let parsed = UDocTicket::from_str(&compact_uri).unwrap();
+        assert_eq!(parsed.to_string(), compact.to_string());
+    }
+
+    #[test]
+    fn test_invalid_uri() {
+        assert!(UDocTicket::from_str("unimusic://join?").is_err());
+        assert!(UDocTicket::from_str("unimusic://join?ticket=doc123").is_err());
+    }
+}
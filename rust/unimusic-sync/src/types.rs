@@ -4,6 +4,8 @@ use iroh_blobs::Hash;
 use iroh_docs::{AuthorId, DocTicket, Entry, NamespaceId};
 use serde::{Deserialize, Serialize};
 
+use crate::errors::SharedError;
+
 use std::fmt::Display;
 use std::str::FromStr;
 
@@ -52,10 +54,24 @@ macro_rules! uniffiable_wrapper {
 pub struct UAuthorId(AuthorId);
 uniffiable_wrapper!(AuthorId, UAuthorId);
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct UNamespaceId(NamespaceId);
 uniffiable_wrapper!(NamespaceId, UNamespaceId);
 
+// NamespaceId serializes as a byte array, which can't be used as a JSON map key
+impl Serialize for UNamespaceId {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for UNamespaceId {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let string = String::deserialize(deserializer)?;
+        Self::from_str(&string).map_err(serde::de::Error::custom)
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct UNodeId(pub NodeId);
 uniffiable_wrapper!(NodeId, UNodeId);
@@ -81,53 +97,108 @@ impl From<NodeData> for UNodeData {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct UHash(Hash);
 uniffiable_wrapper!(Hash, UHash);
 
+/// Ticket granting access to a namespace.
+/// Can be parsed from its plain, compact or `unimusic://join` URI form, see [`crate::ticket`].
 #[derive(Debug, Clone)]
 pub struct UDocTicket(DocTicket);
-uniffiable_wrapper!(DocTicket, UDocTicket);
+
+impl From<DocTicket> for UDocTicket {
+    fn from(value: DocTicket) -> Self {
+        Self(value)
+    }
+}
+
+impl From<UDocTicket> for DocTicket {
+    fn from(value: UDocTicket) -> Self {
+        value.0
+    }
+}
+
+impl Display for UDocTicket {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&self.0, f)
+    }
+}
+
+impl FromStr for UDocTicket {
+    type Err = SharedError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        crate::ticket::parse_ticket(s).map(Self)
+    }
+}
+
+impl From<UDocTicket> for String {
+    fn from(value: UDocTicket) -> Self {
+        value.0.to_string()
+    }
+}
+
+#[cfg(feature = "default")]
+uniffi::custom_type!(UDocTicket, String, {
+    lower: |item| item.to_string(),
+    try_lift: |string| Ok(UDocTicket::from_str(&string)?)
+});
 
 #[cfg_attr(feature = "default", derive(uniffi::Object))]
 #[derive(Debug)]
-pub struct UEntry(Entry);
+pub struct UEntry {
+    entry: Entry,
+    file_len: u64,
+}
 
 impl From<Entry> for UEntry {
     fn from(value: Entry) -> Self {
-        Self(value)
+        Self {
+            file_len: value.content_len(),
+            entry: value,
+        }
+    }
+}
+
+impl UEntry {
+    pub(crate) fn with_file_len(self, file_len: u64) -> Self {
+        Self { file_len, ..self }
     }
 }
 
 #[cfg_attr(feature = "default", uniffi::export)]
 impl UEntry {
     pub fn key(&self) -> String {
-        let key = self.0.key();
+        let key = self.entry.key();
         let path = std::str::from_utf8(key).expect("Key to be UTF-8 encoded path");
         path.to_string()
     }
 
     pub fn content_hash(&self) -> UHash {
-        self.0.content_hash().into()
+        self.entry.content_hash().into()
     }
 
     pub fn content_len(&self) -> u64 {
-        self.0.content_len()
+        self.entry.content_len()
+    }
+
+    /// Length of the file, which differs from the content length for split files
+    pub fn file_len(&self) -> u64 {
+        self.file_len
     }
 
     pub fn timestamp(&self) -> u64 {
-        self.0.timestamp()
+        self.entry.timestamp()
     }
 
     pub fn namespace(&self) -> UNamespaceId {
-        self.0.namespace().into()
+        self.entry.namespace().into()
     }
 
     pub fn author(&self) -> UAuthorId {
-        self.0.author().into()
+        self.entry.author().into()
     }
 
     pub fn is_empty(&self) -> bool {
-        self.0.is_empty()
+        self.entry.is_empty()
     }
 }
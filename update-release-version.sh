@@ -12,6 +12,7 @@ sed -i "" -E "s/(version = \")[^\"]+(\")/\1$version\2/g" android/build.gradle
 
 # Rust
 awk '{ if (!done && /version = \"/) { sub(/(version = \")[^\"]+(\")/, "version = \"" newVersion "\""); done=1 } print }' newVersion="$version" rust/unimusic-sync/Cargo.toml >tmpfile && mv tmpfile rust/unimusic-sync/Cargo.toml
+awk '{ if (!done && /version = \"/) { sub(/(version = \")[^\"]+(\")/, "version = \"" newVersion "\""); done=1 } print }' newVersion="$version" rust/unimusic-sync-cli/Cargo.toml >tmpfile && mv tmpfile rust/unimusic-sync-cli/Cargo.toml
 pushd rust && cargo check && popd
 
 # Node
@@ -23,4 +24,4 @@ popd
 
 git add Package.swift \
     android/build.gradle \
-    rust/Cargo.lock rust/unimusic-sync/Cargo.toml
+    rust/Cargo.lock rust/unimusic-sync/Cargo.toml rust/unimusic-sync-cli/Cargo.toml